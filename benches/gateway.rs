@@ -0,0 +1,236 @@
+//! Benchmarks for broadcast fan-out, subscription filtering and message serialization.
+//!
+//! Run with `cargo bench`. Criterion keeps earlier results under `target/criterion` and reports
+//! changes against them, so compare numbers from the same machine only.
+
+use std::hint::black_box;
+
+use actix_ws::{CloseReason, Closed};
+use actix_ws_fuckery::config::{SlowConsumerConfig, WebSocketServerConfig};
+use actix_ws_fuckery::filter::EventFilter;
+use actix_ws_fuckery::models::websocket::{
+    WebSocketSubscriptionType, WebSocketTokenData,
+    messages::{WebSocketMessage, WebSocketMessageInner, WebSocketMessageResponse},
+};
+use actix_ws_fuckery::ws::{InsertedSession, WebSocketServer, sink::SessionSink};
+use bytes::Bytes;
+use bytestring::ByteString;
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use serde_json::json;
+use tokio::runtime::Runtime;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+const SESSION_COUNTS: &[usize] = &[10, 100, 1000];
+
+/// Discards everything, so only the server's own work is measured.
+struct NullSink;
+
+impl SessionSink for NullSink {
+    async fn text(&mut self, _msg: ByteString) -> Result<(), Closed> {
+        Ok(())
+    }
+
+    async fn binary(&mut self, _bytes: Bytes) -> Result<(), Closed> {
+        Ok(())
+    }
+
+    async fn ping(&mut self, _bytes: Bytes) -> Result<(), Closed> {
+        Ok(())
+    }
+
+    async fn pong(&mut self, _bytes: Bytes) -> Result<(), Closed> {
+        Ok(())
+    }
+
+    async fn close(self, _reason: Option<CloseReason>) -> Result<(), Closed> {
+        Ok(())
+    }
+}
+
+/// A server with `count` guest sessions, half of them filtering out the event being published.
+fn server_with_sessions(
+    runtime: &Runtime,
+    count: usize,
+) -> (WebSocketServer, Vec<InsertedSession>) {
+    runtime.block_on(async {
+        // Wait on full queues rather than dropping events or evicting, so delivery is part of what's
+        // measured
+        let config = WebSocketServerConfig {
+            slow_consumer: SlowConsumerConfig {
+                drop_events_when_lagging: false,
+                max_strikes: u32::MAX,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let server = WebSocketServer::with_config(config);
+        let mut sessions = Vec::with_capacity(count);
+
+        for i in 0..count {
+            let uuid = Uuid::new_v4();
+            let data = WebSocketTokenData::new("guest".to_owned(), None);
+            let inserted = server
+                .insert_session(
+                    uuid,
+                    NullSink,
+                    None,
+                    data,
+                    CancellationToken::new(),
+                    std::convert::identity,
+                )
+                .await;
+
+            server
+                .subscribe_to_event(&uuid, WebSocketSubscriptionType::Transactions)
+                .await
+                .expect("Failed to subscribe");
+            if i % 2 == 0 {
+                let filter =
+                    EventFilter(json!({ "to": "kelsewhere" }).as_object().unwrap().clone());
+                server
+                    .set_event_filter(&uuid, WebSocketSubscriptionType::Transactions, Some(filter))
+                    .await;
+            }
+
+            sessions.push(inserted);
+        }
+
+        (server, sessions)
+    })
+}
+
+/// Warn when sessions were disconnected as slow consumers, which makes later iterations cheaper.
+fn report_evictions(runtime: &Runtime, server: &WebSocketServer, count: usize) {
+    let remaining = runtime.block_on(server.session_count());
+    if remaining < count {
+        println!(
+            "  warning: {} of {count} sessions were evicted",
+            count - remaining
+        );
+    }
+}
+
+/// A multi-threaded runtime, like the server's, to run the async benchmarks on.
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("Failed to build runtime")
+}
+
+fn broadcast(c: &mut Criterion) {
+    let runtime = runtime();
+    let mut group = c.benchmark_group("broadcast");
+
+    for &count in SESSION_COUNTS {
+        let (server, _sessions) = server_with_sessions(&runtime, count);
+        let server = &server;
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_function(BenchmarkId::new("sessions", count), |b| {
+            b.to_async(&runtime)
+                .iter(|| async move { server.broadcast(r#"{"number":42}"#).await.unwrap() });
+        });
+        report_evictions(&runtime, server, count);
+    }
+
+    group.finish();
+}
+
+fn publish_filtered(c: &mut Criterion) {
+    let runtime = runtime();
+    let mut group = c.benchmark_group("publish with filters");
+    let event = json!({
+        "type": "event",
+        "event": "transaction",
+        "transaction": { "from": "kabc", "to": "kdef", "value": 10 },
+    })
+    .to_string();
+
+    for &count in SESSION_COUNTS {
+        let (server, _sessions) = server_with_sessions(&runtime, count);
+        let server = &server;
+        let event = &event;
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_function(BenchmarkId::new("sessions", count), |b| {
+            b.to_async(&runtime).iter(|| async move {
+                server
+                    .publish(WebSocketSubscriptionType::Transactions, event.clone())
+                    .await
+                    .unwrap()
+            });
+        });
+        report_evictions(&runtime, server, count);
+    }
+
+    group.finish();
+}
+
+fn filter_matching(c: &mut Criterion) {
+    let event = json!({
+        "type": "event",
+        "event": "transaction",
+        "transaction": { "from": "kabc", "to": "kdef", "value": 10, "metadata": "x".repeat(64) },
+    });
+    let top_level = EventFilter(
+        json!({ "event": "transaction" })
+            .as_object()
+            .unwrap()
+            .clone(),
+    );
+    let nested = EventFilter(
+        json!({ "to": "kdef", "from": "kabc" })
+            .as_object()
+            .unwrap()
+            .clone(),
+    );
+    let miss = EventFilter(json!({ "to": "kelsewhere" }).as_object().unwrap().clone());
+
+    let mut group = c.benchmark_group("filter");
+    group.bench_function("top-level match", |b| {
+        b.iter(|| top_level.matches(black_box(&event)))
+    });
+    group.bench_function("nested match", |b| {
+        b.iter(|| nested.matches(black_box(&event)))
+    });
+    group.bench_function("miss", |b| b.iter(|| miss.matches(black_box(&event))));
+    group.finish();
+}
+
+fn serialization(c: &mut Criterion) {
+    let response = WebSocketMessage {
+        ok: Some(true),
+        id: Some(1),
+        trace_id: Some("0123456789abcdef0123456789abcdef".to_owned()),
+        idempotency_key: None,
+        r#type: WebSocketMessageInner::Response {
+            data: WebSocketMessageResponse::Subscribe {
+                subscription_level: vec!["blocks".to_owned(), "transactions".to_owned()],
+            },
+        },
+    };
+    let json = serde_json::to_string(&response).unwrap();
+
+    c.bench_function("serialize/response", |b| {
+        b.iter(|| serde_json::to_string(black_box(&response)))
+    });
+    c.bench_function("deserialize/response", |b| {
+        b.iter(|| serde_json::from_str::<WebSocketMessage>(black_box(&json)))
+    });
+    c.bench_function("deserialize/subscribe request", |b| {
+        b.iter(|| {
+            serde_json::from_str::<WebSocketMessage>(black_box(
+                r#"{"id":2,"type":"subscribe","event":"transactions","filter":{"to":"kdef"}}"#,
+            ))
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    broadcast,
+    publish_filtered,
+    filter_matching,
+    serialization
+);
+criterion_main!(benches);
@@ -0,0 +1,159 @@
+//! How broadcast fan-out and message dispatch scale with the number of worker threads, all of them
+//! sharing one server.
+//!
+//! Run with `cargo bench --bench workers`. Every session is a real WebSocket connection over
+//! loopback, so the numbers include framing and the network stack; only compare numbers from the
+//! same machine.
+
+use std::net::TcpListener;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use actix_codec::Framed;
+use actix_web::{App, HttpServer, dev::ServerHandle, rt::System, web};
+use actix_ws_fuckery::models::websocket::WebSocketTokenData;
+use actix_ws_fuckery::ws::{self, WebSocketServer};
+use awc::BoxedSocket;
+use awc::ws::{Codec, Frame, Message};
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use futures::{SinkExt, StreamExt};
+
+const WORKER_COUNTS: &[usize] = &[1, 2, 4, 8];
+const SESSION_COUNTS: &[usize] = &[10, 100, 500];
+
+type Socket = Framed<BoxedSocket, Codec>;
+
+/// A gateway with `workers` worker threads, running on a thread of its own.
+fn start(server: WebSocketServer, workers: usize) -> (String, ServerHandle) {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind");
+    let addr = listener.local_addr().expect("Listener has no address");
+    let (sender, receiver) = mpsc::channel();
+
+    std::thread::spawn(move || {
+        System::new().block_on(async move {
+            let server = web::Data::new(server);
+            let http_server = HttpServer::new(move || {
+                App::new().app_data(server.clone()).service(ws::ws_handler)
+            })
+            .workers(workers)
+            .listen(listener)
+            .expect("Failed to listen")
+            .run();
+
+            let _ = sender.send(http_server.handle());
+            let _ = http_server.await;
+        });
+    });
+
+    let handle = receiver.recv().expect("Server failed to start");
+    (format!("ws://{addr}"), handle)
+}
+
+/// Connect `count` guest sessions, waiting for each one's hello.
+async fn connect(server: &WebSocketServer, url: &str, count: usize) -> Vec<Socket> {
+    // One connection per session, beyond the connector's default limit of 100
+    let client = awc::Client::builder()
+        .connector(awc::Connector::new().limit(0))
+        .finish();
+    let mut sockets = Vec::with_capacity(count);
+
+    for _ in 0..count {
+        let token = server
+            .obtain_token(WebSocketTokenData::new("guest".to_owned(), None))
+            .await;
+        let (_, mut socket) = client
+            .ws(format!("{url}/gateway/{token}"))
+            .connect()
+            .await
+            .expect("Failed to connect");
+        next_text(&mut socket).await;
+        sockets.push(socket);
+    }
+
+    sockets
+}
+
+/// The next text message, passing over heartbeats.
+async fn next_text(socket: &mut Socket) {
+    loop {
+        match socket.next().await {
+            Some(Ok(Frame::Text(_))) => return,
+            Some(Ok(_)) => continue,
+            other => panic!("Session went away: {other:?}"),
+        }
+    }
+}
+
+/// Time from broadcasting a message to every session having it.
+async fn broadcast(server: &WebSocketServer, sockets: &mut [Socket]) -> Duration {
+    let started = Instant::now();
+    server.broadcast(r#"{"number":42}"#).await.unwrap();
+    for socket in sockets.iter_mut() {
+        next_text(socket).await;
+    }
+
+    started.elapsed()
+}
+
+/// Time from every session sending a message at once to all of them having their answers.
+async fn dispatch(sockets: &mut [Socket]) -> Duration {
+    let started = Instant::now();
+    for socket in sockets.iter_mut() {
+        let message = Message::Text(r#"{"id":1,"type":"work"}"#.into());
+        socket.send(message).await.expect("Failed to send");
+    }
+    for socket in sockets.iter_mut() {
+        next_text(socket).await;
+    }
+
+    started.elapsed()
+}
+
+/// Measure `round` against every pairing of worker and session counts, each on a fresh server.
+fn scaling<F>(c: &mut Criterion, name: &str, mut round: F)
+where
+    F: AsyncFnMut(&WebSocketServer, &mut [Socket]) -> Duration,
+{
+    let mut group = c.benchmark_group(name);
+    group.sample_size(10);
+
+    for &workers in WORKER_COUNTS {
+        for &sessions in SESSION_COUNTS {
+            let server = WebSocketServer::new();
+            let (url, handle) = start(server.clone(), workers);
+            let system = System::new();
+            let mut sockets = system.block_on(connect(&server, &url, sessions));
+
+            group.throughput(Throughput::Elements(sessions as u64));
+            let id = BenchmarkId::new(format!("{workers} workers"), sessions);
+            group.bench_function(id, |b| {
+                b.iter_custom(|iters| {
+                    system.block_on(async {
+                        let mut total = Duration::ZERO;
+                        for _ in 0..iters {
+                            total += round(&server, &mut sockets).await;
+                        }
+                        total
+                    })
+                });
+            });
+
+            system.block_on(handle.stop(false));
+        }
+    }
+
+    group.finish();
+}
+
+fn broadcasts(c: &mut Criterion) {
+    scaling(c, "broadcast", async |server, sockets| {
+        broadcast(server, sockets).await
+    });
+}
+
+fn dispatches(c: &mut Criterion) {
+    scaling(c, "dispatch", async |_, sockets| dispatch(sockets).await);
+}
+
+criterion_group!(benches, broadcasts, dispatches);
+criterion_main!(benches);
@@ -0,0 +1,24 @@
+#![no_main]
+
+//! Feeds arbitrary client input through the inbound decoding path: the size limit, JSON parsing and
+//! the Socket.IO packet decoder. None of it may panic, whatever the input.
+//!
+//! Run with `cargo fuzz run inbound_message`.
+
+use actix_ws_fuckery::{config::WebSocketServerConfig, socketio, ws};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Text frames are always valid UTF-8 by the time they reach the parser
+    let text = String::from_utf8_lossy(data);
+    let max_message_size = WebSocketServerConfig::default().max_message_size;
+
+    if let Ok(message) = ws::parse_message(&text, max_message_size) {
+        // Whatever parses has to serialize again, as responses echo parts of it back
+        serde_json::to_string(&message).expect("Parsed message failed to serialize");
+    }
+
+    if let Some(message) = socketio::decode(&text) {
+        let _ = ws::parse_message(&message, max_message_size);
+    }
+});
@@ -0,0 +1,48 @@
+//! Krist addresses, derived from the private keys that own them.
+
+use sha2::{Digest, Sha256};
+
+/// Prefix every v2 address starts with.
+const V2_PREFIX: char = 'k';
+
+/// The v2 address owned by `private_key`, the same one Krist itself would derive.
+pub fn from_private_key(private_key: &str) -> String {
+    let mut chars: [Option<u8>; 9] = [None; 9];
+    let mut hash = sha256(&sha256(private_key));
+    for char in &mut chars {
+        *char = u8::from_str_radix(&hash[..2], 16).ok();
+        hash = sha256(&sha256(&hash));
+    }
+
+    let mut address = String::from(V2_PREFIX);
+    let mut i = 0;
+    while i < chars.len() {
+        let index = usize::from_str_radix(&hash[2 * i..2 * i + 2], 16).unwrap_or_default() % 9;
+        match chars[index].take() {
+            Some(byte) => {
+                address.push(to_base36(byte));
+                i += 1;
+            }
+            None => hash = sha256(&hash),
+        }
+    }
+
+    address
+}
+
+/// Lowercase hex SHA-256 of `input`, which is what gets hashed again at every step.
+fn sha256(input: &str) -> String {
+    format!("{:x}", Sha256::digest(input.as_bytes()))
+}
+
+/// Krist's lossy mapping of a byte onto a digit or lowercase letter.
+fn to_base36(byte: u8) -> char {
+    let code = 48 + byte / 7;
+    let code = match code {
+        _ if code + 39 > 122 => 101,
+        58.. => code + 39,
+        _ => code,
+    };
+
+    char::from(code)
+}
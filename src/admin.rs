@@ -0,0 +1,407 @@
+use actix_web::{
+    HttpRequest, HttpResponse, delete, error::ErrorBadRequest, error::ErrorNotFound,
+    error::ErrorUnauthorized, get, http::header, post, web,
+};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use uuid::Uuid;
+
+use crate::config::RuntimeConfig;
+#[cfg(feature = "krist")]
+use crate::names::NameRecord;
+use crate::pagination::{PageQuery, Paginated};
+use crate::ws::{self, WebSocketServer};
+
+/// Register the admin API routes.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(reload_config)
+        .service(tag_session)
+        .service(untag_session)
+        .service(subscription_stats)
+        .service(sessions)
+        .service(lost_sessions)
+        .service(metrics)
+        .service(message_summary)
+        .service(maintenance)
+        .service(start_maintenance)
+        .service(end_maintenance)
+        .service(firehose);
+
+    #[cfg(feature = "krist")]
+    cfg.service(lock_address)
+        .service(unlock_address)
+        .service(seize_name)
+        .service(restore_name);
+}
+
+/// Reject the request unless it carries the configured admin token.
+pub fn require_admin(req: &HttpRequest, server: &WebSocketServer) -> Result<(), actix_web::Error> {
+    let Some(admin_token) = &server.config().admin_token else {
+        return Err(ErrorNotFound("Admin API is disabled"));
+    };
+
+    let provided = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.strip_prefix("Bearer "));
+
+    match provided {
+        Some(provided) if tokens_match(provided, admin_token) => Ok(()),
+        _ => Err(ErrorUnauthorized("Invalid admin token")),
+    }
+}
+
+/// Compare tokens in constant time, hashing them first so their lengths don't leak either.
+fn tokens_match(provided: &str, expected: &str) -> bool {
+    let provided = Sha256::digest(provided.as_bytes());
+    let expected = Sha256::digest(expected.as_bytes());
+    provided.ct_eq(&expected).into()
+}
+
+/// Replace the runtime configuration with the request body, or reload it from the config file when empty.
+#[post("/admin/reload")]
+pub async fn reload_config(
+    req: HttpRequest,
+    server: web::Data<WebSocketServer>,
+    body: Option<web::Json<RuntimeConfig>>,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_admin(&req, &server)?;
+
+    match body {
+        Some(runtime) => server.reload_config(runtime.into_inner()).await,
+        None => server
+            .reload_config_from_file()
+            .await
+            .map_err(actix_web::error::ErrorInternalServerError)?,
+    }
+
+    Ok(HttpResponse::Ok().json(&*server.runtime_config()))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionTags {
+    pub tags: Vec<String>,
+}
+
+/// Attach tags to a session connected to this node.
+#[post("/admin/sessions/{uuid}/tags")]
+pub async fn tag_session(
+    req: HttpRequest,
+    server: web::Data<WebSocketServer>,
+    uuid: web::Path<Uuid>,
+    body: web::Json<SessionTags>,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_admin(&req, &server)?;
+    let server = server.for_request(&req)?;
+
+    let tags = server
+        .tag_session(&uuid, body.into_inner().tags)
+        .await
+        .map_err(ErrorBadRequest)?
+        .ok_or_else(|| ErrorNotFound("Session not found"))?;
+
+    Ok(HttpResponse::Ok().json(SessionTags { tags }))
+}
+
+/// Remove a tag from a session connected to this node.
+#[delete("/admin/sessions/{uuid}/tags/{tag}")]
+pub async fn untag_session(
+    req: HttpRequest,
+    server: web::Data<WebSocketServer>,
+    path: web::Path<(Uuid, String)>,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_admin(&req, &server)?;
+    let server = server.for_request(&req)?;
+
+    let (uuid, tag) = path.into_inner();
+    let tags = server
+        .untag_session(&uuid, &tag)
+        .await
+        .ok_or_else(|| ErrorNotFound("Session not found"))?;
+
+    Ok(HttpResponse::Ok().json(SessionTags { tags }))
+}
+
+/// Events delivered and dropped per subscription for a session connected to this node.
+#[get("/admin/sessions/{uuid}/stats")]
+pub async fn subscription_stats(
+    req: HttpRequest,
+    server: web::Data<WebSocketServer>,
+    uuid: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_admin(&req, &server)?;
+    let server = server.for_request(&req)?;
+
+    let stats = server
+        .get_subscription_stats(&uuid)
+        .await
+        .ok_or_else(|| ErrorNotFound("Session not found"))?;
+
+    Ok(HttpResponse::Ok().json(stats))
+}
+
+/// Sessions connected to this node, with where each is in its lifecycle and its latest
+/// round-trip time.
+#[get("/admin/sessions")]
+pub async fn sessions(
+    req: HttpRequest,
+    server: web::Data<WebSocketServer>,
+    query: web::Query<PageQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_admin(&req, &server)?;
+    let server = server.for_request(&req)?;
+
+    let sessions = server.list_sessions().await;
+    Ok(HttpResponse::Ok().json(Paginated::from_items(sessions, &query)))
+}
+
+/// Sessions the previous process lost without shutting down, that haven't resumed since.
+#[get("/admin/sessions/lost")]
+pub async fn lost_sessions(
+    req: HttpRequest,
+    server: web::Data<WebSocketServer>,
+    query: web::Query<PageQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_admin(&req, &server)?;
+    let server = server.for_request(&req)?;
+
+    Ok(HttpResponse::Ok().json(Paginated::from_items(server.lost_sessions(), &query)))
+}
+
+/// Counters kept since the server started.
+#[get("/admin/metrics")]
+pub async fn metrics(
+    req: HttpRequest,
+    server: web::Data<WebSocketServer>,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_admin(&req, &server)?;
+    let server = server.for_request(&req)?;
+
+    Ok(HttpResponse::Ok().json(server.metrics().snapshot()))
+}
+
+/// Types and sizes of the inbound messages sampled so far.
+#[get("/admin/messages")]
+pub async fn message_summary(
+    req: HttpRequest,
+    server: web::Data<WebSocketServer>,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_admin(&req, &server)?;
+    let server = server.for_request(&req)?;
+
+    let summary = server
+        .message_summary()
+        .ok_or_else(|| ErrorNotFound("Message sampling is disabled"))?;
+
+    Ok(HttpResponse::Ok().json(summary))
+}
+
+/// Whether the server is in read-only maintenance mode.
+#[get("/admin/maintenance")]
+pub async fn maintenance(
+    req: HttpRequest,
+    server: web::Data<WebSocketServer>,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_admin(&req, &server)?;
+    let server = server.for_request(&req)?;
+
+    Ok(HttpResponse::Ok().json(server.maintenance()))
+}
+
+/// Turn away state-changing messages with `server_maintenance` errors, e.g. during a migration.
+#[post("/admin/maintenance")]
+pub async fn start_maintenance(
+    req: HttpRequest,
+    server: web::Data<WebSocketServer>,
+    body: Option<web::Json<ModerationRequest>>,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_admin(&req, &server)?;
+    let server = server.for_request(&req)?;
+
+    server.start_maintenance(body.and_then(|x| x.into_inner().reason));
+
+    Ok(HttpResponse::Ok().json(server.maintenance()))
+}
+
+#[delete("/admin/maintenance")]
+pub async fn end_maintenance(
+    req: HttpRequest,
+    server: web::Data<WebSocketServer>,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_admin(&req, &server)?;
+    let server = server.for_request(&req)?;
+
+    if !server.end_maintenance() {
+        return Err(ErrorNotFound("The server is not in maintenance"));
+    }
+
+    Ok(HttpResponse::Ok().json(server.maintenance()))
+}
+
+/// Stream gateway events to the admin as they happen, one JSON message each, to tail the
+/// gateway's activity live.
+#[get("/ws/admin")]
+pub async fn firehose(
+    req: HttpRequest,
+    body: web::Payload,
+    server: web::Data<WebSocketServer>,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_admin(&req, &server)?;
+    let server = server.for_request(&req)?;
+    let (response, mut session, mut stream) = actix_ws::handle(&req, body)?;
+
+    let mut events = Box::pin(server.events());
+    actix_web::rt::spawn(async move {
+        let shutting_down = server.shutting_down().clone();
+        loop {
+            tokio::select! {
+                () = shutting_down.cancelled() => break,
+                event = events.next() => {
+                    let Some(event) = event else {
+                        break;
+                    };
+                    let Some(event) = ws::to_text(&event, "firehose event") else {
+                        continue;
+                    };
+                    if session.text(event).await.is_err() {
+                        return;
+                    }
+                }
+                msg = stream.next() => match msg {
+                    Some(Ok(actix_ws::Message::Ping(bytes))) => {
+                        if session.pong(&bytes).await.is_err() {
+                            return;
+                        }
+                    }
+                    Some(Ok(actix_ws::Message::Close(_)) | Err(_)) | None => break,
+                    Some(Ok(_)) => {} // Nothing to say to the firehose
+                },
+            }
+        }
+
+        let _ = session.close(None).await;
+    });
+
+    Ok(response)
+}
+
+/// Why an admin is acting, for the audit log.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ModerationRequest {
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+#[cfg(feature = "krist")]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AddressLock {
+    pub address: String,
+    pub locked: bool,
+}
+
+/// Refuse logins and transactions from an address until it's unlocked.
+#[cfg(feature = "krist")]
+#[post("/admin/addresses/{address}/lock")]
+pub async fn lock_address(
+    req: HttpRequest,
+    server: web::Data<WebSocketServer>,
+    address: web::Path<String>,
+    body: Option<web::Json<ModerationRequest>>,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_admin(&req, &server)?;
+    let server = server.for_request(&req)?;
+
+    let address = address.into_inner();
+    let reason = body.and_then(|x| x.into_inner().reason);
+    server
+        .lock_address(&address, reason)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(AddressLock {
+        address,
+        locked: true,
+    }))
+}
+
+#[cfg(feature = "krist")]
+#[delete("/admin/addresses/{address}/lock")]
+pub async fn unlock_address(
+    req: HttpRequest,
+    server: web::Data<WebSocketServer>,
+    address: web::Path<String>,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_admin(&req, &server)?;
+    let server = server.for_request(&req)?;
+
+    let address = address.into_inner();
+    let unlocked = server
+        .unlock_address(&address)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    if !unlocked {
+        return Err(ErrorNotFound("Address is not locked"));
+    }
+
+    Ok(HttpResponse::Ok().json(AddressLock {
+        address,
+        locked: false,
+    }))
+}
+
+/// Take a name off its owner until it's restored.
+#[cfg(feature = "krist")]
+#[post("/admin/names/{name}/seize")]
+pub async fn seize_name(
+    req: HttpRequest,
+    server: web::Data<WebSocketServer>,
+    name: web::Path<String>,
+    body: Option<web::Json<ModerationRequest>>,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_admin(&req, &server)?;
+    let server = server.for_request(&req)?;
+    if server.name_store().is_none() {
+        return Err(ErrorNotFound("Names are not enabled"));
+    }
+
+    let reason = body.and_then(|x| x.into_inner().reason);
+    let record = server
+        .seize_name(&name, reason)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    name_response(record)
+}
+
+/// Give a seized name back to its owner.
+#[cfg(feature = "krist")]
+#[delete("/admin/names/{name}/seize")]
+pub async fn restore_name(
+    req: HttpRequest,
+    server: web::Data<WebSocketServer>,
+    name: web::Path<String>,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_admin(&req, &server)?;
+    let server = server.for_request(&req)?;
+    if server.name_store().is_none() {
+        return Err(ErrorNotFound("Names are not enabled"));
+    }
+
+    let record = server
+        .restore_name(&name)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    name_response(record)
+}
+
+#[cfg(feature = "krist")]
+fn name_response(record: Option<NameRecord>) -> Result<HttpResponse, actix_web::Error> {
+    match record {
+        Some(record) => Ok(HttpResponse::Ok().json(record)),
+        None => Err(ErrorNotFound("Name not found")),
+    }
+}
@@ -0,0 +1,101 @@
+//! Alarms for connection churn, so operators hear about reconnect storms and attacks while
+//! they're happening.
+
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+/// Rates above which an alarm goes off, each off when unset.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChurnThresholds {
+    pub connects_per_sec: Option<u32>,
+    pub disconnects_per_sec: Option<u32>,
+    /// Gateway tokens turned away.
+    pub auth_failures_per_sec: Option<u32>,
+}
+
+impl ChurnThresholds {
+    fn get(&self, kind: ChurnKind) -> Option<u32> {
+        match kind {
+            ChurnKind::Connects => self.connects_per_sec,
+            ChurnKind::Disconnects => self.disconnects_per_sec,
+            ChurnKind::AuthFailures => self.auth_failures_per_sec,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChurnKind {
+    Connects,
+    Disconnects,
+    AuthFailures,
+}
+
+/// A threshold was exceeded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChurnAlarm {
+    pub kind: ChurnKind,
+    pub threshold: u32,
+}
+
+/// Told when a churn alarm goes off, on top of it being logged and emitted as a
+/// [`GatewayEvent`](crate::events::GatewayEvent), which webhooks can be sent for.
+pub trait AlarmHook: Send + Sync {
+    fn fire(&self, alarm: &ChurnAlarm);
+}
+
+impl<F> AlarmHook for F
+where
+    F: Fn(&ChurnAlarm) + Send + Sync,
+{
+    fn fire(&self, alarm: &ChurnAlarm) {
+        self(alarm)
+    }
+}
+
+/// Counts for the current second.
+#[derive(Debug, Default, Clone, Copy)]
+struct Second {
+    second: u64,
+    count: u32,
+}
+
+/// Counts connects, disconnects and auth failures per second against their thresholds.
+#[derive(Debug)]
+pub struct ChurnMonitor {
+    thresholds: ChurnThresholds,
+    started: Instant,
+    seconds: Mutex<[Second; 3]>,
+}
+
+impl ChurnMonitor {
+    pub fn new(thresholds: ChurnThresholds) -> Self {
+        Self {
+            thresholds,
+            started: Instant::now(),
+            seconds: Mutex::default(),
+        }
+    }
+
+    /// Count one of `kind`, returning an alarm the first time in a second that its threshold is
+    /// exceeded.
+    pub fn record(&self, kind: ChurnKind) -> Option<ChurnAlarm> {
+        let threshold = self.thresholds.get(kind)?;
+        let now = self.started.elapsed().as_secs();
+        let mut seconds = self.seconds.lock().unwrap_or_else(|e| e.into_inner());
+
+        let current = &mut seconds[kind as usize];
+        if current.second != now {
+            *current = Second {
+                second: now,
+                count: 0,
+            };
+        }
+        current.count += 1;
+
+        (current.count == threshold.saturating_add(1)).then_some(ChurnAlarm { kind, threshold })
+    }
+}
@@ -0,0 +1,149 @@
+use std::{
+    io::Write,
+    net::IpAddr,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use uuid::Uuid;
+
+/// A security-relevant action worth keeping a record of.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "action")]
+pub enum AuditAction {
+    TokenIssued,
+    TokenUsed,
+    AuthFailed {
+        reason: String,
+    },
+    PolicyDenied {
+        message_type: String,
+        reason: String,
+    },
+    Login,
+    Logout,
+    Disconnect,
+    Kick {
+        reason: Option<String>,
+    },
+    Ban {
+        reason: Option<String>,
+    },
+    MaintenanceStarted {
+        reason: Option<String>,
+    },
+    MaintenanceEnded,
+    #[cfg(feature = "krist")]
+    Transaction {
+        to: String,
+        amount: u32,
+    },
+    #[cfg(feature = "krist")]
+    AddressLocked {
+        reason: Option<String>,
+    },
+    #[cfg(feature = "krist")]
+    AddressUnlocked,
+    #[cfg(feature = "krist")]
+    NameSeized {
+        name: String,
+        reason: Option<String>,
+    },
+    #[cfg(feature = "krist")]
+    NameRestored {
+        name: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AuditRecord {
+    /// Milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub action: AuditAction,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip: Option<IpAddr>,
+}
+
+impl AuditRecord {
+    pub fn new(action: AuditAction) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_millis() as u64)
+            .unwrap_or_default();
+
+        Self {
+            timestamp,
+            action,
+            session: None,
+            address: None,
+            ip: None,
+        }
+    }
+
+    pub fn session(mut self, session: Uuid) -> Self {
+        self.session = Some(session);
+        self
+    }
+
+    pub fn address(mut self, address: impl Into<String>) -> Self {
+        self.address = Some(address.into());
+        self
+    }
+
+    pub fn ip(mut self, ip: Option<IpAddr>) -> Self {
+        self.ip = ip;
+        self
+    }
+}
+
+/// Destination for audit records.
+pub trait AuditSink: Send + Sync {
+    fn record(&self, record: &AuditRecord);
+}
+
+/// Emits audit records as `tracing` events under the `audit` target.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TracingAuditSink;
+
+impl AuditSink for TracingAuditSink {
+    fn record(&self, record: &AuditRecord) {
+        match serde_json::to_string(record) {
+            Ok(line) => tracing::info!(target: "audit", "{line}"),
+            Err(e) => tracing::error!(target: "audit", "Failed to serialize audit record: {e}"),
+        }
+    }
+}
+
+/// Writes audit records as JSON lines to any writer, e.g. an append-only file.
+pub struct JsonLinesAuditSink<W: Write + Send> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write + Send> JsonLinesAuditSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+        }
+    }
+}
+
+impl<W: Write + Send> AuditSink for JsonLinesAuditSink<W> {
+    fn record(&self, record: &AuditRecord) {
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+
+        let result = serde_json::to_writer(&mut *writer, record)
+            .map_err(std::io::Error::from)
+            .and_then(|()| writer.write_all(b"\n"))
+            .and_then(|()| writer.flush());
+
+        if let Err(e) = result {
+            tracing::error!(target: "audit", "Failed to write audit record: {e}");
+        }
+    }
+}
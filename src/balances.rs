@@ -0,0 +1,142 @@
+//! Changes to Krist balances, sent to `balances` and `ownBalances` subscribers so wallets don't
+//! have to work them out from raw transactions.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// What moved an address's balance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BalanceChangeReason {
+    Transaction,
+    /// Mining a block.
+    Reward,
+    /// The economy's starting balance, given to an address the first time it's seen.
+    StartingBalance,
+    /// Paying for a name.
+    NameRegistration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BalanceChange {
+    pub address: String,
+    pub old: u64,
+    pub new: u64,
+    pub reason: BalanceChangeReason,
+}
+
+impl BalanceChange {
+    pub fn new(
+        address: impl Into<String>,
+        old: u64,
+        new: u64,
+        reason: BalanceChangeReason,
+    ) -> Self {
+        Self {
+            address: address.into(),
+            old,
+            new,
+            reason,
+        }
+    }
+
+    /// The `balance` event telling subscribers about the change.
+    pub fn event(&self) -> serde_json::Value {
+        serde_json::json!({
+            "type": "event",
+            "event": "balance",
+            "address": self.address,
+            "old": self.old,
+            "new": self.new,
+            "reason": self.reason,
+        })
+    }
+}
+
+/// Somewhere to keep balances.
+pub trait BalanceStore: Send + Sync {
+    /// `address`'s balance, which is nothing if it's never been credited.
+    fn get(&self, address: &str) -> anyhow::Result<u64>;
+
+    /// Add `amount` to `address`'s balance, returning the change.
+    fn credit(
+        &self,
+        address: &str,
+        amount: u64,
+        reason: BalanceChangeReason,
+    ) -> anyhow::Result<BalanceChange>;
+
+    /// Start `address` off with `balance` if it's never been seen, returning the change if it
+    /// hadn't.
+    fn open(&self, address: &str, balance: u64) -> anyhow::Result<Option<BalanceChange>>;
+
+    /// Take `amount` from `address`'s balance, returning the change, or `None` if it hasn't got
+    /// that much.
+    fn debit(
+        &self,
+        address: &str,
+        amount: u64,
+        reason: BalanceChangeReason,
+    ) -> anyhow::Result<Option<BalanceChange>>;
+}
+
+/// Keeps balances in memory, shared between clones, e.g. to stand in for a real store in tests.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryBalanceStore {
+    balances: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+impl BalanceStore for MemoryBalanceStore {
+    fn get(&self, address: &str) -> anyhow::Result<u64> {
+        let balances = self.balances.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(balances.get(address).copied().unwrap_or_default())
+    }
+
+    fn credit(
+        &self,
+        address: &str,
+        amount: u64,
+        reason: BalanceChangeReason,
+    ) -> anyhow::Result<BalanceChange> {
+        let mut balances = self.balances.lock().unwrap_or_else(|e| e.into_inner());
+        let balance = balances.entry(address.to_owned()).or_default();
+        let old = *balance;
+        *balance = old
+            .checked_add(amount)
+            .ok_or_else(|| anyhow::anyhow!("{address}'s balance would overflow"))?;
+
+        Ok(BalanceChange::new(address, old, *balance, reason))
+    }
+
+    fn open(&self, address: &str, balance: u64) -> anyhow::Result<Option<BalanceChange>> {
+        let mut balances = self.balances.lock().unwrap_or_else(|e| e.into_inner());
+        if balances.contains_key(address) {
+            return Ok(None);
+        }
+        balances.insert(address.to_owned(), balance);
+
+        let reason = BalanceChangeReason::StartingBalance;
+        Ok(Some(BalanceChange::new(address, 0, balance, reason)))
+    }
+
+    fn debit(
+        &self,
+        address: &str,
+        amount: u64,
+        reason: BalanceChangeReason,
+    ) -> anyhow::Result<Option<BalanceChange>> {
+        let mut balances = self.balances.lock().unwrap_or_else(|e| e.into_inner());
+        let balance = balances.entry(address.to_owned()).or_default();
+        let old = *balance;
+        let Some(new) = old.checked_sub(amount) else {
+            return Ok(None);
+        };
+        *balance = new;
+
+        Ok(Some(BalanceChange::new(address, old, new, reason)))
+    }
+}
@@ -0,0 +1,444 @@
+//! Async client for the gateway, for Rust consumers and the crate's own integration tests.
+//!
+//! The client is built on `awc`, so like the rest of actix it isn't `Send` and has to run on an
+//! actix runtime (e.g. `#[actix_web::main]` or `#[actix_web::test]`). Only plain `http`/`ws` URLs are
+//! supported.
+
+use std::{collections::VecDeque, time::Duration};
+
+use actix_web::rt::time;
+use anyhow::{anyhow, bail};
+use awc::{
+    BoxedSocket,
+    ws::{Codec, Frame, Message},
+};
+use futures::{SinkExt, StreamExt};
+use uuid::Uuid;
+
+use crate::models::secret::Secret;
+use crate::models::websocket::{
+    WebSocketStartConnectionBody, WebSocketStartResponse, WebSocketSubscriptionList,
+    WebSocketSubscriptionType,
+    messages::{Capability, WebSocketMessage, WebSocketMessageInner},
+};
+use crate::policy::ScopeList;
+
+type Socket = actix_codec::Framed<BoxedSocket, Codec>;
+
+#[derive(Debug, Clone)]
+pub struct GatewayClientConfig {
+    /// Base URL of the HTTP API, e.g. `http://127.0.0.1:8080`.
+    pub base_url: String,
+    /// Log in with this private key, or connect as a guest without one.
+    pub private_key: Option<Secret>,
+    /// Ask for a token limited to these scopes, rather than the default ones.
+    pub scopes: Option<ScopeList>,
+    /// Sent as `X-API-Key`, picking the namespace to connect to.
+    pub api_key: Option<String>,
+    /// How to reconnect when the connection drops, or `None` to give up straight away.
+    pub reconnect: Option<ReconnectPolicy>,
+    /// Move to a new connection when the server sends a `reconnect` advisory, after the wait it
+    /// asked for. Otherwise advisories are handed out by [`GatewayClient::recv`] like any message.
+    pub follow_reconnect_advisories: bool,
+}
+
+impl GatewayClientConfig {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into(),
+            private_key: None,
+            scopes: None,
+            api_key: None,
+            reconnect: Some(ReconnectPolicy::default()),
+            follow_reconnect_advisories: true,
+        }
+    }
+}
+
+/// How hard the client tries to get back onto the gateway after losing its connection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconnectPolicy {
+    pub max_attempts: u32,
+    /// Delay before the first attempt.
+    pub initial_delay: Duration,
+    /// Longest delay between attempts, however many have failed.
+    pub max_delay: Duration,
+    /// Factor the delay grows by after every failed attempt.
+    pub multiplier: f64,
+    /// Fraction of each delay that's randomized, between 0 and 1, so clients dropped together
+    /// don't all come back at the same moment.
+    pub jitter: f64,
+    /// Pick the old session back up with its resume token, if the server still remembers it.
+    pub resume: bool,
+    /// Subscribe to the same topics again when the session couldn't be resumed.
+    pub resubscribe: bool,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_delay: Duration::from_millis(250),
+            max_delay: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.5,
+            resume: true,
+            resubscribe: true,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Delay before the given attempt, counting from 1.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let delay = self.initial_delay.as_secs_f64() * self.multiplier.powi(exponent);
+        let delay = Duration::try_from_secs_f64(delay)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+
+        delay.mul_f64(1.0 - self.jitter.clamp(0.0, 1.0) * rand::random::<f64>())
+    }
+}
+
+/// Something received from the gateway.
+#[derive(Debug)]
+pub enum Incoming {
+    Message(WebSocketMessage),
+    /// A published event or broadcast, which can be any JSON.
+    Event(serde_json::Value),
+}
+
+/// Connection to the gateway, speaking [`WebSocketMessage`]s.
+pub struct GatewayClient {
+    config: GatewayClientConfig,
+    http: awc::Client,
+    socket: Socket,
+    /// Received while waiting on a response, and not yet handed out by [`Self::recv`].
+    pending: VecDeque<Incoming>,
+    next_id: usize,
+    resume_token: Option<Uuid>,
+    /// What the server said it supports in the last hello.
+    capabilities: Vec<Capability>,
+    /// Restored by hand when the session couldn't be resumed after a reconnect.
+    subscriptions: WebSocketSubscriptionList,
+    /// How long the server asked to be left alone before the next reconnect attempt.
+    advised_wait: Option<Duration>,
+}
+
+impl GatewayClient {
+    /// Get a token from `/ws/start` and connect with it, waiting for the server's hello.
+    pub async fn connect(config: GatewayClientConfig) -> anyhow::Result<Self> {
+        let http = awc::Client::default();
+        let socket = open(&http, &config, None).await?;
+
+        let mut client = Self {
+            config,
+            http,
+            socket,
+            pending: VecDeque::new(),
+            next_id: 1,
+            resume_token: None,
+            capabilities: Vec::new(),
+            subscriptions: WebSocketSubscriptionList::new(),
+            advised_wait: None,
+        };
+        client.await_hello().await?;
+
+        Ok(client)
+    }
+
+    /// Token for resuming this session, as given in the last hello.
+    pub fn resume_token(&self) -> Option<Uuid> {
+        self.resume_token
+    }
+
+    /// What the server supports, as advertised in the last hello. Capabilities this client doesn't
+    /// know of are left out.
+    pub fn capabilities(&self) -> &[Capability] {
+        &self.capabilities
+    }
+
+    /// Topics this client has subscribed to through [`Self::subscribe`].
+    pub fn subscriptions(&self) -> &WebSocketSubscriptionList {
+        &self.subscriptions
+    }
+
+    /// Send a message, returning the id its response will carry.
+    pub async fn send(&mut self, message: WebSocketMessageInner) -> anyhow::Result<usize> {
+        let (id, message) = self.number(message)?;
+        if self.send_text(message.clone()).await.is_err() {
+            self.reconnect().await?;
+            self.send_text(message).await?;
+        }
+
+        Ok(id)
+    }
+
+    /// Receive the next message or event, reconnecting if the connection drops.
+    pub async fn recv(&mut self) -> anyhow::Result<Incoming> {
+        if let Some(incoming) = self.pending.pop_front() {
+            return Ok(incoming);
+        }
+
+        loop {
+            match self.next_incoming().await? {
+                Some(incoming) => return Ok(incoming),
+                None => self.reconnect().await?,
+            }
+        }
+    }
+
+    /// Send a message and wait for the response to it.
+    ///
+    /// Anything else received in the meantime is kept for [`Self::recv`]. If the connection drops
+    /// before the response arrives, the client reconnects but the request fails.
+    pub async fn request(
+        &mut self,
+        message: WebSocketMessageInner,
+    ) -> anyhow::Result<WebSocketMessage> {
+        let id = self.send(message).await?;
+
+        loop {
+            match self.next_incoming().await? {
+                Some(Incoming::Message(message)) if message.id == Some(id) => return Ok(message),
+                Some(other) => self.pending.push_back(other),
+                None => {
+                    self.reconnect().await?;
+                    bail!("Connection lost before a response arrived");
+                }
+            }
+        }
+    }
+
+    pub async fn subscribe(
+        &mut self,
+        event: WebSocketSubscriptionType,
+    ) -> anyhow::Result<WebSocketMessage> {
+        let response = self
+            .request(WebSocketMessageInner::Subscribe {
+                event: event.clone(),
+                filter: None,
+            })
+            .await?;
+        if response.ok == Some(true) {
+            self.subscriptions.insert(event);
+        }
+
+        Ok(response)
+    }
+
+    pub async fn unsubscribe(
+        &mut self,
+        event: WebSocketSubscriptionType,
+    ) -> anyhow::Result<WebSocketMessage> {
+        let response = self
+            .request(WebSocketMessageInner::Unsubscribe {
+                event: event.clone(),
+            })
+            .await?;
+        if response.ok == Some(true) {
+            self.subscriptions.remove(&event);
+        }
+
+        Ok(response)
+    }
+
+    /// Close the connection, without reconnecting.
+    pub async fn close(mut self) -> anyhow::Result<()> {
+        self.socket
+            .send(Message::Close(None))
+            .await
+            .map_err(|e| anyhow!("Failed to close connection: {e}"))
+    }
+
+    /// Give a message the next id, and serialize it.
+    fn number(&mut self, message: WebSocketMessageInner) -> anyhow::Result<(usize, String)> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let message = WebSocketMessage {
+            ok: None,
+            id: Some(id),
+            trace_id: None,
+            idempotency_key: None,
+            r#type: message,
+        };
+
+        Ok((id, serde_json::to_string(&message)?))
+    }
+
+    async fn send_text(&mut self, text: String) -> anyhow::Result<()> {
+        self.socket
+            .send(Message::Text(text.into()))
+            .await
+            .map_err(|e| anyhow!("Failed to send message: {e}"))
+    }
+
+    /// Read the next message off the socket, answering pings along the way.
+    ///
+    /// Returns `None` once the connection is gone, or has been given up on after a `reconnect`
+    /// advisory.
+    async fn next_incoming(&mut self) -> anyhow::Result<Option<Incoming>> {
+        while let Some(frame) = self.socket.next().await {
+            let text = match frame {
+                Ok(Frame::Text(text)) => text,
+                Ok(Frame::Ping(bytes)) => {
+                    let _ = self.socket.send(Message::Pong(bytes)).await;
+                    continue;
+                }
+                Ok(Frame::Close(reason)) => {
+                    tracing::debug!("Gateway closed the connection: {reason:?}");
+                    return Ok(None);
+                }
+                Ok(Frame::Pong(_) | Frame::Binary(_) | Frame::Continuation(_)) => continue,
+                Err(e) => {
+                    tracing::debug!("Gateway connection failed: {e}");
+                    return Ok(None);
+                }
+            };
+
+            let incoming = match serde_json::from_slice::<WebSocketMessage>(&text) {
+                Ok(message) => Incoming::Message(message),
+                Err(_) => Incoming::Event(serde_json::from_slice(&text)?),
+            };
+
+            if let Incoming::Message(message) = &incoming
+                && let WebSocketMessageInner::Reconnect { wait_ms, reason } = &message.r#type
+                && self.config.follow_reconnect_advisories
+            {
+                tracing::info!("Gateway asked to reconnect in {wait_ms}ms: {reason}");
+                self.advised_wait = Some(Duration::from_millis(*wait_ms));
+                let _ = self.socket.send(Message::Close(None)).await;
+                return Ok(None);
+            }
+
+            return Ok(Some(incoming));
+        }
+
+        Ok(None)
+    }
+
+    /// Wait for the hello that starts every connection, taking the resume token out of it.
+    async fn await_hello(&mut self) -> anyhow::Result<()> {
+        let Some(Incoming::Message(hello)) = self.next_incoming().await? else {
+            bail!("Gateway didn't say hello");
+        };
+        let WebSocketMessageInner::Hello { motd } = &hello.r#type else {
+            bail!("Expected a hello, got {:?}", hello.r#type);
+        };
+
+        self.resume_token = motd
+            .get("resume_token")
+            .and_then(|x| x.as_str())
+            .and_then(|x| x.parse().ok());
+        self.capabilities = motd
+            .get("capabilities")
+            .and_then(|x| x.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|x| serde_json::from_value(x.clone()).ok())
+            .collect();
+
+        Ok(())
+    }
+
+    async fn reconnect(&mut self) -> anyhow::Result<()> {
+        let Some(policy) = self.config.reconnect.clone() else {
+            bail!("Connection to the gateway was lost");
+        };
+
+        for attempt in 1..=policy.max_attempts {
+            let advised = self.advised_wait.take().unwrap_or_default();
+            time::sleep(advised + policy.delay(attempt)).await;
+
+            match self.reestablish(&policy).await {
+                Ok(()) => {
+                    tracing::info!("Reconnected to the gateway after {attempt} attempt(s)");
+                    return Ok(());
+                }
+                Err(e) => tracing::warn!("Reconnect attempt {attempt} failed: {e}"),
+            }
+        }
+
+        bail!("Gave up reconnecting to the gateway")
+    }
+
+    /// Open a new connection, resuming the old session if possible and resubscribing otherwise, as
+    /// far as the policy allows.
+    async fn reestablish(&mut self, policy: &ReconnectPolicy) -> anyhow::Result<()> {
+        let resumed = match self.resume_token {
+            Some(token) if policy.resume => open(&self.http, &self.config, Some(token)).await.ok(),
+            _ => None,
+        };
+        let is_resumed = resumed.is_some();
+        self.socket = match resumed {
+            Some(socket) => socket,
+            None => open(&self.http, &self.config, None).await?,
+        };
+        self.await_hello().await?;
+
+        if is_resumed {
+            return Ok(());
+        }
+        if !policy.resubscribe {
+            self.subscriptions.clear();
+            return Ok(());
+        }
+
+        for event in self.subscriptions.clone() {
+            let message = WebSocketMessageInner::Subscribe {
+                event,
+                filter: None,
+            };
+            let (id, message) = self.number(message)?;
+            self.send_text(message).await?;
+
+            loop {
+                match self.next_incoming().await? {
+                    Some(Incoming::Message(message)) if message.id == Some(id) => break,
+                    Some(other) => self.pending.push_back(other),
+                    None => bail!("Connection lost while resubscribing"),
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Get a token from `/ws/start` and open a socket with it.
+async fn open(
+    http: &awc::Client,
+    config: &GatewayClientConfig,
+    resume_token: Option<Uuid>,
+) -> anyhow::Result<Socket> {
+    let body = WebSocketStartConnectionBody {
+        private_key: config.private_key.clone(),
+        resume_token,
+        scopes: config.scopes.clone(),
+    };
+    let mut request = http.post(format!("{}/ws/start", config.base_url));
+    if let Some(api_key) = &config.api_key {
+        request = request.insert_header(("X-API-Key", api_key.as_str()));
+    }
+    let mut response = request
+        .send_json(&body)
+        .await
+        .map_err(|e| anyhow!("Failed to start connection: {e}"))?;
+    if !response.status().is_success() {
+        bail!("Failed to start connection: {}", response.status());
+    }
+
+    let start: WebSocketStartResponse = response
+        .json()
+        .await
+        .map_err(|e| anyhow!("Invalid /ws/start response: {e}"))?;
+    let (_, socket) = http
+        .ws(start.url)
+        .connect()
+        .await
+        .map_err(|e| anyhow!("Failed to connect to the gateway: {e}"))?;
+
+    Ok(socket)
+}
@@ -0,0 +1,129 @@
+use std::{fmt, net::IpAddr, str::FromStr};
+
+use actix_web::{HttpRequest, http::header};
+use anyhow::anyhow;
+
+/// An IP network in CIDR notation, e.g. `10.0.0.0/8` or a single address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNetwork {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNetwork {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpNetwork {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match input.split_once('/') {
+            Some((addr, prefix)) => (addr.parse::<IpAddr>()?, Some(prefix.parse::<u8>()?)),
+            None => (input.parse::<IpAddr>()?, None),
+        };
+
+        let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(max_prefix);
+        if prefix > max_prefix {
+            return Err(anyhow!("Invalid prefix length in {input}"));
+        }
+
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl fmt::Display for IpNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// Resolve the real client IP, trusting forwarding headers only when they were set by a trusted proxy.
+///
+/// The `Forwarded` header takes precedence over `X-Forwarded-For`. Hops are walked from the nearest
+/// proxy outwards, and the first address that isn't itself a trusted proxy is the client.
+pub fn resolve(req: &HttpRequest, trusted_proxies: &[IpNetwork]) -> Option<IpAddr> {
+    let peer = req.peer_addr()?.ip();
+    let headers = req.headers();
+    let values = |name: &str| {
+        headers
+            .get_all(name)
+            .filter_map(|x| x.to_str().ok())
+            .collect()
+    };
+
+    Some(resolve_from(peer, values, trusted_proxies))
+}
+
+/// Like [`resolve`], for connections that aren't actix requests. `values` gives every value of the
+/// named header.
+pub fn resolve_from<'a>(
+    peer: IpAddr,
+    values: impl Fn(&str) -> Vec<&'a str>,
+    trusted_proxies: &[IpNetwork],
+) -> IpAddr {
+    let is_trusted = |ip: IpAddr| trusted_proxies.iter().any(|x| x.contains(ip));
+    if !is_trusted(peer) {
+        return peer;
+    }
+
+    let hops = forwarded_hops(values);
+    let client = hops
+        .iter()
+        .rev()
+        .find(|&&ip| !is_trusted(ip))
+        .or(hops.first())
+        .copied();
+
+    client.unwrap_or(peer)
+}
+
+/// Addresses from the forwarding headers, client first.
+fn forwarded_hops<'a>(values: impl Fn(&str) -> Vec<&'a str>) -> Vec<IpAddr> {
+    let forwarded: Vec<IpAddr> = values(header::FORWARDED.as_str())
+        .into_iter()
+        .flat_map(|x| x.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.trim().split_once('=')?;
+                key.eq_ignore_ascii_case("for").then(|| parse_node(value))?
+            })
+        })
+        .collect();
+
+    if !forwarded.is_empty() {
+        return forwarded;
+    }
+
+    values("x-forwarded-for")
+        .into_iter()
+        .flat_map(|x| x.split(','))
+        .filter_map(|x| parse_node(x.trim()))
+        .collect()
+}
+
+/// Parse a node as found in forwarding headers: `1.2.3.4`, `1.2.3.4:80`, `"[::1]:80"` or `[::1]`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split_once(']')?.0.parse().ok();
+    }
+
+    node.parse()
+        .ok()
+        .or_else(|| node.rsplit_once(':')?.0.parse().ok())
+}
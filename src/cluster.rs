@@ -0,0 +1,461 @@
+use std::{collections::HashSet, time::Duration};
+
+use futures::StreamExt;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::models::websocket::WebSocketSubscriptionType;
+use crate::ws::WebSocketServer;
+
+#[derive(Debug, Clone)]
+pub struct ClusterConfig {
+    pub redis_url: String,
+    /// Redis channel broadcasts are fanned out over; every node in the cluster must use the same one.
+    ///
+    /// Also used as the prefix for the session registry keys.
+    pub channel: String,
+    /// How long a node counts as alive after its last heartbeat. Sessions registered by a node that
+    /// has gone quiet for longer are skipped and pruned from the registry.
+    pub node_ttl: Duration,
+}
+
+impl ClusterConfig {
+    pub fn new(redis_url: impl Into<String>) -> Self {
+        Self {
+            redis_url: redis_url.into(),
+            channel: "actix-ws:broadcast".to_owned(),
+            node_ttl: Duration::from_secs(30),
+        }
+    }
+}
+
+/// A message as it travels between nodes.
+#[derive(Debug, Serialize, Deserialize)]
+struct ClusterEnvelope {
+    /// The node the message originated on, which has already handled it locally.
+    origin: Uuid,
+    /// Only handle the message on this node, or on every node when absent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target: Option<Uuid>,
+    #[serde(flatten)]
+    command: ClusterCommand,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "command")]
+enum ClusterCommand {
+    Broadcast {
+        /// Only deliver to sessions subscribed to this topic, or to everyone when absent.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        topic: Option<WebSocketSubscriptionType>,
+        payload: String,
+    },
+    /// A broadcast to `topic` that `owner`'s sessions also get through `own_topic`.
+    #[cfg(feature = "krist")]
+    PublishOwned {
+        topic: WebSocketSubscriptionType,
+        own_topic: WebSocketSubscriptionType,
+        owner: String,
+        payload: String,
+    },
+    SendToAddress {
+        address: String,
+        payload: String,
+    },
+    /// Close `address`'s sessions, sending them `notice` first.
+    #[cfg(feature = "krist")]
+    CloseAddress {
+        address: String,
+        notice: String,
+        reason: String,
+    },
+    BroadcastToTag {
+        tag: String,
+        payload: String,
+    },
+    Kick {
+        session: Uuid,
+        reason: Option<String>,
+    },
+}
+
+/// Where a session is connected, as stored in the registry.
+#[derive(Debug, Serialize, Deserialize)]
+struct RegistryEntry {
+    node: Uuid,
+    address: String,
+}
+
+/// Connection to the Redis channel and session registry shared by all nodes in the cluster.
+pub struct RedisCluster {
+    node_id: Uuid,
+    channel: String,
+    node_ttl: Duration,
+    connection: redis::aio::MultiplexedConnection,
+}
+
+impl RedisCluster {
+    pub(crate) async fn connect(config: &ClusterConfig) -> anyhow::Result<(Self, redis::Client)> {
+        let client = redis::Client::open(config.redis_url.as_str())?;
+        let connection = client.get_multiplexed_async_connection().await?;
+
+        let cluster = Self {
+            node_id: Uuid::new_v4(),
+            channel: config.channel.clone(),
+            node_ttl: config.node_ttl,
+            connection,
+        };
+        cluster.heartbeat().await?;
+
+        Ok((cluster, client))
+    }
+
+    pub fn node_id(&self) -> Uuid {
+        self.node_id
+    }
+
+    fn sessions_key(&self) -> String {
+        format!("{}:sessions", self.channel)
+    }
+
+    fn address_key(&self, address: &str) -> String {
+        format!("{}:address:{address}", self.channel)
+    }
+
+    fn node_key(&self, node: Uuid) -> String {
+        format!("{}:node:{node}", self.channel)
+    }
+
+    /// Mark this node as alive for another [`ClusterConfig::node_ttl`].
+    pub(crate) async fn heartbeat(&self) -> anyhow::Result<()> {
+        let mut connection = self.connection.clone();
+        connection
+            .set_ex::<_, _, ()>(
+                self.node_key(self.node_id),
+                1,
+                self.node_ttl.as_secs().max(1),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Keep this node marked alive for as long as it runs.
+    pub(crate) async fn keep_alive(&self) {
+        let mut interval = tokio::time::interval(self.node_ttl / 3);
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.heartbeat().await {
+                tracing::warn!("Failed to send cluster heartbeat: {e}");
+            }
+        }
+    }
+
+    /// Which of `nodes` have sent a heartbeat within the last [`ClusterConfig::node_ttl`].
+    async fn live_nodes(&self, nodes: &HashSet<Uuid>) -> anyhow::Result<HashSet<Uuid>> {
+        if nodes.is_empty() {
+            return Ok(HashSet::new());
+        }
+
+        let nodes: Vec<Uuid> = nodes.iter().copied().collect();
+        let keys: Vec<String> = nodes.iter().map(|&x| self.node_key(x)).collect();
+        let mut connection = self.connection.clone();
+        let alive: Vec<Option<String>> = redis::cmd("MGET")
+            .arg(keys)
+            .query_async(&mut connection)
+            .await?;
+
+        Ok(nodes
+            .into_iter()
+            .zip(alive)
+            .filter_map(|(node, alive)| alive.map(|_| node))
+            .collect())
+    }
+
+    /// Drop registry entries left behind by nodes that went away without deregistering them.
+    async fn prune(&self, sessions: &[(Uuid, &str)]) -> anyhow::Result<()> {
+        if sessions.is_empty() {
+            return Ok(());
+        }
+
+        let mut pipe = redis::pipe();
+        for (session, address) in sessions {
+            pipe.hdel(self.sessions_key(), session.to_string())
+                .srem(self.address_key(address), session.to_string());
+        }
+        let mut connection = self.connection.clone();
+        pipe.exec_async(&mut connection).await?;
+
+        tracing::info!(
+            "Pruned {} sessions of dead nodes from the registry",
+            sessions.len()
+        );
+        Ok(())
+    }
+
+    pub(crate) async fn register_session(
+        &self,
+        session: Uuid,
+        address: &str,
+    ) -> anyhow::Result<()> {
+        let entry = RegistryEntry {
+            node: self.node_id,
+            address: address.to_owned(),
+        };
+        let entry = serde_json::to_string(&entry)?;
+
+        let mut connection = self.connection.clone();
+        redis::pipe()
+            .hset(self.sessions_key(), session.to_string(), entry)
+            .sadd(self.address_key(address), session.to_string())
+            .exec_async(&mut connection)
+            .await?;
+
+        Ok(())
+    }
+
+    pub(crate) async fn deregister_session(
+        &self,
+        session: Uuid,
+        address: &str,
+    ) -> anyhow::Result<()> {
+        let mut connection = self.connection.clone();
+        redis::pipe()
+            .hdel(self.sessions_key(), session.to_string())
+            .srem(self.address_key(address), session.to_string())
+            .exec_async(&mut connection)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn lookup(&self, session: Uuid) -> anyhow::Result<Option<RegistryEntry>> {
+        let mut connection = self.connection.clone();
+        let entry: Option<String> = connection
+            .hget(self.sessions_key(), session.to_string())
+            .await?;
+
+        Ok(entry.map(|x| serde_json::from_str(&x)).transpose()?)
+    }
+
+    /// Live nodes other than this one holding a session for `address`.
+    async fn remote_nodes_for(&self, address: &str) -> anyhow::Result<HashSet<Uuid>> {
+        let mut connection = self.connection.clone();
+        let sessions: Vec<String> = connection.smembers(self.address_key(address)).await?;
+        let sessions: Vec<Uuid> = sessions.iter().filter_map(|x| x.parse().ok()).collect();
+        if sessions.is_empty() {
+            return Ok(HashSet::new());
+        }
+
+        let fields: Vec<String> = sessions.iter().map(Uuid::to_string).collect();
+        let entries: Vec<Option<String>> = redis::cmd("HMGET")
+            .arg(self.sessions_key())
+            .arg(fields)
+            .query_async(&mut connection)
+            .await?;
+
+        let mut placed = Vec::with_capacity(sessions.len());
+        let mut stale = Vec::new();
+        for (session, entry) in sessions.into_iter().zip(entries) {
+            match entry
+                .map(|x| serde_json::from_str::<RegistryEntry>(&x))
+                .transpose()?
+            {
+                Some(entry) => placed.push((session, entry.node)),
+                None => stale.push((session, address)),
+            }
+        }
+
+        let nodes = placed.iter().map(|&(_, node)| node).collect();
+        let live = self.live_nodes(&nodes).await?;
+        stale.extend(
+            placed
+                .iter()
+                .filter(|(_, node)| !live.contains(node))
+                .map(|&(session, _)| (session, address)),
+        );
+        self.prune(&stale).await?;
+
+        Ok(live.into_iter().filter(|&x| x != self.node_id).collect())
+    }
+
+    async fn send(&self, target: Option<Uuid>, command: ClusterCommand) -> anyhow::Result<()> {
+        let envelope = ClusterEnvelope {
+            origin: self.node_id,
+            target,
+            command,
+        };
+        let envelope = serde_json::to_string(&envelope)?;
+
+        let mut connection = self.connection.clone();
+        connection
+            .publish::<_, _, ()>(&self.channel, envelope)
+            .await?;
+
+        Ok(())
+    }
+
+    pub(crate) async fn publish(
+        &self,
+        topic: Option<&WebSocketSubscriptionType>,
+        payload: &str,
+    ) -> anyhow::Result<()> {
+        let command = ClusterCommand::Broadcast {
+            topic: topic.cloned(),
+            payload: payload.to_owned(),
+        };
+
+        self.send(None, command).await
+    }
+
+    #[cfg(feature = "krist")]
+    pub(crate) async fn publish_owned(
+        &self,
+        topic: &WebSocketSubscriptionType,
+        own_topic: &WebSocketSubscriptionType,
+        owner: &str,
+        payload: &str,
+    ) -> anyhow::Result<()> {
+        let command = ClusterCommand::PublishOwned {
+            topic: topic.clone(),
+            own_topic: own_topic.clone(),
+            owner: owner.to_owned(),
+            payload: payload.to_owned(),
+        };
+
+        self.send(None, command).await
+    }
+
+    pub(crate) async fn send_to_address(&self, address: &str, payload: &str) -> anyhow::Result<()> {
+        for node in self.remote_nodes_for(address).await? {
+            let command = ClusterCommand::SendToAddress {
+                address: address.to_owned(),
+                payload: payload.to_owned(),
+            };
+
+            self.send(Some(node), command).await?;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "krist")]
+    pub(crate) async fn close_address(
+        &self,
+        address: &str,
+        notice: &str,
+        reason: &str,
+    ) -> anyhow::Result<()> {
+        for node in self.remote_nodes_for(address).await? {
+            let command = ClusterCommand::CloseAddress {
+                address: address.to_owned(),
+                notice: notice.to_owned(),
+                reason: reason.to_owned(),
+            };
+
+            self.send(Some(node), command).await?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) async fn broadcast_to_tag(&self, tag: &str, payload: &str) -> anyhow::Result<()> {
+        let command = ClusterCommand::BroadcastToTag {
+            tag: tag.to_owned(),
+            payload: payload.to_owned(),
+        };
+
+        self.send(None, command).await
+    }
+
+    pub(crate) async fn kick(&self, session: Uuid, reason: Option<String>) -> anyhow::Result<()> {
+        let Some(entry) = self.lookup(session).await? else {
+            tracing::info!("Tried to kick session {session} but it is not in the cluster registry");
+            return Ok(());
+        };
+        if !self
+            .live_nodes(&HashSet::from([entry.node]))
+            .await?
+            .contains(&entry.node)
+        {
+            tracing::info!("Tried to kick session {session} but its node is gone");
+            return self.prune(&[(session, &entry.address)]).await;
+        }
+
+        self.send(Some(entry.node), ClusterCommand::Kick { session, reason })
+            .await
+    }
+
+    /// Handle messages published by other nodes.
+    pub(crate) async fn subscribe(
+        client: redis::Client,
+        server: WebSocketServer,
+        node_id: Uuid,
+        channel: String,
+    ) -> anyhow::Result<()> {
+        let mut pubsub = client.get_async_pubsub().await?;
+        pubsub.subscribe(&channel).await?;
+
+        tracing::info!("Node {node_id} joined cluster channel {channel}");
+
+        let mut stream = pubsub.on_message();
+        while let Some(msg) = stream.next().await {
+            let envelope = msg
+                .get_payload::<String>()
+                .map_err(anyhow::Error::from)
+                .and_then(|x| Ok(serde_json::from_str::<ClusterEnvelope>(&x)?));
+
+            let envelope = match envelope {
+                Ok(envelope) => envelope,
+                Err(e) => {
+                    tracing::warn!("Ignoring malformed cluster message: {e}");
+                    continue;
+                }
+            };
+
+            if envelope.origin == node_id || envelope.target.is_some_and(|x| x != node_id) {
+                continue;
+            }
+
+            match envelope.command {
+                ClusterCommand::Broadcast {
+                    topic: Some(topic),
+                    payload,
+                } => server.publish_local(topic, payload).await,
+                ClusterCommand::Broadcast {
+                    topic: None,
+                    payload,
+                } => server.broadcast_local(payload).await,
+                #[cfg(feature = "krist")]
+                ClusterCommand::PublishOwned {
+                    topic,
+                    own_topic,
+                    owner,
+                    payload,
+                } => {
+                    server
+                        .publish_owned_local(topic, own_topic, &owner, payload)
+                        .await
+                }
+                ClusterCommand::SendToAddress { address, payload } => {
+                    server.send_to_address_local(&address, payload).await
+                }
+                #[cfg(feature = "krist")]
+                ClusterCommand::CloseAddress {
+                    address,
+                    notice,
+                    reason,
+                } => server.close_address_local(&address, notice, reason).await,
+                ClusterCommand::BroadcastToTag { tag, payload } => {
+                    server.broadcast_to_tag_local(&tag, payload).await
+                }
+                ClusterCommand::Kick { session, reason } => {
+                    server.kick_local(&session, reason).await;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+//! Compression of HTTP responses big enough for it to be worth it, like long `/events` pages,
+//! with whichever of gzip and brotli the client prefers.
+
+use actix_http::encoding::Encoder;
+use actix_web::{
+    Error,
+    body::{BodySize, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{self, ContentEncoding, HeaderValue},
+    middleware::Next,
+    web,
+};
+
+use crate::ws::WebSocketServer;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompressionConfig {
+    /// Smallest response body compressed, in bytes. Bodies of unknown size are always compressed.
+    pub min_size: usize,
+    pub gzip: bool,
+    pub brotli: bool,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            min_size: 1024,
+            gzip: true,
+            brotli: true,
+        }
+    }
+}
+
+impl CompressionConfig {
+    /// The encoding to answer a request with, going by its `Accept-Encoding`. Brotli wins ties.
+    pub fn negotiate(&self, accept_encoding: &str) -> ContentEncoding {
+        let mut best = (ContentEncoding::Identity, 0.0);
+
+        for entry in accept_encoding.split(',') {
+            let mut parts = entry.split(';').map(str::trim);
+            let encoding = match parts.next() {
+                Some("br") if self.brotli => ContentEncoding::Brotli,
+                Some("gzip") if self.gzip => ContentEncoding::Gzip,
+                _ => continue,
+            };
+            let quality = parts
+                .find_map(|x| x.strip_prefix("q="))
+                .map_or(Some(1.0), |x| x.parse::<f32>().ok())
+                .unwrap_or(0.0);
+
+            let wins_tie = encoding == ContentEncoding::Brotli && quality == best.1;
+            if quality > 0.0 && (quality > best.1 || wins_tie) {
+                best = (encoding, quality);
+            }
+        }
+
+        best.0
+    }
+}
+
+/// Compress responses as configured with [`WebSocketServer::with_compression`], leaving them be
+/// on servers without compression.
+pub async fn middleware(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<Encoder<impl MessageBody>>, Error> {
+    let server = req.app_data::<web::Data<WebSocketServer>>().cloned();
+    // WebSocket upgrades stream frames for as long as the session lasts, so leave them be
+    let config = server
+        .as_ref()
+        .and_then(|x| x.compression())
+        .filter(|_| !req.headers().contains_key(header::UPGRADE));
+    let encoding = match (config, req.headers().get(header::ACCEPT_ENCODING)) {
+        (Some(config), Some(accept)) => config.negotiate(accept.to_str().unwrap_or_default()),
+        _ => ContentEncoding::Identity,
+    };
+    let min_size = config.map_or(0, |x| x.min_size);
+
+    let mut response = next.call(req).await?;
+    let worth_it = match response.response().body().size() {
+        BodySize::Sized(size) => size >= min_size as u64,
+        BodySize::Stream => true,
+        BodySize::None => false,
+    };
+    if config.is_some() && worth_it {
+        // Caches have to tell apart responses to clients accepting different encodings
+        response
+            .headers_mut()
+            .append(header::VARY, HeaderValue::from_static("Accept-Encoding"));
+    }
+
+    let encoding = match worth_it {
+        true => encoding,
+        false => ContentEncoding::Identity,
+    };
+    Ok(response.map_body(|head, body| Encoder::response(encoding, head, body)))
+}
@@ -0,0 +1,312 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    path::PathBuf,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::alarms::ChurnThresholds;
+use crate::client_ip::IpNetwork;
+use crate::models::websocket::WebSocketSubscriptionType;
+use crate::policy::PolicyConfig;
+
+#[derive(Debug, Clone)]
+pub struct WebSocketServerConfig {
+    /// Base URL clients connect to, used to build gateway URLs handed out by `/ws/start`.
+    pub public_url: String,
+    /// Disconnect sessions whose smoothed round-trip latency exceeds this, if set.
+    pub max_latency: Option<Duration>,
+    pub slow_consumer: SlowConsumerConfig,
+    /// Settings that can be changed while the server is running.
+    pub runtime: RuntimeConfig,
+    /// JSON file `runtime` is reloaded from on SIGHUP or through the admin API.
+    pub config_path: Option<PathBuf>,
+    /// Bearer token required by the admin API, which is disabled when unset.
+    pub admin_token: Option<String>,
+    /// Proxies whose forwarding headers are trusted to carry the real client IP.
+    pub trusted_proxies: Vec<IpNetwork>,
+    /// File sessions are handed over through when restarting, so they can resume on the new process.
+    pub resume_path: Option<PathBuf>,
+    /// How long responses to state-changing messages are remembered for replay to retries.
+    pub idempotency_window: Duration,
+    /// Most topics a single session may be subscribed to at once.
+    pub max_subscriptions: usize,
+    /// Largest single WebSocket frame accepted from clients.
+    pub max_frame_size: usize,
+    /// Largest message accepted from clients once continuation frames are put back together.
+    pub max_continuation_size: usize,
+    /// Largest text message parsed as JSON; bigger ones are answered with an error without parsing.
+    pub max_message_size: usize,
+    /// Largest binary frame passed to the binary handler.
+    pub max_binary_payload: usize,
+    /// Accept the upgrade for bad gateway tokens and close with an `invalid_token` error, instead of
+    /// failing the upgrade, so browsers can tell a bad token apart from the server being down.
+    pub close_on_invalid_token: bool,
+    /// Separate gateways served from this process, by name. Requests pick one with `X-API-Key`.
+    pub namespaces: HashMap<String, NamespaceConfig>,
+    /// What happens to broadcasts and published events once the server has started shutting down.
+    pub draining_publish: DrainingPublish,
+    /// Window reconnect advisories spread clients over when they're turned away for the server
+    /// being full.
+    pub overload_reconnect_window: Duration,
+    /// How often `stats` subscribers are sent the gateway's stats.
+    pub stats_interval: Duration,
+    /// Fraction of inbound messages whose type and size are sampled, between 0 and 1. Sampling
+    /// is off when unset.
+    pub message_sample_rate: Option<f64>,
+    /// Rates of connection churn that set off an alarm.
+    pub churn_alarms: ChurnThresholds,
+    /// Sandbox for client developers: accept any gateway token, skip authorization, and answer
+    /// every message with its canonical serialization instead of handling it. Never enable this
+    /// in production.
+    pub echo_mode: bool,
+    /// Let clients connect without a private key, with read-only guest access. When off,
+    /// `/ws/start` without one is refused with `401 Unauthorized`, as is any gateway connection
+    /// that isn't logged in, echo mode's included.
+    pub allow_guests: bool,
+    /// Directory every session's inbound frames are recorded to, one file per session, for
+    /// replaying them later. Recording is off when unset.
+    pub record_dir: Option<PathBuf>,
+    /// Most message handlers running at once across every session. Sessions' messages wait
+    /// their turn beyond this, without holding up their heartbeats.
+    pub max_concurrent_handlers: usize,
+    /// How messages of each type are processed relative to the session's others. Types not
+    /// listed are [`Processing::Ordered`].
+    pub message_processing: HashMap<String, Processing>,
+    #[cfg(feature = "krist")]
+    pub economy: EconomyConfig,
+    /// How long block submissions are remembered, so retries of one aren't rewarded twice.
+    #[cfg(feature = "krist")]
+    pub submission_window: Duration,
+    /// Faults injected into every session's output. Debug builds only.
+    #[cfg(debug_assertions)]
+    pub chaos: Option<ChaosConfig>,
+}
+
+impl Default for WebSocketServerConfig {
+    fn default() -> Self {
+        Self {
+            public_url: "ws://127.0.0.1:8080".to_owned(),
+            max_latency: None,
+            slow_consumer: SlowConsumerConfig::default(),
+            runtime: RuntimeConfig::default(),
+            config_path: None,
+            admin_token: None,
+            trusted_proxies: Vec::new(),
+            resume_path: None,
+            idempotency_window: Duration::from_secs(300),
+            max_subscriptions: 8,
+            max_frame_size: 64 * 1024,
+            max_continuation_size: 2 * 1024 * 1024,
+            max_message_size: 1024 * 1024,
+            max_binary_payload: 64 * 1024,
+            close_on_invalid_token: false,
+            namespaces: HashMap::new(),
+            draining_publish: DrainingPublish::default(),
+            overload_reconnect_window: Duration::from_secs(10),
+            stats_interval: Duration::from_secs(10),
+            message_sample_rate: None,
+            churn_alarms: ChurnThresholds::default(),
+            echo_mode: false,
+            allow_guests: true,
+            record_dir: None,
+            max_concurrent_handlers: 1024,
+            message_processing: HashMap::new(),
+            #[cfg(feature = "krist")]
+            economy: EconomyConfig::default(),
+            #[cfg(feature = "krist")]
+            submission_window: Duration::from_secs(300),
+            #[cfg(debug_assertions)]
+            chaos: None,
+        }
+    }
+}
+
+/// Whether a session's message waits for its earlier ones to be handled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Processing {
+    /// Handled after the session's earlier ordered messages, one at a time, e.g. for
+    /// transactions that have to happen in the order they were sent.
+    #[default]
+    Ordered,
+    /// Handled as soon as it arrives, alongside the session's other messages, e.g. for queries.
+    Unordered,
+}
+
+/// How broadcasts and published events are handled while the server is draining, rather than
+/// racing them against sessions being torn down.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DrainingPublish {
+    /// Refuse them with an error.
+    #[default]
+    Reject,
+    /// Append them to the journal without delivering them, for sessions to have replayed once they
+    /// resume on the next process. Refused like `Reject` without a journal.
+    Journal,
+}
+
+/// A gateway of its own within the process, with separate sessions, subscriptions and events.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NamespaceConfig {
+    /// Keys clients of this namespace send as `X-API-Key`.
+    pub api_keys: HashSet<String>,
+    pub runtime: RuntimeConfig,
+}
+
+impl NamespaceConfig {
+    pub fn new(api_key: impl Into<String>) -> Self {
+        Self {
+            api_keys: HashSet::from([api_key.into()]),
+            runtime: RuntimeConfig::default(),
+        }
+    }
+}
+
+/// The Krist economy's rules, sent to clients in the hello so they can check requests up front.
+#[cfg(feature = "krist")]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EconomyConfig {
+    /// What new addresses start out with.
+    pub starting_balance: u32,
+    /// Paid out to whoever mines a block.
+    pub block_reward: u32,
+    pub min_transaction_amount: u32,
+    /// What registering a name costs.
+    pub name_cost: u32,
+    /// Longest transaction metadata may be, in characters.
+    pub max_metadata_length: usize,
+}
+
+#[cfg(feature = "krist")]
+impl Default for EconomyConfig {
+    fn default() -> Self {
+        Self {
+            starting_balance: 0,
+            block_reward: 1,
+            min_transaction_amount: 1,
+            name_cost: 500,
+            max_metadata_length: crate::validation::MAX_METADATA_LENGTH,
+        }
+    }
+}
+
+/// Thresholds for detecting and evicting sessions that can't keep up with their events.
+#[derive(Debug, Clone)]
+pub struct SlowConsumerConfig {
+    /// Maximum number of events queued for a session before it counts as lagging.
+    pub queue_capacity: usize,
+    /// How long a single send may take before it counts as lagging.
+    pub send_timeout: Duration,
+    /// Number of lagging sends tolerated before the session is disconnected.
+    pub max_strikes: u32,
+    /// Drop events for lagging sessions instead of waiting for queue space.
+    pub drop_events_when_lagging: bool,
+}
+
+impl Default for SlowConsumerConfig {
+    fn default() -> Self {
+        Self {
+            queue_capacity: 256,
+            send_timeout: Duration::from_secs(5),
+            max_strikes: 3,
+            drop_events_when_lagging: true,
+        }
+    }
+}
+
+/// Faults to inject into sessions, for checking that cleanup, resume and reconnect paths hold up.
+///
+/// Rates are probabilities between 0 and 1, rolled for every frame sent.
+#[cfg(debug_assertions)]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChaosConfig {
+    /// Fail the send as if the socket had gone away, without telling the client.
+    pub send_failure_rate: f64,
+    /// Silently drop text and binary frames.
+    pub drop_rate: f64,
+    /// Close the connection instead of sending.
+    pub disconnect_rate: f64,
+    /// Delay every send by a random duration in this range.
+    pub latency: Option<(Duration, Duration)>,
+}
+
+#[cfg(debug_assertions)]
+impl std::str::FromStr for ChaosConfig {
+    type Err = anyhow::Error;
+
+    /// Parse e.g. `send_failure=0.01,drop=0.05,disconnect=0.001,latency_ms=10-200`.
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut config = Self::default();
+
+        for pair in input.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("Expected key=value, got {pair}"))?;
+            let rate = || -> anyhow::Result<f64> {
+                let rate: f64 = value.parse()?;
+                if !(0.0..=1.0).contains(&rate) {
+                    anyhow::bail!("{key} must be between 0 and 1");
+                }
+                Ok(rate)
+            };
+
+            match key {
+                "send_failure" => config.send_failure_rate = rate()?,
+                "drop" => config.drop_rate = rate()?,
+                "disconnect" => config.disconnect_rate = rate()?,
+                "latency_ms" => {
+                    let (min, max) = value.split_once('-').unwrap_or((value, value));
+                    let (min, max) = (min.parse()?, max.parse()?);
+                    if min > max {
+                        anyhow::bail!("latency_ms range {value} is backwards");
+                    }
+                    config.latency = Some((Duration::from_millis(min), Duration::from_millis(max)));
+                }
+                _ => anyhow::bail!("Unknown chaos setting {key}"),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+/// Settings that can be reloaded without restarting or dropping connections.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RuntimeConfig {
+    /// Message of the day, sent to clients in the hello message.
+    pub motd: Option<String>,
+    /// Refuse new connections once this many sessions are connected.
+    pub max_sessions: Option<usize>,
+    /// Maximum number of `/ws/start` requests per IP per minute.
+    pub start_rate_limit: Option<u32>,
+    /// Maximum number of HTTP requests per API key, or per IP without one, per minute.
+    pub http_rate_limit: Option<u32>,
+    pub banned_ips: HashSet<IpAddr>,
+    pub banned_addresses: HashSet<String>,
+    /// Restrictions on which sessions may send which message types.
+    pub policy: PolicyConfig,
+    /// Merge events published to a topic within this many milliseconds into a single batch message.
+    pub coalesce_ms: HashMap<WebSocketSubscriptionType, u64>,
+    /// Drop events for a topic that are still queued for a session after this many milliseconds.
+    pub event_ttl_ms: HashMap<WebSocketSubscriptionType, u64>,
+    /// Give up on handling a message after this many milliseconds, answering it with an
+    /// `operation_timeout` error, by message type. `*` covers types not listed.
+    pub handler_timeout_ms: HashMap<String, u64>,
+}
+
+impl RuntimeConfig {
+    pub fn load(path: &std::path::Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    pub fn is_banned(&self, address: &str, ip: Option<IpAddr>) -> bool {
+        self.banned_addresses.contains(address) || ip.is_some_and(|x| self.banned_ips.contains(&x))
+    }
+}
@@ -0,0 +1,101 @@
+//! Wire formats sessions can have their messages sent in.
+//!
+//! Messages are built as JSON throughout the server, and only transcoded on their way out, so
+//! clients that asked for MessagePack or CBOR get binary frames carrying the same structure.
+
+use std::sync::OnceLock;
+
+use bytes::Bytes;
+use bytestring::ByteString;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    #[default]
+    Json,
+    #[serde(rename = "msgpack")]
+    MessagePack,
+    Cbor,
+}
+
+impl Encoding {
+    pub const ALL: [Encoding; 3] = [Encoding::Json, Encoding::MessagePack, Encoding::Cbor];
+
+    /// Transcode a JSON message. Payloads that aren't JSON are sent as a string.
+    pub fn encode(self, json: ByteString) -> Frame {
+        let encoded = match self {
+            Self::Json => return Frame::Text(json),
+            Self::MessagePack => {
+                rmp_serde::to_vec_named(&to_value(&json)).map_err(anyhow::Error::from)
+            }
+            Self::Cbor => {
+                let mut buffer = Vec::new();
+                ciborium::into_writer(&to_value(&json), &mut buffer)
+                    .map(|()| buffer)
+                    .map_err(anyhow::Error::from)
+            }
+        };
+
+        match encoded {
+            Ok(encoded) => Frame::Binary(encoded.into()),
+            Err(e) => {
+                tracing::error!("Failed to encode message as {self:?}, sending JSON: {e}");
+                Frame::Text(json)
+            }
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+fn to_value(json: &str) -> serde_json::Value {
+    serde_json::from_str(json).unwrap_or_else(|_| serde_json::Value::from(json))
+}
+
+/// A message ready to be written to a socket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+    Text(ByteString),
+    Binary(Bytes),
+}
+
+impl From<ByteString> for Frame {
+    fn from(text: ByteString) -> Self {
+        Self::Text(text)
+    }
+}
+
+impl From<String> for Frame {
+    fn from(text: String) -> Self {
+        Self::Text(text.into())
+    }
+}
+
+/// An event being fanned out, encoded at most once per encoding no matter how many sessions get it.
+#[derive(Debug)]
+pub struct EncodedEvent {
+    json: ByteString,
+    encoded: [OnceLock<Frame>; Encoding::ALL.len()],
+}
+
+impl EncodedEvent {
+    pub fn new(json: ByteString) -> Self {
+        Self {
+            json,
+            encoded: Default::default(),
+        }
+    }
+
+    pub fn json(&self) -> &ByteString {
+        &self.json
+    }
+
+    pub fn get(&self, encoding: Encoding) -> Frame {
+        self.encoded[encoding.index()]
+            .get_or_init(|| encoding.encode(self.json.clone()))
+            .clone()
+    }
+}
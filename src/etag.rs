@@ -0,0 +1,67 @@
+//! ETags on HTTP query responses, so clients polling e.g. `/events` are told their copy is still
+//! current with a bodiless `304 Not Modified` instead of downloading it again.
+
+use actix_web::{
+    Error, HttpMessage, HttpResponse,
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::{
+        Method, StatusCode,
+        header::{self, EntityTag, HeaderValue, IfNoneMatch},
+    },
+    middleware::Next,
+};
+use sha2::{Digest, Sha256};
+
+/// The ETag of a response body.
+///
+/// Tags are weak, as compression may encode the same body differently for different clients. They
+/// are a truncated SHA-256 of the body, so every node and every build tags a body the same way.
+pub fn etag(body: &[u8]) -> EntityTag {
+    let digest = Sha256::digest(body);
+    let tag: String = digest[..8].iter().map(|x| format!("{x:02x}")).collect();
+    EntityTag::new_weak(tag)
+}
+
+/// Tag successful `GET` and `HEAD` responses, and answer requests whose `If-None-Match` matches
+/// with `304 Not Modified`.
+///
+/// Streamed responses are left untagged, as their bodies aren't known up front.
+pub async fn middleware<B: MessageBody + 'static>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, Error> {
+    if !matches!(*req.method(), Method::GET | Method::HEAD) {
+        return Ok(next.call(req).await?.map_into_left_body());
+    }
+    let if_none_match = req.get_header::<IfNoneMatch>();
+
+    let response = next.call(req).await?;
+    if response.status() != StatusCode::OK {
+        return Ok(response.map_into_left_body());
+    }
+
+    let (req, response) = response.into_parts();
+    let (response, body) = response.into_parts();
+    let body = match body.try_into_bytes() {
+        Ok(body) => body,
+        Err(body) => {
+            return Ok(ServiceResponse::new(req, response.set_body(body)).map_into_left_body());
+        }
+    };
+
+    let tag = etag(&body);
+    let matched = match &if_none_match {
+        Some(IfNoneMatch::Any) => true,
+        Some(IfNoneMatch::Items(tags)) => tags.iter().any(|x| x.weak_eq(&tag)),
+        None => false,
+    };
+    let value = HeaderValue::from_str(&tag.to_string())?;
+
+    let mut response = match matched {
+        true => HttpResponse::NotModified().finish(),
+        false => response.set_body(body).map_into_boxed_body(),
+    };
+    response.headers_mut().insert(header::ETAG, value);
+    Ok(ServiceResponse::new(req, response).map_into_right_body())
+}
@@ -0,0 +1,76 @@
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::alarms::ChurnKind;
+use crate::models::websocket::WebSocketSubscriptionType;
+
+/// Something notable that happened on the gateway, reported to external systems.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "event")]
+pub enum GatewayEvent {
+    SessionConnected {
+        session: Uuid,
+        address: String,
+    },
+    SessionDisconnected {
+        session: Uuid,
+        address: String,
+    },
+    Login {
+        session: Uuid,
+        address: String,
+    },
+    SubscriptionChanged {
+        session: Uuid,
+        topic: WebSocketSubscriptionType,
+        subscribed: bool,
+    },
+    /// A broadcast or published event was refused, e.g. because the server is shutting down.
+    BroadcastFailed {
+        topic: Option<WebSocketSubscriptionType>,
+        reason: String,
+    },
+    /// Handling a client message failed with an internal error.
+    MessageFailed {
+        session: Uuid,
+        message_type: String,
+        error: String,
+    },
+    /// An event was dropped for a session that had fallen behind.
+    EventDropped {
+        session: Uuid,
+        topic: Option<WebSocketSubscriptionType>,
+    },
+    /// Connections, disconnections or auth failures went over their threshold for the second.
+    ChurnAlarm {
+        kind: ChurnKind,
+        threshold: u32,
+    },
+    #[cfg(feature = "krist")]
+    Transaction {
+        transaction: serde_json::Value,
+    },
+    #[cfg(feature = "krist")]
+    Block {
+        block: serde_json::Value,
+    },
+}
+
+impl GatewayEvent {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::SessionConnected { .. } => "session_connected",
+            Self::SessionDisconnected { .. } => "session_disconnected",
+            Self::Login { .. } => "login",
+            Self::SubscriptionChanged { .. } => "subscription_changed",
+            Self::BroadcastFailed { .. } => "broadcast_failed",
+            Self::MessageFailed { .. } => "message_failed",
+            Self::EventDropped { .. } => "event_dropped",
+            Self::ChurnAlarm { .. } => "churn_alarm",
+            #[cfg(feature = "krist")]
+            Self::Transaction { .. } => "transaction",
+            #[cfg(feature = "krist")]
+            Self::Block { .. } => "block",
+        }
+    }
+}
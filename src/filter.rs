@@ -0,0 +1,45 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Most fields a single filter may constrain.
+pub const MAX_FILTER_FIELDS: usize = 16;
+
+/// Field equality constraints on an event, e.g. `{"to": "kabc123"}`.
+///
+/// Each field is looked up on the event itself, or failing that on the objects it contains, so a filter
+/// on `to` matches both `{"to": ...}` and `{"transaction": {"to": ...}}`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct EventFilter(pub Map<String, Value>);
+
+impl EventFilter {
+    /// A filter constraining a single field.
+    pub fn field(field: impl Into<String>, expected: impl Into<Value>) -> Self {
+        Self(Map::from_iter([(field.into(), expected.into())]))
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.0.len() > MAX_FILTER_FIELDS {
+            anyhow::bail!("Filters can constrain at most {MAX_FILTER_FIELDS} fields");
+        }
+
+        Ok(())
+    }
+
+    pub fn matches(&self, event: &Value) -> bool {
+        self.0
+            .iter()
+            .all(|(field, expected)| lookup(event, field) == Some(expected))
+    }
+}
+
+fn lookup<'a>(event: &'a Value, field: &str) -> Option<&'a Value> {
+    let object = event.as_object()?;
+
+    object.get(field).or_else(|| {
+        object
+            .values()
+            .filter_map(Value::as_object)
+            .find_map(|x| x.get(field))
+    })
+}
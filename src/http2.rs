@@ -0,0 +1,370 @@
+//! WebSockets over HTTP/2 (RFC 8441), for clients and proxies that multiplex gateway connections
+//! over HTTP/2 rather than upgrading a connection of their own.
+//!
+//! The listener speaks cleartext HTTP/2 with prior knowledge, so TLS is left to whatever sits in
+//! front of it. It advertises `SETTINGS_ENABLE_CONNECT_PROTOCOL`, and clients open a session with
+//! an extended `CONNECT` to `/gateway/{token}` carrying `:protocol: websocket`, taking the same
+//! query parameters as the HTTP/1.1 endpoint. Once accepted, the stream carries WebSocket frames
+//! just like an upgraded connection would.
+
+use std::{future::poll_fn, net::IpAddr, sync::Arc};
+
+use actix_codec::{Decoder, Encoder};
+use actix_http::ws::{Codec, Frame, Item, Message};
+use actix_web::web;
+use actix_ws::{AggregatedMessage, CloseCode, CloseReason, Closed, ProtocolError};
+use bytes::{Bytes, BytesMut};
+use bytestring::ByteString;
+use h2::{
+    RecvStream, SendStream,
+    ext::Protocol,
+    server::{self, SendResponse},
+};
+use http::{Method, Request, Response, StatusCode};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+};
+use tracing::{Instrument, field};
+
+use crate::models::websocket::{
+    GatewayQuery,
+    messages::{WebSocketMessage, WebSocketMessageInner},
+};
+use crate::ws::{
+    self, WebSocketServer,
+    runner::{self, Admitted, Connection, Heartbeat, Inbound, Refusal},
+    sink::SessionSink,
+};
+
+/// Accept HTTP/2 connections on `listener` until it fails.
+pub async fn serve(server: WebSocketServer, listener: TcpListener) {
+    let server = Arc::new(server);
+
+    loop {
+        let (io, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                tracing::error!("Failed to accept HTTP/2 connection: {e}");
+                return;
+            }
+        };
+
+        let server = server.clone();
+        tokio::spawn(async move {
+            if let Err(e) = connection(server, io, peer.ip()).await {
+                tracing::debug!("HTTP/2 connection ended: {e}");
+            }
+        });
+    }
+}
+
+/// Handle an HTTP/2 connection, serving each of its streams as a gateway session of its own.
+async fn connection(
+    server: Arc<WebSocketServer>,
+    io: impl AsyncRead + AsyncWrite + Unpin,
+    peer: IpAddr,
+) -> anyhow::Result<()> {
+    let mut connection = server::Builder::new()
+        .enable_connect_protocol()
+        .handshake::<_, Bytes>(io)
+        .await?;
+
+    while let Some(request) = connection.accept().await {
+        let (request, respond) = request?;
+        let span = tracing::info_span!(
+            parent: None,
+            "session",
+            session_id = field::Empty,
+            address = field::Empty,
+            ip = field::Empty,
+            transport = "http2",
+        );
+        let server = server.clone();
+        tokio::spawn(
+            async move {
+                if let Err(e) = accept(server, request, respond, peer).await {
+                    tracing::debug!("HTTP/2 session ended: {e}");
+                }
+            }
+            .instrument(span),
+        );
+    }
+
+    Ok(())
+}
+
+/// Answer a request, starting a session if it's a WebSocket `CONNECT` with a valid token.
+async fn accept(
+    server: Arc<WebSocketServer>,
+    request: Request<RecvStream>,
+    mut respond: SendResponse<Bytes>,
+    peer: IpAddr,
+) -> anyhow::Result<()> {
+    let is_websocket = request.method() == Method::CONNECT
+        && request.extensions().get::<Protocol>().map(Protocol::as_str) == Some("websocket");
+    let Some(token) = request.uri().path().strip_prefix("/gateway/") else {
+        return refuse(respond, StatusCode::NOT_FOUND);
+    };
+    if !is_websocket {
+        return refuse(respond, StatusCode::NOT_FOUND);
+    }
+    if request
+        .headers()
+        .get("sec-websocket-version")
+        .map(|x| x.as_bytes())
+        != Some(b"13")
+    {
+        return refuse(respond, StatusCode::BAD_REQUEST);
+    }
+    let Ok(query) = web::Query::<GatewayQuery>::from_query(request.uri().query().unwrap_or(""))
+    else {
+        return refuse(respond, StatusCode::BAD_REQUEST);
+    };
+
+    // Forwarding headers are only trusted from trusted proxies, same as on the HTTP/1.1 endpoint
+    let ip = Some(server.client_ip_from(peer, |name| {
+        let values = request.headers().get_all(name).iter();
+        values.filter_map(|x| x.to_str().ok()).collect()
+    }));
+    let Admitted {
+        server,
+        token,
+        mut data,
+    } = match runner::admit(server.clone(), token, ip).await {
+        Ok(admitted) => admitted,
+        Err(Refusal::InvalidToken(error)) => return reject_token(&server, respond, error).await,
+        Err(Refusal::Overloaded(window)) => {
+            let advisory = ws::reconnect_advisory(window, "overloaded");
+            let reason = CloseReason {
+                code: CloseCode::Again,
+                description: Some("TryAgainLater".to_owned()),
+            };
+            return goodbye(respond, advisory, reason).await;
+        }
+        Err(refusal) => return refuse(respond, StatusCode::from_u16(refusal.status())?),
+    };
+    data.encoding = query.encoding;
+
+    let send = respond.send_response(Response::new(()), false)?;
+    let frames = Frames::new(request.into_body(), &server);
+    let connection = Connection {
+        token,
+        data,
+        sink: FrameSink::new(send),
+        ip,
+        codec: std::convert::identity,
+        since_seq: query.since_seq,
+        heartbeat: Heartbeat::Ping,
+    };
+    if let Some(running) = runner::start(server, connection).await {
+        running.run(frames).await;
+    }
+
+    Ok(())
+}
+
+/// Refuse a request with `status`.
+fn refuse(mut respond: SendResponse<Bytes>, status: StatusCode) -> anyhow::Result<()> {
+    let response = Response::builder().status(status).body(())?;
+    respond.send_response(response, true)?;
+
+    Ok(())
+}
+
+/// Turn away a bad token, refusing the request unless the server is configured to accept it and
+/// close with an `invalid_token` error instead.
+async fn reject_token(
+    server: &WebSocketServer,
+    respond: SendResponse<Bytes>,
+    error: String,
+) -> anyhow::Result<()> {
+    if !server.config().close_on_invalid_token {
+        return refuse(respond, StatusCode::BAD_REQUEST);
+    }
+
+    let message = WebSocketMessage {
+        ok: Some(false),
+        id: None,
+        trace_id: None,
+        idempotency_key: None,
+        r#type: WebSocketMessageInner::Error {
+            error: "invalid_token".to_owned(),
+            message: error,
+            parameter: None,
+        },
+    };
+    let reason = CloseReason {
+        code: CloseCode::Other(ws::INVALID_TOKEN_CLOSE_CODE),
+        description: Some("InvalidToken".to_owned()),
+    };
+
+    let message = serde_json::to_string(&message)
+        .inspect_err(|e| tracing::error!("Failed to serialize invalid_token error: {e}"))
+        .ok();
+
+    goodbye(respond, message, reason).await
+}
+
+/// Accept the request only to send `message` and close again.
+async fn goodbye(
+    mut respond: SendResponse<Bytes>,
+    message: Option<String>,
+    reason: CloseReason,
+) -> anyhow::Result<()> {
+    let mut sink = FrameSink::new(respond.send_response(Response::new(()), false)?);
+    if let Some(message) = message {
+        let _ = sink.text(message.into()).await;
+    }
+    let _ = sink.close(Some(reason)).await;
+
+    Ok(())
+}
+
+/// Reads WebSocket frames off a request body, joining fragmented messages back together.
+struct Frames {
+    body: RecvStream,
+    codec: Codec,
+    buf: BytesMut,
+    /// The fragments of a message so far, and whether it's text.
+    partial: Option<(bool, BytesMut)>,
+    max_continuation_size: usize,
+}
+
+impl Frames {
+    fn new(body: RecvStream, server: &WebSocketServer) -> Self {
+        Self {
+            body,
+            codec: Codec::new().max_size(server.config().max_frame_size),
+            buf: BytesMut::new(),
+            partial: None,
+            max_continuation_size: server.config().max_continuation_size,
+        }
+    }
+
+    /// Turn a frame into a message, or `None` while a fragmented message isn't complete yet.
+    fn aggregate(&mut self, frame: Frame) -> Result<Option<AggregatedMessage>, ProtocolError> {
+        let (text, bytes) = match frame {
+            Frame::Text(bytes) => (true, bytes),
+            Frame::Binary(bytes) => (false, bytes),
+            Frame::Ping(bytes) => return Ok(Some(AggregatedMessage::Ping(bytes))),
+            Frame::Pong(bytes) => return Ok(Some(AggregatedMessage::Pong(bytes))),
+            Frame::Close(reason) => return Ok(Some(AggregatedMessage::Close(reason))),
+            Frame::Continuation(item) => {
+                let (first, bytes, last) = match item {
+                    Item::FirstText(bytes) => (Some(true), bytes, false),
+                    Item::FirstBinary(bytes) => (Some(false), bytes, false),
+                    Item::Continue(bytes) => (None, bytes, false),
+                    Item::Last(bytes) => (None, bytes, true),
+                };
+
+                let (text, partial) = match (first, self.partial.as_mut()) {
+                    (Some(text), None) => self.partial.insert((text, BytesMut::new())),
+                    (None, Some(partial)) => partial,
+                    (Some(_), Some(_)) => return Err(ProtocolError::ContinuationStarted),
+                    (None, None) => return Err(ProtocolError::ContinuationNotStarted),
+                };
+                if partial.len() + bytes.len() > self.max_continuation_size {
+                    self.partial = None;
+                    return Err(ProtocolError::Overflow);
+                }
+                partial.extend_from_slice(&bytes);
+
+                let text = *text;
+                match last {
+                    true => (text, self.partial.take().unwrap_or_default().1.freeze()),
+                    false => return Ok(None),
+                }
+            }
+        };
+
+        match text {
+            true => ByteString::try_from(bytes)
+                .map(|text| Some(AggregatedMessage::Text(text)))
+                .map_err(|e| {
+                    ProtocolError::Io(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        e.to_string(),
+                    ))
+                }),
+            false => Ok(Some(AggregatedMessage::Binary(bytes))),
+        }
+    }
+}
+
+impl Inbound for Frames {
+    async fn recv(&mut self) -> Option<Result<AggregatedMessage, ProtocolError>> {
+        loop {
+            match self.codec.decode(&mut self.buf) {
+                Ok(Some(frame)) => match self.aggregate(frame) {
+                    Ok(Some(msg)) => return Some(Ok(msg)),
+                    Ok(None) => continue,
+                    Err(e) => return Some(Err(e)),
+                },
+                Ok(None) => {}
+                Err(e) => return Some(Err(e)),
+            }
+
+            let data = self.body.data().await?.ok()?;
+            let _ = self.body.flow_control().release_capacity(data.len());
+            self.buf.extend_from_slice(&data);
+        }
+    }
+}
+
+/// Writes messages as WebSocket frames on a stream's response body.
+struct FrameSink {
+    send: SendStream<Bytes>,
+    codec: Codec,
+}
+
+impl FrameSink {
+    fn new(send: SendStream<Bytes>) -> Self {
+        Self {
+            send,
+            codec: Codec::new(),
+        }
+    }
+
+    /// Encode `msg`, writing it out as fast as the client's flow control window allows.
+    async fn write(&mut self, msg: Message) -> Result<(), Closed> {
+        let mut buf = BytesMut::new();
+        self.codec.encode(msg, &mut buf).map_err(|_| Closed)?;
+        let mut buf = buf.freeze();
+
+        while !buf.is_empty() {
+            self.send.reserve_capacity(buf.len());
+            let capacity = poll_fn(|cx| self.send.poll_capacity(cx))
+                .await
+                .ok_or(Closed)?
+                .map_err(|_| Closed)?;
+            let chunk = buf.split_to(capacity.min(buf.len()));
+            self.send.send_data(chunk, false).map_err(|_| Closed)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl SessionSink for FrameSink {
+    async fn text(&mut self, msg: ByteString) -> Result<(), Closed> {
+        self.write(Message::Text(msg)).await
+    }
+
+    async fn binary(&mut self, bytes: Bytes) -> Result<(), Closed> {
+        self.write(Message::Binary(bytes)).await
+    }
+
+    async fn ping(&mut self, bytes: Bytes) -> Result<(), Closed> {
+        self.write(Message::Ping(bytes)).await
+    }
+
+    async fn pong(&mut self, bytes: Bytes) -> Result<(), Closed> {
+        self.write(Message::Pong(bytes)).await
+    }
+
+    async fn close(mut self, reason: Option<CloseReason>) -> Result<(), Closed> {
+        self.write(Message::Close(reason)).await?;
+        self.send.send_data(Bytes::new(), true).map_err(|_| Closed)
+    }
+}
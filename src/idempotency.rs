@@ -0,0 +1,98 @@
+use std::time::Duration;
+
+use dashmap::{DashMap, mapref::entry::Entry};
+use tokio::time::Instant;
+
+const PURGE_THRESHOLD: usize = 10_000;
+
+/// Identifies a request across retries: who sent it, and the key they attached to it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct IdempotencyKey {
+    /// The address for logged in sessions, or the session itself for guests.
+    pub scope: String,
+    pub key: String,
+}
+
+/// What to do with a request carrying an idempotency key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Begin {
+    /// First time the key has been seen, so handle the request.
+    New,
+    /// Already handled; send back the original response instead.
+    Replay(String),
+    /// The original request is still being handled.
+    InFlight,
+}
+
+#[derive(Debug)]
+struct CachedResponse {
+    seen: Instant,
+    response: Option<String>,
+}
+
+/// Responses to state-changing requests, remembered so client retries aren't executed twice.
+#[derive(Debug)]
+pub struct IdempotencyCache {
+    window: Duration,
+    responses: DashMap<IdempotencyKey, CachedResponse>,
+}
+
+impl IdempotencyCache {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            responses: DashMap::new(),
+        }
+    }
+
+    /// Claim a key for a request about to be handled.
+    pub fn begin(&self, key: IdempotencyKey) -> Begin {
+        if self.responses.len() >= PURGE_THRESHOLD {
+            self.purge_expired();
+        }
+
+        let now = Instant::now();
+        match self.responses.entry(key) {
+            Entry::Occupied(entry) if now.duration_since(entry.get().seen) < self.window => {
+                match &entry.get().response {
+                    Some(response) => Begin::Replay(response.clone()),
+                    None => Begin::InFlight,
+                }
+            }
+            Entry::Occupied(mut entry) => {
+                entry.insert(CachedResponse {
+                    seen: now,
+                    response: None,
+                });
+                Begin::New
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(CachedResponse {
+                    seen: now,
+                    response: None,
+                });
+                Begin::New
+            }
+        }
+    }
+
+    /// Remember the response to a request, to be replayed on retries.
+    pub fn complete(&self, key: &IdempotencyKey, response: String) {
+        if let Some(mut entry) = self.responses.get_mut(key) {
+            entry.response = Some(response);
+        }
+    }
+
+    /// Release a key whose request failed without a response, so a retry is handled afresh.
+    pub fn abandon(&self, key: &IdempotencyKey) {
+        self.responses
+            .remove_if(key, |_, entry| entry.response.is_none());
+    }
+
+    /// Forget responses older than the window, so the map doesn't grow without bound.
+    pub fn purge_expired(&self) {
+        let now = Instant::now();
+        self.responses
+            .retain(|_, entry| now.duration_since(entry.seen) < self.window);
+    }
+}
@@ -0,0 +1,153 @@
+use std::{
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{models::websocket::WebSocketSubscriptionType, pagination::Paginated};
+
+#[derive(Debug, Clone)]
+pub struct JournalConfig {
+    pub path: String,
+    /// Oldest events are discarded once the journal holds more than this many.
+    pub max_events: usize,
+}
+
+impl JournalConfig {
+    pub fn new(path: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            max_events: 100_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub seq: u64,
+    /// Milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    /// The subscription the event was published to, or `None` for broadcasts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub topic: Option<WebSocketSubscriptionType>,
+    pub payload: String,
+}
+
+/// Which end of the journal a page starts from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Order {
+    /// Oldest first.
+    Asc,
+    /// Newest first.
+    #[default]
+    Desc,
+}
+
+/// Append-only, size-bounded log of published events.
+pub struct EventJournal {
+    tree: sled::Db,
+    max_events: usize,
+}
+
+impl EventJournal {
+    pub fn open(config: &JournalConfig) -> anyhow::Result<Self> {
+        let tree = sled::open(Path::new(&config.path))?;
+
+        Ok(Self {
+            tree,
+            max_events: config.max_events,
+        })
+    }
+
+    /// Record an event, returning its sequence number.
+    ///
+    /// JSON object payloads get the sequence number added as a `seq` field, so clients can resume from it.
+    pub fn append(
+        &self,
+        topic: Option<&WebSocketSubscriptionType>,
+        payload: &str,
+    ) -> anyhow::Result<JournalEntry> {
+        // sled ids are monotonic across restarts, so they double as sequence numbers
+        let seq = self.tree.generate_id()?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_millis() as u64)
+            .unwrap_or_default();
+
+        let payload = match serde_json::from_str::<serde_json::Value>(payload) {
+            Ok(serde_json::Value::Object(mut object)) => {
+                object.insert("seq".to_owned(), seq.into());
+                serde_json::Value::Object(object).to_string()
+            }
+            _ => payload.to_owned(),
+        };
+
+        let entry = JournalEntry {
+            seq,
+            timestamp,
+            topic: topic.cloned(),
+            payload,
+        };
+        self.tree
+            .insert(seq.to_be_bytes(), serde_json::to_vec(&entry)?)?;
+
+        while self.tree.len() > self.max_events {
+            self.tree.pop_min()?;
+        }
+
+        Ok(entry)
+    }
+
+    /// Events with a sequence number greater than `since_seq`, oldest first.
+    pub fn since(&self, since_seq: u64, limit: usize) -> anyhow::Result<Vec<JournalEntry>> {
+        let Some(start) = since_seq.checked_add(1) else {
+            return Ok(Vec::new());
+        };
+
+        self.tree
+            .range(start.to_be_bytes()..)
+            .take(limit)
+            .map(|x| Ok(serde_json::from_slice(&x?.1)?))
+            .collect()
+    }
+
+    /// Up to `limit` of the events `filter` picks, skipping the first `offset` of them.
+    pub fn page(
+        &self,
+        order: Order,
+        offset: usize,
+        limit: usize,
+        filter: impl Fn(&JournalEntry) -> bool,
+    ) -> anyhow::Result<Paginated<JournalEntry>> {
+        let entries: Box<dyn Iterator<Item = _>> = match order {
+            Order::Asc => Box::new(self.tree.iter()),
+            Order::Desc => Box::new(self.tree.iter().rev()),
+        };
+
+        let mut total = 0;
+        let mut page = Vec::new();
+        for entry in entries {
+            let entry: JournalEntry = serde_json::from_slice(&entry?.1)?;
+            if !filter(&entry) {
+                continue;
+            }
+
+            if total >= offset && page.len() < limit {
+                page.push(entry);
+            }
+            total += 1;
+        }
+
+        Ok(Paginated::new(total, offset, page))
+    }
+
+    pub fn latest_seq(&self) -> anyhow::Result<Option<u64>> {
+        let last = self.tree.last()?;
+
+        Ok(last.map(|(key, _)| {
+            u64::from_be_bytes(key.as_ref().try_into().expect("Journal keys are 8 bytes"))
+        }))
+    }
+}
@@ -0,0 +1,163 @@
+use std::{sync::Arc, time::Duration};
+
+use futures::StreamExt;
+use rskafka::client::{
+    ClientBuilder,
+    consumer::{StartOffset, StreamConsumerBuilder},
+    partition::{PartitionClient, UnknownTopicHandling},
+};
+
+use crate::models::websocket::WebSocketSubscriptionType;
+use crate::ws::WebSocketServer;
+
+#[derive(Debug, Clone)]
+pub struct KafkaConfig {
+    pub brokers: Vec<String>,
+    pub topics: Vec<String>,
+    /// Replay each partition from its earliest retained record instead of only consuming new ones.
+    pub from_beginning: bool,
+    /// Delay before reconnecting a failed partition consumer, doubled for each failure in a row.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl KafkaConfig {
+    pub fn new(brokers: Vec<String>, topics: Vec<String>) -> Self {
+        Self {
+            brokers,
+            topics,
+            from_beginning: false,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+/// A record consumed from Kafka, handed to the feed's mapper.
+#[derive(Debug, Clone)]
+pub struct KafkaRecord {
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+    pub key: Option<Vec<u8>>,
+    pub value: Option<Vec<u8>>,
+}
+
+/// Turns a record into the subscription it belongs to and the message to send, or `None` to skip it.
+pub type KafkaMapper =
+    dyn Fn(&KafkaRecord) -> Option<(WebSocketSubscriptionType, String)> + Send + Sync;
+
+/// Consumes Kafka topics and publishes their records to matching subscriptions.
+pub struct KafkaFeed {
+    config: KafkaConfig,
+    mapper: Arc<KafkaMapper>,
+}
+
+impl KafkaFeed {
+    pub fn new(
+        config: KafkaConfig,
+        mapper: impl Fn(&KafkaRecord) -> Option<(WebSocketSubscriptionType, String)>
+        + Send
+        + Sync
+        + 'static,
+    ) -> Self {
+        Self {
+            config,
+            mapper: Arc::new(mapper),
+        }
+    }
+
+    /// Start consuming every partition of the configured topics in the background.
+    ///
+    /// Records are only published to this node's sessions, as every node is expected to run a feed
+    /// of its own.
+    pub async fn start(self, server: WebSocketServer) -> anyhow::Result<()> {
+        let client = ClientBuilder::new(self.config.brokers.clone())
+            .build()
+            .await?;
+        let start_offset = match self.config.from_beginning {
+            true => StartOffset::Earliest,
+            false => StartOffset::Latest,
+        };
+
+        let topics = client.list_topics().await?;
+        for name in &self.config.topics {
+            let Some(topic) = topics.iter().find(|x| &x.name == name) else {
+                anyhow::bail!("Kafka topic {name} does not exist");
+            };
+
+            for &partition in &topic.partitions {
+                let partition_client = client
+                    .partition_client(name.clone(), partition, UnknownTopicHandling::Retry)
+                    .await?;
+                let consumer = PartitionConsumer {
+                    client: Arc::new(partition_client),
+                    topic: name.clone(),
+                    partition,
+                    mapper: self.mapper.clone(),
+                    config: self.config.clone(),
+                };
+                tokio::spawn(consumer.run(server.clone(), start_offset));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Publishes the records of one partition, reconnecting whenever the stream fails.
+struct PartitionConsumer {
+    client: Arc<PartitionClient>,
+    topic: String,
+    partition: i32,
+    mapper: Arc<KafkaMapper>,
+    config: KafkaConfig,
+}
+
+impl PartitionConsumer {
+    async fn run(self, server: WebSocketServer, mut start_offset: StartOffset) {
+        let Self {
+            client,
+            topic: name,
+            partition,
+            mapper,
+            config,
+        } = self;
+        let mut backoff = config.initial_backoff;
+
+        loop {
+            tracing::info!("Consuming Kafka topic {name} partition {partition}");
+            let mut stream = StreamConsumerBuilder::new(client.clone(), start_offset).build();
+
+            while let Some(result) = stream.next().await {
+                let (record, _high_watermark) = match result {
+                    Ok(record) => record,
+                    Err(e) => {
+                        tracing::error!(
+                            "Kafka consumer for {name}/{partition} failed, reconnecting in {backoff:?}: {e}"
+                        );
+                        break;
+                    }
+                };
+                // Carry on after this record when reconnecting, rather than skipping or repeating any
+                start_offset = StartOffset::At(record.offset + 1);
+                backoff = config.initial_backoff;
+
+                let record = KafkaRecord {
+                    topic: name.clone(),
+                    partition,
+                    offset: record.offset,
+                    key: record.record.key,
+                    value: record.record.value,
+                };
+
+                if let Some((topic, msg)) = mapper(&record) {
+                    server.publish_local(topic, msg).await;
+                }
+            }
+
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(config.max_backoff);
+        }
+    }
+}
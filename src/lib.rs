@@ -1,2 +1,60 @@
+pub mod address;
+pub mod admin;
+pub mod alarms;
+pub mod audit;
+#[cfg(feature = "krist")]
+pub mod balances;
+pub mod client;
+pub mod client_ip;
+#[cfg(feature = "cluster")]
+pub mod cluster;
+pub mod compression;
+pub mod config;
+pub mod encoding;
+pub mod etag;
+pub mod events;
+pub mod filter;
+#[cfg(feature = "http2")]
+pub mod http2;
+pub mod idempotency;
+pub mod journal;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod listeners;
+pub mod loadtest;
+#[cfg(feature = "krist")]
+pub mod locks;
+pub mod metrics;
 pub mod models;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+#[cfg(feature = "krist")]
+pub mod names;
+#[cfg(feature = "nats")]
+pub mod nats;
+pub mod pagination;
+pub mod policy;
+pub mod ratelimit;
+pub mod recorder;
+pub mod resume;
+pub mod sampler;
+pub mod schema;
+pub mod session_store;
+#[cfg(feature = "socketio")]
+pub mod socketio;
+pub mod stats;
+#[cfg(feature = "krist")]
+pub mod submissions;
+#[cfg(unix)]
+pub mod systemd;
+pub mod telemetry;
+pub mod test_utils;
+#[cfg(feature = "tls")]
+pub mod tls;
+#[cfg(feature = "krist")]
+pub mod validation;
+#[cfg(feature = "webhooks")]
+pub mod webhooks;
+#[cfg(feature = "webtransport")]
+pub mod webtransport;
 pub mod ws;
@@ -0,0 +1,139 @@
+use std::{fmt, io, net::SocketAddr, path::PathBuf, str::FromStr};
+
+use actix_http::Request;
+use actix_service::IntoServiceFactory;
+use actix_web::{
+    Error, HttpServer,
+    body::MessageBody,
+    dev::{AppConfig, Response, Service, ServiceFactory},
+};
+use anyhow::anyhow;
+
+/// An address the HTTP server accepts connections on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Listener {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+    /// An already listening socket, e.g. passed in through systemd socket activation.
+    Fd(i32),
+}
+
+impl Listener {
+    /// Parse a comma-separated list of listeners, e.g. `127.0.0.1:8080,unix:/run/gateway.sock`.
+    pub fn parse_list(input: &str) -> anyhow::Result<Vec<Self>> {
+        input
+            .split(',')
+            .map(str::trim)
+            .filter(|x| !x.is_empty())
+            .map(str::parse)
+            .collect()
+    }
+}
+
+impl FromStr for Listener {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = input.strip_prefix("unix:") {
+            return Ok(Self::Unix(path.into()));
+        }
+
+        if let Some(fd) = input.strip_prefix("fd:") {
+            return fd
+                .parse()
+                .map(Self::Fd)
+                .map_err(|e| anyhow!("Invalid listener {input}: {e}"));
+        }
+
+        let addr = input.strip_prefix("tcp:").unwrap_or(input);
+        addr.parse()
+            .map(Self::Tcp)
+            .map_err(|e| anyhow!("Invalid listener {input}: {e}"))
+    }
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "tcp:{addr}"),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+            Self::Fd(fd) => write!(f, "fd:{fd}"),
+        }
+    }
+}
+
+/// Bind `server` to every listener, serving TLS on TCP listeners when `tls` is given.
+pub fn bind<F, I, S, B>(
+    mut server: HttpServer<F, I, S, B>,
+    listeners: &[Listener],
+    #[cfg(feature = "tls")] tls: Option<&rustls::ServerConfig>,
+) -> io::Result<HttpServer<F, I, S, B>>
+where
+    F: Fn() -> I + Send + Clone + 'static,
+    I: IntoServiceFactory<S, Request>,
+    S: ServiceFactory<Request, Config = AppConfig> + 'static,
+    S::Error: Into<Error> + 'static,
+    S::InitError: fmt::Debug,
+    S::Response: Into<Response<B>> + 'static,
+    <S::Service as Service<Request>>::Future: 'static,
+    S::Service: 'static,
+    B: MessageBody + 'static,
+{
+    for listener in listeners {
+        tracing::info!("Listening on {listener}");
+
+        server = match listener {
+            #[cfg(feature = "tls")]
+            Listener::Tcp(addr) if tls.is_some() => {
+                server.bind_rustls_0_23(addr, tls.cloned().expect("Checked above"))?
+            }
+            Listener::Tcp(addr) => server.bind(addr)?,
+            #[cfg(unix)]
+            Listener::Unix(path) => server.bind_uds(path)?,
+            #[cfg(unix)]
+            Listener::Fd(fd) => match inherit(*fd)? {
+                #[cfg(feature = "tls")]
+                Inherited::Tcp(listener) if tls.is_some() => {
+                    server.listen_rustls_0_23(listener, tls.cloned().expect("Checked above"))?
+                }
+                Inherited::Tcp(listener) => server.listen(listener)?,
+                Inherited::Unix(listener) => server.listen_uds(listener)?,
+            },
+            #[cfg(not(unix))]
+            Listener::Unix(_) | Listener::Fd(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("{listener} is not supported on this platform"),
+                ));
+            }
+        };
+    }
+
+    Ok(server)
+}
+
+#[cfg(unix)]
+enum Inherited {
+    Tcp(std::net::TcpListener),
+    Unix(std::os::unix::net::UnixListener),
+}
+
+/// Take ownership of a listening socket file descriptor, working out whether it is TCP or Unix.
+#[cfg(unix)]
+fn inherit(fd: i32) -> io::Result<Inherited> {
+    use std::os::fd::FromRawFd;
+
+    // Safety: the descriptor was handed to us to own, and is only ever wrapped once
+    let listener = unsafe { std::os::unix::net::UnixListener::from_raw_fd(fd) };
+
+    // Only Unix sockets have a Unix local address, anything else must be TCP
+    if listener.local_addr().is_ok() {
+        listener.set_nonblocking(true)?;
+        return Ok(Inherited::Unix(listener));
+    }
+
+    let listener = std::net::TcpListener::from(std::os::fd::OwnedFd::from(listener));
+    listener.set_nonblocking(true)?;
+
+    Ok(Inherited::Tcp(listener))
+}
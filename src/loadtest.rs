@@ -0,0 +1,214 @@
+//! `loadtest` subcommand: opens many gateway connections against a running server and measures how
+//! long broadcasts take to reach all of them.
+//!
+//! Broadcasts are triggered through the `GET /` endpoint, carrying a sequence number that clients
+//! match up with when it was sent.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use actix_web::rt::time;
+use anyhow::{Context, anyhow, bail};
+
+use crate::client::{GatewayClient, GatewayClientConfig, Incoming};
+use crate::models::websocket::WebSocketSubscriptionType;
+
+#[derive(Debug, Clone)]
+pub struct LoadTestConfig {
+    /// Base URL of the server's HTTP API.
+    pub url: String,
+    pub clients: usize,
+    /// Connections opened per second while ramping up.
+    pub rate: u32,
+    pub broadcasts: u32,
+    pub broadcast_interval: Duration,
+    /// How long to wait for stragglers after the last broadcast.
+    pub grace: Duration,
+}
+
+impl Default for LoadTestConfig {
+    fn default() -> Self {
+        Self {
+            url: "http://127.0.0.1:8080".to_owned(),
+            clients: 100,
+            rate: 100,
+            broadcasts: 20,
+            broadcast_interval: Duration::from_millis(250),
+            grace: Duration::from_secs(2),
+        }
+    }
+}
+
+impl LoadTestConfig {
+    /// Parse the subcommand's arguments, e.g. `--clients 5000 --rate 100`.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> anyhow::Result<Self> {
+        let mut config = Self::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().with_context(|| format!("{arg} needs a value"));
+            match arg.as_str() {
+                "--url" => config.url = value()?,
+                "--clients" => config.clients = value()?.parse()?,
+                "--rate" => config.rate = value()?.parse()?,
+                "--broadcasts" => config.broadcasts = value()?.parse()?,
+                "--interval-ms" => {
+                    config.broadcast_interval = Duration::from_millis(value()?.parse()?)
+                }
+                "--grace-ms" => config.grace = Duration::from_millis(value()?.parse()?),
+                _ => bail!("Unknown loadtest argument {arg}"),
+            }
+        }
+
+        if config.rate == 0 {
+            bail!("--rate must be at least 1");
+        }
+
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LoadTestReport {
+    pub connected: usize,
+    pub failed: usize,
+    /// Broadcast deliveries expected, i.e. connected clients times broadcasts sent.
+    pub expected: usize,
+    /// Fan-out latencies of every delivery that arrived, sorted.
+    pub latencies: Vec<Duration>,
+}
+
+impl LoadTestReport {
+    pub fn percentile(&self, percentile: f64) -> Option<Duration> {
+        let last = self.latencies.len().checked_sub(1)?;
+        let index = ((last as f64) * percentile / 100.0).round() as usize;
+        self.latencies.get(index).copied()
+    }
+}
+
+impl std::fmt::Display for LoadTestReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "clients: {} connected, {} failed",
+            self.connected, self.failed
+        )?;
+        writeln!(f, "deliveries: {}/{}", self.latencies.len(), self.expected)?;
+
+        for percentile in [50.0, 90.0, 99.0, 100.0] {
+            match self.percentile(percentile) {
+                Some(latency) => writeln!(f, "p{percentile}: {latency:?}")?,
+                None => writeln!(f, "p{percentile}: -")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Run the load test. Clients aren't `Send`, so this has to run inside a `LocalSet`.
+pub async fn run(config: LoadTestConfig) -> anyhow::Result<LoadTestReport> {
+    let sent_at: Rc<RefCell<HashMap<i64, Instant>>> = Rc::default();
+    let latencies: Rc<RefCell<Vec<Duration>>> = Rc::default();
+    let mut tasks = Vec::with_capacity(config.clients);
+    let mut connecting = Vec::with_capacity(config.clients);
+
+    tracing::info!(
+        "Opening {} connections at {}/s",
+        config.clients,
+        config.rate
+    );
+    let mut ramp = time::interval(Duration::from_secs(1) / config.rate);
+    for _ in 0..config.clients {
+        ramp.tick().await;
+
+        let client_config = GatewayClientConfig {
+            reconnect: None,
+            ..GatewayClientConfig::new(&config.url)
+        };
+        let sent_at = sent_at.clone();
+        let latencies = latencies.clone();
+        let (connected, connected_receiver) = tokio::sync::oneshot::channel();
+        connecting.push(connected_receiver);
+
+        tasks.push(tokio::task::spawn_local(async move {
+            let client = async {
+                let mut client = GatewayClient::connect(client_config).await?;
+                client.subscribe(WebSocketSubscriptionType::Blocks).await?;
+                anyhow::Ok(client)
+            };
+            let mut client = match client.await {
+                Ok(client) => {
+                    let _ = connected.send(Ok(()));
+                    client
+                }
+                Err(e) => {
+                    let _ = connected.send(Err(e));
+                    return;
+                }
+            };
+
+            while let Ok(incoming) = client.recv().await {
+                let Incoming::Event(event) = incoming else {
+                    continue;
+                };
+                let Some(number) = event.get("number").and_then(|x| x.as_i64()) else {
+                    continue;
+                };
+
+                if let Some(sent_at) = sent_at.borrow().get(&number) {
+                    latencies.borrow_mut().push(sent_at.elapsed());
+                }
+            }
+        }));
+    }
+
+    let mut report = LoadTestReport::default();
+    for connected in connecting {
+        match connected.await {
+            Ok(Ok(())) => report.connected += 1,
+            Ok(Err(e)) => {
+                tracing::debug!("Client failed to connect: {e}");
+                report.failed += 1;
+            }
+            Err(_) => report.failed += 1,
+        }
+    }
+    tracing::info!(
+        "{} clients connected, {} failed",
+        report.connected,
+        report.failed
+    );
+
+    let http = awc::Client::default();
+    let mut interval = time::interval(config.broadcast_interval);
+    for number in 0..config.broadcasts {
+        interval.tick().await;
+
+        let number = i64::from(number);
+        sent_at.borrow_mut().insert(number, Instant::now());
+        let response = http
+            .get(format!("{}/", config.url))
+            .send_json(&serde_json::json!({ "number": number }))
+            .await
+            .map_err(|e| anyhow!("Failed to trigger broadcast: {e}"))?;
+        if !response.status().is_success() {
+            bail!("Failed to trigger broadcast: {}", response.status());
+        }
+    }
+
+    time::sleep(config.grace).await;
+    for task in tasks {
+        task.abort();
+    }
+
+    report.expected = report.connected * config.broadcasts as usize;
+    report.latencies = latencies.take();
+    report.latencies.sort();
+
+    Ok(report)
+}
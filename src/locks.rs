@@ -0,0 +1,81 @@
+//! Addresses an admin has locked, kept so locks outlive the process.
+
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+/// Somewhere to keep address locks.
+pub trait LockStore: Send + Sync {
+    /// Every locked address, with the reason given for locking it.
+    fn load(&self) -> anyhow::Result<HashMap<String, Option<String>>>;
+
+    /// Lock `address`, or replace the reason it's locked for.
+    fn lock(&self, address: &str, reason: Option<&str>) -> anyhow::Result<()>;
+
+    fn unlock(&self, address: &str) -> anyhow::Result<()>;
+}
+
+/// Keeps locks in memory, shared between clones, e.g. to stand in for a real store in tests.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryLockStore {
+    locks: Arc<Mutex<HashMap<String, Option<String>>>>,
+}
+
+impl LockStore for MemoryLockStore {
+    fn load(&self) -> anyhow::Result<HashMap<String, Option<String>>> {
+        let locks = self.locks.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(locks.clone())
+    }
+
+    fn lock(&self, address: &str, reason: Option<&str>) -> anyhow::Result<()> {
+        let mut locks = self.locks.lock().unwrap_or_else(|e| e.into_inner());
+        locks.insert(address.to_owned(), reason.map(str::to_owned));
+        Ok(())
+    }
+
+    fn unlock(&self, address: &str) -> anyhow::Result<()> {
+        let mut locks = self.locks.lock().unwrap_or_else(|e| e.into_inner());
+        locks.remove(address);
+        Ok(())
+    }
+}
+
+/// Keeps locks in a sled database on disk.
+pub struct SledLockStore {
+    tree: sled::Db,
+}
+
+impl SledLockStore {
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Ok(Self {
+            tree: sled::open(path)?,
+        })
+    }
+}
+
+impl LockStore for SledLockStore {
+    fn load(&self) -> anyhow::Result<HashMap<String, Option<String>>> {
+        let mut locks = HashMap::new();
+        for entry in self.tree.iter() {
+            let (address, reason) = entry?;
+            let address = String::from_utf8(address.to_vec())?;
+            locks.insert(address, serde_json::from_slice(&reason)?);
+        }
+
+        Ok(locks)
+    }
+
+    fn lock(&self, address: &str, reason: Option<&str>) -> anyhow::Result<()> {
+        self.tree.insert(address, serde_json::to_vec(&reason)?)?;
+        self.tree.flush()?;
+        Ok(())
+    }
+
+    fn unlock(&self, address: &str) -> anyhow::Result<()> {
+        self.tree.remove(address)?;
+        self.tree.flush()?;
+        Ok(())
+    }
+}
@@ -1,7 +1,41 @@
-use actix_web::{App, HttpResponse, HttpServer, get, middleware::Logger, web};
+use std::time::Duration;
 
-use actix_ws_fuckery::ws::{self, WebSocketServer};
+use actix_web::{
+    App, HttpResponse, HttpServer,
+    dev::ServerHandle,
+    get,
+    middleware::{self, Logger},
+    web,
+};
+
+#[cfg(feature = "http2")]
+use actix_ws_fuckery::http2;
+#[cfg(feature = "krist")]
+use actix_ws_fuckery::locks::SledLockStore;
+#[cfg(unix)]
+use actix_ws_fuckery::systemd;
+#[cfg(feature = "tls")]
+use actix_ws_fuckery::tls::{ReloadableCertResolver, TlsConfig};
+#[cfg(feature = "webtransport")]
+use actix_ws_fuckery::webtransport;
+use actix_ws_fuckery::{
+    admin,
+    compression::{self, CompressionConfig},
+    config::{DrainingPublish, Processing, RuntimeConfig, WebSocketServerConfig},
+    etag,
+    journal::JournalConfig,
+    listeners::{self, Listener},
+    loadtest::{self, LoadTestConfig},
+    metrics, ratelimit,
+    recorder::{self, Pacing, Recording},
+    session_store::SledSessionStore,
+    telemetry::{self, LogFormat},
+    ws::{self, BroadcastMode, WebSocketServer, sink::RecordedFrame},
+};
+use anyhow::Context;
 use serde::{Deserialize, Serialize};
+#[cfg(unix)]
+use tokio::signal::unix::{SignalKind, signal};
 
 #[derive(Debug, Serialize, Deserialize)]
 struct MyObj {
@@ -16,28 +50,342 @@ async fn index(
     let item = item.into_inner();
     let string = serde_json::to_string(&item).expect("fucked up");
 
-    server.broadcast(string).await;
+    let report = server
+        .broadcast_with(string, BroadcastMode::AwaitDelivery(Duration::from_secs(1)))
+        .await
+        .map_err(actix_web::error::ErrorServiceUnavailable)?;
 
-    Ok(HttpResponse::Ok().body("Sent number to clients :3"))
+    Ok(HttpResponse::Ok().body(format!("Sent number to {} clients :3", report.delivered)))
 }
 
-#[tokio::main(flavor = "current_thread")]
+#[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt::init();
+    let log_format = match std::env::var("LOG_FORMAT") {
+        Ok(format) => format.parse()?,
+        Err(_) => LogFormat::default(),
+    };
+    let _telemetry = telemetry::init(log_format)?;
+
+    let mut args = std::env::args().skip(1);
+    if let Some(command) = args.next() {
+        match command.as_str() {
+            "loadtest" => {
+                let config = LoadTestConfig::from_args(args)?;
+                let local = tokio::task::LocalSet::new();
+                let report = local.run_until(loadtest::run(config)).await?;
+                print!("{report}");
+                return Ok(());
+            }
+            "replay" => {
+                let path = args.next().context("replay needs a recording to play")?;
+                let pacing = match args.next().as_deref() {
+                    Some("--realtime") => Pacing::Recorded,
+                    Some(arg) => anyhow::bail!("Unknown replay argument {arg}"),
+                    None => Pacing::Immediate,
+                };
+                let recording = Recording::load(path)?;
+                let server = WebSocketServer::new();
+                for frame in recorder::replay(&server, &recording, pacing).await {
+                    match frame {
+                        RecordedFrame::Text(text) => println!("{text}"),
+                        frame => println!("{frame:?}"),
+                    }
+                }
+                return Ok(());
+            }
+            _ => anyhow::bail!("Unknown command {command}"),
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    let tls = match (std::env::var_os("TLS_CERT"), std::env::var_os("TLS_KEY")) {
+        (Some(cert_path), Some(key_path)) => {
+            let config = TlsConfig {
+                cert_path: cert_path.into(),
+                key_path: key_path.into(),
+            };
+            Some(ReloadableCertResolver::load(config)?)
+        }
+        _ => None,
+    };
+
+    let mut config = WebSocketServerConfig {
+        admin_token: std::env::var("ADMIN_TOKEN").ok(),
+        config_path: std::env::var_os("CONFIG_PATH").map(Into::into),
+        trusted_proxies: match std::env::var("TRUSTED_PROXIES") {
+            Ok(proxies) => proxies
+                .split(',')
+                .map(|x| x.trim().parse())
+                .collect::<anyhow::Result<_>>()?,
+            Err(_) => Vec::new(),
+        },
+        resume_path: std::env::var_os("RESUME_PATH").map(Into::into),
+        close_on_invalid_token: std::env::var_os("CLOSE_ON_INVALID_TOKEN").is_some(),
+        echo_mode: std::env::var_os("ECHO_MODE").is_some(),
+        allow_guests: std::env::var_os("DISALLOW_GUESTS").is_none(),
+        record_dir: std::env::var_os("RECORD_DIR").map(Into::into),
+        draining_publish: match std::env::var("DRAINING_PUBLISH").as_deref() {
+            Ok("journal") => DrainingPublish::Journal,
+            _ => DrainingPublish::Reject,
+        },
+        message_sample_rate: std::env::var("MESSAGE_SAMPLE_RATE")
+            .ok()
+            .map(|x| x.parse())
+            .transpose()?,
+        ..Default::default()
+    };
+    #[cfg(feature = "tls")]
+    if tls.is_some() {
+        config.public_url = "wss://127.0.0.1:8080".to_owned();
+    }
+    if let Ok(handlers) = std::env::var("MAX_CONCURRENT_HANDLERS") {
+        config.max_concurrent_handlers = handlers.parse()?;
+    }
+    if let Ok(kinds) = std::env::var("UNORDERED_MESSAGE_TYPES") {
+        config.message_processing = kinds
+            .split(',')
+            .map(|x| (x.trim().to_owned(), Processing::Unordered))
+            .collect();
+    }
+    if let Ok(public_url) = std::env::var("PUBLIC_URL") {
+        config.public_url = public_url;
+    }
+    if config.echo_mode {
+        tracing::warn!("Echo mode is on: any token is accepted and no message is handled");
+    }
+    #[cfg(debug_assertions)]
+    if let Ok(chaos) = std::env::var("CHAOS") {
+        tracing::warn!("Chaos mode is on: {chaos}");
+        config.chaos = Some(chaos.parse()?);
+    }
+    if let Some(path) = &config.config_path {
+        config.runtime = RuntimeConfig::load(path)?;
+    }
+    if let Ok(path) = std::env::var("NAMESPACES_PATH") {
+        config.namespaces = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    }
+    if let Ok(thresholds) = std::env::var("CHURN_ALARMS") {
+        config.churn_alarms = serde_json::from_str(&thresholds)?;
+    }
+
+    let websocket_server = WebSocketServer::with_config(config);
+    let websocket_server = match std::env::var("JOURNAL_PATH") {
+        Ok(path) => websocket_server.with_journal(JournalConfig::new(path))?,
+        Err(_) => websocket_server,
+    };
+    let websocket_server = match std::env::var("SESSION_STORE_PATH") {
+        Ok(path) => websocket_server.with_session_store(SledSessionStore::open(path)?)?,
+        Err(_) => websocket_server,
+    };
+    #[cfg(feature = "krist")]
+    let websocket_server = match std::env::var("LOCKS_PATH") {
+        Ok(path) => websocket_server.with_lock_store(SledLockStore::open(path)?)?,
+        Err(_) => websocket_server,
+    };
+    websocket_server.load_resume_state().await?;
+
+    let websocket_server = match std::env::var("COMPRESSION_MIN_SIZE").as_deref() {
+        Ok("off") => websocket_server,
+        Ok(min_size) => websocket_server.with_compression(CompressionConfig {
+            min_size: min_size.parse()?,
+            ..Default::default()
+        }),
+        Err(_) => websocket_server.with_compression(CompressionConfig::default()),
+    };
+
+    #[cfg(feature = "cluster")]
+    let websocket_server = match std::env::var("REDIS_URL") {
+        Ok(url) => {
+            let config = actix_ws_fuckery::cluster::ClusterConfig::new(url);
+            websocket_server.join_cluster(config).await?
+        }
+        Err(_) => websocket_server,
+    };
+
+    #[cfg(feature = "nats")]
+    let websocket_server = match std::env::var("NATS_URL") {
+        Ok(url) => {
+            let config = actix_ws_fuckery::nats::NatsConfig::new(url);
+            websocket_server.attach_nats(config).await?
+        }
+        Err(_) => websocket_server,
+    };
+
+    #[cfg(feature = "mqtt")]
+    let websocket_server = match std::env::var("MQTT_HOST") {
+        Ok(host) => {
+            let config = actix_ws_fuckery::mqtt::MqttConfig::new(host, 1883);
+            websocket_server.with_mqtt(config)
+        }
+        Err(_) => websocket_server,
+    };
+
+    #[cfg(unix)]
+    {
+        let server = websocket_server.clone();
+        #[cfg(feature = "tls")]
+        let tls = tls.clone();
+        tokio::spawn(async move {
+            let mut hangups =
+                signal(SignalKind::hangup()).expect("Failed to install SIGHUP handler");
+
+            while hangups.recv().await.is_some() {
+                if let Err(e) = server.reload_config_from_file().await {
+                    tracing::error!("Failed to reload configuration: {e}");
+                }
+
+                #[cfg(feature = "tls")]
+                if let Some(tls) = &tls
+                    && let Err(e) = tls.reload()
+                {
+                    tracing::error!("Failed to reload TLS certificate: {e}");
+                }
+            }
+        });
+    }
+
+    // Sockets handed over by systemd take precedence over anything we'd bind ourselves
+    #[cfg(unix)]
+    let inherited = systemd::listen_fds();
+    #[cfg(not(unix))]
+    let inherited = None;
+    let listeners = match (inherited, std::env::var("LISTEN")) {
+        (Some(listeners), _) => listeners,
+        (None, Ok(listeners)) => Listener::parse_list(&listeners)?,
+        (None, Err(_)) => vec![Listener::Tcp(([127, 0, 0, 1], 8080).into())],
+    };
+    // Admin routes get their own listeners when configured, otherwise they share the public ones
+    let admin_listeners = match std::env::var("ADMIN_LISTEN") {
+        Ok(listeners) => Listener::parse_list(&listeners)?,
+        Err(_) => Vec::new(),
+    };
+    let separate_admin = !admin_listeners.is_empty();
+    let workers = match std::env::var("WORKERS") {
+        Ok(workers) => workers.parse()?,
+        Err(_) => std::thread::available_parallelism().map_or(1, |x| x.get()),
+    };
+
+    #[cfg(feature = "http2")]
+    if let Ok(addr) = std::env::var("HTTP2_ADDR") {
+        let listener = tokio::net::TcpListener::bind(&addr).await?;
+        tracing::info!("Accepting WebSockets over HTTP/2 on {addr}");
+        tokio::spawn(http2::serve(websocket_server.clone(), listener));
+    }
 
-    let websocket_server = WebSocketServer::new();
+    #[cfg(feature = "webtransport")]
+    if let Ok(addr) = std::env::var("WEBTRANSPORT_ADDR") {
+        let Some(tls) = &tls else {
+            anyhow::bail!("WEBTRANSPORT_ADDR needs TLS_CERT and TLS_KEY to be set");
+        };
+        let endpoint = webtransport::endpoint(addr.parse()?, tls.server_config())?;
+        tracing::info!("Accepting WebTransport sessions on {addr}");
+        tokio::spawn(webtransport::serve(websocket_server.clone(), endpoint));
+    }
 
-    HttpServer::new(move || {
+    #[cfg(feature = "tls")]
+    let tls = tls.map(|x| x.server_config());
+
+    // Every worker shares the same server state rather than getting its own copy
+    let websocket_server = web::Data::new(websocket_server);
+    let public_server = websocket_server.clone();
+    let http_server = HttpServer::new(move || {
         App::new()
+            .wrap(middleware::from_fn(etag::middleware))
+            .wrap(middleware::from_fn(compression::middleware))
+            .wrap(middleware::from_fn(ratelimit::middleware))
+            .wrap(middleware::from_fn(metrics::middleware))
             .wrap(Logger::default())
-            .app_data(web::Data::new(websocket_server.clone()))
-            .service(ws::ws_handler)
-            .service(ws::start_ws)
+            .app_data(public_server.clone())
+            .configure(ws::configure)
             .service(index)
+            .configure(|cfg| {
+                if !separate_admin {
+                    admin::configure(cfg);
+                }
+            })
+    })
+    .workers(workers)
+    // Sessions have already been handed over by the time the server stops, so don't wait long on them
+    .shutdown_timeout(5)
+    .disable_signals();
+    let http_server = listeners::bind(
+        http_server,
+        &listeners,
+        #[cfg(feature = "tls")]
+        tls.as_ref(),
+    )?
+    .run();
+
+    let mut handles = vec![http_server.handle()];
+
+    if !separate_admin {
+        spawn_shutdown(websocket_server.get_ref().clone(), handles);
+        #[cfg(unix)]
+        {
+            systemd::notify_ready();
+            systemd::spawn_watchdog();
+        }
+
+        http_server.await?;
+        return Ok(());
+    }
+
+    let admin_state = websocket_server.clone();
+    let admin_server = HttpServer::new(move || {
+        App::new()
+            .wrap(middleware::from_fn(etag::middleware))
+            .wrap(middleware::from_fn(compression::middleware))
+            .wrap(middleware::from_fn(ratelimit::middleware))
+            .wrap(middleware::from_fn(metrics::middleware))
+            .wrap(Logger::default())
+            .app_data(admin_state.clone())
+            .configure(admin::configure)
     })
-    .bind("127.0.0.1:8080")?
-    .run()
-    .await?;
+    .workers(1)
+    .disable_signals();
+    let admin_server = listeners::bind(
+        admin_server,
+        &admin_listeners,
+        #[cfg(feature = "tls")]
+        None,
+    )?
+    .run();
+
+    handles.push(admin_server.handle());
+    spawn_shutdown(websocket_server.get_ref().clone(), handles);
+    #[cfg(unix)]
+    {
+        systemd::notify_ready();
+        systemd::spawn_watchdog();
+    }
+
+    futures::try_join!(http_server, admin_server)?;
 
     Ok(())
 }
+
+/// Hand sessions over for resumption before stopping the HTTP servers, once asked to shut down.
+fn spawn_shutdown(server: WebSocketServer, handles: Vec<ServerHandle>) {
+    tokio::spawn(async move {
+        #[cfg(unix)]
+        {
+            let mut terminate =
+                signal(SignalKind::terminate()).expect("Failed to install SIGTERM handler");
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = tokio::signal::ctrl_c().await;
+
+        tracing::info!("Shutting down");
+        #[cfg(unix)]
+        systemd::notify_stopping();
+
+        server.shutdown().await;
+        for handle in handles {
+            handle.stop(true).await;
+        }
+    });
+}
@@ -0,0 +1,135 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use actix_web::{
+    Error,
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+    web,
+};
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use crate::ws::WebSocketServer;
+
+/// Upper bounds of the latency histogram buckets, in milliseconds. Anything slower goes in a
+/// last, unbounded bucket.
+pub const LATENCY_BUCKETS_MS: &[f64] = &[
+    1.0, 2.5, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0,
+];
+
+/// Counters kept over the lifetime of the server.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Events dropped from a session's queue because they outlived their TTL.
+    pub events_expired: AtomicU64,
+    /// Connections closed for sending a frame or message over the size limits.
+    pub oversized_messages: AtomicU64,
+    /// Inbound messages recorded by the message sampler, if it's enabled.
+    pub messages_sampled: AtomicU64,
+    /// How long client messages took to handle, by message type and outcome.
+    pub message_latency: LatencyHistograms,
+    /// How long HTTP requests took, by route and status code.
+    pub http_latency: LatencyHistograms,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MetricsSnapshot {
+    pub events_expired: u64,
+    pub oversized_messages: u64,
+    pub messages_sampled: u64,
+    pub message_latency: BTreeMap<String, BTreeMap<String, Histogram>>,
+    pub http_latency: BTreeMap<String, BTreeMap<String, Histogram>>,
+}
+
+impl Metrics {
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            events_expired: self.events_expired.load(Ordering::Relaxed),
+            oversized_messages: self.oversized_messages.load(Ordering::Relaxed),
+            messages_sampled: self.messages_sampled.load(Ordering::Relaxed),
+            message_latency: self.message_latency.snapshot(),
+            http_latency: self.http_latency.snapshot(),
+        }
+    }
+}
+
+/// How a set of durations was distributed over [`LATENCY_BUCKETS_MS`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Histogram {
+    pub count: u64,
+    pub sum_ms: f64,
+    /// Counts per bucket, plus one for anything slower than the last.
+    pub buckets: Vec<u64>,
+}
+
+impl Histogram {
+    pub fn observe(&mut self, duration: Duration) {
+        if self.buckets.is_empty() {
+            self.buckets = vec![0; LATENCY_BUCKETS_MS.len() + 1];
+        }
+
+        let ms = duration.as_secs_f64() * 1000.0;
+        let bucket = LATENCY_BUCKETS_MS.partition_point(|&max| max < ms);
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum_ms += ms;
+    }
+}
+
+/// Latency histograms keyed by what was timed and how it turned out.
+#[derive(Debug, Default)]
+pub struct LatencyHistograms {
+    histograms: Mutex<BTreeMap<String, BTreeMap<String, Histogram>>>,
+}
+
+impl LatencyHistograms {
+    pub fn observe(&self, key: &str, outcome: &str, duration: Duration) {
+        let mut histograms = self.histograms.lock().unwrap_or_else(|e| e.into_inner());
+        histograms
+            .entry(key.to_owned())
+            .or_default()
+            .entry(outcome.to_owned())
+            .or_default()
+            .observe(duration);
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, BTreeMap<String, Histogram>> {
+        self.histograms
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+/// Time HTTP requests, recording them by the route they matched and their status code.
+///
+/// Requests matching no route are grouped together as `unmatched`, so they can't grow the
+/// metrics without bound.
+pub async fn middleware(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let Some(server) = req.app_data::<web::Data<WebSocketServer>>().cloned() else {
+        return next.call(req).await;
+    };
+
+    let started = Instant::now();
+    let response = next.call(req).await?;
+
+    let route = response.request().match_pattern();
+    server.metrics().http_latency.observe(
+        route.as_deref().unwrap_or("unmatched"),
+        response.status().as_str(),
+        started.elapsed(),
+    );
+
+    Ok(response)
+}
@@ -1,12 +1,45 @@
 pub mod messages;
+pub mod state;
 
-use dashmap::DashSet;
-use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeSet,
+    net::IpAddr,
+    time::{Duration, SystemTime},
+};
+
+use dashmap::{DashMap, DashSet};
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de::Error as _};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::encoding::Encoding;
+use crate::filter::EventFilter;
+use crate::journal::JournalEntry;
+use crate::models::secret::Secret;
+use crate::pagination::Paginated;
+use crate::policy::{Scope, ScopeList};
+use crate::resume::ResumeState;
+use crate::ws::outbound::OutboundQueue;
+use state::SessionState;
+
+/// Start of the pseudo-addresses guests are given, which no real address can begin with.
+pub const GUEST_ADDRESS_PREFIX: &str = "guest-";
+
+/// Whether `address` is a guest's pseudo-address rather than a real one.
+pub fn is_guest_address(address: &str) -> bool {
+    address.starts_with(GUEST_ADDRESS_PREFIX)
+}
 
 #[derive(Debug, Default, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct WebSocketStartConnectionBody {
     #[serde(rename = "privatekey")]
-    pub private_key: Option<String>,
+    pub private_key: Option<Secret>,
+    /// Resume token from a previous connection, restoring its address and subscriptions.
+    #[serde(default)]
+    pub resume_token: Option<Uuid>,
+    /// Narrow down what the session may do, e.g. for a token handed to a third party.
+    #[serde(default)]
+    pub scopes: Option<ScopeList>,
 }
 
 #[derive(Debug, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
@@ -16,80 +49,204 @@ pub struct WebSocketStartResponse {
     pub expires: u16,
 }
 
-#[derive(Debug, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GatewayQuery {
+    /// Replay journaled events after this sequence number once connected.
+    pub since_seq: Option<u64>,
+    /// Format the server sends messages in. Clients still send JSON either way.
+    #[serde(default)]
+    pub encoding: Encoding,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EventsQuery {
+    #[serde(default)]
+    pub since_seq: u64,
+    pub limit: Option<usize>,
+    #[serde(default)]
+    pub offset: usize,
+    pub topic: Option<WebSocketSubscriptionType>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EventsResponse {
+    pub ok: bool,
+    pub latest_seq: Option<u64>,
+    #[serde(flatten)]
+    pub events: Paginated<JournalEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DrainRequest {
+    /// Sessions are told to reconnect at random points within this many milliseconds.
+    pub window_ms: u64,
+}
+
+impl Default for DrainRequest {
+    fn default() -> Self {
+        Self { window_ms: 30_000 }
+    }
+}
+
+/// Whether the server is in read-only maintenance mode, turning away state-changing messages
+/// while still answering queries and delivering events.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MaintenanceStatus {
+    pub enabled: bool,
+    /// Told to clients whose messages are turned away.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DrainResponse {
+    pub ok: bool,
+    /// How many sessions were told to reconnect.
+    pub sessions: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct WebSocketTokenData {
     pub address: String,
-    pub private_key: Option<String>,
+    pub private_key: Option<Secret>,
+    /// Set when the token was issued for a resumed session.
+    pub resume: Option<ResumeState>,
+    #[serde(default)]
+    pub encoding: Encoding,
+    #[serde(default = "Scope::default_scopes")]
+    pub scopes: ScopeList,
 }
 
 #[derive(Clone)]
 pub struct WebSocketSessionData {
     pub address: String,
-    pub private_key: Option<String>,
-    pub session: actix_ws::Session,
-    pub subscriptions: DashSet<WebSocketSubscriptionType>,
+    pub private_key: Option<Secret>,
+    pub ip: Option<IpAddr>,
+    pub state: SessionState,
+    pub subscriptions: WebSocketSubscriptionList,
+    /// Filters narrowing down which events of a subscription are delivered.
+    pub filters: DashMap<WebSocketSubscriptionType, EventFilter>,
+    /// Events delivered and dropped per subscription since the session connected.
+    pub stats: DashMap<WebSocketSubscriptionType, SubscriptionStats>,
+    /// Free-form labels for targeting groups of sessions, e.g. beta users or app versions.
+    pub tags: DashSet<String>,
+    /// Smoothed round-trip latency, measured from heartbeat pings.
+    pub latency: Option<Duration>,
+    pub outbound: OutboundQueue,
+    /// Format messages are sent to the session in.
+    pub encoding: Encoding,
+    /// Cancelled when the session closes, stopping every task serving it.
+    pub cancel: CancellationToken,
+    /// Handed to the client in the hello message, and redeemed to resume the session after a restart.
+    pub resume_token: Uuid,
+    pub connected_at: SystemTime,
+    /// What the session's token allows it to do.
+    pub scopes: ScopeList,
 }
 
-#[derive(Clone, Debug, Hash, Eq, Serialize, Deserialize, PartialEq, PartialOrd)]
-#[serde(rename_all = "camelCase")]
-pub enum WebSocketSubscriptionType {
-    Blocks,
-    OwnBlocks,
-    Transactions,
-    OwnTransactions,
-    Names,
-    OwnNames,
-    Motd,
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WebSocketSessionInfo {
+    pub uuid: Uuid,
+    pub address: String,
+    pub ip: Option<IpAddr>,
+    pub state: SessionState,
+    pub subscriptions: WebSocketSubscriptionList,
+    pub tags: Vec<String>,
+    #[serde(rename = "latencyMs")]
+    pub latency_ms: Option<f64>,
+    #[serde(rename = "queueDepth")]
+    pub queue_depth: usize,
+    #[serde(rename = "expiredEvents")]
+    pub expired_events: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubscriptionStats {
+    pub delivered: u64,
+    /// Events dropped because the session was lagging.
+    pub dropped: u64,
+}
+
+/// A session's subscriptions, deduplicated and in a stable order.
+pub type WebSocketSubscriptionList = BTreeSet<WebSocketSubscriptionType>;
+
+/// Defines the subscription levels along with their wire names, so parsing, printing and serde can't
+/// disagree about them.
+macro_rules! subscription_types {
+    ($($variant:ident => $name:literal,)*) => {
+        #[derive(Clone, Debug, Hash, Eq, PartialEq, PartialOrd, Ord)]
+        pub enum WebSocketSubscriptionType {
+            $($variant,)*
+        }
+
+        impl WebSocketSubscriptionType {
+            /// Every subscription level, in the order they sort.
+            pub const ALL: &[Self] = &[$(Self::$variant,)*];
+
+            /// The name of this level on the wire.
+            pub fn as_str(&self) -> &'static str {
+                match self {
+                    $(Self::$variant => $name,)*
+                }
+            }
+        }
+
+        impl std::str::FromStr for WebSocketSubscriptionType {
+            type Err = ();
+
+            fn from_str(input: &str) -> Result<Self, Self::Err> {
+                match input {
+                    $($name => Ok(Self::$variant),)*
+                    _ => Err(()),
+                }
+            }
+        }
+    };
+}
+
+subscription_types! {
+    Blocks => "blocks",
+    OwnBlocks => "ownBlocks",
+    Transactions => "transactions",
+    OwnTransactions => "ownTransactions",
+    Names => "names",
+    OwnNames => "ownNames",
+    Balances => "balances",
+    OwnBalances => "ownBalances",
+    Motd => "motd",
+    Stats => "stats",
 }
 
 impl WebSocketSubscriptionType {
-    pub fn is_valid(subscription_type: &str) -> bool {
-        subscription_type
-            .parse::<WebSocketSubscriptionType>()
-            .is_ok()
+    pub fn into_string(&self) -> String {
+        self.as_str().to_owned()
     }
+}
 
-    pub fn into_string(&self) -> String {
-        match self {
-            WebSocketSubscriptionType::Blocks => "blocks".to_owned(),
-            WebSocketSubscriptionType::OwnBlocks => "ownBlocks".to_owned(),
-            WebSocketSubscriptionType::Transactions => "transactions".to_owned(),
-            WebSocketSubscriptionType::OwnTransactions => "ownTransactions".to_owned(),
-            WebSocketSubscriptionType::Names => "names".to_owned(),
-            WebSocketSubscriptionType::OwnNames => "ownNames".to_owned(),
-            WebSocketSubscriptionType::Motd => "motd".to_owned(),
-        }
+impl Serialize for WebSocketSubscriptionType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
     }
 }
 
-impl std::str::FromStr for WebSocketSubscriptionType {
-    type Err = ();
+impl<'de> Deserialize<'de> for WebSocketSubscriptionType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let level = String::deserialize(deserializer)?;
 
-    fn from_str(input: &str) -> Result<Self, Self::Err> {
-        match input {
-            "blocks" => Ok(Self::Blocks),
-            "ownBlocks" => Ok(Self::OwnBlocks),
-            "transactions" => Ok(Self::Transactions),
-            "ownTransactions" => Ok(Self::OwnTransactions),
-            "names" => Ok(Self::Names),
-            "ownNames" => Ok(Self::OwnNames),
-            "motd" => Ok(Self::Motd),
-            _ => Err(()),
-        }
+        level.parse().map_err(|()| {
+            let valid: Vec<&str> = Self::ALL.iter().map(Self::as_str).collect();
+            D::Error::custom(format!(
+                "invalid subscription level \"{level}\", expected one of: {}",
+                valid.join(", ")
+            ))
+        })
     }
 }
 
 impl std::fmt::Display for WebSocketSubscriptionType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Blocks => write!(f, "blocks"),
-            Self::OwnBlocks => write!(f, "ownBlocks"),
-            Self::Transactions => write!(f, "transactions"),
-            Self::OwnTransactions => write!(f, "ownTransactions"),
-            Self::Names => write!(f, "names"),
-            Self::OwnNames => write!(f, "ownNames"),
-            Self::Motd => write!(f, "motd"),
-        }
+        f.write_str(self.as_str())
     }
 }
 
@@ -98,7 +255,16 @@ impl WebSocketTokenData {
     pub fn new(address: String, private_key: Option<String>) -> Self {
         Self {
             address,
-            private_key,
+            private_key: private_key.map(Secret::from),
+            resume: None,
+            encoding: Encoding::default(),
+            scopes: Scope::default_scopes(),
         }
     }
+
+    /// A token for a guest, under a pseudo-address of its own so it can still be told apart.
+    pub fn guest() -> Self {
+        let id = Uuid::new_v4().simple().to_string();
+        Self::new(format!("{GUEST_ADDRESS_PREFIX}{}", &id[..12]), None)
+    }
 }
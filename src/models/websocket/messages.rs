@@ -1,16 +1,64 @@
+use std::collections::HashMap;
+
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize, Serialize)]
+use crate::encoding::Encoding;
+use crate::filter::EventFilter;
+#[cfg(feature = "krist")]
+use crate::journal::Order;
+use crate::models::secret::Secret;
+use crate::models::websocket::{SubscriptionStats, WebSocketSubscriptionType};
+#[cfg(feature = "krist")]
+use crate::names::NameRecord;
+#[cfg(feature = "krist")]
+use crate::pagination::Paginated;
+
+/// Version of the gateway protocol, bumped whenever it changes in a way clients have to handle.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Something the server supports, advertised in its hello so clients can adapt to it.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case", tag = "capability")]
+pub enum Capability {
+    Protocol {
+        version: u32,
+    },
+    /// Formats the server can send messages in, chosen with `?encoding=` when connecting.
+    Encodings {
+        encodings: Vec<Encoding>,
+    },
+    /// Largest text message the server will handle, in bytes.
+    MaxPayload {
+        bytes: usize,
+    },
+    /// Topics sessions can subscribe to.
+    Subscriptions {
+        levels: Vec<WebSocketSubscriptionType>,
+    },
+    /// Sessions can be resumed with the token from their hello after reconnecting.
+    Resume {
+        /// Whether sessions can also be resumed after the server restarts.
+        across_restarts: bool,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct WebSocketMessage {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub ok: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub id: Option<usize>,
+    /// Correlation id joining client logs with server traces, echoed back in responses.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<String>,
+    /// Idempotency key; retries of a state-changing message with the same key get the original response.
+    #[serde(rename = "ref", default, skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
     #[serde(flatten)]
     pub r#type: WebSocketMessageInner,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case", tag = "type")]
 pub enum WebSocketMessageInner {
     Hello {
@@ -20,20 +68,57 @@ pub enum WebSocketMessageInner {
 
     Keepalive {
         server_time: String,
+        /// Whatever else the server's keepalive provider supplies, e.g. the chain height.
+        #[serde(flatten)]
+        fields: serde_json::Map<String, serde_json::Value>,
+    },
+
+    Warning {
+        warning: String,
+        message: String,
+    },
+
+    /// Asks the client to reconnect after waiting `wait_ms`, e.g. so a draining server can hand its
+    /// sessions over to another instance without them all arriving at once.
+    Reconnect {
+        wait_ms: u64,
+        reason: String,
+    },
+
+    Error {
+        error: String,
+        message: String,
+        /// The request field at fault, for validation errors.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        parameter: Option<String>,
+    },
+
+    /// Several events published in quick succession, delivered together.
+    Batch {
+        events: Vec<serde_json::Value>,
     },
 
+    /// The request type is carried in `data`, as its `responding_to` tag.
     Response {
-        responding_to: String,
         #[serde(flatten)]
         data: WebSocketMessageResponse,
     },
 
+    #[cfg(feature = "krist")]
     Work,
 
+    /// A solution to the current block, mined for `address`.
+    #[cfg(feature = "krist")]
+    SubmitBlock {
+        address: String,
+        nonce: String,
+    },
+
+    #[cfg(feature = "krist")]
     MakeTransaction {
         /// The privatekey of your address.
         #[serde(rename = "privatekey")]
-        private_key: String,
+        private_key: Secret,
 
         /// The recipient of the transaction.
         to: String,
@@ -47,6 +132,7 @@ pub enum WebSocketMessageInner {
 
     GetValidSubscriptionLevels,
 
+    #[cfg(feature = "krist")]
     Address {
         address: String,
 
@@ -55,31 +141,151 @@ pub enum WebSocketMessageInner {
         fetch_names: Option<bool>,
     },
 
+    /// Page through past transactions, e.g. to backfill what was missed before subscribing.
+    #[cfg(feature = "krist")]
+    GetTransactions {
+        /// Only transactions to or from this address.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        address: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        limit: Option<usize>,
+        #[serde(default)]
+        offset: usize,
+        #[serde(default)]
+        order: Order,
+    },
+
+    /// Page through past blocks.
+    #[cfg(feature = "krist")]
+    GetBlocks {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        limit: Option<usize>,
+        #[serde(default)]
+        offset: usize,
+        #[serde(default)]
+        order: Order,
+    },
+
+    /// Look up who owns a name and where it points.
+    #[cfg(feature = "krist")]
+    GetName {
+        name: String,
+    },
+
+    /// Register a name to the session's address, paying the economy's name cost.
+    #[cfg(feature = "krist")]
+    RegisterName {
+        name: String,
+    },
+
+    /// Point a name the session owns somewhere else, or clear its A record with `null`.
+    #[cfg(feature = "krist")]
+    UpdateName {
+        name: String,
+        #[serde(default)]
+        a: Option<String>,
+    },
+
     Me,
     GetSubscriptionLevel,
+    /// Count the events delivered and dropped per subscription since the session connected.
+    GetSubscriptionStats,
     Logout,
     Login {
         #[serde(rename = "privatekey")]
-        private_key: String,
+        private_key: Secret,
     },
 
     Subscribe {
-        event: String,
+        event: WebSocketSubscriptionType,
+        /// Only deliver events matching this filter.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        filter: Option<EventFilter>,
     },
 
     Unsubscribe {
-        event: String,
+        event: WebSocketSubscriptionType,
+    },
+
+    /// Attach tags to the session, so it can be targeted by tag broadcasts.
+    Tag {
+        tags: Vec<String>,
     },
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+impl WebSocketMessageInner {
+    /// Whether handling this message changes state, so retries must not handle it twice.
+    pub fn is_state_changing(&self) -> bool {
+        match self {
+            #[cfg(feature = "krist")]
+            Self::SubmitBlock { .. }
+            | Self::MakeTransaction { .. }
+            | Self::RegisterName { .. }
+            | Self::UpdateName { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// The wire name of this message type, as found in the `type` field.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Hello { .. } => "hello",
+            Self::Keepalive { .. } => "keepalive",
+            Self::Warning { .. } => "warning",
+            Self::Reconnect { .. } => "reconnect",
+            Self::Error { .. } => "error",
+            Self::Batch { .. } => "batch",
+            Self::Response { .. } => "response",
+            #[cfg(feature = "krist")]
+            Self::Work => "work",
+            #[cfg(feature = "krist")]
+            Self::SubmitBlock { .. } => "submit_block",
+            #[cfg(feature = "krist")]
+            Self::MakeTransaction { .. } => "make_transaction",
+            Self::GetValidSubscriptionLevels => "get_valid_subscription_levels",
+            #[cfg(feature = "krist")]
+            Self::Address { .. } => "address",
+            #[cfg(feature = "krist")]
+            Self::GetTransactions { .. } => "get_transactions",
+            #[cfg(feature = "krist")]
+            Self::GetBlocks { .. } => "get_blocks",
+            #[cfg(feature = "krist")]
+            Self::GetName { .. } => "get_name",
+            #[cfg(feature = "krist")]
+            Self::RegisterName { .. } => "register_name",
+            #[cfg(feature = "krist")]
+            Self::UpdateName { .. } => "update_name",
+            Self::Me => "me",
+            Self::GetSubscriptionLevel => "get_subscription_level",
+            Self::GetSubscriptionStats => "get_subscription_stats",
+            Self::Logout => "logout",
+            Self::Login { .. } => "login",
+            Self::Subscribe { .. } => "subscribe",
+            Self::Unsubscribe { .. } => "unsubscribe",
+            Self::Tag { .. } => "tag",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(tag = "responding_to", rename_all = "snake_case")]
 pub enum WebSocketMessageResponse {
+    #[cfg(feature = "krist")]
     Work {
         /// The current Krist work (difficulty)
         work: usize,
     },
 
+    /// The block was accepted, and `reward` paid out to `address`.
+    #[cfg(feature = "krist")]
+    SubmitBlock {
+        address: String,
+        reward: u32,
+        /// `address`'s balance with the reward.
+        balance: u64,
+    },
+
+    #[cfg(feature = "krist")]
     MakeTransaction {
         transaction: serde_json::Value,
     },
@@ -89,10 +295,38 @@ pub enum WebSocketMessageResponse {
         valid_subscription_levels: serde_json::Value,
     },
 
+    #[cfg(feature = "krist")]
     Address {
         address: serde_json::Value,
     },
 
+    #[cfg(feature = "krist")]
+    GetTransactions {
+        #[serde(flatten)]
+        page: Paginated<serde_json::Value>,
+    },
+
+    #[cfg(feature = "krist")]
+    GetBlocks {
+        #[serde(flatten)]
+        page: Paginated<serde_json::Value>,
+    },
+
+    #[cfg(feature = "krist")]
+    GetName {
+        name: NameRecord,
+    },
+
+    #[cfg(feature = "krist")]
+    RegisterName {
+        name: NameRecord,
+    },
+
+    #[cfg(feature = "krist")]
+    UpdateName {
+        name: NameRecord,
+    },
+
     Me {
         /// Whether the current user is a guest or not
         is_guest: bool,
@@ -104,6 +338,10 @@ pub enum WebSocketMessageResponse {
         subscription_level: Vec<String>,
     },
 
+    GetSubscriptionStats {
+        stats: HashMap<WebSocketSubscriptionType, SubscriptionStats>,
+    },
+
     Logout {
         /// Whether the current user is a guest or not
         is_guest: bool,
@@ -122,4 +360,43 @@ pub enum WebSocketMessageResponse {
     Unsubscribe {
         subscription_level: Vec<String>,
     },
+
+    Tag {
+        tags: Vec<String>,
+    },
+}
+
+impl WebSocketMessageResponse {
+    /// The request type this responds to, as found in the `responding_to` field.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "krist")]
+            Self::Work { .. } => "work",
+            #[cfg(feature = "krist")]
+            Self::SubmitBlock { .. } => "submit_block",
+            #[cfg(feature = "krist")]
+            Self::MakeTransaction { .. } => "make_transaction",
+            Self::GetValidSubscriptionLevels { .. } => "get_valid_subscription_levels",
+            #[cfg(feature = "krist")]
+            Self::Address { .. } => "address",
+            #[cfg(feature = "krist")]
+            Self::GetTransactions { .. } => "get_transactions",
+            #[cfg(feature = "krist")]
+            Self::GetBlocks { .. } => "get_blocks",
+            #[cfg(feature = "krist")]
+            Self::GetName { .. } => "get_name",
+            #[cfg(feature = "krist")]
+            Self::RegisterName { .. } => "register_name",
+            #[cfg(feature = "krist")]
+            Self::UpdateName { .. } => "update_name",
+            Self::Me { .. } => "me",
+            Self::GetSubscriptionLevel { .. } => "get_subscription_level",
+            Self::GetSubscriptionStats { .. } => "get_subscription_stats",
+            Self::Logout { .. } => "logout",
+            Self::Login { .. } => "login",
+            Self::Subscribe { .. } => "subscribe",
+            Self::Unsubscribe { .. } => "unsubscribe",
+            Self::Tag { .. } => "tag",
+        }
+    }
 }
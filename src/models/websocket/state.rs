@@ -0,0 +1,106 @@
+use serde::{Deserialize, Serialize};
+
+use super::messages::WebSocketMessageInner;
+
+/// Where a session is in its lifecycle.
+///
+/// Sessions start out `Handshaking`, become `Ready` (or `Authenticated` when logged in with a private
+/// key) once greeted, move to `Draining` while the server winds them down, and end up `Closed`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SessionState {
+    #[default]
+    Handshaking,
+    Ready,
+    Authenticated,
+    Draining,
+    Closed,
+}
+
+/// Where the server as a whole is in its lifecycle.
+///
+/// Servers start out `Running`, move to `Draining` once they start winding sessions down, and end up
+/// `Stopped` when every session is gone.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ServerState {
+    #[default]
+    Running,
+    Draining,
+    Stopped,
+}
+
+/// Why a message was refused in the session's current state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateRejected {
+    NotReady,
+    Draining,
+    Closed,
+    NotLoggedIn,
+    AlreadyLoggedIn,
+}
+
+impl StateRejected {
+    /// Machine-readable error code sent back to the client.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::NotReady => "session_not_ready",
+            Self::Draining => "session_draining",
+            Self::Closed => "session_closed",
+            Self::NotLoggedIn => "not_logged_in",
+            Self::AlreadyLoggedIn => "already_logged_in",
+        }
+    }
+}
+
+impl std::fmt::Display for StateRejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotReady => write!(f, "The session is still being set up"),
+            Self::Draining => write!(f, "The session is being wound down"),
+            Self::Closed => write!(f, "The session is closed"),
+            Self::NotLoggedIn => write!(f, "You are not logged in"),
+            Self::AlreadyLoggedIn => write!(f, "You are already logged in"),
+        }
+    }
+}
+
+impl std::error::Error for StateRejected {}
+
+impl SessionState {
+    /// Whether a session may move from this state to `next`.
+    pub fn can_transition_to(self, next: SessionState) -> bool {
+        use SessionState::*;
+
+        matches!(
+            (self, next),
+            (Handshaking, Ready | Authenticated | Closed)
+                | (Ready, Authenticated | Draining | Closed)
+                | (Authenticated, Ready | Draining | Closed)
+                | (Draining, Closed)
+        )
+    }
+
+    /// Check whether a client message may be handled in this state.
+    pub fn accepts(self, msg: &WebSocketMessageInner) -> Result<(), StateRejected> {
+        self.accepts_messages()?;
+
+        match (self, msg) {
+            (Self::Ready, WebSocketMessageInner::Logout) => Err(StateRejected::NotLoggedIn),
+            (Self::Authenticated, WebSocketMessageInner::Login { .. }) => {
+                Err(StateRejected::AlreadyLoggedIn)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Check whether client messages may be handled at all in this state.
+    pub fn accepts_messages(self) -> Result<(), StateRejected> {
+        match self {
+            Self::Handshaking => Err(StateRejected::NotReady),
+            Self::Draining => Err(StateRejected::Draining),
+            Self::Closed => Err(StateRejected::Closed),
+            Self::Ready | Self::Authenticated => Ok(()),
+        }
+    }
+}
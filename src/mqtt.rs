@@ -0,0 +1,75 @@
+use std::time::Duration;
+
+use rumqttc::{AsyncClient, MqttOptions, QoS};
+
+use crate::models::websocket::WebSocketSubscriptionType;
+
+#[derive(Debug, Clone)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    /// Events are published to `<prefix>/<subscription type>`, and broadcasts to `<prefix>/broadcast`.
+    pub topic_prefix: String,
+}
+
+impl MqttConfig {
+    pub fn new(host: impl Into<String>, port: u16) -> Self {
+        Self {
+            host: host.into(),
+            port,
+            client_id: format!(
+                "{}-{}",
+                env!("CARGO_PKG_NAME"),
+                uuid::Uuid::new_v4().simple()
+            ),
+            topic_prefix: "gateway".to_owned(),
+        }
+    }
+}
+
+/// Mirrors the event stream onto an MQTT broker for clients that can't speak WebSocket.
+pub struct MqttBridge {
+    client: AsyncClient,
+    topic_prefix: String,
+}
+
+impl MqttBridge {
+    pub(crate) fn connect(config: &MqttConfig) -> Self {
+        let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+        options.set_keep_alive(Duration::from_secs(30));
+
+        let (client, mut event_loop) = AsyncClient::new(options, 256);
+
+        // The event loop drives the connection and has to be polled for publishes to go out
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = event_loop.poll().await {
+                    tracing::warn!("MQTT connection error: {e}");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        });
+
+        Self {
+            client,
+            topic_prefix: config.topic_prefix.clone(),
+        }
+    }
+
+    pub(crate) async fn publish(&self, topic: Option<&WebSocketSubscriptionType>, payload: &str) {
+        let topic = match topic {
+            Some(topic) => format!("{}/{topic}", self.topic_prefix),
+            None => format!("{}/broadcast", self.topic_prefix),
+        };
+
+        let result = self
+            .client
+            .publish(&topic, QoS::AtLeastOnce, false, payload.as_bytes().to_vec())
+            .await;
+
+        if let Err(e) = result {
+            tracing::warn!("Failed to publish to MQTT topic {topic}: {e}");
+        }
+    }
+}
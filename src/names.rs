@@ -0,0 +1,160 @@
+//! Krist names, who owns them and where they point.
+
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NameRecord {
+    /// Without the `.kst` suffix.
+    pub name: String,
+    pub owner: String,
+    /// The name's A record, e.g. a URL it points to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub a: Option<String>,
+    /// Milliseconds since the Unix epoch.
+    pub registered: u64,
+    /// When the A record was last changed, in milliseconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated: Option<u64>,
+    /// Taken off its owner by an admin until it's restored, and can't be updated meanwhile.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub seized: bool,
+}
+
+impl NameRecord {
+    pub fn new(name: impl Into<String>, owner: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            owner: owner.into(),
+            a: None,
+            registered: now_millis(),
+            updated: None,
+            seized: false,
+        }
+    }
+
+    /// Point the name at `a`, or at nothing.
+    pub fn set_a(&mut self, a: Option<String>) {
+        self.a = a;
+        self.updated = Some(now_millis());
+    }
+
+    /// The `name` event telling subscribers the name changed.
+    pub fn event(&self) -> serde_json::Value {
+        serde_json::json!({
+            "type": "event",
+            "event": "name",
+            "name": self,
+        })
+    }
+}
+
+/// Somewhere to keep names.
+pub trait NameStore: Send + Sync {
+    fn get(&self, name: &str) -> anyhow::Result<Option<NameRecord>>;
+
+    /// Insert or replace the record for `record.name`.
+    fn put(&self, record: &NameRecord) -> anyhow::Result<()>;
+
+    /// Insert `record` unless its name is taken, returning whether it was inserted.
+    fn insert(&self, record: &NameRecord) -> anyhow::Result<bool>;
+
+    /// How many names `owner` owns.
+    fn count_owned(&self, owner: &str) -> anyhow::Result<usize>;
+}
+
+/// Keeps names in memory, shared between clones, e.g. to stand in for a real store in tests.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryNameStore {
+    names: Arc<Mutex<HashMap<String, NameRecord>>>,
+}
+
+impl NameStore for MemoryNameStore {
+    fn get(&self, name: &str) -> anyhow::Result<Option<NameRecord>> {
+        let names = self.names.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(names.get(name).cloned())
+    }
+
+    fn put(&self, record: &NameRecord) -> anyhow::Result<()> {
+        let mut names = self.names.lock().unwrap_or_else(|e| e.into_inner());
+        names.insert(record.name.clone(), record.clone());
+        Ok(())
+    }
+
+    fn insert(&self, record: &NameRecord) -> anyhow::Result<bool> {
+        let mut names = self.names.lock().unwrap_or_else(|e| e.into_inner());
+        if names.contains_key(&record.name) {
+            return Ok(false);
+        }
+        names.insert(record.name.clone(), record.clone());
+        Ok(true)
+    }
+
+    fn count_owned(&self, owner: &str) -> anyhow::Result<usize> {
+        let names = self.names.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(names.values().filter(|x| x.owner == owner).count())
+    }
+}
+
+/// Keeps names in a sled database on disk.
+pub struct SledNameStore {
+    tree: sled::Db,
+}
+
+impl SledNameStore {
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Ok(Self {
+            tree: sled::open(path)?,
+        })
+    }
+}
+
+impl NameStore for SledNameStore {
+    fn get(&self, name: &str) -> anyhow::Result<Option<NameRecord>> {
+        match self.tree.get(name)? {
+            Some(record) => Ok(Some(serde_json::from_slice(&record)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn put(&self, record: &NameRecord) -> anyhow::Result<()> {
+        self.tree
+            .insert(&record.name, serde_json::to_vec(record)?)?;
+        self.tree.flush()?;
+        Ok(())
+    }
+
+    fn insert(&self, record: &NameRecord) -> anyhow::Result<bool> {
+        let value = serde_json::to_vec(record)?;
+        let inserted = self
+            .tree
+            .compare_and_swap(&record.name, None as Option<&[u8]>, Some(value))?
+            .is_ok();
+        self.tree.flush()?;
+        Ok(inserted)
+    }
+
+    fn count_owned(&self, owner: &str) -> anyhow::Result<usize> {
+        let mut count = 0;
+        for entry in self.tree.iter() {
+            let (_, record) = entry?;
+            let record: NameRecord = serde_json::from_slice(&record)?;
+            count += usize::from(record.owner == owner);
+        }
+
+        Ok(count)
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_millis() as u64)
+        .unwrap_or_default()
+}
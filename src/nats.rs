@@ -0,0 +1,95 @@
+use futures::StreamExt;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::models::websocket::{WebSocketSubscriptionType, messages::WebSocketMessage};
+use crate::ws::WebSocketServer;
+
+#[derive(Debug, Clone)]
+pub struct NatsConfig {
+    pub url: String,
+    /// Prefix for every subject the bridge uses.
+    ///
+    /// Events published to `<prefix>.events.<topic>` are delivered to sessions subscribed to `<topic>`,
+    /// and client messages are forwarded to `<prefix>.client.<message type>`.
+    pub subject_prefix: String,
+}
+
+impl NatsConfig {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            subject_prefix: "gateway".to_owned(),
+        }
+    }
+}
+
+/// A client message as forwarded to NATS.
+#[derive(Debug, Serialize)]
+struct ClientEvent<'a> {
+    session: Uuid,
+    address: &'a str,
+    message: &'a WebSocketMessage,
+}
+
+/// Bidirectional bridge between subscription topics and NATS subjects.
+pub struct NatsBridge {
+    client: async_nats::Client,
+    subject_prefix: String,
+}
+
+impl NatsBridge {
+    pub(crate) async fn connect(config: &NatsConfig) -> anyhow::Result<Self> {
+        let client = async_nats::connect(config.url.as_str()).await?;
+
+        Ok(Self {
+            client,
+            subject_prefix: config.subject_prefix.clone(),
+        })
+    }
+
+    /// Deliver events published by backend services to subscribed sessions.
+    pub(crate) async fn subscribe(&self, server: WebSocketServer) -> anyhow::Result<()> {
+        let prefix = format!("{}.events.", self.subject_prefix);
+        let mut subscriber = self.client.subscribe(format!("{prefix}>")).await?;
+
+        tracing::info!("Listening for NATS events on {prefix}>");
+
+        while let Some(msg) = subscriber.next().await {
+            let Some(topic) = msg
+                .subject
+                .strip_prefix(&prefix)
+                .and_then(|x| x.parse::<WebSocketSubscriptionType>().ok())
+            else {
+                tracing::warn!("Ignoring NATS event on unknown subject {}", msg.subject);
+                continue;
+            };
+
+            match String::from_utf8(msg.payload.to_vec()) {
+                Ok(payload) => server.publish_local(topic, payload).await,
+                Err(e) => tracing::warn!("Ignoring non UTF-8 NATS event: {e}"),
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) async fn forward(
+        &self,
+        session: Uuid,
+        address: &str,
+        message: &WebSocketMessage,
+    ) -> anyhow::Result<()> {
+        let subject = format!("{}.client.{}", self.subject_prefix, message.r#type.kind());
+        let event = ClientEvent {
+            session,
+            address,
+            message,
+        };
+        let payload = serde_json::to_vec(&event)?;
+
+        self.client.publish(subject, payload.into()).await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,73 @@
+//! One shape for every list clients page through, whether over HTTP or the gateway, so paging
+//! works the same everywhere.
+
+use serde::{Deserialize, Serialize};
+
+/// Items in a page when the client doesn't ask for a size.
+pub const DEFAULT_PAGE_SIZE: usize = 50;
+/// Most items in a page, however many the client asks for.
+pub const MAX_PAGE_SIZE: usize = 1000;
+
+/// The size of a page asked for with `limit`, capped at [`MAX_PAGE_SIZE`].
+pub fn page_size(limit: Option<usize>) -> usize {
+    limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE)
+}
+
+/// Which page of a list to return, as taken in HTTP query strings.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PageQuery {
+    pub limit: Option<usize>,
+    #[serde(default)]
+    pub offset: usize,
+}
+
+/// Some of a list's items, starting `offset` items in.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Paginated<T> {
+    /// How many items there are, across every page.
+    pub total: usize,
+    /// How many items are in this page.
+    pub count: usize,
+    pub offset: usize,
+    pub items: Vec<T>,
+}
+
+impl<T> Paginated<T> {
+    pub fn new(total: usize, offset: usize, items: Vec<T>) -> Self {
+        Self {
+            total,
+            count: items.len(),
+            offset,
+            items,
+        }
+    }
+
+    /// Page through a whole list.
+    pub fn from_items(items: impl IntoIterator<Item = T>, query: &PageQuery) -> Self {
+        let mut total = 0;
+        let mut page = Vec::new();
+        let size = page_size(query.limit);
+        for item in items {
+            if total >= query.offset && page.len() < size {
+                page.push(item);
+            }
+            total += 1;
+        }
+
+        Self::new(total, query.offset, page)
+    }
+
+    /// Convert each of the page's items, keeping its place in the list.
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Paginated<U> {
+        Paginated::new(
+            self.total,
+            self.offset,
+            self.items.into_iter().map(f).collect(),
+        )
+    }
+
+    /// Whether there are more items after this page.
+    pub fn has_more(&self) -> bool {
+        self.offset.saturating_add(self.count) < self.total
+    }
+}
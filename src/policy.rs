@@ -0,0 +1,176 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::websocket::{
+    WebSocketSessionData, WebSocketSubscriptionType, messages::WebSocketMessageInner,
+};
+
+/// What a gateway token allows its session to do, so narrower tokens can be handed to third parties.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    /// Reading state and subscribing to events.
+    ReadOnly,
+    /// Making transactions, on top of everything read-only allows.
+    Transact,
+    /// Everything, including message types the policy reserves for admins.
+    Admin,
+}
+
+pub type ScopeList = BTreeSet<Scope>;
+
+impl Scope {
+    /// Scopes granted to tokens that don't ask for any.
+    pub fn default_scopes() -> ScopeList {
+        BTreeSet::from([Scope::ReadOnly, Scope::Transact])
+    }
+
+    /// The scope a message needs unless the policy says otherwise.
+    pub fn required_by(message: &WebSocketMessageInner) -> Self {
+        match message.is_state_changing() {
+            true => Self::Transact,
+            false => Self::ReadOnly,
+        }
+    }
+
+    /// Whether holding this scope is enough for something requiring `required`.
+    pub fn covers(self, required: Scope) -> bool {
+        self == Self::Admin || self == required || required == Self::ReadOnly
+    }
+}
+
+impl std::fmt::Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ReadOnly => write!(f, "read_only"),
+            Self::Transact => write!(f, "transact"),
+            Self::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+/// Requirements a session must meet to send a particular message type.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PolicyRule {
+    /// Reject the message type outright.
+    pub disabled: bool,
+    /// Only allow sessions logged in with a private key, not guests.
+    pub authenticated: bool,
+    /// Only allow addresses holding at least one of these roles, if any are listed.
+    pub roles: HashSet<String>,
+    /// Only allow sessions subscribed to this topic.
+    pub subscription: Option<WebSocketSubscriptionType>,
+    /// Require this scope instead of the one the message type needs by default.
+    pub scope: Option<Scope>,
+}
+
+/// Rules checked before a client message is handled.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PolicyConfig {
+    /// Rules keyed by the message type, as found in its `type` field. Unlisted types are allowed.
+    pub rules: HashMap<String, PolicyRule>,
+    /// Roles granted to each address.
+    pub roles: HashMap<String, HashSet<String>>,
+}
+
+/// Why a message was refused by the policy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PolicyDenied {
+    Disabled,
+    Unauthenticated,
+    MissingRole,
+    MissingSubscription(WebSocketSubscriptionType),
+    MissingScope(Scope),
+}
+
+impl PolicyDenied {
+    /// Machine-readable error code sent back to the client.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Disabled => "message_disabled",
+            Self::Unauthenticated => "auth_required",
+            Self::MissingRole => "missing_role",
+            Self::MissingSubscription(_) => "missing_subscription",
+            Self::MissingScope(_) => "missing_scope",
+        }
+    }
+}
+
+impl std::fmt::Display for PolicyDenied {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Disabled => write!(f, "This message type is disabled"),
+            Self::Unauthenticated => write!(f, "You must be logged in to send this message"),
+            Self::MissingRole => write!(f, "You are not allowed to send this message"),
+            Self::MissingSubscription(topic) => {
+                write!(f, "You must be subscribed to {topic} to send this message")
+            }
+            Self::MissingScope(scope) => {
+                write!(f, "Your token needs the {scope} scope to send this message")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PolicyDenied {}
+
+impl PolicyConfig {
+    /// Check whether `session` may send `message`.
+    pub fn check(
+        &self,
+        message: &WebSocketMessageInner,
+        session: &WebSocketSessionData,
+    ) -> Result<(), PolicyDenied> {
+        self.check_kind(message.kind(), Scope::required_by(message), session)
+    }
+
+    /// Check whether `session` may send a message of type `kind`, which needs `scope` unless a
+    /// rule says otherwise.
+    pub fn check_kind(
+        &self,
+        kind: &str,
+        scope: Scope,
+        session: &WebSocketSessionData,
+    ) -> Result<(), PolicyDenied> {
+        let rule = self.rules.get(kind);
+
+        let required = rule.and_then(|rule| rule.scope).unwrap_or(scope);
+        if !session.scopes.iter().any(|scope| scope.covers(required)) {
+            return Err(PolicyDenied::MissingScope(required));
+        }
+
+        let Some(rule) = rule else {
+            return Ok(());
+        };
+
+        if rule.disabled {
+            return Err(PolicyDenied::Disabled);
+        }
+
+        if rule.authenticated && session.private_key.is_none() {
+            return Err(PolicyDenied::Unauthenticated);
+        }
+
+        if !rule.roles.is_empty() {
+            let granted = self
+                .roles
+                .get(&session.address)
+                .is_some_and(|roles| !roles.is_disjoint(&rule.roles));
+
+            if !granted {
+                return Err(PolicyDenied::MissingRole);
+            }
+        }
+
+        if let Some(topic) = &rule.subscription
+            && !session.subscriptions.contains(topic)
+        {
+            return Err(PolicyDenied::MissingSubscription(topic.clone()));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,177 @@
+use std::{hash::Hash, sync::Mutex, time::Duration};
+
+use actix_web::{
+    Error, HttpResponse,
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{self, HeaderMap, HeaderName, HeaderValue},
+    middleware::Next,
+    web,
+};
+use dashmap::DashMap;
+use tokio::time::Instant;
+
+use crate::ws::WebSocketServer;
+
+const WINDOW: Duration = Duration::from_secs(60);
+const PURGE_THRESHOLD: usize = 10_000;
+
+/// Where a request stands against its limit, as reported in `RateLimit-*` headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitStatus {
+    pub allowed: bool,
+    pub limit: u32,
+    /// Requests left in the current window.
+    pub remaining: u32,
+    /// Time until the current window ends.
+    pub reset: Duration,
+}
+
+impl RateLimitStatus {
+    /// Set the `RateLimit-Limit`, `RateLimit-Remaining` and `RateLimit-Reset` headers, plus
+    /// `Retry-After` if the request was turned away.
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        // Round up, so clients waiting out the reset don't come back a moment too early
+        let reset = self.reset.as_secs() + u64::from(self.reset.subsec_nanos() > 0);
+
+        headers.insert(
+            HeaderName::from_static("ratelimit-limit"),
+            HeaderValue::from(self.limit),
+        );
+        headers.insert(
+            HeaderName::from_static("ratelimit-remaining"),
+            HeaderValue::from(self.remaining),
+        );
+        headers.insert(
+            HeaderName::from_static("ratelimit-reset"),
+            HeaderValue::from(reset),
+        );
+        if !self.allowed {
+            headers.insert(header::RETRY_AFTER, HeaderValue::from(reset));
+        }
+    }
+}
+
+/// Fixed-window request counter, keyed by e.g. client IP.
+#[derive(Debug)]
+pub struct RateLimiter<K: Eq + Hash> {
+    windows: DashMap<K, (Instant, u32)>,
+    /// When expired windows were last purged. Purges are at most a window apart, so a flood of
+    /// new keys can't make every request pay for a scan of the whole map.
+    last_purge: Mutex<Instant>,
+}
+
+impl<K: Eq + Hash> Default for RateLimiter<K> {
+    fn default() -> Self {
+        Self {
+            windows: DashMap::new(),
+            last_purge: Mutex::new(Instant::now()),
+        }
+    }
+}
+
+impl<K: Eq + Hash> RateLimiter<K> {
+    /// Count a request from `key`, returning whether it is within `limit` requests per minute.
+    pub fn check(&self, key: K, limit: u32) -> bool {
+        self.acquire(key, limit).allowed
+    }
+
+    /// Count a request from `key` against `limit` requests per minute.
+    pub fn acquire(&self, key: K, limit: u32) -> RateLimitStatus {
+        let now = Instant::now();
+        if self.windows.len() >= PURGE_THRESHOLD {
+            self.purge_if_due(now);
+        }
+
+        let mut window = self.windows.entry(key).or_insert((now, 0));
+
+        if now.duration_since(window.0) >= WINDOW {
+            *window = (now, 0);
+        }
+
+        window.1 = window.1.saturating_add(1);
+        RateLimitStatus {
+            allowed: window.1 <= limit,
+            limit,
+            remaining: limit.saturating_sub(window.1),
+            reset: WINDOW.saturating_sub(now.duration_since(window.0)),
+        }
+    }
+
+    /// Purge expired windows unless that was already done within the last window, or another
+    /// request is doing it right now.
+    fn purge_if_due(&self, now: Instant) {
+        let Ok(mut last_purge) = self.last_purge.try_lock() else {
+            return;
+        };
+        if now.duration_since(*last_purge) < WINDOW {
+            return;
+        }
+        *last_purge = now;
+        drop(last_purge);
+
+        self.purge_expired();
+    }
+
+    /// Forget windows that have expired, so the map doesn't grow without bound.
+    pub fn purge_expired(&self) {
+        let now = Instant::now();
+        self.windows
+            .retain(|_, (started, _)| now.duration_since(*started) < WINDOW);
+    }
+}
+
+/// Limit every HTTP request to `RuntimeConfig::http_rate_limit` a minute, counted per API key, or
+/// per client IP for requests without one.
+///
+/// Only the API keys of configured namespaces get an allowance of their own. Anything else is
+/// counted against the client's IP, so making up a new key for every request gets a client nowhere.
+///
+/// WebSocket upgrades are let through, as they already need a token from the rate limited
+/// `/ws/start`.
+pub async fn middleware(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let server = req.app_data::<web::Data<WebSocketServer>>().cloned();
+    let limit = server
+        .as_ref()
+        .and_then(|x| x.runtime_config().http_rate_limit);
+    let (Some(server), Some(limit)) = (server, limit) else {
+        return Ok(next.call(req).await?.map_into_left_body());
+    };
+    if req.headers().contains_key(header::UPGRADE) {
+        return Ok(next.call(req).await?.map_into_left_body());
+    }
+
+    let key = match api_key(req.headers()).filter(|x| server.is_api_key(x)) {
+        Some(key) => format!("key:{key}"),
+        None => match server.client_ip(req.request()) {
+            Some(ip) => format!("ip:{ip}"),
+            None => return Ok(next.call(req).await?.map_into_left_body()),
+        },
+    };
+
+    let status = server.http_limiter().acquire(key, limit);
+    if !status.allowed {
+        let mut response = HttpResponse::TooManyRequests().body("Too many requests");
+        status.apply(response.headers_mut());
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+
+    let mut response = next.call(req).await?;
+    status.apply(response.headers_mut());
+    Ok(response.map_into_left_body())
+}
+
+/// The key a request identifies itself with, from `X-API-Key` or a bearer token.
+fn api_key(headers: &HeaderMap) -> Option<&str> {
+    if let Some(key) = headers.get("x-api-key") {
+        return key.to_str().ok();
+    }
+
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.strip_prefix("Bearer "))
+}
@@ -0,0 +1,222 @@
+//! Recordings of the frames sessions send, so a session a client reported a bug in can be played
+//! back through the handlers offline.
+//!
+//! A recording is a JSON lines file: a [`RecordingHeader`] describing the session, then one
+//! [`InboundFrame`] per frame it sent, in order.
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::models::websocket::{WebSocketTokenData, state::SessionState};
+use crate::telemetry;
+use crate::ws::{
+    self, WebSocketServer,
+    sink::{RecordedFrame, RecordingSink},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordingHeader {
+    pub session: Uuid,
+    pub address: String,
+    /// Whether the session had a private key, which itself is never recorded.
+    pub authenticated: bool,
+    /// When the session started, in milliseconds since the Unix epoch.
+    pub started: u64,
+}
+
+impl RecordingHeader {
+    pub fn new(session: Uuid, address: impl Into<String>, authenticated: bool) -> Self {
+        Self {
+            session,
+            address: address.into(),
+            authenticated,
+            started: now_millis(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Frame {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InboundFrame {
+    /// Milliseconds since the session started.
+    pub at_ms: u64,
+    #[serde(flatten)]
+    pub frame: Frame,
+}
+
+/// Where the recording of `session` is written under `dir`.
+pub fn recording_path(dir: &Path, session: Uuid) -> PathBuf {
+    dir.join(format!("{session}.jsonl"))
+}
+
+/// Appends a session's frames to its recording as they arrive.
+pub struct SessionRecorder {
+    file: File,
+    started: Instant,
+}
+
+impl SessionRecorder {
+    /// Start a recording for the session `header` describes under `dir`, replacing any earlier
+    /// one of the same session.
+    pub fn create(dir: &Path, header: &RecordingHeader) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let path = recording_path(dir, header.session);
+        let mut file = File::create(&path)
+            .with_context(|| format!("Failed to create recording {}", path.display()))?;
+        write_line(&mut file, header)?;
+
+        Ok(Self {
+            file,
+            started: Instant::now(),
+        })
+    }
+
+    /// Append a frame, with private keys in text frames [redacted](telemetry::redact).
+    pub fn record(&mut self, frame: Frame) -> anyhow::Result<()> {
+        let frame = match frame {
+            Frame::Text(text) => Frame::Text(telemetry::redact(&text).into_owned()),
+            binary => binary,
+        };
+        let frame = InboundFrame {
+            at_ms: self.started.elapsed().as_millis() as u64,
+            frame,
+        };
+        write_line(&mut self.file, &frame)
+    }
+}
+
+/// A recording read back from disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recording {
+    pub header: RecordingHeader,
+    pub frames: Vec<InboundFrame>,
+}
+
+impl Recording {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open recording {}", path.display()))?;
+        let mut lines = BufReader::new(file).lines();
+
+        let header = lines.next().context("Recording is empty")??;
+        let header = serde_json::from_str(&header).context("Invalid recording header")?;
+        let frames = lines
+            .enumerate()
+            .map(|(i, line)| {
+                serde_json::from_str(&line?)
+                    .with_context(|| format!("Invalid frame on line {}", i + 2))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self { header, frames })
+    }
+}
+
+/// How quickly a recording is played back.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Pacing {
+    /// Each frame straight after the last was handled.
+    #[default]
+    Immediate,
+    /// With the same gaps between frames as when they were recorded.
+    Recorded,
+}
+
+/// Feed a recording's frames through `server`'s handlers as if its session had sent them again,
+/// returning every frame written back, in order.
+pub async fn replay(
+    server: &WebSocketServer,
+    recording: &Recording,
+    pacing: Pacing,
+) -> Vec<RecordedFrame> {
+    let RecordingHeader {
+        session,
+        address,
+        authenticated,
+        ..
+    } = &recording.header;
+    let sink = RecordingSink::new();
+    let inserted = server
+        .insert_session(
+            *session,
+            sink.clone(),
+            None,
+            WebSocketTokenData::new(address.clone(), None),
+            CancellationToken::new(),
+            std::convert::identity,
+        )
+        .await;
+    let Some(outbound) = server.outbound(session).await else {
+        return sink.frames();
+    };
+
+    let state = match authenticated {
+        true => SessionState::Authenticated,
+        false => SessionState::Ready,
+    };
+    server.transition(session, state).await;
+    // Same as a live session: guests' retries are told apart by session
+    let idempotency_scope = match authenticated {
+        true => address.clone(),
+        false => session.to_string(),
+    };
+
+    let started = Instant::now();
+    for InboundFrame { at_ms, frame } in &recording.frames {
+        if pacing == Pacing::Recorded {
+            tokio::time::sleep_until(started + Duration::from_millis(*at_ms)).await;
+        }
+
+        match frame {
+            Frame::Text(text) => {
+                ws::handle_text(
+                    server,
+                    &outbound,
+                    *session,
+                    address,
+                    &idempotency_scope,
+                    text,
+                )
+                .await
+            }
+            Frame::Binary(bytes) => {
+                ws::handle_binary(server, &outbound, *session, address, bytes.clone().into()).await
+            }
+        }
+    }
+
+    let _ = outbound.flush().await;
+    inserted.guard.cleanup().await;
+    sink.frames()
+}
+
+fn write_line(file: &mut File, value: &impl Serialize) -> anyhow::Result<()> {
+    let mut line = serde_json::to_vec(value)?;
+    line.push(b'\n');
+    file.write_all(&line)?;
+    Ok(())
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_millis() as u64)
+        .unwrap_or_default()
+}
@@ -0,0 +1,59 @@
+use std::{collections::HashMap, io::Write, path::Path};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::filter::EventFilter;
+use crate::models::secret::Secret;
+use crate::models::websocket::{WebSocketSubscriptionList, WebSocketSubscriptionType};
+use crate::policy::{Scope, ScopeList};
+
+/// Everything needed to restore a session after the server restarts.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResumeState {
+    pub address: String,
+    pub private_key: Option<Secret>,
+    pub subscriptions: WebSocketSubscriptionList,
+    #[serde(default)]
+    pub filters: HashMap<WebSocketSubscriptionType, EventFilter>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default = "Scope::default_scopes")]
+    pub scopes: ScopeList,
+    /// Latest journaled event the session had been sent, replayed from on resume.
+    pub last_seq: Option<u64>,
+}
+
+/// Write the sessions being handed over to the next process, keyed by resume token.
+pub fn save(path: &Path, sessions: &HashMap<Uuid, ResumeState>) -> anyhow::Result<()> {
+    // Write then rename, so the next process never sees a half-written file
+    let tmp = path.with_extension("tmp");
+    let _ = std::fs::remove_file(&tmp);
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    // Sessions carry private keys, so keep the file to ourselves
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let mut file = options.open(&tmp)?;
+    file.write_all(&serde_json::to_vec(sessions)?)?;
+    file.sync_all()?;
+    std::fs::rename(tmp, path)?;
+
+    Ok(())
+}
+
+/// Take over the sessions handed over by the previous process, if it left any.
+pub fn load(path: &Path) -> anyhow::Result<HashMap<Uuid, ResumeState>> {
+    let contents = match std::fs::read(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    // Resume tokens are single use, so don't let a later restart pick them up again
+    std::fs::remove_file(path)?;
+
+    Ok(serde_json::from_slice(&contents)?)
+}
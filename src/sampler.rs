@@ -0,0 +1,84 @@
+//! Samples of the messages clients send, by type and size, for capacity planning and tuning the
+//! protocol.
+
+use std::{collections::BTreeMap, sync::Mutex};
+
+use serde::{Deserialize, Serialize};
+
+/// Upper bounds of the size buckets messages are sorted into, in bytes. Anything bigger goes in
+/// a last, unbounded bucket.
+pub const SIZE_BUCKETS: &[usize] = &[64, 256, 1024, 4096, 16 * 1024, 64 * 1024, 256 * 1024];
+
+/// How the sampled messages of one type were distributed.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MessageTypeSummary {
+    pub count: u64,
+    pub total_bytes: u64,
+    pub max_bytes: usize,
+    /// Counts per bucket of [`SIZE_BUCKETS`], plus one for bigger messages.
+    pub sizes: Vec<u64>,
+}
+
+impl MessageTypeSummary {
+    fn record(&mut self, size: usize) {
+        if self.sizes.is_empty() {
+            self.sizes = vec![0; SIZE_BUCKETS.len() + 1];
+        }
+
+        let bucket = SIZE_BUCKETS.partition_point(|&max| max < size);
+        self.sizes[bucket] += 1;
+        self.count += 1;
+        self.total_bytes += size as u64;
+        self.max_bytes = self.max_bytes.max(size);
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MessageSummary {
+    /// Fraction of messages sampled.
+    pub rate: f64,
+    pub size_buckets: Vec<usize>,
+    /// By message type, with `invalid` for messages that couldn't be parsed and `binary` for
+    /// binary frames.
+    pub types: BTreeMap<String, MessageTypeSummary>,
+}
+
+/// Records the type and size of a random fraction of inbound messages.
+#[derive(Debug)]
+pub struct MessageSampler {
+    rate: f64,
+    types: Mutex<BTreeMap<String, MessageTypeSummary>>,
+}
+
+impl MessageSampler {
+    /// Sample `rate` of messages, between 0 and 1.
+    pub fn new(rate: f64) -> Self {
+        Self {
+            rate: rate.clamp(0.0, 1.0),
+            types: Mutex::default(),
+        }
+    }
+
+    /// Maybe record a message of `kind` that was `size` bytes long, returning whether it was.
+    pub fn sample(&self, kind: &str, size: usize) -> bool {
+        if !rand::random_bool(self.rate) {
+            return false;
+        }
+
+        let mut types = self.types.lock().unwrap_or_else(|e| e.into_inner());
+        match types.get_mut(kind) {
+            Some(summary) => summary.record(size),
+            None => types.entry(kind.to_owned()).or_default().record(size),
+        }
+        true
+    }
+
+    pub fn summary(&self) -> MessageSummary {
+        let types = self.types.lock().unwrap_or_else(|e| e.into_inner());
+        MessageSummary {
+            rate: self.rate,
+            size_buckets: SIZE_BUCKETS.to_vec(),
+            types: types.clone(),
+        }
+    }
+}
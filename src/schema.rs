@@ -0,0 +1,383 @@
+//! The JSON shape of each event broadcast to subscribers, worked out from what serde actually
+//! emits, so changes that would break existing subscribers are caught before they ship.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::alarms::ChurnKind;
+#[cfg(feature = "krist")]
+use crate::balances::{BalanceChange, BalanceChangeReason};
+use crate::events::GatewayEvent;
+use crate::models::websocket::WebSocketSubscriptionType;
+#[cfg(feature = "krist")]
+use crate::names::NameRecord;
+
+/// The shape of a JSON value.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum Schema {
+    /// Anything at all, e.g. a value whose samples disagree on what it is.
+    Any,
+    Null,
+    Bool,
+    Integer,
+    Number,
+    String,
+    Array {
+        items: Box<Schema>,
+    },
+    Object {
+        /// Fields that are always there.
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        required: BTreeMap<String, Schema>,
+        /// Fields that are only sometimes there.
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        optional: BTreeMap<String, Schema>,
+    },
+    /// Either null or `of`.
+    Nullable {
+        of: Box<Schema>,
+    },
+}
+
+impl Schema {
+    /// The shape of `value`, taking every field it has as required.
+    pub fn of(value: &Value) -> Self {
+        match value {
+            Value::Null => Self::Null,
+            Value::Bool(_) => Self::Bool,
+            Value::Number(x) if x.is_f64() => Self::Number,
+            Value::Number(_) => Self::Integer,
+            Value::String(_) => Self::String,
+            Value::Array(items) => Self::Array {
+                items: Box::new(Self::of_samples(items).unwrap_or(Self::Any)),
+            },
+            Value::Object(fields) => Self::Object {
+                required: fields
+                    .iter()
+                    .map(|(name, x)| (name.clone(), Self::of(x)))
+                    .collect(),
+                optional: BTreeMap::new(),
+            },
+        }
+    }
+
+    /// The narrowest shape every one of `samples` has, or `None` without any.
+    pub fn of_samples<'a>(samples: impl IntoIterator<Item = &'a Value>) -> Option<Self> {
+        samples.into_iter().map(Self::of).reduce(Self::merge)
+    }
+
+    /// The narrowest shape both `self` and `other` fit.
+    pub fn merge(self, other: Self) -> Self {
+        match (self, other) {
+            (a, b) if a == b => a,
+            (Self::Any, _) | (_, Self::Any) => Self::Any,
+            (Self::Null, x) | (x, Self::Null) => x.nullable(),
+            (Self::Nullable { of }, x) | (x, Self::Nullable { of }) => of.merge(x).nullable(),
+            (Self::Integer, Self::Number) | (Self::Number, Self::Integer) => Self::Number,
+            (Self::Array { items: a }, Self::Array { items: b }) => Self::Array {
+                items: Box::new(a.merge(*b)),
+            },
+            (
+                Self::Object {
+                    required: a_required,
+                    optional: a_optional,
+                },
+                Self::Object {
+                    required: b_required,
+                    optional: b_optional,
+                },
+            ) => {
+                let mut b = fields(b_required, b_optional);
+                let mut required = BTreeMap::new();
+                let mut optional = BTreeMap::new();
+
+                for (name, (a, a_always)) in fields(a_required, a_optional) {
+                    match b.remove(&name) {
+                        Some((b, b_always)) if a_always && b_always => {
+                            required.insert(name, a.merge(b));
+                        }
+                        Some((b, _)) => {
+                            optional.insert(name, a.merge(b));
+                        }
+                        None => {
+                            optional.insert(name, a);
+                        }
+                    }
+                }
+                optional.extend(b.into_iter().map(|(name, (x, _))| (name, x)));
+
+                Self::Object { required, optional }
+            }
+            _ => Self::Any,
+        }
+    }
+
+    /// Ways a reader expecting values shaped like `self` would be thrown by ones shaped like
+    /// `new`. New fields are fine, but removed fields or changed types aren't.
+    pub fn breaking_changes(&self, new: &Schema) -> Vec<String> {
+        let mut changes = Vec::new();
+        self.diff(new, "$", &mut changes);
+        changes
+    }
+
+    fn diff(&self, new: &Schema, path: &str, changes: &mut Vec<String>) {
+        match (self, new) {
+            (old, new) if old == new => {}
+            (Self::Any, _)
+            | (Self::Number, Self::Integer)
+            | (Self::Nullable { .. }, Self::Null) => {}
+            (Self::Nullable { of }, Self::Nullable { of: new }) => of.diff(new, path, changes),
+            (Self::Nullable { of }, new) => of.diff(new, path, changes),
+            (old, Self::Nullable { of }) if *old != Self::Null => {
+                changes.push(format!("{path} may now be null"));
+                old.diff(of, path, changes);
+            }
+            (Self::Array { items }, Self::Array { items: new }) => {
+                items.diff(new, &format!("{path}[]"), changes)
+            }
+            (
+                Self::Object { required, optional },
+                Self::Object {
+                    required: new_required,
+                    optional: new_optional,
+                },
+            ) => {
+                for (name, old) in required {
+                    let path = format!("{path}.{name}");
+                    match (new_required.get(name), new_optional.get(name)) {
+                        (Some(new), _) => old.diff(new, &path, changes),
+                        (None, Some(new)) => {
+                            changes.push(format!("{path} may now be missing"));
+                            old.diff(new, &path, changes);
+                        }
+                        (None, None) => changes.push(format!("{path} was removed")),
+                    }
+                }
+                // Readers can't have relied on optional fields being there
+                for (name, old) in optional {
+                    if let Some(new) = new_required.get(name).or(new_optional.get(name)) {
+                        old.diff(new, &format!("{path}.{name}"), changes);
+                    }
+                }
+            }
+            (old, new) => changes.push(format!(
+                "{path} changed from {} to {}",
+                old.describe(),
+                new.describe()
+            )),
+        }
+    }
+
+    fn nullable(self) -> Self {
+        match self {
+            Self::Any | Self::Null | Self::Nullable { .. } => self,
+            x => Self::Nullable { of: Box::new(x) },
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Self::Any => "anything".to_owned(),
+            Self::Null => "null".to_owned(),
+            Self::Bool => "a bool".to_owned(),
+            Self::Integer => "an integer".to_owned(),
+            Self::Number => "a number".to_owned(),
+            Self::String => "a string".to_owned(),
+            Self::Array { .. } => "an array".to_owned(),
+            Self::Object { .. } => "an object".to_owned(),
+            Self::Nullable { of } => format!("{} or null", of.describe()),
+        }
+    }
+}
+
+/// Each of an object's fields, and whether it's always there.
+fn fields(
+    required: BTreeMap<String, Schema>,
+    optional: BTreeMap<String, Schema>,
+) -> BTreeMap<String, (Schema, bool)> {
+    let required = required.into_iter().map(|(name, x)| (name, (x, true)));
+    let optional = optional.into_iter().map(|(name, x)| (name, (x, false)));
+    required.chain(optional).collect()
+}
+
+/// An event broadcast to subscribers, and its shape as of its current version.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventSchema {
+    pub event: String,
+    /// Bumped whenever the event changes in a way that breaks its subscribers.
+    pub version: u32,
+    pub schema: Schema,
+}
+
+impl EventSchema {
+    /// The schema of the event `samples` are instances of, named after their `event` field.
+    ///
+    /// Samples should between them have each field both with and without every optional part,
+    /// e.g. `Some` and `None`, so the schema says which parts may be missing or null.
+    fn new(version: u32, samples: impl IntoIterator<Item = impl Serialize>) -> Self {
+        let samples: Vec<Value> = samples
+            .into_iter()
+            .map(|x| serde_json::to_value(x).expect("Failed to serialize sample event"))
+            .collect();
+        let event = samples
+            .first()
+            .and_then(|x| x["event"].as_str())
+            .expect("Sample events need an event field")
+            .to_owned();
+
+        Self {
+            event,
+            version,
+            schema: Schema::of_samples(&samples).expect("Events need samples"),
+        }
+    }
+
+    /// Whether subscribers written against `previous` can still read this version of the event,
+    /// or the version was bumped to say they can't.
+    pub fn check_compatible(&self, previous: &EventSchema) -> anyhow::Result<()> {
+        if self.version < previous.version {
+            anyhow::bail!(
+                "{} went back from version {} to {}",
+                self.event,
+                previous.version,
+                self.version
+            );
+        }
+
+        let changes = previous.schema.breaking_changes(&self.schema);
+        if self.version == previous.version && !changes.is_empty() {
+            anyhow::bail!(
+                "{} changed in ways that break its subscribers without a version bump: {}",
+                self.event,
+                changes.join(", ")
+            );
+        }
+
+        Ok(())
+    }
+}
+
+const SAMPLE_ADDRESS: &str = "k0000000000";
+
+/// Every event broadcast to subscribers, whether gateway events for webhooks and other external
+/// systems or events sent to WebSocket sessions.
+pub fn registry() -> Vec<EventSchema> {
+    let session = Uuid::nil();
+    let address = || SAMPLE_ADDRESS.to_owned();
+    let topic = WebSocketSubscriptionType::Blocks;
+
+    let events = vec![
+        EventSchema::new(
+            1,
+            [GatewayEvent::SessionConnected {
+                session,
+                address: address(),
+            }],
+        ),
+        EventSchema::new(
+            1,
+            [GatewayEvent::SessionDisconnected {
+                session,
+                address: address(),
+            }],
+        ),
+        EventSchema::new(
+            1,
+            [GatewayEvent::Login {
+                session,
+                address: address(),
+            }],
+        ),
+        EventSchema::new(
+            1,
+            [GatewayEvent::SubscriptionChanged {
+                session,
+                topic: topic.clone(),
+                subscribed: true,
+            }],
+        ),
+        EventSchema::new(
+            1,
+            [
+                GatewayEvent::BroadcastFailed {
+                    topic: Some(topic.clone()),
+                    reason: String::new(),
+                },
+                GatewayEvent::BroadcastFailed {
+                    topic: None,
+                    reason: String::new(),
+                },
+            ],
+        ),
+        EventSchema::new(
+            1,
+            [GatewayEvent::MessageFailed {
+                session,
+                message_type: String::new(),
+                error: String::new(),
+            }],
+        ),
+        EventSchema::new(
+            1,
+            [
+                GatewayEvent::EventDropped {
+                    session,
+                    topic: Some(topic.clone()),
+                },
+                GatewayEvent::EventDropped {
+                    session,
+                    topic: None,
+                },
+            ],
+        ),
+        EventSchema::new(
+            1,
+            [GatewayEvent::ChurnAlarm {
+                kind: ChurnKind::Connects,
+                threshold: 0,
+            }],
+        ),
+    ];
+    #[cfg(feature = "krist")]
+    let events = [events, krist_events()].concat();
+
+    events
+}
+
+#[cfg(feature = "krist")]
+fn krist_events() -> Vec<EventSchema> {
+    let address = || SAMPLE_ADDRESS.to_owned();
+
+    vec![
+        // Transactions and blocks are passed through as given, so only their envelope is fixed
+        EventSchema::new(
+            1,
+            [GatewayEvent::Transaction {
+                transaction: serde_json::json!({}),
+            }],
+        ),
+        EventSchema::new(
+            1,
+            [GatewayEvent::Block {
+                block: serde_json::json!({}),
+            }],
+        ),
+        EventSchema::new(
+            1,
+            [BalanceChange::new(address(), 0, 1, BalanceChangeReason::Reward).event()],
+        ),
+        EventSchema::new(1, {
+            let mut updated = NameRecord::new("example", address());
+            updated.set_a(Some(String::new()));
+            updated.seized = true;
+            [
+                NameRecord::new("example", address()).event(),
+                updated.event(),
+            ]
+        }),
+    ]
+}
@@ -0,0 +1,242 @@
+//! Write-behind persistence of lightweight session records.
+//!
+//! Changes are queued as sessions come, go and subscribe, and written out in batches off the hot
+//! path. Whatever is still in the store when the server starts belonged to sessions the previous
+//! process lost without shutting down cleanly.
+
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot};
+use uuid::Uuid;
+
+use crate::models::websocket::{WebSocketSessionData, WebSocketSubscriptionList};
+use crate::policy::{Scope, ScopeList};
+
+/// How often queued changes are written to the store.
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What's kept about a session outside of memory.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionRecord {
+    pub uuid: Uuid,
+    pub address: String,
+    /// Milliseconds since the Unix epoch.
+    pub connected_at: u64,
+    pub subscriptions: WebSocketSubscriptionList,
+    /// Lets the client pick its session back up after a crash.
+    pub resume_token: Uuid,
+    #[serde(default = "Scope::default_scopes")]
+    pub scopes: ScopeList,
+}
+
+impl SessionRecord {
+    pub fn new(uuid: Uuid, data: &WebSocketSessionData) -> Self {
+        let connected_at = data
+            .connected_at
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_millis() as u64)
+            .unwrap_or_default();
+
+        Self {
+            uuid,
+            address: data.address.clone(),
+            connected_at,
+            subscriptions: data.subscriptions.clone(),
+            resume_token: data.resume_token,
+            scopes: data.scopes.clone(),
+        }
+    }
+}
+
+/// A session the previous process lost track of, as shown through the admin API.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LostSession {
+    pub uuid: Uuid,
+    pub address: String,
+    #[serde(rename = "connectedAt")]
+    pub connected_at: u64,
+    pub subscriptions: WebSocketSubscriptionList,
+}
+
+impl From<&SessionRecord> for LostSession {
+    fn from(record: &SessionRecord) -> Self {
+        Self {
+            uuid: record.uuid,
+            address: record.address.clone(),
+            connected_at: record.connected_at,
+            subscriptions: record.subscriptions.clone(),
+        }
+    }
+}
+
+/// Somewhere to keep session records across restarts.
+pub trait SessionStore: Send + Sync {
+    /// Apply a batch of changes: records to insert or replace, and sessions to forget.
+    fn write(&self, saved: &[SessionRecord], removed: &[Uuid]) -> anyhow::Result<()>;
+
+    fn load(&self) -> anyhow::Result<Vec<SessionRecord>>;
+}
+
+/// Keeps records in memory, shared between clones, e.g. to stand in for a real store in tests.
+#[derive(Debug, Default, Clone)]
+pub struct MemorySessionStore {
+    records: Arc<Mutex<HashMap<Uuid, SessionRecord>>>,
+}
+
+impl SessionStore for MemorySessionStore {
+    fn write(&self, saved: &[SessionRecord], removed: &[Uuid]) -> anyhow::Result<()> {
+        let mut records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        for uuid in removed {
+            records.remove(uuid);
+        }
+        for record in saved {
+            records.insert(record.uuid, record.clone());
+        }
+        Ok(())
+    }
+
+    fn load(&self) -> anyhow::Result<Vec<SessionRecord>> {
+        let records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(records.values().cloned().collect())
+    }
+}
+
+/// Keeps records in a sled database on disk.
+pub struct SledSessionStore {
+    tree: sled::Db,
+}
+
+impl SledSessionStore {
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Ok(Self {
+            tree: sled::open(path)?,
+        })
+    }
+}
+
+impl SessionStore for SledSessionStore {
+    fn write(&self, saved: &[SessionRecord], removed: &[Uuid]) -> anyhow::Result<()> {
+        let mut batch = sled::Batch::default();
+        for uuid in removed {
+            batch.remove(uuid.as_bytes());
+        }
+        for record in saved {
+            batch.insert(record.uuid.as_bytes(), serde_json::to_vec(record)?);
+        }
+
+        self.tree.apply_batch(batch)?;
+        self.tree.flush()?;
+        Ok(())
+    }
+
+    fn load(&self) -> anyhow::Result<Vec<SessionRecord>> {
+        let mut records = Vec::new();
+        for entry in self.tree.iter() {
+            let (key, value) = entry?;
+            match serde_json::from_slice(&value) {
+                Ok(record) => records.push(record),
+                Err(e) => tracing::warn!("Skipping unreadable session record {key:?}: {e}"),
+            }
+        }
+        Ok(records)
+    }
+}
+
+enum Change {
+    Save(SessionRecord),
+    Remove(Uuid),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Queues changes for a background task to write to the store in batches.
+///
+/// Only the latest change to each session is written, so busy sessions don't cost a write per change.
+#[derive(Clone)]
+pub struct SessionStoreWriter {
+    tx: mpsc::UnboundedSender<Change>,
+}
+
+impl SessionStoreWriter {
+    pub fn spawn(store: Arc<dyn SessionStore>, interval: Duration) -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(run(store, rx, interval));
+        Self { tx }
+    }
+
+    pub fn save(&self, record: SessionRecord) {
+        let _ = self.tx.send(Change::Save(record));
+    }
+
+    pub fn remove(&self, uuid: Uuid) {
+        let _ = self.tx.send(Change::Remove(uuid));
+    }
+
+    /// Write everything queued so far, waiting until it's in the store.
+    pub async fn flush(&self) {
+        let (tx, rx) = oneshot::channel();
+        if self.tx.send(Change::Flush(tx)).is_ok() {
+            let _ = rx.await;
+        }
+    }
+}
+
+async fn run(
+    store: Arc<dyn SessionStore>,
+    mut rx: mpsc::UnboundedReceiver<Change>,
+    interval: Duration,
+) {
+    let mut pending = HashMap::new();
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            change = rx.recv() => match change {
+                Some(Change::Save(record)) => {
+                    pending.insert(record.uuid, Some(record));
+                }
+                Some(Change::Remove(uuid)) => {
+                    pending.insert(uuid, None);
+                }
+                Some(Change::Flush(done)) => {
+                    write(&store, &mut pending).await;
+                    let _ = done.send(());
+                }
+                None => {
+                    write(&store, &mut pending).await;
+                    return;
+                }
+            },
+            _ = ticker.tick() => write(&store, &mut pending).await,
+        }
+    }
+}
+
+async fn write(store: &Arc<dyn SessionStore>, pending: &mut HashMap<Uuid, Option<SessionRecord>>) {
+    if pending.is_empty() {
+        return;
+    }
+
+    let mut saved = Vec::new();
+    let mut removed = Vec::new();
+    for (uuid, record) in pending.drain() {
+        match record {
+            Some(record) => saved.push(record),
+            None => removed.push(uuid),
+        }
+    }
+
+    let store = store.clone();
+    let result = tokio::task::spawn_blocking(move || store.write(&saved, &removed)).await;
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => tracing::error!("Failed to write session records: {e}"),
+        Err(e) => tracing::error!("Session store writer panicked: {e}"),
+    }
+}
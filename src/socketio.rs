@@ -0,0 +1,284 @@
+//! Compatibility endpoint for Socket.IO clients (Engine.IO v4, WebSocket transport only).
+//!
+//! Clients get a token from `/ws/start` as usual and pass it in the connect packet's auth payload,
+//! e.g. `io(url, { transports: ["websocket"], auth: { token } })`. Events are then mapped onto the
+//! regular gateway messages: `socket.emit("subscribe", "transactions")` is the same as sending
+//! `{"type": "subscribe", "event": "transactions"}`, and gateway events arrive as Socket.IO events
+//! named after them.
+
+use std::{sync::Arc, time::Duration};
+
+use actix_web::{HttpRequest, HttpResponse, error::ErrorBadRequest, get, rt::time, web};
+use actix_ws::{AggregatedMessage, AggregatedMessageStream, ProtocolError, Session};
+use bytestring::ByteString;
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+use tokio::{sync::watch, time::Instant};
+use tokio_util::sync::CancellationToken;
+use tracing::{Instrument, field};
+use uuid::Uuid;
+
+use crate::ws::{
+    WebSocketServer,
+    outbound::OutboundQueue,
+    runner::{self, Admitted, Connection, Heartbeat, Inbound},
+};
+
+const PING_INTERVAL: Duration = Duration::from_secs(25);
+const PING_TIMEOUT: Duration = Duration::from_secs(20);
+
+#[derive(Debug, Deserialize)]
+pub struct EngineQuery {
+    #[serde(rename = "EIO")]
+    pub eio: Option<String>,
+    pub transport: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ConnectAuth {
+    token: Option<String>,
+}
+
+#[get("/socket.io/")]
+pub async fn socketio_handler(
+    req: HttpRequest,
+    body: web::Payload,
+    query: web::Query<EngineQuery>,
+    server: web::Data<WebSocketServer>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if query.eio.as_deref() != Some("4") {
+        return Err(ErrorBadRequest("Only Engine.IO v4 is supported"));
+    }
+    if query.transport.as_deref() != Some("websocket") {
+        return Err(ErrorBadRequest("Only the websocket transport is supported"));
+    }
+
+    let server = server.into_inner();
+    let (response, session, stream) = actix_ws::handle(&req, body)?;
+    let stream = stream
+        .max_frame_size(server.config().max_frame_size)
+        .aggregate_continuations()
+        .max_continuation_size(server.config().max_continuation_size);
+    let ip = server.client_ip(&req);
+
+    let span = tracing::info_span!(
+        parent: None,
+        "session",
+        session_id = field::Empty,
+        address = field::Empty,
+        ip = field::Empty,
+        transport = "socket.io",
+    );
+    actix_web::rt::spawn(
+        async move {
+            if let Err(e) = serve(server, session, stream, ip).await {
+                tracing::debug!("Socket.IO session ended: {e}");
+            }
+        }
+        .instrument(span),
+    );
+
+    Ok(response)
+}
+
+/// Run the Engine.IO and Socket.IO handshakes, then serve the session like a gateway connection.
+async fn serve(
+    server: Arc<WebSocketServer>,
+    mut session: Session,
+    mut stream: AggregatedMessageStream,
+    ip: Option<std::net::IpAddr>,
+) -> anyhow::Result<()> {
+    let open = json!({
+        "sid": Uuid::new_v4().simple().to_string(),
+        "upgrades": [],
+        "pingInterval": PING_INTERVAL.as_millis() as u64,
+        "pingTimeout": PING_TIMEOUT.as_millis() as u64,
+        "maxPayload": server.config().max_message_size,
+    });
+    session.text(format!("0{open}")).await?;
+
+    // Nothing but the connect packet is expected until the session is set up
+    let auth = match time::timeout(PING_TIMEOUT, stream.recv()).await {
+        Ok(Some(Ok(AggregatedMessage::Text(text)))) => match text.strip_prefix("40") {
+            Some(auth) => serde_json::from_str::<ConnectAuth>(auth).unwrap_or_default(),
+            None => return reject(session, "Expected a connect packet").await,
+        },
+        _ => return reject(session, "Expected a connect packet").await,
+    };
+
+    let token = auth.token.as_deref().unwrap_or_default();
+    let Admitted {
+        server,
+        token,
+        data,
+    } = match runner::admit(server, token, ip).await {
+        Ok(admitted) => admitted,
+        Err(refusal) => return reject(session, &refusal.to_string()).await,
+    };
+
+    // The connect ack has to go out before anything the outbound queue might send
+    session
+        .text(format!(
+            "40{}",
+            json!({ "sid": token.simple().to_string() })
+        ))
+        .await?;
+
+    let connection = Connection {
+        token,
+        data,
+        sink: session,
+        ip,
+        codec: encode,
+        since_seq: None,
+        heartbeat: Heartbeat::Transport,
+    };
+    let Some(running) = runner::start(server, connection).await else {
+        return Ok(());
+    };
+
+    // Engine.IO heartbeats are pings from the server, answered with pongs among the packets
+    let (alive, answered) = watch::channel(Instant::now());
+    running.supervise("socket.io heartbeat", move |outbound, cancel| {
+        heartbeat(token, outbound, answered, cancel)
+    });
+
+    running.run(Packets { stream, alive }).await;
+
+    Ok(())
+}
+
+/// Ping a session every [`PING_INTERVAL`], closing it if a pong doesn't come back within
+/// [`PING_TIMEOUT`].
+async fn heartbeat(
+    token: Uuid,
+    outbound: OutboundQueue,
+    mut answered: watch::Receiver<Instant>,
+    cancel: CancellationToken,
+) {
+    let mut interval = time::interval_at(Instant::now() + PING_INTERVAL, PING_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = cancel.cancelled() => break,
+        }
+
+        if outbound.raw("2").is_err() {
+            break;
+        }
+
+        let pong = time::timeout(PING_TIMEOUT, answered.changed()).await;
+        if !matches!(pong, Ok(Ok(()))) {
+            tracing::info!("Socket.IO session {token} missed a heartbeat");
+            outbound.close(None);
+            break;
+        }
+    }
+}
+
+/// Engine.IO packets from a session, with its events turned into gateway messages.
+struct Packets {
+    stream: AggregatedMessageStream,
+    /// Told whenever a pong arrives.
+    alive: watch::Sender<Instant>,
+}
+
+impl Inbound for Packets {
+    async fn recv(&mut self) -> Option<Result<AggregatedMessage, ProtocolError>> {
+        loop {
+            let text = match self.stream.recv().await? {
+                Ok(AggregatedMessage::Text(text)) => text,
+                Ok(AggregatedMessage::Pong(_) | AggregatedMessage::Binary(_)) => continue,
+                other => return Some(other),
+            };
+
+            match text.as_bytes().first() {
+                // Pong
+                Some(b'3') => {
+                    let _ = self.alive.send(Instant::now());
+                }
+                // Engine.IO close, or Socket.IO disconnect
+                Some(b'1') => return Some(Ok(AggregatedMessage::Close(None))),
+                _ if text.starts_with("41") => return Some(Ok(AggregatedMessage::Close(None))),
+                _ => match decode(&text) {
+                    Some(message) => return Some(Ok(AggregatedMessage::Text(message.into()))),
+                    None => tracing::debug!("Ignoring Socket.IO packet {text:?}"),
+                },
+            }
+        }
+    }
+}
+
+/// Refuse a connect packet with a Socket.IO connect error, then hang up.
+async fn reject(mut session: Session, message: &str) -> anyhow::Result<()> {
+    session
+        .text(format!("44{}", json!({ "message": message })))
+        .await?;
+    session.close(None).await?;
+
+    Ok(())
+}
+
+/// Turn an outgoing gateway message into a Socket.IO packet.
+///
+/// Replies to requests that carried an id become acks, and everything else becomes an event named
+/// after the message's `event` or `type`.
+fn encode(msg: ByteString) -> ByteString {
+    let value = match serde_json::from_str::<Value>(&msg) {
+        Ok(value @ Value::Object(_)) => value,
+        // Plain broadcasts that aren't JSON objects
+        _ => return format!("42{}", json!(["message", &*msg])).into(),
+    };
+
+    if let Some(id) = value.get("id").and_then(Value::as_u64) {
+        return format!("43{id}{}", json!([value])).into();
+    }
+
+    let name = match value.get("type").and_then(Value::as_str) {
+        Some("event") => value.get("event").and_then(Value::as_str),
+        name => name,
+    }
+    .unwrap_or("message");
+
+    format!("42{}", json!([name, value])).into()
+}
+
+/// Turn a Socket.IO event packet (`42[id]["name", arg]`) into a gateway message.
+///
+/// Only the default namespace is supported. A plain string argument is taken as the topic for
+/// `subscribe` and `unsubscribe`, so `emit("subscribe", "blocks")` works.
+pub fn decode(packet: &str) -> Option<String> {
+    let packet = packet.strip_prefix("42")?;
+    let args_start = packet.find('[')?;
+    let id = match &packet[..args_start] {
+        "" => None,
+        id => Some(id.parse::<u64>().ok()?),
+    };
+
+    let Value::Array(mut args) = serde_json::from_str(&packet[args_start..]).ok()? else {
+        return None;
+    };
+    if args.is_empty() {
+        return None;
+    }
+    let Value::String(name) = args.remove(0) else {
+        return None;
+    };
+
+    let mut message = match args.into_iter().next() {
+        Some(Value::Object(object)) => object,
+        Some(Value::String(topic)) if name == "subscribe" || name == "unsubscribe" => {
+            Map::from_iter([("event".to_owned(), Value::String(topic))])
+        }
+        Some(other) => Map::from_iter([("data".to_owned(), other)]),
+        None => Map::new(),
+    };
+    message.insert("type".to_owned(), Value::String(name));
+    if let Some(id) = id {
+        message.insert("id".to_owned(), id.into());
+    }
+
+    // Let the gateway report anything malformed, rather than guessing at it here
+    Some(Value::Object(message).to_string())
+}
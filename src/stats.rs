@@ -0,0 +1,62 @@
+//! Aggregated numbers about the gateway, published to `stats` subscribers so public status pages
+//! don't need admin access.
+
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+/// A snapshot of the gateway, as sent to `stats` subscribers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServerStats {
+    /// Sessions connected to this node.
+    pub connected: usize,
+    /// Transactions published in the last minute.
+    #[cfg(feature = "krist")]
+    pub transactions_per_minute: u64,
+    #[cfg(feature = "krist")]
+    pub work: usize,
+}
+
+/// Counts things over the last minute, a second at a time.
+#[derive(Debug)]
+pub struct MinuteCounter {
+    started: Instant,
+    /// Counts by second since `started`, each kept in the slot for its second modulo 60.
+    seconds: Mutex<[(u64, u64); 60]>,
+}
+
+impl Default for MinuteCounter {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            seconds: Mutex::new([(0, 0); 60]),
+        }
+    }
+}
+
+impl MinuteCounter {
+    pub fn record(&self) {
+        let now = self.started.elapsed().as_secs();
+        let mut seconds = self.seconds.lock().unwrap_or_else(|e| e.into_inner());
+
+        let (second, count) = &mut seconds[(now % 60) as usize];
+        if *second != now {
+            *second = now;
+            *count = 0;
+        }
+        *count += 1;
+    }
+
+    /// How many were recorded in the last 60 seconds.
+    pub fn count(&self) -> u64 {
+        let now = self.started.elapsed().as_secs();
+        let seconds = self.seconds.lock().unwrap_or_else(|e| e.into_inner());
+
+        seconds
+            .iter()
+            .filter(|(second, _)| now - second < 60)
+            .map(|(_, count)| count)
+            .sum()
+    }
+}
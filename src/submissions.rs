@@ -0,0 +1,97 @@
+//! Block solutions submitted recently, so a miner retrying after a timeout isn't rewarded twice
+//! for the same one.
+
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::Mutex,
+    time::Duration,
+};
+
+use tokio::time::Instant;
+
+/// Most submissions remembered at once; the oldest are forgotten early past this.
+pub const MAX_RECENT_SUBMISSIONS: usize = 10_000;
+
+/// A solution, as the address it was mined for and its nonce.
+pub type Submission = (String, String);
+
+#[derive(Debug, Default)]
+struct Recent {
+    /// In the order they were submitted, for expiring them.
+    order: VecDeque<(Instant, Submission)>,
+    seen: HashSet<Submission>,
+}
+
+impl Recent {
+    fn forget_oldest(&mut self) {
+        if let Some((_, submission)) = self.order.pop_front() {
+            self.seen.remove(&submission);
+        }
+    }
+}
+
+/// Solutions submitted within the last window, up to a fixed number of them.
+#[derive(Debug)]
+pub struct SubmissionCache {
+    window: Duration,
+    capacity: usize,
+    recent: Mutex<Recent>,
+}
+
+impl SubmissionCache {
+    pub fn new(window: Duration) -> Self {
+        Self::with_capacity(window, MAX_RECENT_SUBMISSIONS)
+    }
+
+    pub fn with_capacity(window: Duration, capacity: usize) -> Self {
+        Self {
+            window,
+            capacity,
+            recent: Mutex::new(Recent::default()),
+        }
+    }
+
+    /// Remember a submission, returning `false` if it was already submitted within the window.
+    pub fn record(&self, address: &str, nonce: &str) -> bool {
+        let submission = (address.to_owned(), nonce.to_owned());
+        let now = Instant::now();
+        let mut recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+
+        while recent
+            .order
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) >= self.window)
+        {
+            recent.forget_oldest();
+        }
+        if recent.seen.contains(&submission) {
+            return false;
+        }
+
+        while recent.order.len() >= self.capacity.max(1) {
+            recent.forget_oldest();
+        }
+        recent.seen.insert(submission.clone());
+        recent.order.push_back((now, submission));
+        true
+    }
+
+    /// Forget a submission, e.g. one that was recorded but couldn't be rewarded, so it can be
+    /// submitted again.
+    pub fn forget(&self, address: &str, nonce: &str) {
+        let submission = (address.to_owned(), nonce.to_owned());
+        let mut recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        if recent.seen.remove(&submission) {
+            recent.order.retain(|(_, x)| *x != submission);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        let recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        recent.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,101 @@
+use std::{
+    env, io,
+    os::unix::net::{SocketAddr, UnixDatagram},
+    time::Duration,
+};
+
+use crate::listeners::Listener;
+
+/// First file descriptor passed by systemd socket activation.
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// Listeners inherited through systemd socket activation, if this process was socket-activated.
+pub fn listen_fds() -> Option<Vec<Listener>> {
+    let pid = env::var("LISTEN_PID").ok()?.parse::<u32>().ok()?;
+    if pid != std::process::id() {
+        return None;
+    }
+
+    let count = env::var("LISTEN_FDS").ok()?.parse::<i32>().ok()?;
+
+    // Don't pass the sockets on to anything we might spawn
+    unsafe {
+        env::remove_var("LISTEN_PID");
+        env::remove_var("LISTEN_FDS");
+        env::remove_var("LISTEN_FDNAMES");
+    }
+
+    Some(
+        (SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count)
+            .map(Listener::Fd)
+            .collect(),
+    )
+}
+
+/// Send a state update to the service manager, doing nothing when not running under systemd.
+pub fn notify(state: &str) -> io::Result<()> {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return Ok(());
+    };
+    let path = path.to_string_lossy();
+
+    let addr = match path.strip_prefix('@') {
+        Some(name) => abstract_addr(name)?,
+        None => SocketAddr::from_pathname(path.as_ref())?,
+    };
+
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to_addr(state.as_bytes(), &addr)?;
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn abstract_addr(name: &str) -> io::Result<SocketAddr> {
+    use std::os::linux::net::SocketAddrExt;
+
+    SocketAddr::from_abstract_name(name)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn abstract_addr(_name: &str) -> io::Result<SocketAddr> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Abstract notify sockets are only supported on Linux",
+    ))
+}
+
+/// Tell systemd the service finished starting up.
+pub fn notify_ready() {
+    if let Err(e) = notify("READY=1") {
+        tracing::warn!("Failed to notify systemd of readiness: {e}");
+    }
+}
+
+pub fn notify_stopping() {
+    if let Err(e) = notify("STOPPING=1") {
+        tracing::warn!("Failed to notify systemd of shutdown: {e}");
+    }
+}
+
+/// Keep pinging the systemd watchdog at half its configured interval, if it is enabled.
+pub fn spawn_watchdog() {
+    let Some(interval) = env::var("WATCHDOG_USEC")
+        .ok()
+        .and_then(|x| x.parse::<u64>().ok())
+        .map(|x| Duration::from_micros(x) / 2)
+    else {
+        return;
+    };
+
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+
+        loop {
+            ticks.tick().await;
+            if let Err(e) = notify("WATCHDOG=1") {
+                tracing::warn!("Failed to ping systemd watchdog: {e}");
+            }
+        }
+    });
+}
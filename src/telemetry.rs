@@ -0,0 +1,236 @@
+use std::{borrow::Cow, io::Write};
+
+use tracing::Subscriber;
+use tracing_subscriber::{
+    EnvFilter, Layer, fmt::MakeWriter, layer::SubscriberExt, registry::LookupSpan,
+    util::SubscriberInitExt,
+};
+use uuid::Uuid;
+
+/// How log lines are written out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable lines.
+    #[default]
+    Text,
+    /// One JSON object per line, for log pipelines to index. Gateway activity carries the same
+    /// field names throughout: `session_id`, `address` and `message_type` on the spans it happens
+    /// in, and `event` for gateway events.
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => anyhow::bail!("Unknown log format {input}, expected text or json"),
+        }
+    }
+}
+
+/// Keeps the OTLP exporter alive, flushing any buffered spans when dropped.
+#[derive(Default)]
+pub struct TelemetryGuard {
+    #[cfg(feature = "otel")]
+    provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        #[cfg(feature = "otel")]
+        if let Some(provider) = self.provider.take()
+            && let Err(e) = provider.shutdown()
+        {
+            eprintln!("Failed to shut down OTLP tracer provider: {e}");
+        }
+    }
+}
+
+/// Install the global tracing subscriber, writing logs to stdout in `format`.
+///
+/// With the `otel` feature enabled and `OTEL_EXPORTER_OTLP_ENDPOINT` set, spans are also exported over OTLP.
+pub fn init(format: LogFormat) -> anyhow::Result<TelemetryGuard> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt_layer(format, std::io::stdout));
+
+    #[cfg(feature = "otel")]
+    if std::env::var_os("OTEL_EXPORTER_OTLP_ENDPOINT").is_some() {
+        use opentelemetry::trace::TracerProvider;
+
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .build()?;
+        let provider = opentelemetry_sdk::trace::SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(
+                opentelemetry_sdk::Resource::builder()
+                    .with_service_name(env!("CARGO_PKG_NAME"))
+                    .build(),
+            )
+            .build();
+        let tracer = provider.tracer(env!("CARGO_PKG_NAME"));
+
+        registry
+            .with(tracing_opentelemetry::layer().with_tracer(tracer))
+            .try_init()?;
+
+        return Ok(TelemetryGuard {
+            provider: Some(provider),
+        });
+    }
+
+    registry.try_init()?;
+
+    Ok(TelemetryGuard::default())
+}
+
+/// A layer writing log lines to `writer` in `format`, with secrets [redacted](redact).
+pub fn fmt_layer<S, W>(format: LogFormat, writer: W) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer().with_writer(Redacting(writer));
+    match format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Json => layer
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .boxed(),
+    }
+}
+
+/// Fields whose values are masked in log output.
+pub const SECRET_FIELDS: &[&str] = &["private_key", "privatekey"];
+
+/// Mask the quoted values of [`SECRET_FIELDS`] in a log line, whether they were logged with
+/// `Debug` (`private_key: Some("...")`), as JSON (`"privatekey":"..."`) or nested in a JSON log
+/// line's message, leaving everything else, like message types and ids, as it was.
+pub fn redact(line: &str) -> Cow<'_, str> {
+    if !SECRET_FIELDS.iter().any(|x| line.contains(x)) {
+        return Cow::Borrowed(line);
+    }
+
+    let mut redacted = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some((start, field)) = SECRET_FIELDS
+        .iter()
+        .filter_map(|field| rest.find(field).map(|x| (x, *field)))
+        .min()
+    {
+        let after = start + field.len();
+        redacted.push_str(&rest[..after]);
+        rest = &rest[after..];
+
+        if let Some((value_start, value_end)) = secret_value(rest) {
+            redacted.push_str(&rest[..value_start]);
+            redacted.push_str("[redacted]");
+            rest = &rest[value_end..];
+        }
+    }
+    redacted.push_str(rest);
+
+    Cow::Owned(redacted)
+}
+
+/// Where the quoted value following a field name starts and ends, if there is one.
+fn secret_value(rest: &str) -> Option<(usize, usize)> {
+    let separator = rest
+        .trim_start_matches(['"', '\\'])
+        .trim_start_matches([':', '='])
+        .trim_start();
+    // Neither a `:` nor a `=` followed the field name, so it was only mentioned
+    if separator.len() == rest.trim_start_matches(['"', '\\']).len() {
+        return None;
+    }
+    let value = separator.strip_prefix("Some(").unwrap_or(separator);
+
+    // In JSON log lines, the quotes around values logged with `Debug` are escaped
+    let quote = match value {
+        x if x.starts_with("\\\"") => "\\\"",
+        x if x.starts_with('"') => "\"",
+        _ => return None,
+    };
+    let start = rest.len() - value.len() + quote.len();
+    let end = start + closing_quote(&rest[start..], quote)?;
+
+    Some((start, end))
+}
+
+/// Where the first `quote` not escaped with a backslash is.
+fn closing_quote(value: &str, quote: &str) -> Option<usize> {
+    let mut offset = 0;
+    loop {
+        let found = offset + value[offset..].find(quote)?;
+        let backslashes = value[..found]
+            .bytes()
+            .rev()
+            .take_while(|&x| x == b'\\')
+            .count();
+        // Inside JSON, `Debug`'s escaped quotes show up as `\\\"` and its escaped backslashes as
+        // `\\\\`, so only the count before the quote's own backslash tells them apart
+        let escaped = match quote {
+            "\"" => backslashes % 2 == 1,
+            _ => backslashes % 4 == 2,
+        };
+        if !escaped {
+            return Some(found);
+        }
+        offset = found + quote.len();
+    }
+}
+
+/// Hands out writers that [`redact`] what's written to them.
+struct Redacting<W>(W);
+
+impl<'a, W: MakeWriter<'a>> MakeWriter<'a> for Redacting<W> {
+    type Writer = RedactingWriter<W::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter(self.0.make_writer())
+    }
+}
+
+/// Log lines are formatted in full before being written in one go, so they can be redacted write
+/// by write.
+struct RedactingWriter<W>(W);
+
+impl<W: Write> Write for RedactingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match std::str::from_utf8(buf) {
+            Ok(line) => self.0.write_all(redact(line).as_bytes())?,
+            Err(_) => self.0.write_all(buf)?,
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Correlation id for the current span, sent back to clients in responses.
+///
+/// This is the OTLP trace id when one is available, otherwise a random id.
+pub fn correlation_id() -> String {
+    #[cfg(feature = "otel")]
+    {
+        use opentelemetry::trace::TraceContextExt;
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+        let context = tracing::Span::current().context();
+        let span_context = context.span().span_context().clone();
+        if span_context.is_valid() {
+            return span_context.trace_id().to_string();
+        }
+    }
+
+    Uuid::new_v4().simple().to_string()
+}
@@ -0,0 +1,294 @@
+//! Boots the whole app on an ephemeral port, for end-to-end tests, or drives a single session's
+//! handlers without a socket at all.
+//!
+//! Everything lives in memory: no journal, resume state file or cluster is configured unless the
+//! test asks for one.
+
+use std::fmt::Display;
+use std::net::{SocketAddr, TcpListener};
+use std::time::Duration;
+
+use actix_web::{App, HttpServer, dev::ServerHandle, middleware, rt::time, web};
+use anyhow::anyhow;
+use awc::{
+    BoxedSocket,
+    ws::{Codec, Frame, Message},
+};
+use futures::{SinkExt, StreamExt};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::admin;
+use crate::client::{GatewayClient, GatewayClientConfig};
+use crate::compression;
+use crate::config::WebSocketServerConfig;
+use crate::etag;
+use crate::metrics;
+use crate::models::websocket::{WebSocketTokenData, state::SessionState};
+use crate::ratelimit;
+use crate::ws::{self, InsertedSession, WebSocketServer, sink::RecordingSink};
+
+/// How long tests wait for the server to send something before giving up.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A running gateway, stopped when [`Self::stop`] is called or the test's runtime shuts down.
+pub struct TestServer {
+    server: WebSocketServer,
+    addr: SocketAddr,
+    handle: ServerHandle,
+}
+
+impl TestServer {
+    pub async fn start() -> anyhow::Result<Self> {
+        Self::start_with_config(WebSocketServerConfig::default()).await
+    }
+
+    /// Start a server with `config`, overriding its public URL to point at the chosen port.
+    pub async fn start_with_config(config: WebSocketServerConfig) -> anyhow::Result<Self> {
+        Self::start_with(config, std::convert::identity).await
+    }
+
+    /// Like [`Self::start_with_config`], with `build` adding to the server before it's started,
+    /// e.g. to give it stores or hooks of its own.
+    pub async fn start_with(
+        mut config: WebSocketServerConfig,
+        build: impl FnOnce(WebSocketServer) -> WebSocketServer,
+    ) -> anyhow::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", 0))?;
+        let addr = listener.local_addr()?;
+        config.public_url = format!("ws://{addr}");
+
+        let server = build(WebSocketServer::with_config(config));
+        let data = web::Data::new(server.clone());
+        let http_server = HttpServer::new(move || {
+            App::new()
+                .wrap(middleware::from_fn(etag::middleware))
+                .wrap(middleware::from_fn(compression::middleware))
+                .wrap(middleware::from_fn(ratelimit::middleware))
+                .wrap(middleware::from_fn(metrics::middleware))
+                .app_data(data.clone())
+                .configure(ws::configure)
+                .configure(admin::configure)
+        })
+        .workers(1)
+        .disable_signals()
+        .listen(listener)?
+        .run();
+
+        let handle = http_server.handle();
+        actix_web::rt::spawn(http_server);
+
+        Ok(Self {
+            server,
+            addr,
+            handle,
+        })
+    }
+
+    pub fn server(&self) -> &WebSocketServer {
+        &self.server
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Base URL of the HTTP API.
+    pub fn base_url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Issue a gateway token directly, skipping `/ws/start`.
+    pub async fn issue_token(&self, address: &str, private_key: Option<String>) -> Uuid {
+        let data = WebSocketTokenData::new(address.to_owned(), private_key);
+        self.server.obtain_token(data).await
+    }
+
+    /// Open a raw gateway connection with `token`, for tests that need to see every frame.
+    pub async fn socket(&self, token: impl Display) -> anyhow::Result<TestSocket> {
+        let url = format!("ws://{}/gateway/{token}", self.addr);
+        let (_, framed) = awc::Client::default()
+            .ws(url)
+            .connect()
+            .await
+            .map_err(|e| anyhow!("Failed to connect to the gateway: {e}"))?;
+        Ok(TestSocket { framed })
+    }
+
+    /// Connect a guest client.
+    pub async fn connect(&self) -> anyhow::Result<GatewayClient> {
+        GatewayClient::connect(self.client_config()).await
+    }
+
+    /// Connect a client logged in with `private_key`.
+    pub async fn connect_as(&self, private_key: &str) -> anyhow::Result<GatewayClient> {
+        let config = GatewayClientConfig {
+            private_key: Some(private_key.into()),
+            ..self.client_config()
+        };
+        GatewayClient::connect(config).await
+    }
+
+    /// Client config pointing at this server, without reconnecting so failures surface in tests.
+    pub fn client_config(&self) -> GatewayClientConfig {
+        GatewayClientConfig {
+            reconnect: None,
+            ..GatewayClientConfig::new(self.base_url())
+        }
+    }
+
+    pub async fn stop(self) {
+        self.server.shutdown().await;
+        self.handle.stop(true).await;
+    }
+}
+
+/// A raw gateway connection opened with [`TestServer::socket`].
+pub struct TestSocket {
+    framed: actix_codec::Framed<BoxedSocket, Codec>,
+}
+
+impl TestSocket {
+    /// Send `message` and return the next text message, without checking it's the reply.
+    pub async fn send(&mut self, message: serde_json::Value) -> serde_json::Value {
+        self.write(message).await;
+        self.next_text().await
+    }
+
+    /// Send `message` without waiting for anything back.
+    pub async fn write(&mut self, message: serde_json::Value) {
+        self.write_frame(Message::Text(message.to_string().into()))
+            .await;
+    }
+
+    pub async fn write_frame(&mut self, message: Message) {
+        self.framed.send(message).await.expect("The socket closed");
+    }
+
+    /// The next frame sent, other than heartbeat pings.
+    pub async fn next_frame(&mut self) -> Frame {
+        time::timeout(TIMEOUT, async {
+            loop {
+                match self.framed.next().await {
+                    Some(Ok(Frame::Ping(_))) => continue,
+                    Some(Ok(frame)) => return frame,
+                    _ => panic!("The socket closed"),
+                }
+            }
+        })
+        .await
+        .expect("Nothing was sent")
+    }
+
+    /// The next text frame sent, parsed as JSON.
+    pub async fn next_text(&mut self) -> serde_json::Value {
+        loop {
+            if let Frame::Text(text) = self.next_frame().await {
+                return serde_json::from_slice(&text).expect("Sent invalid JSON");
+            }
+        }
+    }
+}
+
+/// A session registered straight with a [`WebSocketServer`], writing to a [`RecordingSink`]
+/// rather than a socket, so its messages can be handled without starting the app.
+pub struct TestSession {
+    server: WebSocketServer,
+    uuid: Uuid,
+    address: String,
+    sink: RecordingSink,
+    _inserted: InsertedSession,
+}
+
+impl TestSession {
+    /// A guest session that's ready for messages.
+    pub async fn guest(server: &WebSocketServer) -> Self {
+        let data = WebSocketTokenData::new("guest".to_owned(), None);
+        Self::connect(server, data, SessionState::Ready).await
+    }
+
+    /// A session for `data`, moved straight to `state`.
+    pub async fn connect(
+        server: &WebSocketServer,
+        data: WebSocketTokenData,
+        state: SessionState,
+    ) -> Self {
+        let sink = RecordingSink::new();
+        let uuid = Uuid::new_v4();
+        let address = data.address.clone();
+        let inserted = server
+            .insert_session(
+                uuid,
+                sink.clone(),
+                None,
+                data,
+                CancellationToken::new(),
+                std::convert::identity,
+            )
+            .await;
+        server.transition(&uuid, state).await;
+
+        Self {
+            server: server.clone(),
+            uuid,
+            address,
+            sink,
+            _inserted: inserted,
+        }
+    }
+
+    pub fn uuid(&self) -> Uuid {
+        self.uuid
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Every text message sent to the session so far, parsed as JSON.
+    pub fn messages(&self) -> Vec<serde_json::Value> {
+        self.sink.messages()
+    }
+
+    /// Handle `message` as if the client sent it, returning the reply, i.e. the first thing
+    /// other than an event sent after it.
+    pub async fn send(&self, message: serde_json::Value) -> serde_json::Value {
+        self.send_text(&message.to_string()).await
+    }
+
+    /// Like [`Self::send`], for text that may not be valid JSON.
+    pub async fn send_text(&self, text: &str) -> serde_json::Value {
+        let sent = self.sink.messages().len();
+        let outbound = self
+            .server
+            .outbound(&self.uuid)
+            .await
+            .expect("The session is gone");
+        ws::handle_text(
+            &self.server,
+            &outbound,
+            self.uuid,
+            &self.address,
+            &self.uuid.to_string(),
+            text,
+        )
+        .await;
+
+        time::timeout(TIMEOUT, async {
+            loop {
+                if let Some(reply) = self
+                    .sink
+                    .messages()
+                    .into_iter()
+                    .skip(sent)
+                    .find(|x| x["type"] != "event")
+                {
+                    return reply;
+                }
+                time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("No reply was sent")
+    }
+}
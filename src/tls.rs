@@ -0,0 +1,87 @@
+use std::{
+    fmt,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+
+use rustls::{
+    ServerConfig,
+    crypto::ring::sign::any_supported_type,
+    pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+};
+
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    /// PEM file containing the certificate chain, leaf first.
+    pub cert_path: PathBuf,
+    /// PEM file containing the private key.
+    pub key_path: PathBuf,
+}
+
+/// Serves the certificate from [`TlsConfig`], swapping it in place when reloaded.
+pub struct ReloadableCertResolver {
+    config: TlsConfig,
+    certified_key: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ReloadableCertResolver {
+    pub fn load(config: TlsConfig) -> anyhow::Result<Arc<Self>> {
+        let certified_key = load_certified_key(&config)?;
+
+        Ok(Arc::new(Self {
+            config,
+            certified_key: RwLock::new(Arc::new(certified_key)),
+        }))
+    }
+
+    /// Re-read the certificate and key from disk; new handshakes use them immediately.
+    pub fn reload(&self) -> anyhow::Result<()> {
+        let certified_key = load_certified_key(&self.config)?;
+        *self
+            .certified_key
+            .write()
+            .unwrap_or_else(|e| e.into_inner()) = Arc::new(certified_key);
+
+        tracing::info!("Reloaded TLS certificate from {:?}", self.config.cert_path);
+
+        Ok(())
+    }
+
+    pub fn server_config(self: &Arc<Self>) -> ServerConfig {
+        let mut config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_cert_resolver(self.clone());
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        config
+    }
+}
+
+impl fmt::Debug for ReloadableCertResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReloadableCertResolver")
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ResolvesServerCert for ReloadableCertResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(
+            self.certified_key
+                .read()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone(),
+        )
+    }
+}
+
+fn load_certified_key(config: &TlsConfig) -> anyhow::Result<CertifiedKey> {
+    let certs = CertificateDer::pem_file_iter(&config.cert_path)?.collect::<Result<Vec<_>, _>>()?;
+    let key = PrivateKeyDer::from_pem_file(&config.key_path)?;
+    let key = any_supported_type(&key)?;
+
+    Ok(CertifiedKey::new(certs, key))
+}
@@ -0,0 +1,183 @@
+//! Checks on Krist addresses, names and transaction metadata, the same ones the handlers run, so
+//! clients can catch bad input before sending it.
+
+/// Length of every address, v1 or v2.
+pub const ADDRESS_LENGTH: usize = 10;
+/// Longest a name may be, not counting the `.kst` suffix.
+pub const MAX_NAME_LENGTH: usize = 64;
+/// Longest transaction metadata may be, unless the server is configured otherwise.
+pub const MAX_METADATA_LENGTH: usize = 255;
+/// Longest a block submission's nonce may be.
+pub const MAX_NONCE_LENGTH: usize = 24;
+/// Longest a name's A record may be.
+pub const MAX_A_RECORD_LENGTH: usize = 255;
+
+/// Why a value was turned away.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    Empty,
+    WrongLength {
+        expected: usize,
+        actual: usize,
+    },
+    TooLong {
+        max: usize,
+        actual: usize,
+    },
+    TooSmall {
+        min: u64,
+        actual: u64,
+    },
+    /// A character that isn't allowed, at this byte offset.
+    InvalidCharacter {
+        character: char,
+        index: usize,
+    },
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Empty => f.write_str("Must not be empty"),
+            Self::WrongLength { expected, actual } => {
+                write!(f, "Must be {expected} characters long, not {actual}")
+            }
+            Self::TooLong { max, actual } => {
+                write!(f, "Must be at most {max} characters long, not {actual}")
+            }
+            Self::TooSmall { min, actual } => write!(f, "Must be at least {min}, not {actual}"),
+            Self::InvalidCharacter { character, index } => {
+                write!(f, "Invalid character {character:?} at position {index}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Check `address` is either a v2 address (`k` then 9 lowercase letters or digits) or a legacy
+/// v1 address (10 lowercase hex digits).
+pub fn validate_address(address: &str) -> Result<(), ValidationError> {
+    if address.is_empty() {
+        return Err(ValidationError::Empty);
+    }
+    let length = address.chars().count();
+    if length != ADDRESS_LENGTH {
+        return Err(ValidationError::WrongLength {
+            expected: ADDRESS_LENGTH,
+            actual: length,
+        });
+    }
+
+    let allowed: fn(char) -> bool = match address.starts_with('k') {
+        true => |c| c.is_ascii_lowercase() || c.is_ascii_digit(),
+        false => |c| matches!(c, 'a'..='f' | '0'..='9'),
+    };
+    first_invalid(address, allowed)
+}
+
+pub fn is_valid_address(address: &str) -> bool {
+    validate_address(address).is_ok()
+}
+
+/// Check `name` is 1 to 64 lowercase letters or digits. A trailing `.kst` is allowed and ignored.
+pub fn validate_name(name: &str) -> Result<(), ValidationError> {
+    let name = name.strip_suffix(".kst").unwrap_or(name);
+    if name.is_empty() {
+        return Err(ValidationError::Empty);
+    }
+    let length = name.chars().count();
+    if length > MAX_NAME_LENGTH {
+        return Err(ValidationError::TooLong {
+            max: MAX_NAME_LENGTH,
+            actual: length,
+        });
+    }
+
+    first_invalid(name, |c| c.is_ascii_lowercase() || c.is_ascii_digit())
+}
+
+pub fn is_valid_name(name: &str) -> bool {
+    validate_name(name).is_ok()
+}
+
+/// Check the recipient of a transaction, which is either an address or a name like
+/// `example.kst`, optionally with a metaname in front, as in `donate@example.kst`.
+pub fn validate_recipient(to: &str) -> Result<(), ValidationError> {
+    let (_, name) = to.rsplit_once('@').unwrap_or(("", to));
+    match name.strip_suffix(".kst") {
+        Some(_) => validate_name(name),
+        None => validate_address(to),
+    }
+}
+
+/// Check transaction metadata is at most 255 characters of printable ASCII and newlines.
+pub fn validate_metadata(metadata: &str) -> Result<(), ValidationError> {
+    validate_metadata_within(metadata, MAX_METADATA_LENGTH)
+}
+
+/// Like [`validate_metadata`], for servers allowing metadata up to `max_length` characters.
+pub fn validate_metadata_within(metadata: &str, max_length: usize) -> Result<(), ValidationError> {
+    if metadata.is_empty() {
+        return Err(ValidationError::Empty);
+    }
+    let length = metadata.chars().count();
+    if length > max_length {
+        return Err(ValidationError::TooLong {
+            max: max_length,
+            actual: length,
+        });
+    }
+
+    first_invalid(metadata, |c| c == '\n' || matches!(c, ' '..='~'))
+}
+
+/// Check a transaction sends at least `min`.
+pub fn validate_amount(amount: u32, min: u32) -> Result<(), ValidationError> {
+    match amount < min {
+        true => Err(ValidationError::TooSmall {
+            min: min.into(),
+            actual: amount.into(),
+        }),
+        false => Ok(()),
+    }
+}
+
+/// Check a block submission's nonce is 1 to 24 characters long.
+pub fn validate_nonce(nonce: &str) -> Result<(), ValidationError> {
+    if nonce.is_empty() {
+        return Err(ValidationError::Empty);
+    }
+    let length = nonce.chars().count();
+    if length > MAX_NONCE_LENGTH {
+        return Err(ValidationError::TooLong {
+            max: MAX_NONCE_LENGTH,
+            actual: length,
+        });
+    }
+
+    Ok(())
+}
+
+/// Check a name's A record is at most 255 characters of printable ASCII.
+pub fn validate_a_record(a: &str) -> Result<(), ValidationError> {
+    if a.is_empty() {
+        return Err(ValidationError::Empty);
+    }
+    let length = a.chars().count();
+    if length > MAX_A_RECORD_LENGTH {
+        return Err(ValidationError::TooLong {
+            max: MAX_A_RECORD_LENGTH,
+            actual: length,
+        });
+    }
+
+    first_invalid(a, |c| matches!(c, ' '..='~'))
+}
+
+fn first_invalid(value: &str, allowed: impl Fn(char) -> bool) -> Result<(), ValidationError> {
+    match value.char_indices().find(|(_, c)| !allowed(*c)) {
+        Some((index, character)) => Err(ValidationError::InvalidCharacter { character, index }),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,126 @@
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::events::GatewayEvent;
+
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    pub urls: Vec<String>,
+    /// Key used to sign payloads; receivers verify the `X-Signature-256` header with it.
+    pub secret: String,
+    /// Only deliver these event kinds, or every event when empty.
+    pub events: Vec<String>,
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for each one after.
+    pub initial_backoff: Duration,
+    pub timeout: Duration,
+}
+
+impl WebhookConfig {
+    pub fn new(urls: Vec<String>, secret: impl Into<String>) -> Self {
+        Self {
+            urls,
+            secret: secret.into(),
+            events: Vec::new(),
+            max_retries: 5,
+            initial_backoff: Duration::from_millis(500),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// POSTs signed gateway events to the configured URLs.
+pub struct WebhookDispatcher {
+    client: reqwest::Client,
+    config: WebhookConfig,
+}
+
+impl WebhookDispatcher {
+    pub fn new(config: WebhookConfig) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder().timeout(config.timeout).build()?;
+
+        Ok(Self { client, config })
+    }
+
+    pub fn dispatch(&self, event: &GatewayEvent) {
+        if !self.config.events.is_empty() && !self.config.events.iter().any(|x| x == event.kind()) {
+            return;
+        }
+
+        let body = match serde_json::to_vec(event) {
+            Ok(body) => body,
+            Err(e) => {
+                tracing::error!("Failed to serialize webhook payload: {e}");
+                return;
+            }
+        };
+        let signature = self.sign(&body);
+
+        for url in &self.config.urls {
+            let client = self.client.clone();
+            let config = self.config.clone();
+            let url = url.clone();
+            let body = body.clone();
+            let signature = signature.clone();
+
+            tokio::spawn(async move {
+                deliver(client, config, url, body, signature).await;
+            });
+        }
+    }
+
+    fn sign(&self, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.config.secret.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(body);
+
+        format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+    }
+}
+
+async fn deliver(
+    client: reqwest::Client,
+    config: WebhookConfig,
+    url: String,
+    body: Vec<u8>,
+    signature: String,
+) {
+    let mut backoff = config.initial_backoff;
+
+    for attempt in 0..=config.max_retries {
+        let result = client
+            .post(&url)
+            .header("Content-Type", "application/json")
+            .header("X-Signature-256", &signature)
+            .body(body.clone())
+            .send()
+            .await;
+
+        let retryable = match result {
+            Ok(response) if response.status().is_success() => return,
+            Ok(response) => {
+                let status = response.status();
+                tracing::warn!("Webhook {url} responded with {status} (attempt {attempt})");
+                status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            }
+            Err(e) => {
+                tracing::warn!("Webhook {url} failed: {e} (attempt {attempt})");
+                true
+            }
+        };
+
+        if !retryable {
+            return;
+        }
+
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+    }
+
+    tracing::error!(
+        "Giving up on webhook {url} after {} retries",
+        config.max_retries
+    );
+}
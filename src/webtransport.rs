@@ -0,0 +1,256 @@
+//! Experimental WebTransport endpoint, carrying the gateway protocol over QUIC for clients on lossy
+//! networks.
+//!
+//! Clients get a token from `/ws/start` as usual and open a WebTransport session to
+//! `https://host:port/gateway/{token}`. They then open one bidirectional stream, over which the
+//! same JSON messages as on a WebSocket are exchanged, one per line. Messages are always JSON, and
+//! there are no pings, as QUIC's idle timeout notices clients that have gone away.
+
+use std::{future::poll_fn, net::SocketAddr, sync::Arc};
+
+use actix_ws::{AggregatedMessage, CloseReason, Closed, ProtocolError};
+use bytes::Bytes;
+use bytestring::ByteString;
+use futures::StreamExt;
+use h3::{
+    ext::Protocol, frame::FrameStream, proto::frame::Frame, quic::BidiStream,
+    server::RequestStream, stream::BufRecvStream, webtransport::SessionId,
+};
+use http::{Method, Request, Response, StatusCode};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio_util::codec::{FramedRead, LinesCodec, LinesCodecError};
+use tracing::{Instrument, field};
+use uuid::Uuid;
+
+use crate::encoding::Encoding;
+use crate::models::websocket::WebSocketTokenData;
+use crate::ws::{
+    WebSocketServer,
+    runner::{self, Admitted, Connection, Heartbeat, Inbound, Refusal},
+    sink::SessionSink,
+};
+
+type Stream = BufRecvStream<h3_quinn::BidiStream<Bytes>, Bytes>;
+
+/// A QUIC endpoint listening on `addr`, using `tls` with HTTP/3 negotiated.
+pub fn endpoint(
+    addr: SocketAddr,
+    mut tls: rustls::ServerConfig,
+) -> anyhow::Result<quinn::Endpoint> {
+    tls.alpn_protocols = vec![b"h3".to_vec()];
+    let crypto = quinn::crypto::rustls::QuicServerConfig::try_from(tls)?;
+    let config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+
+    Ok(quinn::Endpoint::server(config, addr)?)
+}
+
+/// Accept WebTransport sessions on `endpoint` until it's closed.
+pub async fn serve(server: WebSocketServer, endpoint: quinn::Endpoint) {
+    let server = Arc::new(server);
+
+    while let Some(incoming) = endpoint.accept().await {
+        let span = tracing::info_span!(
+            parent: None,
+            "session",
+            session_id = field::Empty,
+            address = field::Empty,
+            ip = field::Empty,
+            transport = "webtransport",
+        );
+        let server = server.clone();
+        tokio::spawn(
+            async move {
+                if let Err(e) = connection(server, incoming).await {
+                    tracing::debug!("WebTransport connection ended: {e}");
+                }
+            }
+            .instrument(span),
+        );
+    }
+}
+
+/// A session whose CONNECT has been accepted, waiting for the client to open its stream.
+struct Accepted {
+    id: SessionId,
+    server: Arc<WebSocketServer>,
+    token: Uuid,
+    data: WebSocketTokenData,
+    /// Closing this ends the session, so it's held onto for as long as the session lasts.
+    connect: RequestStream<h3_quinn::BidiStream<Bytes>, Bytes>,
+}
+
+/// Handle the HTTP/3 connection, accepting one WebTransport session and serving its stream.
+async fn connection(server: Arc<WebSocketServer>, incoming: quinn::Incoming) -> anyhow::Result<()> {
+    let ip = Some(incoming.remote_address().ip());
+    let mut connection = h3::server::builder()
+        .enable_webtransport(true)
+        .enable_extended_connect(true)
+        .enable_datagram(true)
+        .max_webtransport_sessions(1)
+        .build::<_, Bytes>(h3_quinn::Connection::new(incoming.await?))
+        .await?;
+
+    let mut accepted = None;
+    // Bidirectional streams are either requests or WebTransport streams, and only their first
+    // frame tells which
+    while let Some(stream) = poll_fn(|cx| connection.poll_accept_request_stream(cx)).await? {
+        let mut frames = FrameStream::new(BufRecvStream::new(stream));
+        let frame = poll_fn(|cx| frames.poll_next(cx)).await;
+
+        if let Ok(Some(Frame::WebTransportStream(id))) = frame {
+            match accepted.take() {
+                Some(session @ Accepted { id: expected, .. }) if id == expected => {
+                    tokio::spawn(
+                        run(session, frames.into_inner(), ip).instrument(tracing::Span::current()),
+                    );
+                }
+                other => {
+                    tracing::debug!("Refusing a stream for unknown WebTransport session {id:?}");
+                    accepted = other;
+                }
+            }
+            continue;
+        }
+
+        let (request, stream) = connection
+            .create_resolver(frames)
+            .accept_with_frame(frame)?
+            .resolve()
+            .await?;
+        if let Some(session) = accept(&server, request, stream, ip).await? {
+            accepted = Some(session);
+        }
+    }
+
+    Ok(())
+}
+
+/// Answer a request, accepting it as a session if it's a WebTransport CONNECT with a valid token.
+async fn accept(
+    server: &Arc<WebSocketServer>,
+    request: Request<()>,
+    mut connect: RequestStream<h3_quinn::BidiStream<Bytes>, Bytes>,
+    ip: Option<std::net::IpAddr>,
+) -> anyhow::Result<Option<Accepted>> {
+    let is_webtransport = request.method() == Method::CONNECT
+        && request.extensions().get::<Protocol>() == Some(&Protocol::WEB_TRANSPORT);
+    let token = request.uri().path().strip_prefix("/gateway/");
+    let Some(token) = token.filter(|_| is_webtransport) else {
+        return respond(connect, StatusCode::NOT_FOUND).await;
+    };
+
+    let Admitted {
+        server,
+        token,
+        mut data,
+    } = match runner::admit(server.clone(), token, ip).await {
+        Ok(admitted) => admitted,
+        Err(Refusal::InvalidToken(_)) => return respond(connect, StatusCode::UNAUTHORIZED).await,
+        Err(refusal) => return respond(connect, StatusCode::from_u16(refusal.status())?).await,
+    };
+    // Lines of text can't carry the binary encodings
+    data.encoding = Encoding::Json;
+
+    // Sessions are identified by their CONNECT stream's id, not its index among requests
+    let id = SessionId::try_from(connect.id().into_inner())
+        .map_err(|_| anyhow::anyhow!("CONNECT stream id is out of range"))?;
+    connect.send_response(Response::new(())).await?;
+
+    Ok(Some(Accepted {
+        id,
+        server,
+        token,
+        data,
+        connect,
+    }))
+}
+
+/// Refuse a request with `status`.
+async fn respond(
+    mut stream: RequestStream<h3_quinn::BidiStream<Bytes>, Bytes>,
+    status: StatusCode,
+) -> anyhow::Result<Option<Accepted>> {
+    let response = Response::builder().status(status).body(())?;
+    stream.send_response(response).await?;
+    stream.finish().await?;
+
+    Ok(None)
+}
+
+/// Serve an accepted session over `stream`, like a gateway connection.
+async fn run(session: Accepted, stream: Stream, ip: Option<std::net::IpAddr>) {
+    let Accepted {
+        server,
+        token,
+        data,
+        mut connect,
+        ..
+    } = session;
+    let (send, recv) = stream.split();
+    let codec = LinesCodec::new_with_max_length(server.config().max_message_size);
+
+    let connection = Connection {
+        token,
+        data,
+        sink: LineSink(send),
+        ip,
+        codec: std::convert::identity,
+        since_seq: None,
+        heartbeat: Heartbeat::Keepalive,
+    };
+    let Some(running) = runner::start(server, connection).await else {
+        return;
+    };
+
+    // The session ends with its CONNECT stream
+    running.supervise("webtransport session", |_, _| async move {
+        while let Ok(Some(_)) = connect.recv_data().await {}
+    });
+
+    running.run(Lines(FramedRead::new(recv, codec))).await;
+}
+
+/// Reads each line as a text message, skipping blank ones.
+struct Lines<R>(FramedRead<R, LinesCodec>);
+
+impl<R: AsyncRead + Unpin> Inbound for Lines<R> {
+    async fn recv(&mut self) -> Option<Result<AggregatedMessage, ProtocolError>> {
+        loop {
+            return match self.0.next().await? {
+                Ok(line) if line.is_empty() => continue,
+                Ok(line) => Some(Ok(AggregatedMessage::Text(line.into()))),
+                Err(LinesCodecError::MaxLineLengthExceeded) => Some(Err(ProtocolError::Overflow)),
+                Err(_) => None,
+            };
+        }
+    }
+}
+
+/// Writes each text message as a line. Binary messages and pings have no place on the stream, so
+/// are dropped.
+struct LineSink<W>(W);
+
+impl<W: AsyncWrite + Unpin + Send + 'static> SessionSink for LineSink<W> {
+    async fn text(&mut self, msg: ByteString) -> Result<(), Closed> {
+        let mut line = Vec::with_capacity(msg.len() + 1);
+        line.extend_from_slice(msg.as_bytes());
+        line.push(b'\n');
+        self.0.write_all(&line).await.map_err(|_| Closed)
+    }
+
+    async fn binary(&mut self, _bytes: Bytes) -> Result<(), Closed> {
+        Ok(())
+    }
+
+    async fn ping(&mut self, _bytes: Bytes) -> Result<(), Closed> {
+        Ok(())
+    }
+
+    async fn pong(&mut self, _bytes: Bytes) -> Result<(), Closed> {
+        Ok(())
+    }
+
+    async fn close(mut self, _reason: Option<CloseReason>) -> Result<(), Closed> {
+        self.0.shutdown().await.map_err(|_| Closed)
+    }
+}
@@ -1,382 +1,4322 @@
+#[cfg(feature = "krist")]
+use std::sync::atomic::AtomicUsize;
 use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    panic::AssertUnwindSafe,
     str::FromStr,
-    sync::Arc,
-    time::{Duration, Instant},
+    sync::{Arc, LazyLock, Once, OnceLock, RwLock},
+    time::{Duration, SystemTime},
 };
 
-use actix_web::{HttpRequest, HttpResponse, error::ErrorBadRequest, get, post, rt::time, web};
-use actix_ws::{AggregatedMessage, Session};
+use actix_web::{
+    HttpRequest, HttpResponse,
+    error::{
+        ErrorBadRequest, ErrorForbidden, ErrorServiceUnavailable, ErrorTooManyRequests,
+        ErrorUnauthorized, InternalError,
+    },
+    get,
+    http::StatusCode,
+    post,
+    rt::time,
+    web,
+};
+use actix_ws::{CloseCode, CloseReason, Session};
 use anyhow::anyhow;
+use bytes::Bytes;
 use bytestring::ByteString;
 use dashmap::{DashMap, DashSet};
-use futures::{StreamExt, stream::FuturesUnordered};
-use tokio::sync::Mutex;
-use tracing::instrument;
+use futures::{FutureExt, Stream, StreamExt, stream::FuturesUnordered};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{Mutex, Semaphore, broadcast},
+    time::Instant,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{Instrument, field, instrument};
 use uuid::Uuid;
 
+use crate::address;
+use crate::admin;
+use crate::alarms::{AlarmHook, ChurnKind, ChurnMonitor};
+use crate::audit::{AuditAction, AuditRecord, AuditSink, TracingAuditSink};
+#[cfg(feature = "krist")]
+use crate::balances::{BalanceChange, BalanceChangeReason, BalanceStore};
+use crate::client_ip;
+#[cfg(feature = "cluster")]
+use crate::cluster::{ClusterConfig, RedisCluster};
+use crate::compression::CompressionConfig;
+use crate::config::{DrainingPublish, RuntimeConfig, WebSocketServerConfig};
+use crate::encoding::{EncodedEvent, Encoding};
+use crate::events::GatewayEvent;
+use crate::filter::EventFilter;
+use crate::idempotency::{Begin, IdempotencyCache, IdempotencyKey};
+#[cfg(feature = "krist")]
+use crate::journal::JournalEntry;
+use crate::journal::{EventJournal, JournalConfig, Order};
+#[cfg(feature = "krist")]
+use crate::locks::LockStore;
+use crate::metrics::Metrics;
+use crate::models::secret::Secret;
+#[cfg(feature = "krist")]
+use crate::models::websocket::state::StateRejected;
 use crate::models::websocket::{
-    WebSocketSessionData, WebSocketTokenData,
-    messages::{WebSocketMessage, WebSocketMessageInner, WebSocketMessageResponse},
+    DrainRequest, DrainResponse, EventsQuery, EventsResponse, GatewayQuery, MaintenanceStatus,
+    WebSocketStartConnectionBody, WebSocketStartResponse, WebSocketSubscriptionList,
+    WebSocketSubscriptionType,
 };
 use crate::models::websocket::{
-    WebSocketStartConnectionBody, WebSocketStartResponse, WebSocketSubscriptionType,
+    SubscriptionStats, WebSocketSessionData, WebSocketSessionInfo, WebSocketTokenData,
+    is_guest_address,
+    messages::{
+        Capability, PROTOCOL_VERSION, WebSocketMessage, WebSocketMessageInner,
+        WebSocketMessageResponse,
+    },
+    state::{ServerState, SessionState},
 };
+#[cfg(feature = "mqtt")]
+use crate::mqtt::{MqttBridge, MqttConfig};
+#[cfg(feature = "krist")]
+use crate::names::{NameRecord, NameStore};
+#[cfg(feature = "nats")]
+use crate::nats::{NatsBridge, NatsConfig};
+use crate::pagination;
+#[cfg(feature = "krist")]
+use crate::pagination::Paginated;
+use crate::policy::{PolicyDenied, Scope, ScopeList};
+use crate::ratelimit::RateLimiter;
+use crate::resume::{self, ResumeState};
+use crate::sampler::{MessageSampler, MessageSummary};
+use crate::session_store::{
+    FLUSH_INTERVAL, LostSession, SessionRecord, SessionStore, SessionStoreWriter,
+};
+#[cfg(feature = "krist")]
+use crate::stats::MinuteCounter;
+use crate::stats::ServerStats;
+#[cfg(feature = "krist")]
+use crate::submissions::SubmissionCache;
+use crate::telemetry;
+#[cfg(feature = "krist")]
+use crate::validation::{self, ValidationError};
+#[cfg(feature = "webhooks")]
+use crate::webhooks::{WebhookConfig, WebhookDispatcher};
+use binary::BinaryHandler;
+use guard::SessionGuard;
+use interceptor::{InterceptorChain, MessageKind, OutboundInterceptor};
+use keepalive::KeepaliveProvider;
+use listeners::{Listeners, SessionHandle};
+use middleware::{InboundMiddleware, MiddlewareChain, Rejection};
+use outbound::{OutboundQueue, PushOutcome, TextCodec};
+use routes::{RegisteredRoute, Route, RouteContext, Routes};
+use runner::{Admitted, Connection, Heartbeat, Refusal};
+use scheduler::{BroadcastHandle, Scheduler};
+use sink::SessionSink;
+
+pub mod binary;
+#[cfg(debug_assertions)]
+pub mod chaos;
+pub(crate) mod dispatch;
+pub mod guard;
+pub mod interceptor;
+pub mod keepalive;
+pub mod listeners;
+pub mod middleware;
+pub mod outbound;
+pub mod routes;
+pub(crate) mod runner;
+pub mod scheduler;
+pub mod sink;
 
 const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
 const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
 const TOKEN_EXPIRATION: Duration = Duration::from_secs(30);
+const MAX_REPLAY_EVENTS: usize = 1000;
+/// Work handed out until [`WebSocketServer::set_work`] is called.
+#[cfg(feature = "krist")]
+const INITIAL_WORK: usize = 69420;
+const RESUME_EXPIRATION: Duration = Duration::from_secs(60);
+const MAX_SESSION_TAGS: usize = 32;
+const MAX_TAG_LENGTH: usize = 64;
+/// How far a [`WebSocketServer::events`] stream may fall behind before it starts skipping events.
+pub const EVENT_STREAM_CAPACITY: usize = 1024;
+/// Close code for connections turned away because of a bad gateway token.
+pub(crate) const INVALID_TOKEN_CLOSE_CODE: u16 = 4001;
+
+/// Reference point for the monotonic timestamps carried in heartbeat pings.
+///
+/// Timekeeping goes through tokio's clock throughout, so tests can pause and advance it.
+static PING_EPOCH: LazyLock<Instant> = LazyLock::new(Instant::now);
 
 #[derive(Clone)]
 pub struct WebSocketServer {
     inner: Arc<Mutex<WebSocketServerInner>>,
+    config: Arc<WebSocketServerConfig>,
+    runtime: Arc<RwLock<Arc<RuntimeConfig>>>,
+    lifecycle: Arc<RwLock<ServerState>>,
+    /// Cancelled once the server starts shutting down, for its own background tasks to stop with it.
+    shutting_down: CancellationToken,
+    start_limiter: Arc<RateLimiter<IpAddr>>,
+    http_limiter: Arc<RateLimiter<String>>,
+    audit: Arc<dyn AuditSink>,
+    binary: Option<Arc<dyn BinaryHandler>>,
+    /// How HTTP responses are compressed, if they are at all.
+    compression: Option<Arc<CompressionConfig>>,
+    /// Supplies fields for keepalives beyond `server_time`.
+    keepalive: Option<Arc<dyn KeepaliveProvider>>,
+    /// Run over every message on its way to a session.
+    interceptors: Arc<InterceptorChain>,
+    /// Run over every message sessions send, before it reaches its handler.
+    middleware: Arc<MiddlewareChain>,
+    /// Typed handlers for message types of a downstream user's own.
+    routes: Arc<Routes>,
+    /// Told about what clients do, without being able to change it.
+    listeners: Arc<Listeners>,
+    /// Feeds the streams handed out by [`WebSocketServer::events`].
+    events: broadcast::Sender<GatewayEvent>,
+    metrics: Arc<Metrics>,
+    idempotency: Arc<IdempotencyCache>,
+    journal: Option<Arc<EventJournal>>,
+    #[cfg(feature = "krist")]
+    names: Option<Arc<dyn NameStore>>,
+    /// Where block rewards are paid out to, and transactions move funds between.
+    #[cfg(feature = "krist")]
+    balances: Option<Arc<dyn BalanceStore>>,
+    /// Addresses refused logins and transactions by an admin, with the reason given.
+    #[cfg(feature = "krist")]
+    locked_addresses: Arc<DashMap<String, Option<String>>>,
+    /// Where locks are kept so they outlive the process, if anywhere.
+    #[cfg(feature = "krist")]
+    lock_store: Option<Arc<dyn LockStore>>,
+    maintenance: Arc<RwLock<MaintenanceStatus>>,
+    #[cfg(feature = "krist")]
+    submissions: Arc<SubmissionCache>,
+    /// Transactions published over the last minute, for `stats`.
+    #[cfg(feature = "krist")]
+    transactions: Arc<MinuteCounter>,
+    #[cfg(feature = "krist")]
+    work: Arc<AtomicUsize>,
+    /// Started the first time a session subscribes to `stats`.
+    stats_ticker: Arc<Once>,
+    /// Handlers currently running for any session hold one of these.
+    handler_permits: Arc<Semaphore>,
+    sampler: Option<Arc<MessageSampler>>,
+    churn: Arc<ChurnMonitor>,
+    alarm_hooks: Arc<Vec<Arc<dyn AlarmHook>>>,
+    session_store: Option<SessionStoreWriter>,
+    /// Sessions the previous process lost without shutting down, by resume token.
+    lost_sessions: Arc<DashMap<Uuid, SessionRecord>>,
+    /// Events waiting out their topic's coalescing window.
+    pending_batches: Arc<DashMap<WebSocketSubscriptionType, Vec<ByteString>>>,
+    /// Started the first time a broadcast is scheduled.
+    scheduler: Arc<OnceLock<Scheduler>>,
+    namespaces: Arc<Namespaces>,
+    #[cfg(feature = "cluster")]
+    cluster: Option<Arc<RedisCluster>>,
+    #[cfg(feature = "nats")]
+    nats: Option<Arc<NatsBridge>>,
+    #[cfg(feature = "webhooks")]
+    webhooks: Option<Arc<WebhookDispatcher>>,
+    #[cfg(feature = "mqtt")]
+    mqtt: Option<Arc<MqttBridge>>,
 }
 
 #[derive(Clone, Default)]
 pub struct WebSocketServerInner {
     sessions: DashMap<Uuid, WebSocketSessionData>,
+    /// The sessions logged in as each address, so they can be found without scanning every session.
+    addresses: DashMap<String, HashSet<Uuid>>,
+    /// The sessions subscribed to each topic, so publishing only visits interested sessions.
+    topics: DashMap<WebSocketSubscriptionType, HashSet<Uuid>>,
     pending_tokens: DashMap<Uuid, WebSocketTokenData>,
+    pending_resumes: DashMap<Uuid, ResumeState>,
+}
+
+impl WebSocketServerInner {
+    fn index_session(&self, uuid: Uuid, data: &WebSocketSessionData) {
+        self.index_address(&data.address, uuid);
+        for topic in data.subscriptions.iter() {
+            self.index_topic(topic.clone(), uuid);
+        }
+    }
+
+    fn unindex_session(&self, uuid: Uuid, data: &WebSocketSessionData) {
+        self.unindex_address(&data.address, uuid);
+        for topic in data.subscriptions.iter() {
+            self.unindex_topic(topic, uuid);
+        }
+    }
+
+    fn index_address(&self, address: &str, uuid: Uuid) {
+        self.addresses
+            .entry(address.to_owned())
+            .or_default()
+            .insert(uuid);
+    }
+
+    fn unindex_address(&self, address: &str, uuid: Uuid) {
+        self.addresses.remove_if_mut(address, |_, sessions| {
+            sessions.remove(&uuid);
+            sessions.is_empty()
+        });
+    }
+
+    fn index_topic(&self, topic: WebSocketSubscriptionType, uuid: Uuid) {
+        self.topics.entry(topic).or_default().insert(uuid);
+    }
+
+    fn unindex_topic(&self, topic: &WebSocketSubscriptionType, uuid: Uuid) {
+        self.topics.remove_if_mut(topic, |_, sessions| {
+            sessions.remove(&uuid);
+            sessions.is_empty()
+        });
+    }
+}
+
+/// Gateways of their own sharing the process with the root one.
+#[derive(Default)]
+struct Namespaces {
+    servers: HashMap<String, WebSocketServer>,
+    /// Which namespace each API key belongs to.
+    api_keys: HashMap<String, String>,
+}
+
+/// Returned by [`WebSocketServer::broadcast`] and [`WebSocketServer::publish`] once the server has
+/// started shutting down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerDraining;
+
+impl std::fmt::Display for ServerDraining {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The server is shutting down")
+    }
+}
+
+impl std::error::Error for ServerDraining {}
+
+/// How long [`WebSocketServer::broadcast_with`] waits on a broadcast before returning.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BroadcastMode {
+    /// Return straight away, leaving the broadcast to be queued in the background.
+    FireAndForget,
+    /// Wait until every session's queue has taken it, or dropped it for lagging.
+    #[default]
+    AwaitEnqueue,
+    /// Also wait until it's been written to the sessions' sockets, for up to the given time.
+    AwaitDelivery(Duration),
+}
+
+/// What was known about a broadcast by the time [`WebSocketServer::broadcast_with`] returned.
+///
+/// Fire-and-forget broadcasts return before anything is known, so they report nothing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BroadcastReport {
+    /// Sessions the broadcast was queued for.
+    pub queued: usize,
+    /// Sessions it was written to in time, when waiting for delivery.
+    pub delivered: usize,
+}
+
+/// Sessions an event was handed to by `push_events`.
+#[derive(Default)]
+struct Pushed {
+    queued: usize,
+    /// Sessions that lagged for too long, and should be evicted.
+    evicted: Vec<Uuid>,
+    /// Completed once the event is written, when writes are being tracked.
+    written: Vec<tokio::sync::oneshot::Receiver<()>>,
+}
+
+/// A session just registered with the server.
+pub struct InsertedSession {
+    /// Handed to the client, to resume the session after a restart.
+    pub resume_token: Uuid,
+    /// Removes the session again when dropped.
+    pub guard: SessionGuard,
+    /// Whether an existing session with the same uuid was closed to make room for this one.
+    pub replaced: bool,
+}
+
+/// A session tried to subscribe to more topics than
+/// [`WebSocketServerConfig::max_subscriptions`] allows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TooManySubscriptions {
+    pub max: usize,
+}
+
+impl std::fmt::Display for TooManySubscriptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Sessions can subscribe to at most {} topics", self.max)
+    }
+}
+
+impl std::error::Error for TooManySubscriptions {}
+
+impl Default for WebSocketServer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl WebSocketServer {
     pub fn new() -> Self {
+        Self::with_config(WebSocketServerConfig::default())
+    }
+
+    pub fn with_config(config: WebSocketServerConfig) -> Self {
         let inner = WebSocketServerInner::default();
 
+        let mut namespaces = Namespaces::default();
+        for (name, namespace) in &config.namespaces {
+            let server = Self::with_config(WebSocketServerConfig {
+                runtime: namespace.runtime.clone(),
+                namespaces: HashMap::new(),
+                // Reloading and handing sessions over on restart are left to the root gateway
+                config_path: None,
+                resume_path: None,
+                ..config.clone()
+            });
+            namespaces.servers.insert(name.clone(), server);
+            for key in &namespace.api_keys {
+                namespaces.api_keys.insert(key.clone(), name.clone());
+            }
+        }
+
         Self {
             inner: Arc::new(Mutex::new(inner)),
+            runtime: Arc::new(RwLock::new(Arc::new(config.runtime.clone()))),
+            lifecycle: Arc::default(),
+            shutting_down: CancellationToken::new(),
+            start_limiter: Arc::default(),
+            http_limiter: Arc::default(),
+            idempotency: Arc::new(IdempotencyCache::new(config.idempotency_window)),
+            #[cfg(feature = "krist")]
+            submissions: Arc::new(SubmissionCache::new(config.submission_window)),
+            #[cfg(feature = "krist")]
+            transactions: Arc::default(),
+            #[cfg(feature = "krist")]
+            work: Arc::new(AtomicUsize::new(INITIAL_WORK)),
+            stats_ticker: Arc::new(Once::new()),
+            handler_permits: Arc::new(Semaphore::new(config.max_concurrent_handlers)),
+            sampler: config
+                .message_sample_rate
+                .map(|rate| Arc::new(MessageSampler::new(rate))),
+            churn: Arc::new(ChurnMonitor::new(config.churn_alarms.clone())),
+            alarm_hooks: Arc::default(),
+            config: Arc::new(config),
+            audit: Arc::new(TracingAuditSink),
+            binary: None,
+            compression: None,
+            keepalive: None,
+            interceptors: Arc::default(),
+            middleware: Arc::default(),
+            routes: Arc::default(),
+            listeners: Arc::default(),
+            events: broadcast::channel(EVENT_STREAM_CAPACITY).0,
+            metrics: Arc::default(),
+            journal: None,
+            #[cfg(feature = "krist")]
+            names: None,
+            #[cfg(feature = "krist")]
+            balances: None,
+            #[cfg(feature = "krist")]
+            locked_addresses: Arc::default(),
+            #[cfg(feature = "krist")]
+            lock_store: None,
+            maintenance: Arc::default(),
+            session_store: None,
+            lost_sessions: Arc::default(),
+            pending_batches: Arc::default(),
+            scheduler: Arc::default(),
+            namespaces: Arc::new(namespaces),
+            #[cfg(feature = "cluster")]
+            cluster: None,
+            #[cfg(feature = "nats")]
+            nats: None,
+            #[cfg(feature = "webhooks")]
+            webhooks: None,
+            #[cfg(feature = "mqtt")]
+            mqtt: None,
         }
     }
 
-    pub async fn insert_session(&self, uuid: Uuid, session: Session, data: WebSocketTokenData) {
-        let subscriptions = DashSet::from_iter(vec![
-            WebSocketSubscriptionType::OwnTransactions,
-            WebSocketSubscriptionType::Blocks,
-        ]);
-
-        let session_data = WebSocketSessionData {
-            address: data.address,
-            private_key: data.private_key,
-            session,
-            subscriptions,
-        };
-
-        self.inner.lock().await.sessions.insert(uuid, session_data);
+    /// Mirror broadcasts and events onto an MQTT broker.
+    #[cfg(feature = "mqtt")]
+    pub fn with_mqtt(mut self, config: MqttConfig) -> Self {
+        self.mqtt = Some(Arc::new(MqttBridge::connect(&config)));
+        self
     }
 
-    pub async fn cleanup_session(&self, uuid: &Uuid) {
-        tracing::info!("Cleaning up session {uuid}");
-        self.inner.lock().await.sessions.remove(uuid);
+    /// POST gateway events to external URLs.
+    #[cfg(feature = "webhooks")]
+    pub fn with_webhooks(mut self, config: WebhookConfig) -> anyhow::Result<Self> {
+        self.webhooks = Some(Arc::new(WebhookDispatcher::new(config)?));
+        Ok(self)
     }
 
-    #[instrument(skip_all, fields(address = token_data.address))]
-    pub async fn obtain_token(&self, token_data: WebSocketTokenData) -> Uuid {
-        let inner = self.inner.lock().await;
-        let inner_clone = self.inner.clone();
+    /// Report a gateway event to any attached integrations and [`Self::events`] streams.
+    pub fn emit(&self, event: GatewayEvent) {
+        tracing::debug!(event = event.kind(), "Emitting gateway event");
+        match event {
+            GatewayEvent::SessionConnected { .. } => self.count_churn(ChurnKind::Connects),
+            GatewayEvent::SessionDisconnected { .. } => self.count_churn(ChurnKind::Disconnects),
+            _ => {}
+        }
 
-        let uuid = Uuid::new_v4();
+        #[cfg(feature = "webhooks")]
+        if let Some(webhooks) = &self.webhooks {
+            webhooks.dispatch(&event);
+        }
 
-        let _ = inner.pending_tokens.insert(uuid, token_data);
-        tracing::debug!("Inserting token {uuid} into cache");
+        // Nobody listening isn't an error
+        let _ = self.events.send(event);
+    }
 
-        actix_web::rt::spawn(async move {
-            time::sleep(TOKEN_EXPIRATION).await;
+    /// Gateway events from now on, for embedders to react to instead of polling the admin API.
+    ///
+    /// Events are skipped if the stream falls more than [`EVENT_STREAM_CAPACITY`] behind.
+    pub fn events(&self) -> impl Stream<Item = GatewayEvent> + use<> {
+        futures::stream::unfold(self.events.subscribe(), |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => return Some((event, receiver)),
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        tracing::warn!("Event stream fell behind, skipping {skipped} events");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        })
+    }
 
-            let inner_mutex = inner_clone.lock().await;
-            if inner_mutex.pending_tokens.contains_key(&uuid) {
-                tracing::info!("Removing token {uuid}, expired");
-                inner_mutex.pending_tokens.remove(&uuid);
+    /// Fan broadcasts out through Redis so they also reach sessions connected to other nodes.
+    #[cfg(feature = "cluster")]
+    pub async fn join_cluster(mut self, config: ClusterConfig) -> anyhow::Result<Self> {
+        let (cluster, client) = RedisCluster::connect(&config).await?;
+        let node_id = cluster.node_id();
+        let cluster = Arc::new(cluster);
+        self.cluster = Some(cluster.clone());
+
+        tokio::spawn(async move { cluster.keep_alive().await });
+
+        let server = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = RedisCluster::subscribe(client, server, node_id, config.channel).await {
+                tracing::error!("Cluster subscription failed: {e}");
             }
         });
 
-        uuid
+        Ok(self)
     }
 
-    pub async fn use_token(&self, uuid: &Uuid) -> Result<WebSocketTokenData, anyhow::Error> {
-        let inner = self.inner.lock().await;
+    /// Replace the default audit sink, which writes records to the `audit` tracing target.
+    pub fn with_audit_sink(mut self, sink: impl AuditSink + 'static) -> Self {
+        self.audit = Arc::new(sink);
+        self
+    }
 
-        tracing::debug!("Using token {uuid}");
+    /// Run every message sent to sessions past `interceptor`, after any added before it.
+    ///
+    /// Only sessions connecting afterwards are affected, so add interceptors before serving.
+    pub fn with_outbound_interceptor(
+        mut self,
+        interceptor: impl OutboundInterceptor + 'static,
+    ) -> Self {
+        Arc::make_mut(&mut self.interceptors).push(interceptor);
+        self
+    }
 
-        let (_uuid, token) = inner
-            .pending_tokens
-            .remove(uuid)
-            .ok_or_else(|| anyhow!("Expected token to exist"))?; // TODO: Use proper error messages instead of anyhow
+    /// Run every message sessions send past `middleware` once it has passed the server's own
+    /// checks, after any added before it.
+    pub fn with_inbound_middleware(mut self, middleware: impl InboundMiddleware + 'static) -> Self {
+        Arc::make_mut(&mut self.middleware).push(middleware);
+        self
+    }
 
-        Ok(token)
+    /// Handle the message types `routes` has handlers for with them, instead of the built-in ones.
+    pub fn with_routes(mut self, routes: Routes) -> Self {
+        self.routes = Arc::new(routes);
+        self
     }
 
-    pub async fn subscribe_to_event(&self, uuid: &Uuid, event: WebSocketSubscriptionType) {
-        let inner = self.inner.lock().await;
+    /// Call `listener` with every message of type `kind` sessions send that gets past the server's
+    /// checks and middleware, before it's handled.
+    pub fn on_message_type<F, Fut>(mut self, kind: impl Into<String>, listener: F) -> Self
+    where
+        F: Fn(SessionHandle, Arc<WebSocketMessage>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        Arc::make_mut(&mut self.listeners).on_message_type(kind, listener);
+        self
+    }
 
-        let entry = inner.sessions.get_mut(uuid);
-        if let Some(data) = entry {
-            tracing::info!("Session {uuid} subscribed to event {event}");
-            data.subscriptions.insert(event);
-        } else {
-            tracing::info!("Tried to subscribe to event {event} but found a non-existent session");
-        }
+    /// Call `listener` with every message for the route `R` that gets past the server's checks and
+    /// middleware, before it's handled.
+    pub fn on_route<R, F, Fut>(mut self, listener: F) -> Self
+    where
+        R: Route + Sync,
+        F: Fn(SessionHandle, Arc<R>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        Arc::make_mut(&mut self.listeners).on_route(listener);
+        self
     }
 
-    pub async fn unsubscribe_from_event(&self, uuid: &Uuid, event: &WebSocketSubscriptionType) {
-        let inner = self.inner.lock().await;
+    /// Call `listener` whenever a session becomes authenticated.
+    pub fn on_login<F, Fut>(mut self, listener: F) -> Self
+    where
+        F: Fn(SessionHandle) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        Arc::make_mut(&mut self.listeners).on_login(listener);
+        self
+    }
 
-        let entry = inner.sessions.get_mut(uuid);
-        if let Some(data) = entry {
-            tracing::info!("Session {uuid} unsubscribed from event {event}");
-            data.subscriptions.remove(event);
+    /// Pass binary frames from sessions to `handler`, instead of ignoring them.
+    pub fn with_binary_handler(mut self, handler: impl BinaryHandler + 'static) -> Self {
+        self.binary = Some(Arc::new(handler));
+        self
+    }
+
+    /// Compress HTTP responses passing through [`compression::middleware`](crate::compression::middleware).
+    pub fn with_compression(mut self, config: CompressionConfig) -> Self {
+        self.compression = Some(Arc::new(config));
+        self
+    }
+
+    pub fn compression(&self) -> Option<&CompressionConfig> {
+        self.compression.as_deref()
+    }
+
+    /// Send the fields `provider` supplies in keepalives alongside `server_time`.
+    pub fn with_keepalive_provider(mut self, provider: impl KeepaliveProvider + 'static) -> Self {
+        self.keepalive = Some(Arc::new(provider));
+        self
+    }
+
+    /// What this server supports, as advertised in its hello.
+    pub fn capabilities(&self) -> Vec<Capability> {
+        vec![
+            Capability::Protocol {
+                version: PROTOCOL_VERSION,
+            },
+            Capability::Encodings {
+                encodings: Encoding::ALL.to_vec(),
+            },
+            Capability::MaxPayload {
+                bytes: self.config.max_message_size,
+            },
+            Capability::Subscriptions {
+                levels: WebSocketSubscriptionType::ALL.to_vec(),
+            },
+            Capability::Resume {
+                across_restarts: self.config.resume_path.is_some(),
+            },
+        ]
+    }
+
+    /// The keepalive to send `session` between pings.
+    pub fn keepalive(&self, session: Uuid, address: &str) -> WebSocketMessage {
+        let fields = self
+            .keepalive
+            .as_deref()
+            .map(|x| keepalive::provided_fields(x, session, address))
+            .unwrap_or_default();
+
+        WebSocketMessage {
+            ok: None,
+            id: None,
+            trace_id: None,
+            idempotency_key: None,
+            r#type: WebSocketMessageInner::Keepalive {
+                server_time: keepalive::server_time(SystemTime::now()),
+                fields,
+            },
         }
     }
 
-    pub async fn get_subscription_list(&self, uuid: &Uuid) -> Vec<WebSocketSubscriptionType> {
-        let inner = self.inner.lock().await;
+    pub fn audit(&self, record: AuditRecord) {
+        if let AuditAction::AuthFailed { .. } = record.action {
+            self.count_churn(ChurnKind::AuthFailures);
+        }
+        self.audit.record(&record);
+    }
 
-        let entry = inner.sessions.get(uuid);
-        if let Some(data) = entry {
-            let subscriptions: Vec<WebSocketSubscriptionType> =
-                data.subscriptions.iter().map(|x| x.clone()).collect(); // not my fav piece of code but it works
-            return subscriptions;
+    /// Also tell `hook` whenever a churn alarm goes off.
+    pub fn with_alarm_hook(mut self, hook: impl AlarmHook + 'static) -> Self {
+        Arc::make_mut(&mut self.alarm_hooks).push(Arc::new(hook));
+        self
+    }
+
+    /// Count one of `kind` towards its churn threshold, raising the alarm if it's exceeded.
+    fn count_churn(&self, kind: ChurnKind) {
+        let Some(alarm) = self.churn.record(kind) else {
+            return;
+        };
+
+        tracing::warn!(
+            "Churn alarm: {:?} went over {} per second",
+            alarm.kind,
+            alarm.threshold
+        );
+        for hook in self.alarm_hooks.iter() {
+            hook.fire(&alarm);
         }
+        self.emit(GatewayEvent::ChurnAlarm {
+            kind: alarm.kind,
+            threshold: alarm.threshold,
+        });
+    }
 
-        Vec::new()
+    /// Record published events so clients can catch up on what they missed.
+    pub fn with_journal(mut self, config: JournalConfig) -> anyhow::Result<Self> {
+        self.journal = Some(Arc::new(EventJournal::open(&config)?));
+        Ok(self)
     }
 
-    /// Broadcast a message to all connected clients
-    pub async fn broadcast(&self, msg: impl Into<ByteString>) {
-        let msg = msg.into();
+    pub fn journal(&self) -> Option<&EventJournal> {
+        self.journal.as_deref()
+    }
 
-        let inner = self.inner.lock().await;
-        let mut futures = FuturesUnordered::new();
+    /// Look names up in and save name updates to `store`.
+    #[cfg(feature = "krist")]
+    pub fn with_name_store(mut self, store: impl NameStore + 'static) -> Self {
+        self.names = Some(Arc::new(store));
+        self
+    }
+
+    #[cfg(feature = "krist")]
+    pub fn name_store(&self) -> Option<&dyn NameStore> {
+        self.names.as_deref()
+    }
 
-        for mut entry in inner.sessions.iter_mut() {
-            let msg = msg.clone();
-            tracing::info!("Sending msg: {msg}");
+    /// Keep balances in `store`, accepting block submissions and transactions.
+    #[cfg(feature = "krist")]
+    pub fn with_balance_store(mut self, store: impl BalanceStore + 'static) -> Self {
+        self.balances = Some(Arc::new(store));
+        self
+    }
 
-            futures.push(async move {
-                let session_data = entry.value_mut();
-                session_data.session.text(msg).await
-            });
-        }
+    #[cfg(feature = "krist")]
+    pub fn balance_store(&self) -> Option<&dyn BalanceStore> {
+        self.balances.as_deref()
+    }
 
-        while let Some(result) = futures.next().await {
-            if result.is_err() {
-                tracing::warn!("Got an unexpected closed session");
-            }
+    /// Keep address locks in `store`, picking up those made before the server started.
+    #[cfg(feature = "krist")]
+    pub fn with_lock_store(mut self, store: impl LockStore + 'static) -> anyhow::Result<Self> {
+        let locks = store.load()?;
+        if !locks.is_empty() {
+            tracing::info!("Loaded {} address locks", locks.len());
         }
+        self.locked_addresses = Arc::new(locks.into_iter().collect());
+        self.lock_store = Some(Arc::new(store));
+
+        Ok(self)
     }
-}
 
-#[post("/ws/start")]
-pub async fn start_ws(
-    server: web::Data<WebSocketServer>,
-    details: Option<web::Json<WebSocketStartConnectionBody>>,
-) -> Result<HttpResponse, actix_web::Error> {
-    let details = details.map(|d| d.into_inner()).unwrap_or_default(); // I do not like this, we ball. Blame serde-json.
+    /// Turn away state-changing messages until maintenance ends, still answering queries and
+    /// delivering events. Returns whether maintenance wasn't already underway.
+    pub fn start_maintenance(&self, reason: Option<String>) -> bool {
+        let mut maintenance = self.maintenance.write().unwrap_or_else(|e| e.into_inner());
+        let started = !maintenance.enabled;
+        *maintenance = MaintenanceStatus {
+            enabled: true,
+            reason: reason.clone(),
+        };
+        drop(maintenance);
 
-    let token = match details.private_key {
-        Some(private_key) => {
-            let address = String::from("dummyaddr");
-            let token_data = WebSocketTokenData::new(address, Some(private_key));
+        self.audit(AuditRecord::new(AuditAction::MaintenanceStarted { reason }));
+        started
+    }
 
-            server.obtain_token(token_data).await
+    /// Handle state-changing messages again, returning whether maintenance was underway.
+    pub fn end_maintenance(&self) -> bool {
+        let previous =
+            std::mem::take(&mut *self.maintenance.write().unwrap_or_else(|e| e.into_inner()));
+        if !previous.enabled {
+            return false;
         }
-        None => {
-            let token_data = WebSocketTokenData::new("guest".into(), None);
 
-            server.obtain_token(token_data).await
+        self.audit(AuditRecord::new(AuditAction::MaintenanceEnded));
+        true
+    }
+
+    pub fn maintenance(&self) -> MaintenanceStatus {
+        self.maintenance
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// What state-changing messages are answered with while in read-only maintenance, if it's on.
+    fn maintenance_message(&self) -> Option<String> {
+        let maintenance = self.maintenance();
+        if !maintenance.enabled {
+            return None;
         }
-    };
 
-    let response = WebSocketStartResponse {
-        ok: true,
-        url: format!("ws://127.0.0.1:8080/gateway/{token}"),
-        expires: 30,
-    };
+        Some(match maintenance.reason {
+            Some(reason) => format!("The server is in read-only maintenance: {reason}"),
+            None => "The server is in read-only maintenance".to_owned(),
+        })
+    }
 
-    Ok(HttpResponse::Ok().json(response))
-}
+    /// Refuse logins and transactions from `address` until it's unlocked, closing its sessions
+    /// after telling them why. Returns whether it wasn't locked already.
+    #[cfg(feature = "krist")]
+    pub async fn lock_address(
+        &self,
+        address: &str,
+        reason: Option<String>,
+    ) -> anyhow::Result<bool> {
+        if let Some(store) = &self.lock_store {
+            store.lock(address, reason.as_deref())?;
+        }
+        let newly_locked = self
+            .locked_addresses
+            .insert(address.to_owned(), reason.clone())
+            .is_none();
 
-#[get("/gateway/{token}")]
-#[instrument(skip_all, fields(token = *token), level = "debug")]
-pub async fn ws_handler(
-    req: HttpRequest,
-    body: web::Payload,
-    token: web::Path<String>,
-    server: web::Data<WebSocketServer>,
-) -> Result<HttpResponse, actix_web::Error> {
-    let token = token.into_inner();
-    let server = server.into_inner(); // guh but okay
-    let (response, mut session, stream) = actix_ws::handle(&req, body)?;
+        self.audit(
+            AuditRecord::new(AuditAction::AddressLocked {
+                reason: reason.clone(),
+            })
+            .address(address),
+        );
+        let event = serde_json::json!({
+            "type": "event",
+            "event": "address_locked",
+            "address": address,
+            "reason": reason,
+        });
+        self.close_address(address, event.to_string(), "Address locked".to_owned())
+            .await;
 
-    let mut stream = stream
-        .max_frame_size(64 * 1024)
-        .aggregate_continuations()
-        .max_continuation_size(2 * 1024 * 1024);
+        Ok(newly_locked)
+    }
 
-    let token = Uuid::from_str(&token).map_err(ErrorBadRequest)?;
-    let data = server
-        .use_token(&token)
-        .await
-        .expect("Token does not exist, sad");
+    /// Lift a lock from `address`, returning whether it was locked.
+    #[cfg(feature = "krist")]
+    pub async fn unlock_address(&self, address: &str) -> anyhow::Result<bool> {
+        if let Some(store) = &self.lock_store {
+            store.unlock(address)?;
+        }
+        if self.locked_addresses.remove(address).is_none() {
+            return Ok(false);
+        }
 
-    tracing::info!("Inserting new session (address: {})", data.address);
-    server.insert_session(token, session.clone(), data).await;
+        self.audit(AuditRecord::new(AuditAction::AddressUnlocked).address(address));
+        let event = serde_json::json!({
+            "type": "event",
+            "event": "address_unlocked",
+            "address": address,
+        });
+        self.send_to_address(address, event.to_string()).await;
 
-    let alive = Arc::new(Mutex::new(Instant::now()));
-    let mut session2 = session.clone();
-    let alive2 = alive.clone();
+        Ok(true)
+    }
 
-    // Heartbeat stuff
-    actix_web::rt::spawn(async move {
-        let mut interval = time::interval(HEARTBEAT_INTERVAL);
+    #[cfg(feature = "krist")]
+    pub fn is_address_locked(&self, address: &str) -> bool {
+        self.locked_addresses.contains_key(address)
+    }
 
-        loop {
-            interval.tick().await;
-            if session2.ping(b"").await.is_err() {
-                break;
-            }
+    /// Take a name off its owner until it's restored, returning it as it now stands, or `None` if
+    /// there's no such name.
+    #[cfg(feature = "krist")]
+    pub async fn seize_name(
+        &self,
+        name: &str,
+        reason: Option<String>,
+    ) -> anyhow::Result<Option<NameRecord>> {
+        let action = AuditAction::NameSeized {
+            name: name.to_owned(),
+            reason,
+        };
+        self.set_name_seized(name, true, action).await
+    }
 
-            if Instant::now().duration_since(*alive2.lock().await) > CLIENT_TIMEOUT {
-                let _ = session2.close(None).await;
-                break;
-            }
+    /// Give a seized name back to its owner.
+    #[cfg(feature = "krist")]
+    pub async fn restore_name(&self, name: &str) -> anyhow::Result<Option<NameRecord>> {
+        let action = AuditAction::NameRestored {
+            name: name.to_owned(),
+        };
+        self.set_name_seized(name, false, action).await
+    }
+
+    #[cfg(feature = "krist")]
+    async fn set_name_seized(
+        &self,
+        name: &str,
+        seized: bool,
+        action: AuditAction,
+    ) -> anyhow::Result<Option<NameRecord>> {
+        let Some(names) = &self.names else {
+            return Err(anyhow!("Names are not enabled"));
+        };
+        let Some(mut record) = names.get(name)? else {
+            return Ok(None);
+        };
+        if record.seized == seized {
+            return Ok(Some(record));
         }
-    });
 
-    // Message handling
-    actix_web::rt::spawn(async move {
-        while let Some(Ok(msg)) = stream.recv().await {
-            match msg {
-                AggregatedMessage::Ping(bytes) => {
-                    if session.pong(&bytes).await.is_err() {
-                        tracing::error!("Failed to send pong back to session");
-                        return;
-                    }
-                }
+        record.seized = seized;
+        names.put(&record)?;
+        self.audit(AuditRecord::new(action).address(&record.owner));
+        let _ = self.publish_name(&record).await;
 
-                AggregatedMessage::Text(string) => {
-                    let msg: WebSocketMessage =
-                        serde_json::from_str(&string).expect("wtf happened vro");
-                    tracing::info!("{:?}", msg);
+        Ok(Some(record))
+    }
 
-                    handle_websocket_message(&mut session, &token, &server, msg).await;
-                }
+    /// Block solutions submitted recently, e.g. for a route taking over `submit_block` to check
+    /// submissions against.
+    #[cfg(feature = "krist")]
+    pub fn submissions(&self) -> &SubmissionCache {
+        &self.submissions
+    }
+
+    /// Keep records of connected sessions in `store`, picking up those the previous process lost.
+    pub fn with_session_store(
+        mut self,
+        store: impl SessionStore + 'static,
+    ) -> anyhow::Result<Self> {
+        let lost = store.load()?;
+        let uuids: Vec<Uuid> = lost.iter().map(|x| x.uuid).collect();
+        store.write(&[], &uuids)?;
 
-                AggregatedMessage::Close(reason) => {
-                    let _ = session.close(reason).await;
+        if !lost.is_empty() {
+            tracing::warn!("Found {} sessions lost by the previous process", lost.len());
+        }
+        self.lost_sessions = Arc::new(
+            lost.into_iter()
+                .map(|record| (record.resume_token, record))
+                .collect(),
+        );
+        self.session_store = Some(SessionStoreWriter::spawn(Arc::new(store), FLUSH_INTERVAL));
 
-                    tracing::info!("Got close, cleaning up");
-                    server.cleanup_session(&token).await;
+        Ok(self)
+    }
 
-                    return;
-                }
+    /// Write any queued session record changes out to the store.
+    pub async fn flush_session_store(&self) {
+        if let Some(store) = &self.session_store {
+            store.flush().await;
+        }
+    }
 
-                AggregatedMessage::Pong(_) => {
-                    *alive.lock().await = Instant::now();
-                }
+    /// Sessions the previous process lost without shutting down, that haven't resumed since,
+    /// oldest first.
+    pub fn lost_sessions(&self) -> Vec<LostSession> {
+        let mut sessions: Vec<LostSession> = self
+            .lost_sessions
+            .iter()
+            .map(|entry| LostSession::from(entry.value()))
+            .collect();
+        sessions.sort_by_key(|x| (x.connected_at, x.uuid));
+        sessions
+    }
 
-                _ => (), // Binary data is just ignored
-            }
+    fn forget_session(&self, uuid: Uuid) {
+        if let Some(store) = &self.session_store {
+            store.remove(uuid);
         }
+    }
 
-        let _ = session.close(None).await;
-        server.cleanup_session(&token).await;
-    });
+    /// Queue the session's latest state to be written to the session store.
+    fn persist_session(&self, uuid: Uuid, data: &WebSocketSessionData) {
+        if let Some(store) = &self.session_store {
+            store.save(SessionRecord::new(uuid, data));
+        }
+    }
 
-    Ok(response)
-}
+    /// The gateway serving namespace `name`.
+    pub fn namespace(&self, name: &str) -> Option<&WebSocketServer> {
+        self.namespaces.servers.get(name)
+    }
 
-async fn handle_websocket_message(
-    session: &mut Session,
-    uuid: &Uuid,
-    server: &WebSocketServer,
-    message: WebSocketMessage,
-) {
-    match message.r#type {
-        WebSocketMessageInner::Hello { motd: _ } => {} // Not sent by client
-        WebSocketMessageInner::Keepalive { server_time: _ } => {} // Not sent by client
-        WebSocketMessageInner::Response {
-            responding_to: _,
-            data: _,
-        } => {} // Not sent by client
-        WebSocketMessageInner::Work => {
-            let message = WebSocketMessageInner::Response {
-                responding_to: "work".to_owned(),
-                data: WebSocketMessageResponse::Work { work: 69420 },
-            };
-            let message =
-                serde_json::to_string(&message).expect("Failed to turn response into string");
-            let _ = session.text(message).await;
+    /// The gateway serving a request, picked by its `X-API-Key` header when namespaces are configured.
+    pub fn for_request(&self, req: &HttpRequest) -> Result<WebSocketServer, actix_web::Error> {
+        if self.namespaces.servers.is_empty() {
+            return Ok(self.clone());
         }
-        WebSocketMessageInner::MakeTransaction {
-            private_key: _,
-            to: _,
-            amount: _,
-            metadata: _,
-        } => todo!(),
-        WebSocketMessageInner::GetValidSubscriptionLevels => todo!(),
-        WebSocketMessageInner::Address {
-            address: _,
-            fetch_names: _,
-        } => todo!(),
-        WebSocketMessageInner::Me => todo!(),
-        WebSocketMessageInner::GetSubscriptionLevel => todo!(),
-        WebSocketMessageInner::Logout => todo!(),
-        WebSocketMessageInner::Login { private_key: _ } => todo!(),
-        WebSocketMessageInner::Subscribe { event } => {
-            if WebSocketSubscriptionType::is_valid(&event) {
-                let event = WebSocketSubscriptionType::from_str(&event).expect("guh");
-                let _ = server.subscribe_to_event(uuid, event).await;
-
-                let subscription_list = server.get_subscription_list(uuid).await;
-                let subscription_list: Vec<String> = subscription_list
-                    .into_iter()
-                    .map(|x| x.into_string())
-                    .collect();
-
-                let message = WebSocketMessage {
-                    ok: Some(true),
-                    id: message.id,
-                    r#type: WebSocketMessageInner::Response {
-                        responding_to: "subscribe".to_owned(),
-                        data: WebSocketMessageResponse::Subscribe {
-                            subscription_level: subscription_list,
-                        },
-                    },
-                };
+        let Some(key) = req.headers().get("x-api-key") else {
+            return Ok(self.clone());
+        };
 
-                let message =
-                    serde_json::to_string(&message).expect("Failed to turn response into string");
-                let _ = session.text(message).await;
-            } else {
-                // Send a message to the session
+        key.to_str()
+            .ok()
+            .and_then(|key| self.namespaces.api_keys.get(key))
+            .and_then(|name| self.namespace(name))
+            .cloned()
+            .ok_or_else(|| ErrorUnauthorized("Unknown API key"))
+    }
+
+    /// Whether `key` is the API key of one of the configured namespaces.
+    pub fn is_api_key(&self, key: &str) -> bool {
+        self.namespaces.api_keys.contains_key(key)
+    }
+
+    /// The namespace gateway a `/ws/start` token was issued by, if it wasn't this one.
+    pub async fn namespace_for_token(&self, token: &Uuid) -> Option<WebSocketServer> {
+        for server in self.namespaces.servers.values() {
+            if server.inner.lock().await.pending_tokens.contains_key(token) {
+                return Some(server.clone());
             }
         }
-        WebSocketMessageInner::Unsubscribe { event } => {
-            if WebSocketSubscriptionType::is_valid(&event) {
-                let event = WebSocketSubscriptionType::from_str(&event).expect("guh");
-                let _ = server.unsubscribe_from_event(uuid, &event).await;
-
-                let subscription_list = server.get_subscription_list(uuid).await;
-                let subscription_list: Vec<String> = subscription_list
-                    .into_iter()
-                    .map(|x| x.into_string())
-                    .collect();
-
-                let message = WebSocketMessage {
-                    ok: Some(true),
-                    id: message.id,
-                    r#type: WebSocketMessageInner::Response {
-                        responding_to: "unsubscribe".to_owned(),
-                        data: WebSocketMessageResponse::Unsubscribe {
-                            subscription_level: subscription_list,
-                        },
-                    },
-                };
+        None
+    }
 
-                let message =
-                    serde_json::to_string(&message).expect("Failed to turn response into string");
-                let _ = session.text(message).await;
-            } else {
-                // Send a message to the session
-            }
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// The types and sizes of the inbound messages sampled so far, if sampling is enabled.
+    pub fn message_summary(&self) -> Option<MessageSummary> {
+        self.sampler.as_ref().map(|x| x.summary())
+    }
+
+    fn sample_message(&self, kind: &str, size: usize) {
+        if let Some(sampler) = &self.sampler
+            && sampler.sample(kind, size)
+        {
+            self.metrics
+                .messages_sampled
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        }
+    }
+
+    /// The current Krist work, as answered to `work` requests and sent to `stats` subscribers.
+    #[cfg(feature = "krist")]
+    pub fn work(&self) -> usize {
+        self.work.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    #[cfg(feature = "krist")]
+    pub fn set_work(&self, work: usize) {
+        self.work.store(work, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    /// What `stats` subscribers are told about this node.
+    pub async fn stats(&self) -> ServerStats {
+        ServerStats {
+            connected: self.session_count().await,
+            #[cfg(feature = "krist")]
+            transactions_per_minute: self.transactions.count(),
+            #[cfg(feature = "krist")]
+            work: self.work(),
         }
     }
+
+    /// Send `stats` subscribers on this node the node's stats every `stats_interval`, unless
+    /// that's already being done.
+    ///
+    /// Each node only counts its own sessions, so the stats aren't published to the cluster.
+    fn start_stats_ticker(&self) {
+        self.stats_ticker.call_once(|| {
+            let server = self.clone();
+            tokio::spawn(async move {
+                let mut ticker = time::interval(server.config.stats_interval);
+                ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+                while server.server_state() != ServerState::Stopped {
+                    ticker.tick().await;
+                    let topic = WebSocketSubscriptionType::Stats;
+                    if server.sessions_for_topic(&topic).await.is_empty() {
+                        continue;
+                    }
+
+                    let event = serde_json::json!({
+                        "type": "event",
+                        "event": "stats",
+                        "stats": server.stats().await,
+                    });
+                    server.publish_local(topic, event.to_string()).await;
+                }
+            });
+        });
+    }
+
+    pub fn config(&self) -> &WebSocketServerConfig {
+        &self.config
+    }
+
+    pub fn server_state(&self) -> ServerState {
+        *self.lifecycle.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Cancelled once [`Self::shutdown`] is called.
+    pub(crate) fn shutting_down(&self) -> &CancellationToken {
+        &self.shutting_down
+    }
+
+    pub(crate) fn set_server_state(&self, state: ServerState) {
+        tracing::info!("Server is now {state:?}");
+        *self.lifecycle.write().unwrap_or_else(|e| e.into_inner()) = state;
+    }
+
+    /// Deal with an event sent once the server has started shutting down, as configured by
+    /// [`WebSocketServerConfig::draining_publish`].
+    fn hold_for_restart(
+        &self,
+        topic: Option<&WebSocketSubscriptionType>,
+        msg: &str,
+    ) -> Result<(), ServerDraining> {
+        let result = match (self.config.draining_publish, &self.journal) {
+            (DrainingPublish::Journal, Some(journal)) => match journal.append(topic, msg) {
+                Ok(_) => Ok(()),
+                Err(e) => {
+                    tracing::error!("Failed to journal event while draining: {e}");
+                    Err(ServerDraining)
+                }
+            },
+            _ => Err(ServerDraining),
+        };
+
+        if let Err(e) = result {
+            self.emit(GatewayEvent::BroadcastFailed {
+                topic: topic.cloned(),
+                reason: e.to_string(),
+            });
+        }
+        result
+    }
+
+    /// The current runtime configuration, which may change on reload.
+    pub fn runtime_config(&self) -> Arc<RuntimeConfig> {
+        self.runtime
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Swap in new runtime configuration, disconnecting any sessions it bans.
+    pub async fn reload_config(&self, runtime: RuntimeConfig) {
+        tracing::info!("Reloading runtime configuration");
+        let runtime = Arc::new(runtime);
+        *self.runtime.write().unwrap_or_else(|e| e.into_inner()) = runtime.clone();
+
+        let banned: Vec<Uuid> = {
+            let inner = self.inner.lock().await;
+            inner
+                .sessions
+                .iter()
+                .filter(|entry| runtime.is_banned(&entry.address, entry.ip))
+                .map(|entry| *entry.key())
+                .collect()
+        };
+
+        for uuid in banned {
+            let ban = AuditAction::Ban { reason: None };
+            self.disconnect_local(&uuid, ban, Some("Banned".to_owned()))
+                .await;
+        }
+    }
+
+    pub async fn reload_config_from_file(&self) -> anyhow::Result<()> {
+        let Some(path) = &self.config.config_path else {
+            return Err(anyhow!("No config file to reload from"));
+        };
+
+        self.reload_config(RuntimeConfig::load(path)?).await;
+
+        Ok(())
+    }
+
+    /// The client IP behind `req`, looking through trusted proxies.
+    pub fn client_ip(&self, req: &HttpRequest) -> Option<IpAddr> {
+        client_ip::resolve(req, &self.config.trusted_proxies)
+    }
+
+    /// The client IP behind a connection from `peer` that isn't an actix request, looking through
+    /// trusted proxies. `values` gives every value of the named header.
+    pub fn client_ip_from<'a>(
+        &self,
+        peer: IpAddr,
+        values: impl Fn(&str) -> Vec<&'a str>,
+    ) -> IpAddr {
+        client_ip::resolve_from(peer, values, &self.config.trusted_proxies)
+    }
+
+    pub(crate) fn http_limiter(&self) -> &RateLimiter<String> {
+        &self.http_limiter
+    }
+
+    /// Tell a session it went over the frame or message size limits, and close it with 1009.
+    pub(crate) fn close_oversized(&self, outbound: &OutboundQueue) {
+        tracing::info!("Closing session for sending an oversized message");
+        self.metrics
+            .oversized_messages
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+
+        let error = WebSocketMessage {
+            ok: Some(false),
+            id: None,
+            trace_id: None,
+            idempotency_key: None,
+            r#type: WebSocketMessageInner::Error {
+                error: "message_too_large".to_owned(),
+                message: format!(
+                    "Frames can be at most {} bytes, and messages {} bytes",
+                    self.config.max_frame_size, self.config.max_continuation_size
+                ),
+                parameter: None,
+            },
+        };
+        // Sent ahead of queued responses, so it's written before the close frame
+        if let Some(error) = to_text(&error, "message_too_large error") {
+            let _ = outbound.control(error);
+        }
+        outbound.close(Some(CloseReason {
+            code: CloseCode::Size,
+            description: Some("MessageTooLarge".to_owned()),
+        }));
+    }
+
+    pub async fn session_count(&self) -> usize {
+        self.inner.lock().await.sessions.len()
+    }
+
+    /// Check whether a new connection may be accepted under the current runtime configuration.
+    pub async fn admit(&self, address: &str, ip: Option<IpAddr>) -> Result<(), actix_web::Error> {
+        if self.server_state() != ServerState::Running {
+            return Err(ErrorServiceUnavailable("The server is draining"));
+        }
+        let runtime = self.runtime_config();
+
+        if runtime.is_banned(address, ip) {
+            return Err(ErrorForbidden("Banned"));
+        }
+
+        #[cfg(feature = "krist")]
+        if self.is_address_locked(address) {
+            return Err(ErrorForbidden("Address is locked"));
+        }
+
+        if let Some(max_sessions) = runtime.max_sessions
+            && self.session_count().await >= max_sessions
+        {
+            return Err(ErrorServiceUnavailable("Too many connected sessions"));
+        }
+
+        Ok(())
+    }
+
+    /// Bridge subscription topics to NATS subjects, in both directions.
+    #[cfg(feature = "nats")]
+    pub async fn attach_nats(mut self, config: NatsConfig) -> anyhow::Result<Self> {
+        let bridge = Arc::new(NatsBridge::connect(&config).await?);
+        self.nats = Some(bridge.clone());
+
+        let server = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = bridge.subscribe(server).await {
+                tracing::error!("NATS subscription failed: {e}");
+            }
+        });
+
+        Ok(self)
+    }
+
+    /// Forward a client message to NATS, if a bridge is attached.
+    ///
+    /// Messages carrying private keys are never forwarded.
+    #[cfg(feature = "nats")]
+    pub async fn forward_client_message(
+        &self,
+        uuid: &Uuid,
+        address: &str,
+        message: &WebSocketMessage,
+    ) {
+        let Some(nats) = &self.nats else {
+            return;
+        };
+
+        // Both carry a private key
+        match message.r#type {
+            WebSocketMessageInner::Login { .. } => return,
+            #[cfg(feature = "krist")]
+            WebSocketMessageInner::MakeTransaction { .. } => return,
+            _ => {}
+        }
+
+        if let Err(e) = nats.forward(*uuid, address, message).await {
+            tracing::warn!("Failed to forward message to NATS: {e}");
+        }
+    }
+
+    fn spawn_outbound(
+        &self,
+        session: impl SessionSink,
+        codec: TextCodec,
+        encoding: Encoding,
+    ) -> OutboundQueue {
+        let slow_consumer = self.config.slow_consumer.clone();
+
+        #[cfg(debug_assertions)]
+        if let Some(chaos) = &self.config.chaos {
+            let session = chaos::ChaosSink::new(session, chaos.clone());
+            return OutboundQueue::spawn_with_codec(
+                session,
+                slow_consumer,
+                self.metrics.clone(),
+                codec,
+                encoding,
+            );
+        }
+
+        OutboundQueue::spawn_with_codec(
+            session,
+            slow_consumer,
+            self.metrics.clone(),
+            codec,
+            encoding,
+        )
+    }
+
+    /// Register a newly connected session, superseding any existing session with the same uuid.
+    pub async fn insert_session(
+        &self,
+        uuid: Uuid,
+        session: impl SessionSink,
+        ip: Option<IpAddr>,
+        data: WebSocketTokenData,
+        cancel: CancellationToken,
+        codec: TextCodec,
+    ) -> InsertedSession {
+        let (subscriptions, filters, tags) = match data.resume {
+            Some(resume) => (
+                resume.subscriptions,
+                DashMap::from_iter(resume.filters),
+                DashSet::from_iter(resume.tags),
+            ),
+            None => (
+                WebSocketSubscriptionList::from([
+                    WebSocketSubscriptionType::OwnTransactions,
+                    WebSocketSubscriptionType::Blocks,
+                ]),
+                DashMap::new(),
+                DashSet::new(),
+            ),
+        };
+        let resume_token = Uuid::new_v4();
+
+        let encoding = data.encoding;
+        let scopes = data.scopes;
+        let outbound = self
+            .spawn_outbound(session, codec, encoding)
+            .with_interceptors(uuid, self.interceptors.clone());
+        let session_data = WebSocketSessionData {
+            address: data.address,
+            private_key: data.private_key,
+            ip,
+            state: SessionState::Handshaking,
+            subscriptions,
+            filters,
+            stats: DashMap::new(),
+            tags,
+            latency: None,
+            outbound,
+            encoding,
+            cancel,
+            resume_token,
+            connected_at: SystemTime::now(),
+            scopes,
+        };
+        let address = session_data.address.clone();
+        if session_data
+            .subscriptions
+            .contains(&WebSocketSubscriptionType::Stats)
+        {
+            self.start_stats_ticker();
+        }
+
+        let previous = {
+            let inner = self.inner.lock().await;
+            if let Some(previous) = inner.sessions.get(&uuid) {
+                inner.unindex_session(uuid, &previous);
+            }
+            inner.index_session(uuid, &session_data);
+            self.persist_session(uuid, &session_data);
+            inner.sessions.insert(uuid, session_data)
+        };
+        let replaced = previous.is_some();
+
+        if let Some(mut previous) = previous {
+            tracing::warn!("Session {uuid} was superseded by a new connection");
+            previous.state = SessionState::Closed;
+            previous.cancel.cancel();
+            previous.outbound.close(Some(CloseReason {
+                code: CloseCode::Policy,
+                description: Some("Superseded".to_owned()),
+            }));
+
+            self.emit(GatewayEvent::SessionDisconnected {
+                session: uuid,
+                address: previous.address,
+            });
+        }
+
+        #[cfg(feature = "cluster")]
+        if let Some(cluster) = &self.cluster
+            && let Err(e) = cluster.register_session(uuid, &address).await
+        {
+            tracing::warn!("Failed to register session {uuid} with the cluster: {e}");
+        }
+
+        self.emit(GatewayEvent::SessionConnected {
+            session: uuid,
+            address,
+        });
+
+        InsertedSession {
+            resume_token,
+            guard: SessionGuard::new(self.clone(), uuid, resume_token),
+            replaced,
+        }
+    }
+
+    /// Remove a session from the server, returning its data if it was still connected.
+    pub async fn cleanup_session(&self, uuid: &Uuid) -> Option<WebSocketSessionData> {
+        let removed = {
+            let inner = self.inner.lock().await;
+            let removed = inner.sessions.remove(uuid)?;
+            inner.unindex_session(*uuid, &removed.1);
+            self.forget_session(*uuid);
+            removed
+        };
+        self.finish_cleanup(uuid, removed).await
+    }
+
+    /// Like [`Self::cleanup_session`], but only if `uuid` still belongs to the session issued `resume_token`.
+    pub(crate) async fn cleanup_session_instance(
+        &self,
+        uuid: &Uuid,
+        resume_token: Uuid,
+    ) -> Option<WebSocketSessionData> {
+        let removed = {
+            let inner = self.inner.lock().await;
+            let removed = inner
+                .sessions
+                .remove_if(uuid, |_, data| data.resume_token == resume_token)?;
+            inner.unindex_session(*uuid, &removed.1);
+            self.forget_session(*uuid);
+            removed
+        };
+        self.finish_cleanup(uuid, removed).await
+    }
+
+    async fn finish_cleanup(
+        &self,
+        uuid: &Uuid,
+        (_uuid, mut data): (Uuid, WebSocketSessionData),
+    ) -> Option<WebSocketSessionData> {
+        tracing::info!("Cleaning up session {uuid}");
+        if data.state == SessionState::Authenticated {
+            self.audit(
+                AuditRecord::new(AuditAction::Disconnect)
+                    .session(*uuid)
+                    .address(&data.address)
+                    .ip(data.ip),
+            );
+        }
+        data.state = SessionState::Closed;
+        data.cancel.cancel();
+
+        #[cfg(feature = "cluster")]
+        if let Some(cluster) = &self.cluster
+            && let Err(e) = cluster.deregister_session(*uuid, &data.address).await
+        {
+            tracing::warn!("Failed to deregister session {uuid} from the cluster: {e}");
+        }
+
+        self.emit(GatewayEvent::SessionDisconnected {
+            session: *uuid,
+            address: data.address.clone(),
+        });
+
+        Some(data)
+    }
+
+    /// Disconnect a session, wherever in the cluster it is connected.
+    pub async fn kick(&self, uuid: &Uuid, reason: Option<String>) {
+        if !self.kick_local(uuid, reason.clone()).await {
+            #[cfg(feature = "cluster")]
+            if let Some(cluster) = &self.cluster
+                && let Err(e) = cluster.kick(*uuid, reason).await
+            {
+                tracing::error!("Failed to kick session {uuid} through the cluster: {e}");
+            }
+        }
+    }
+
+    /// Close every session logged in as `address`, wherever in the cluster it is connected,
+    /// sending it `notice` first.
+    #[cfg(feature = "krist")]
+    async fn close_address(&self, address: &str, notice: String, reason: String) {
+        #[cfg(feature = "cluster")]
+        if let Some(cluster) = &self.cluster
+            && let Err(e) = cluster.close_address(address, &notice, &reason).await
+        {
+            tracing::error!("Failed to close {address}'s sessions through the cluster: {e}");
+        }
+
+        self.close_address_local(address, notice, reason).await;
+    }
+
+    /// Like [`Self::close_address`], for the sessions on this node only.
+    #[cfg(feature = "krist")]
+    pub(crate) async fn close_address_local(&self, address: &str, notice: String, reason: String) {
+        for uuid in self.sessions_for_address(address).await {
+            // Sent as a control frame, so it's written before the close rather than discarded
+            if let Some(outbound) = self.outbound(&uuid).await {
+                let _ = outbound.control(notice.clone());
+            }
+            self.kick_local(&uuid, Some(reason.clone())).await;
+        }
+    }
+
+    /// Disconnect a session connected to this node, returning whether it was found.
+    pub async fn kick_local(&self, uuid: &Uuid, reason: Option<String>) -> bool {
+        let kick = AuditAction::Kick {
+            reason: reason.clone(),
+        };
+        self.disconnect_local(uuid, kick, reason).await
+    }
+
+    /// Close a session connected to this node with `reason`, auditing it as `action`. Returns
+    /// whether it was found.
+    async fn disconnect_local(
+        &self,
+        uuid: &Uuid,
+        action: AuditAction,
+        reason: Option<String>,
+    ) -> bool {
+        let Some(data) = self.cleanup_session(uuid).await else {
+            return false;
+        };
+
+        tracing::info!("Disconnecting session {uuid}");
+        self.audit(
+            AuditRecord::new(action)
+                .session(*uuid)
+                .address(&data.address)
+                .ip(data.ip),
+        );
+
+        let reason = CloseReason {
+            code: CloseCode::Policy,
+            description: reason,
+        };
+        data.outbound.close(Some(reason));
+
+        true
+    }
+
+    #[instrument(skip_all, fields(address = token_data.address))]
+    pub async fn obtain_token(&self, token_data: WebSocketTokenData) -> Uuid {
+        let inner = self.inner.lock().await;
+        let inner_clone = self.inner.clone();
+
+        let uuid = Uuid::new_v4();
+
+        let _ = inner.pending_tokens.insert(uuid, token_data);
+        tracing::debug!("Inserting token {uuid} into cache");
+
+        // Not tied to the worker's local runtime, so tokens can be issued from any thread
+        tokio::spawn(async move {
+            time::sleep(TOKEN_EXPIRATION).await;
+
+            let inner_mutex = inner_clone.lock().await;
+            if inner_mutex.pending_tokens.contains_key(&uuid) {
+                tracing::info!("Removing token {uuid}, expired");
+                inner_mutex.pending_tokens.remove(&uuid);
+            }
+        });
+
+        uuid
+    }
+
+    pub async fn use_token(&self, uuid: &Uuid) -> Result<WebSocketTokenData, anyhow::Error> {
+        let inner = self.inner.lock().await;
+
+        tracing::debug!("Using token {uuid}");
+
+        let (_uuid, token) = inner
+            .pending_tokens
+            .remove(uuid)
+            .ok_or_else(|| anyhow!("Expected token to exist"))?; // TODO: Use proper error messages instead of anyhow
+
+        Ok(token)
+    }
+
+    /// Pick up the sessions the previous process handed over when it shut down.
+    pub async fn load_resume_state(&self) -> anyhow::Result<usize> {
+        let Some(path) = &self.config.resume_path else {
+            return Ok(0);
+        };
+
+        let sessions = resume::load(path)?;
+        let count = sessions.len();
+        let inner = self.inner.lock().await;
+
+        for (token, state) in sessions {
+            inner.pending_resumes.insert(token, state);
+
+            let inner_clone = self.inner.clone();
+            tokio::spawn(async move {
+                time::sleep(RESUME_EXPIRATION).await;
+
+                if inner_clone
+                    .lock()
+                    .await
+                    .pending_resumes
+                    .remove(&token)
+                    .is_some()
+                {
+                    tracing::info!("Removing resume token {token}, expired");
+                }
+            });
+        }
+
+        tracing::info!("Loaded {count} resumable sessions");
+
+        Ok(count)
+    }
+
+    /// Exchange a resume token for the state of the session it was issued to.
+    ///
+    /// Sessions lost in a crash only get their address and subscriptions back, their private key
+    /// was never persisted.
+    pub async fn redeem_resume_token(&self, token: &Uuid) -> Option<ResumeState> {
+        let inner = self.inner.lock().await;
+        if let Some((_token, state)) = inner.pending_resumes.remove(token) {
+            return Some(state);
+        }
+
+        let (_token, record) = self.lost_sessions.remove(token)?;
+        tracing::info!(
+            "Resuming session {} lost by the previous process",
+            record.uuid
+        );
+        Some(ResumeState {
+            address: record.address,
+            private_key: None,
+            subscriptions: record.subscriptions,
+            filters: Default::default(),
+            tags: Vec::new(),
+            scopes: record.scopes,
+            last_seq: None,
+        })
+    }
+
+    /// Stop issuing tokens and ask every session to reconnect, each at a random point within
+    /// `window` so they don't all arrive at the next instance at once. Sessions still connected
+    /// keep getting events until they go. Returns how many were asked.
+    pub async fn drain(&self, window: Duration) -> usize {
+        if self.server_state() == ServerState::Running {
+            self.set_server_state(ServerState::Draining);
+        }
+
+        let sessions: Vec<OutboundQueue> = {
+            let inner = self.inner.lock().await;
+            inner
+                .sessions
+                .iter()
+                .map(|entry| entry.outbound.clone())
+                .collect()
+        };
+
+        for outbound in &sessions {
+            if let Some(advisory) = reconnect_advisory(window, "draining") {
+                let _ = outbound.control(advisory);
+            }
+        }
+        tracing::info!("Draining, asked {} sessions to reconnect", sessions.len());
+
+        let mut count = sessions.len();
+        for namespace in self.namespaces.servers.values() {
+            count += Box::pin(namespace.drain(window)).await;
+        }
+        count
+    }
+
+    /// Disconnect every session ahead of a restart, telling them to reconnect with their resume token.
+    ///
+    /// Their state is handed over to the next process through the configured resume file.
+    pub async fn shutdown(&self) {
+        self.set_server_state(ServerState::Draining);
+        self.shutting_down.cancel();
+
+        let last_seq = self
+            .journal
+            .as_ref()
+            .and_then(|journal| journal.latest_seq().ok().flatten());
+
+        let uuids: Vec<Uuid> = {
+            let inner = self.inner.lock().await;
+            inner.sessions.iter().map(|entry| *entry.key()).collect()
+        };
+
+        // Stop taking messages before anyone's state is captured
+        for uuid in &uuids {
+            self.transition(uuid, SessionState::Draining).await;
+        }
+
+        let mut handover = std::collections::HashMap::new();
+        let mut sessions = Vec::new();
+        for uuid in uuids {
+            let Some(data) = self.cleanup_session(&uuid).await else {
+                continue;
+            };
+
+            let state = ResumeState {
+                address: data.address.clone(),
+                private_key: data.private_key.clone(),
+                subscriptions: data.subscriptions.clone(),
+                filters: data
+                    .filters
+                    .iter()
+                    .map(|x| (x.key().clone(), x.value().clone()))
+                    .collect(),
+                tags: data.tags.iter().map(|x| x.clone()).collect(),
+                scopes: data.scopes.clone(),
+                last_seq,
+            };
+            handover.insert(data.resume_token, state);
+            sessions.push(data.outbound);
+        }
+
+        // Save before disconnecting anyone, so nobody comes back before their state is on disk
+        if let Some(path) = &self.config.resume_path
+            && let Err(e) = resume::save(path, &handover)
+        {
+            tracing::error!("Failed to save resume state: {e}");
+        }
+        // Sessions handed over cleanly aren't lost, so make sure the next process doesn't think so
+        self.flush_session_store().await;
+
+        tracing::info!("Disconnecting {} sessions for restart", sessions.len());
+
+        let warning = WebSocketMessage {
+            ok: None,
+            id: None,
+            trace_id: None,
+            idempotency_key: None,
+            r#type: WebSocketMessageInner::Warning {
+                warning: "restarting".to_owned(),
+                message: "The server is restarting, reconnect with your resume token".to_owned(),
+            },
+        };
+        let warning = to_text(&warning, "restarting warning");
+
+        for outbound in sessions {
+            if let Some(warning) = &warning {
+                let _ = outbound.control(warning.clone());
+            }
+
+            let reason = CloseReason {
+                code: CloseCode::Restart,
+                description: Some("Restarting".to_owned()),
+            };
+            outbound.close(Some(reason));
+        }
+
+        self.set_server_state(ServerState::Stopped);
+
+        for namespace in self.namespaces.servers.values() {
+            Box::pin(namespace.shutdown()).await;
+        }
+    }
+
+    /// Check a client message against the runtime policy before it is handled.
+    pub async fn authorize(
+        &self,
+        uuid: &Uuid,
+        message: &WebSocketMessage,
+    ) -> Result<(), PolicyDenied> {
+        let kind = message.r#type.kind();
+        self.authorize_kind(uuid, kind, Scope::required_by(&message.r#type))
+            .await
+    }
+
+    /// Like [`Self::authorize`], for message types the server doesn't know itself.
+    pub async fn authorize_kind(
+        &self,
+        uuid: &Uuid,
+        kind: &str,
+        scope: Scope,
+    ) -> Result<(), PolicyDenied> {
+        let runtime = self.runtime_config();
+        let inner = self.inner.lock().await;
+
+        let Some(data) = inner.sessions.get(uuid) else {
+            return Ok(());
+        };
+
+        let result = runtime.policy.check_kind(kind, scope, &data);
+
+        if let Err(denied) = &result {
+            let action = AuditAction::PolicyDenied {
+                message_type: kind.to_owned(),
+                reason: denied.to_string(),
+            };
+            self.audit(
+                AuditRecord::new(action)
+                    .session(*uuid)
+                    .address(&data.address)
+                    .ip(data.ip),
+            );
+        }
+
+        result
+    }
+
+    /// Subscribe a session to a topic, unless it is already at its subscription limit.
+    pub async fn subscribe_to_event(
+        &self,
+        uuid: &Uuid,
+        event: WebSocketSubscriptionType,
+    ) -> Result<(), TooManySubscriptions> {
+        let inner = self.inner.lock().await;
+
+        let entry = inner.sessions.get_mut(uuid);
+        if let Some(mut data) = entry {
+            let max = self.config.max_subscriptions;
+            if !data.subscriptions.contains(&event) && data.subscriptions.len() >= max {
+                return Err(TooManySubscriptions { max });
+            }
+
+            tracing::info!("Session {uuid} subscribed to event {event}");
+            let added = data.subscriptions.insert(event.clone());
+            inner.index_topic(event.clone(), *uuid);
+            self.persist_session(*uuid, &data);
+
+            if added {
+                if event == WebSocketSubscriptionType::Stats {
+                    self.start_stats_ticker();
+                }
+                self.emit(GatewayEvent::SubscriptionChanged {
+                    session: *uuid,
+                    topic: event,
+                    subscribed: true,
+                });
+            }
+        } else {
+            tracing::info!("Tried to subscribe to event {event} but found a non-existent session");
+        }
+
+        Ok(())
+    }
+
+    pub async fn unsubscribe_from_event(&self, uuid: &Uuid, event: &WebSocketSubscriptionType) {
+        let inner = self.inner.lock().await;
+
+        let entry = inner.sessions.get_mut(uuid);
+        if let Some(mut data) = entry {
+            tracing::info!("Session {uuid} unsubscribed from event {event}");
+            let removed = data.subscriptions.remove(event);
+            data.filters.remove(event);
+            inner.unindex_topic(event, *uuid);
+            self.persist_session(*uuid, &data);
+
+            if removed {
+                self.emit(GatewayEvent::SubscriptionChanged {
+                    session: *uuid,
+                    topic: event.clone(),
+                    subscribed: false,
+                });
+            }
+        }
+    }
+
+    /// Narrow down which events of a subscription are delivered to a session, or clear its filter.
+    pub async fn set_event_filter(
+        &self,
+        uuid: &Uuid,
+        event: WebSocketSubscriptionType,
+        filter: Option<EventFilter>,
+    ) {
+        let inner = self.inner.lock().await;
+
+        if let Some(data) = inner.sessions.get(uuid) {
+            match filter {
+                Some(filter) => data.filters.insert(event, filter),
+                None => data.filters.remove(&event).map(|(_, filter)| filter),
+            };
+        }
+    }
+
+    /// Events delivered and dropped per subscription since the session connected.
+    pub async fn get_subscription_stats(
+        &self,
+        uuid: &Uuid,
+    ) -> Option<HashMap<WebSocketSubscriptionType, SubscriptionStats>> {
+        let inner = self.inner.lock().await;
+        let data = inner.sessions.get(uuid)?;
+
+        Some(
+            data.stats
+                .iter()
+                .map(|x| (x.key().clone(), *x.value()))
+                .collect(),
+        )
+    }
+
+    /// A session's subscriptions, sorted so responses come out the same every time.
+    pub async fn get_subscription_list(&self, uuid: &Uuid) -> WebSocketSubscriptionList {
+        let inner = self.inner.lock().await;
+
+        let entry = inner.sessions.get(uuid);
+        if let Some(data) = entry {
+            return data.subscriptions.clone();
+        }
+
+        WebSocketSubscriptionList::new()
+    }
+
+    /// Attach tags to a session, returning its full set of tags, or `None` if it isn't connected.
+    ///
+    /// Either every tag is attached or, if any of them is refused, none are.
+    pub async fn tag_session(
+        &self,
+        uuid: &Uuid,
+        tags: impl IntoIterator<Item = String>,
+    ) -> anyhow::Result<Option<Vec<String>>> {
+        let tags: HashSet<String> = tags.into_iter().collect();
+        if tags
+            .iter()
+            .any(|tag| tag.is_empty() || tag.len() > MAX_TAG_LENGTH)
+        {
+            return Err(anyhow!(
+                "Tags must be between 1 and {MAX_TAG_LENGTH} bytes long"
+            ));
+        }
+
+        let inner = self.inner.lock().await;
+        let Some(data) = inner.sessions.get(uuid) else {
+            return Ok(None);
+        };
+
+        let added = tags.iter().filter(|tag| !data.tags.contains(*tag)).count();
+        if data.tags.len() + added > MAX_SESSION_TAGS {
+            return Err(anyhow!("Sessions can have at most {MAX_SESSION_TAGS} tags"));
+        }
+        for tag in tags {
+            data.tags.insert(tag);
+        }
+
+        Ok(Some(data.tags.iter().map(|x| x.clone()).collect()))
+    }
+
+    /// Remove a tag from a session, returning its remaining tags, or `None` if it isn't connected.
+    pub async fn untag_session(&self, uuid: &Uuid, tag: &str) -> Option<Vec<String>> {
+        let inner = self.inner.lock().await;
+        let data = inner.sessions.get(uuid)?;
+        data.tags.remove(tag);
+
+        Some(data.tags.iter().map(|x| x.clone()).collect())
+    }
+
+    /// The outbound queue of a session connected to this node.
+    pub async fn outbound(&self, uuid: &Uuid) -> Option<OutboundQueue> {
+        let inner = self.inner.lock().await;
+        let data = inner.sessions.get(uuid)?;
+
+        Some(data.outbound.clone())
+    }
+
+    pub async fn session_address(&self, uuid: &Uuid) -> Option<String> {
+        let inner = self.inner.lock().await;
+        let data = inner.sessions.get(uuid)?;
+
+        Some(data.address.clone())
+    }
+
+    /// Where a session is in its lifecycle, or `Closed` if it is no longer connected.
+    pub async fn session_state(&self, uuid: &Uuid) -> SessionState {
+        let inner = self.inner.lock().await;
+        inner
+            .sessions
+            .get(uuid)
+            .map_or(SessionState::Closed, |data| data.state)
+    }
+
+    /// Move a session to `next`, returning whether that is a valid transition from its current state.
+    pub async fn transition(&self, uuid: &Uuid, next: SessionState) -> bool {
+        let inner = self.inner.lock().await;
+        let Some(mut data) = inner.sessions.get_mut(uuid) else {
+            return false;
+        };
+
+        if !data.state.can_transition_to(next) {
+            tracing::warn!(
+                "Refusing to move session {uuid} from {:?} to {next:?}",
+                data.state
+            );
+            return false;
+        }
+
+        tracing::debug!("Session {uuid} is now {next:?}");
+        let previous = std::mem::replace(&mut data.state, next);
+
+        let audited = match (previous, next) {
+            (_, SessionState::Authenticated) => Some(AuditAction::Login),
+            (SessionState::Authenticated, SessionState::Ready) => Some(AuditAction::Logout),
+            _ => None,
+        };
+        if let Some(action) = audited {
+            self.audit(
+                AuditRecord::new(action)
+                    .session(*uuid)
+                    .address(&data.address)
+                    .ip(data.ip),
+            );
+        }
+
+        if next == SessionState::Authenticated {
+            self.emit(GatewayEvent::Login {
+                session: *uuid,
+                address: data.address.clone(),
+            });
+            let session = SessionHandle::new(*uuid, &data.address, self.clone());
+            self.listeners.login(session);
+        }
+        true
+    }
+
+    /// Fold a round-trip sample into the session's smoothed latency, returning the new estimate.
+    pub async fn record_latency(&self, uuid: &Uuid, sample: Duration) -> Option<Duration> {
+        let inner = self.inner.lock().await;
+
+        let mut data = inner.sessions.get_mut(uuid)?;
+        let latency = match data.latency {
+            Some(latency) => (latency * 7 + sample) / 8,
+            None => sample,
+        };
+        data.latency = Some(latency);
+
+        Some(latency)
+    }
+
+    /// Change the address a session is logged in as, on login or logout. Returns whether the
+    /// session was found.
+    pub async fn set_session_address(&self, uuid: &Uuid, address: String) -> bool {
+        let previous = {
+            let inner = self.inner.lock().await;
+            let Some(mut data) = inner.sessions.get_mut(uuid) else {
+                return false;
+            };
+            let previous = std::mem::replace(&mut data.address, address.clone());
+            self.persist_session(*uuid, &data);
+            drop(data);
+
+            inner.unindex_address(&previous, *uuid);
+            inner.index_address(&address, *uuid);
+            previous
+        };
+
+        #[cfg(feature = "cluster")]
+        if let Some(cluster) = &self.cluster {
+            if let Err(e) = cluster.deregister_session(*uuid, &previous).await {
+                tracing::warn!("Failed to deregister session {uuid} from the cluster: {e}");
+            }
+            if let Err(e) = cluster.register_session(*uuid, &address).await {
+                tracing::warn!("Failed to register session {uuid} with the cluster: {e}");
+            }
+        }
+
+        tracing::debug!("Session {uuid} changed address from {previous} to {address}");
+        true
+    }
+
+    /// The sessions on this node logged in as `address`.
+    pub async fn sessions_for_address(&self, address: &str) -> HashSet<Uuid> {
+        let inner = self.inner.lock().await;
+        inner
+            .addresses
+            .get(address)
+            .map(|x| x.clone())
+            .unwrap_or_default()
+    }
+
+    /// The sessions on this node subscribed to `topic`.
+    pub async fn sessions_for_topic(&self, topic: &WebSocketSubscriptionType) -> HashSet<Uuid> {
+        let inner = self.inner.lock().await;
+        inner
+            .topics
+            .get(topic)
+            .map(|x| x.clone())
+            .unwrap_or_default()
+    }
+
+    pub async fn list_sessions(&self) -> Vec<WebSocketSessionInfo> {
+        let inner = self.inner.lock().await;
+
+        inner
+            .sessions
+            .iter()
+            .map(|entry| WebSocketSessionInfo {
+                uuid: *entry.key(),
+                address: entry.address.clone(),
+                ip: entry.ip,
+                state: entry.state,
+                subscriptions: entry.subscriptions.clone(),
+                tags: entry.tags.iter().map(|x| x.clone()).collect(),
+                latency_ms: entry.latency.map(|x| x.as_secs_f64() * 1000.0),
+                queue_depth: entry.outbound.depth(),
+                expired_events: entry.outbound.expired(),
+            })
+            .collect()
+    }
+
+    /// Broadcast a message to all connected clients, including those on other cluster nodes
+    pub async fn broadcast(&self, msg: impl Into<ByteString>) -> Result<(), ServerDraining> {
+        self.broadcast_with(msg, BroadcastMode::AwaitEnqueue)
+            .await
+            .map(|_| ())
+    }
+
+    /// Broadcast a message like [`Self::broadcast`], choosing how long to wait on it.
+    ///
+    /// Only sessions connected to this node are counted in the report.
+    pub async fn broadcast_with(
+        &self,
+        msg: impl Into<ByteString>,
+        mode: BroadcastMode,
+    ) -> Result<BroadcastReport, ServerDraining> {
+        let msg = msg.into();
+        if self.shutting_down.is_cancelled() {
+            return self
+                .hold_for_restart(None, &msg)
+                .map(|()| BroadcastReport::default());
+        }
+
+        let timeout = match mode {
+            BroadcastMode::FireAndForget => {
+                let server = self.clone();
+                tokio::spawn(async move { server.fan_out(msg, false).await });
+                return Ok(BroadcastReport::default());
+            }
+            BroadcastMode::AwaitEnqueue => None,
+            BroadcastMode::AwaitDelivery(timeout) => Some(timeout),
+        };
+
+        let pushed = self.fan_out(msg, timeout.is_some()).await;
+        let mut report = BroadcastReport {
+            queued: pushed.queued,
+            delivered: 0,
+        };
+
+        if let Some(timeout) = timeout {
+            let deadline = Instant::now() + timeout;
+            let mut written: FuturesUnordered<_> = pushed.written.into_iter().collect();
+            while let Ok(Some(result)) = tokio::time::timeout_at(deadline, written.next()).await {
+                if result.is_ok() {
+                    report.delivered += 1;
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Hand a broadcast to the cluster and bridges, and queue it for the sessions on this node.
+    async fn fan_out(&self, msg: ByteString, track_writes: bool) -> Pushed {
+        #[cfg(feature = "cluster")]
+        if let Some(cluster) = &self.cluster
+            && let Err(e) = cluster.publish(None, &msg).await
+        {
+            tracing::error!("Failed to publish broadcast to cluster: {e}");
+        }
+
+        #[cfg(feature = "mqtt")]
+        if let Some(mqtt) = &self.mqtt {
+            mqtt.publish(None, &msg).await;
+        }
+
+        let msg = self.record(None, msg);
+        self.deliver(|_| true, msg, track_writes).await
+    }
+
+    /// Broadcast a message at a later point in time.
+    pub fn broadcast_at(
+        &self,
+        at: impl Into<Instant>,
+        msg: impl Into<ByteString>,
+    ) -> BroadcastHandle {
+        self.scheduler
+            .get_or_init(|| Scheduler::spawn(self.clone()))
+            .schedule(at.into(), msg.into())
+    }
+
+    /// Broadcast a message once `delay` has passed.
+    pub fn broadcast_after(&self, delay: Duration, msg: impl Into<ByteString>) -> BroadcastHandle {
+        self.broadcast_at(Instant::now() + delay, msg)
+    }
+
+    /// Broadcast a message to the clients connected to this node only
+    pub async fn broadcast_local(&self, msg: impl Into<ByteString>) {
+        let msg = self.record(None, msg.into());
+        self.deliver(|_| true, msg, false).await;
+    }
+
+    /// Send a message to every session logged in as `address`, wherever in the cluster it is connected
+    pub async fn send_to_address(&self, address: &str, msg: impl Into<ByteString>) {
+        let msg = msg.into();
+
+        #[cfg(feature = "cluster")]
+        if let Some(cluster) = &self.cluster
+            && let Err(e) = cluster.send_to_address(address, &msg).await
+        {
+            tracing::error!("Failed to send message to {address} through the cluster: {e}");
+        }
+
+        self.send_to_address_local(address, msg).await;
+    }
+
+    /// Send a message to the sessions on this node logged in as `address`
+    pub async fn send_to_address_local(&self, address: &str, msg: impl Into<ByteString>) {
+        let recipients: Vec<_> = {
+            let inner = self.inner.lock().await;
+            let Some(uuids) = inner.addresses.get(address).map(|x| x.clone()) else {
+                return;
+            };
+            uuids
+                .into_iter()
+                .filter_map(|uuid| {
+                    let data = inner.sessions.get(&uuid)?;
+                    Some((uuid, data.outbound.clone(), data.encoding))
+                })
+                .collect()
+        };
+
+        let pushed = self
+            .push_events(recipients, msg.into(), None, None, false)
+            .await;
+        self.evict(pushed.evicted).await;
+    }
+
+    /// Send a message to every session carrying `tag`, wherever in the cluster it is connected
+    pub async fn broadcast_to_tag(&self, tag: &str, msg: impl Into<ByteString>) {
+        let msg = msg.into();
+
+        #[cfg(feature = "cluster")]
+        if let Some(cluster) = &self.cluster
+            && let Err(e) = cluster.broadcast_to_tag(tag, &msg).await
+        {
+            tracing::error!("Failed to broadcast to tag {tag} through the cluster: {e}");
+        }
+
+        self.broadcast_to_tag_local(tag, msg).await;
+    }
+
+    /// Send a message to the sessions on this node carrying `tag`
+    pub async fn broadcast_to_tag_local(&self, tag: &str, msg: impl Into<ByteString>) {
+        self.deliver(|data| data.tags.contains(tag), msg.into(), false)
+            .await;
+    }
+
+    /// Send an event to every client subscribed to `topic`, including those on other cluster nodes
+    pub async fn publish(
+        &self,
+        topic: WebSocketSubscriptionType,
+        msg: impl Into<ByteString>,
+    ) -> Result<(), ServerDraining> {
+        let msg = msg.into();
+        if self.shutting_down.is_cancelled() {
+            return self.hold_for_restart(Some(&topic), &msg);
+        }
+
+        #[cfg(feature = "cluster")]
+        if let Some(cluster) = &self.cluster
+            && let Err(e) = cluster.publish(Some(&topic), &msg).await
+        {
+            tracing::error!("Failed to publish event to cluster: {e}");
+        }
+
+        #[cfg(feature = "mqtt")]
+        if let Some(mqtt) = &self.mqtt {
+            mqtt.publish(Some(&topic), &msg).await;
+        }
+
+        self.publish_local(topic, msg).await;
+        Ok(())
+    }
+
+    /// Send an event to the clients on this node subscribed to `topic`
+    pub async fn publish_local(
+        &self,
+        topic: WebSocketSubscriptionType,
+        msg: impl Into<ByteString>,
+    ) {
+        #[cfg(feature = "krist")]
+        if topic == WebSocketSubscriptionType::Transactions {
+            self.transactions.record();
+        }
+
+        let msg = self.record(Some(&topic), msg.into());
+
+        if let Some(&window) = self.runtime_config().coalesce_ms.get(&topic) {
+            self.coalesce(topic, Duration::from_millis(window), msg);
+            return;
+        }
+
+        let ttl = self.event_ttl(&topic);
+        let payload = msg.clone();
+        let event = std::sync::OnceLock::new();
+        let wants = |data: &WebSocketSessionData| {
+            data.filters
+                .get(&topic)
+                .is_none_or(|filter| filter.matches(event.get_or_init(|| parse_event(&payload))))
+        };
+
+        self.deliver_to_topic(&topic, wants, msg, 1, ttl).await;
+    }
+
+    /// Tell everyone subscribed to `names` that a name changed, and its owner's sessions if
+    /// they're only subscribed to `ownNames`.
+    #[cfg(feature = "krist")]
+    async fn publish_name(&self, record: &NameRecord) -> Result<(), ServerDraining> {
+        self.publish_owned(
+            WebSocketSubscriptionType::Names,
+            WebSocketSubscriptionType::OwnNames,
+            &record.owner,
+            record.event().to_string(),
+        )
+        .await
+    }
+
+    /// Tell everyone subscribed to `balances` that an address's balance changed, and that
+    /// address's sessions if they're only subscribed to `ownBalances`.
+    #[cfg(feature = "krist")]
+    pub async fn publish_balance(&self, change: &BalanceChange) -> Result<(), ServerDraining> {
+        self.publish_owned(
+            WebSocketSubscriptionType::Balances,
+            WebSocketSubscriptionType::OwnBalances,
+            &change.address,
+            change.event().to_string(),
+        )
+        .await
+    }
+
+    /// Give `address` the economy's starting balance the first time `balances` sees it.
+    #[cfg(feature = "krist")]
+    async fn open_account(&self, balances: &dyn BalanceStore, address: &str) -> anyhow::Result<()> {
+        let starting_balance = self.config.economy.starting_balance;
+        if let Some(change) = balances.open(address, starting_balance.into())?
+            && change.new > 0
+        {
+            let _ = self.publish_balance(&change).await;
+        }
+
+        Ok(())
+    }
+
+    /// Publish `event` to `topic`, then deliver it to `owner`'s sessions subscribed to `own_topic`
+    /// but not `topic`, so they don't get it twice, wherever in the cluster they are connected.
+    #[cfg(feature = "krist")]
+    async fn publish_owned(
+        &self,
+        topic: WebSocketSubscriptionType,
+        own_topic: WebSocketSubscriptionType,
+        owner: &str,
+        event: String,
+    ) -> Result<(), ServerDraining> {
+        if self.shutting_down.is_cancelled() {
+            return self.hold_for_restart(Some(&topic), &event);
+        }
+
+        #[cfg(feature = "cluster")]
+        if let Some(cluster) = &self.cluster
+            && let Err(e) = cluster
+                .publish_owned(&topic, &own_topic, owner, &event)
+                .await
+        {
+            tracing::error!("Failed to publish event to cluster: {e}");
+        }
+
+        #[cfg(feature = "mqtt")]
+        if let Some(mqtt) = &self.mqtt {
+            mqtt.publish(Some(&topic), &event).await;
+        }
+
+        self.publish_owned_local(topic, own_topic, owner, event)
+            .await;
+        Ok(())
+    }
+
+    /// Like [`Self::publish_owned`], for the sessions on this node only.
+    #[cfg(feature = "krist")]
+    pub(crate) async fn publish_owned_local(
+        &self,
+        topic: WebSocketSubscriptionType,
+        own_topic: WebSocketSubscriptionType,
+        owner: &str,
+        event: impl Into<ByteString>,
+    ) {
+        let event = event.into();
+        self.publish_local(topic.clone(), event.clone()).await;
+
+        let ttl = self.event_ttl(&own_topic);
+        let owned = |data: &WebSocketSessionData| {
+            data.address == owner && !data.subscriptions.contains(&topic)
+        };
+        self.deliver_to_topic(&own_topic, owned, event, 1, ttl)
+            .await;
+    }
+
+    /// How long the handler for messages of type `kind` may take before it's given up on.
+    fn handler_timeout(&self, kind: &str) -> Option<Duration> {
+        let runtime = self.runtime_config();
+        runtime
+            .handler_timeout_ms
+            .get(kind)
+            .or_else(|| runtime.handler_timeout_ms.get("*"))
+            .map(|&x| Duration::from_millis(x))
+    }
+
+    /// How long events for `topic` may sit in a session's queue before they are dropped.
+    fn event_ttl(&self, topic: &WebSocketSubscriptionType) -> Option<Duration> {
+        let runtime = self.runtime_config();
+        runtime
+            .event_ttl_ms
+            .get(topic)
+            .map(|&x| Duration::from_millis(x))
+    }
+
+    /// Hold an event back until its topic's coalescing window closes, then send everything held as one batch.
+    fn coalesce(&self, topic: WebSocketSubscriptionType, window: Duration, msg: ByteString) {
+        let mut pending = self.pending_batches.entry(topic.clone()).or_default();
+        pending.push(msg);
+
+        // The first event in a window is responsible for flushing it
+        if pending.len() == 1 {
+            let server = self.clone();
+            tokio::spawn(async move {
+                time::sleep(window).await;
+                server.flush_batch(topic).await;
+            });
+        }
+    }
+
+    async fn flush_batch(&self, topic: WebSocketSubscriptionType) {
+        let Some((_topic, events)) = self.pending_batches.remove(&topic) else {
+            return;
+        };
+
+        let events: Vec<(ByteString, serde_json::Value)> = events
+            .into_iter()
+            .map(|x| {
+                let event = parse_event(&x);
+                (x, event)
+            })
+            .collect();
+        let ttl = self.event_ttl(&topic);
+
+        // Sessions filtering the topic each get a batch of just the events they asked for
+        let filters: Vec<EventFilter> = {
+            let inner = self.inner.lock().await;
+            let mut filters = Vec::new();
+            for entry in inner.sessions.iter() {
+                if let Some(filter) = entry.filters.get(&topic)
+                    && !filters.contains(&*filter)
+                {
+                    filters.push(filter.clone());
+                }
+            }
+            filters
+        };
+
+        if let Some(msg) = batch_message(events.iter().collect()) {
+            let wants = |data: &WebSocketSessionData| !data.filters.contains_key(&topic);
+            let count = events.len() as u64;
+            self.deliver_to_topic(&topic, wants, msg, count, ttl).await;
+        }
+
+        for filter in filters {
+            let matching: Vec<_> = events
+                .iter()
+                .filter(|(_, event)| filter.matches(event))
+                .collect();
+            let count = matching.len() as u64;
+            let Some(msg) = batch_message(matching) else {
+                continue;
+            };
+
+            let wants = |data: &WebSocketSessionData| {
+                data.filters.get(&topic).is_some_and(|x| *x == filter)
+            };
+            self.deliver_to_topic(&topic, wants, msg, count, ttl).await;
+        }
+    }
+
+    /// Append an event to the journal, if there is one, returning the payload to deliver.
+    fn record(&self, topic: Option<&WebSocketSubscriptionType>, msg: ByteString) -> ByteString {
+        let Some(journal) = &self.journal else {
+            return msg;
+        };
+
+        match journal.append(topic, &msg) {
+            Ok(entry) => entry.payload.into(),
+            Err(e) => {
+                tracing::error!("Failed to append event to journal: {e}");
+                msg
+            }
+        }
+    }
+
+    /// Resend journaled events a session missed while it was disconnected.
+    pub async fn replay(&self, uuid: &Uuid, since_seq: u64) {
+        let Some(journal) = &self.journal else {
+            return;
+        };
+
+        let mut entries = match journal.since(since_seq, MAX_REPLAY_EVENTS) {
+            Ok(entries) => entries,
+            Err(e) => {
+                tracing::error!("Failed to read journal for replay: {e}");
+                return;
+            }
+        };
+
+        let outbound = {
+            let inner = self.inner.lock().await;
+            let Some(data) = inner.sessions.get(uuid) else {
+                return;
+            };
+
+            // Filter up front so the server isn't locked while waiting on the queue
+            entries.retain(|entry| {
+                entry
+                    .topic
+                    .as_ref()
+                    .is_none_or(|topic| data.subscriptions.contains(topic))
+            });
+
+            data.outbound.clone()
+        };
+
+        tracing::info!("Replaying {} events to session {uuid}", entries.len());
+        for entry in entries {
+            if let Some(payload) = outbound.intercept(MessageKind::Event, entry.payload.into()) {
+                outbound.push(payload).await;
+            }
+        }
+    }
+
+    /// Queue `msg` for every session `filter` picks.
+    async fn deliver(
+        &self,
+        filter: impl Fn(&WebSocketSessionData) -> bool,
+        msg: ByteString,
+        track_writes: bool,
+    ) -> Pushed {
+        let recipients: Vec<_> = {
+            let inner = self.inner.lock().await;
+            inner
+                .sessions
+                .iter()
+                .filter(|entry| filter(entry.value()))
+                .map(|entry| (*entry.key(), entry.outbound.clone(), entry.encoding))
+                .collect()
+        };
+
+        let mut pushed = self
+            .push_events(recipients, msg, None, None, track_writes)
+            .await;
+        self.evict(std::mem::take(&mut pushed.evicted)).await;
+        pushed
+    }
+
+    /// Queue `count` events in `msg` for the sessions subscribed to `topic` that `filter` picks.
+    async fn deliver_to_topic(
+        &self,
+        topic: &WebSocketSubscriptionType,
+        filter: impl Fn(&WebSocketSessionData) -> bool,
+        msg: ByteString,
+        count: u64,
+        ttl: Option<Duration>,
+    ) {
+        let recipients: Vec<_> = {
+            let inner = self.inner.lock().await;
+            let Some(uuids) = inner.topics.get(topic).map(|x| x.clone()) else {
+                return;
+            };
+            uuids
+                .into_iter()
+                .filter_map(|uuid| {
+                    let data = inner.sessions.get(&uuid)?;
+                    filter(&data).then(|| (uuid, data.outbound.clone(), data.encoding))
+                })
+                .collect()
+        };
+
+        let pushed = self
+            .push_events(recipients, msg, Some((topic, count)), ttl, false)
+            .await;
+        self.evict(pushed.evicted).await;
+    }
+
+    /// Queue `msg` for `recipients`, noting the sessions that lagged for too long, and optionally
+    /// how to tell when it's been written to the others.
+    ///
+    /// Pushing can wait on a full queue, so recipients are picked beforehand and the server isn't
+    /// locked meanwhile.
+    async fn push_events(
+        &self,
+        recipients: Vec<(Uuid, OutboundQueue, Encoding)>,
+        msg: ByteString,
+        topic: Option<(&WebSocketSubscriptionType, u64)>,
+        ttl: Option<Duration>,
+        track_writes: bool,
+    ) -> Pushed {
+        let mut futures = FuturesUnordered::new();
+        let event = EncodedEvent::new(msg);
+
+        for (uuid, outbound, encoding) in recipients {
+            let Some(msg) = outbound.intercept_event(&event, encoding) else {
+                continue;
+            };
+
+            futures.push(async move {
+                match track_writes {
+                    true => {
+                        let (outcome, written) = outbound.push_tracked(msg, ttl).await;
+                        (uuid, outcome, Some(written))
+                    }
+                    false => (uuid, outbound.push_with_ttl(msg, ttl).await, None),
+                }
+            });
+        }
+
+        let mut pushed = Pushed::default();
+        let mut outcomes = Vec::new();
+        while let Some((uuid, outcome, written)) = futures.next().await {
+            if topic.is_some() {
+                outcomes.push((uuid, outcome));
+            }
+
+            match outcome {
+                PushOutcome::Queued => {
+                    pushed.queued += 1;
+                    pushed.written.extend(written);
+                }
+                PushOutcome::Dropped => {
+                    tracing::debug!("Dropped event for lagging session {uuid}");
+                    self.emit(GatewayEvent::EventDropped {
+                        session: uuid,
+                        topic: topic.map(|(topic, _)| topic.clone()),
+                    });
+                }
+                PushOutcome::Evict => pushed.evicted.push(uuid),
+                PushOutcome::Closed => tracing::warn!("Got an unexpected closed session"),
+            }
+        }
+
+        if let Some((topic, count)) = topic {
+            let inner = self.inner.lock().await;
+            for (uuid, outcome) in outcomes {
+                let Some(data) = inner.sessions.get(&uuid) else {
+                    continue;
+                };
+                let mut stats = data.stats.entry(topic.clone()).or_default();
+                match outcome {
+                    PushOutcome::Queued => stats.delivered += count,
+                    PushOutcome::Dropped | PushOutcome::Evict => stats.dropped += count,
+                    PushOutcome::Closed => {}
+                }
+            }
+        }
+
+        pushed
+    }
+
+    async fn evict(&self, evicted: Vec<Uuid>) {
+        for uuid in evicted {
+            tracing::warn!("Disconnecting slow consumer {uuid}");
+            if let Some(data) = self.cleanup_session(&uuid).await {
+                let reason = CloseReason {
+                    code: CloseCode::Policy,
+                    description: Some("SlowConsumer".to_owned()),
+                };
+                data.outbound.close(Some(reason));
+            }
+        }
+    }
+}
+
+/// Register the public gateway routes.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(ws_handler)
+        .service(start_ws)
+        .service(drain_ws)
+        .service(get_events);
+    #[cfg(feature = "socketio")]
+    cfg.service(crate::socketio::socketio_handler);
+}
+
+#[post("/ws/start")]
+pub async fn start_ws(
+    req: HttpRequest,
+    server: web::Data<WebSocketServer>,
+    body: web::Bytes,
+) -> Result<HttpResponse, actix_web::Error> {
+    let details = parse_start_body(&body)?;
+    let server = server.for_request(&req)?;
+    if server.server_state() != ServerState::Running {
+        return Err(ErrorServiceUnavailable("The server is draining"));
+    }
+    let ip = server.client_ip(&req);
+
+    if let (Some(ip), Some(limit)) = (ip, server.runtime_config().start_rate_limit)
+        && !server.start_limiter.check(ip, limit)
+    {
+        return Err(ErrorTooManyRequests("Too many requests"));
+    }
+
+    if let Some(scopes) = &details.scopes
+        && scopes.contains(&Scope::Admin)
+    {
+        admin::require_admin(&req, &server)?;
+    }
+
+    let mut token_data = match (details.resume_token, details.private_key) {
+        (Some(resume_token), _) => {
+            let Some(resume) = server.redeem_resume_token(&resume_token).await else {
+                return Err(ErrorBadRequest("Unknown or expired resume token"));
+            };
+
+            WebSocketTokenData {
+                address: resume.address.clone(),
+                private_key: resume.private_key.clone(),
+                scopes: resume.scopes.clone(),
+                resume: Some(resume),
+                encoding: Encoding::default(),
+            }
+        }
+        (None, Some(private_key)) => {
+            let address = address::from_private_key(private_key.expose());
+            WebSocketTokenData {
+                private_key: Some(private_key),
+                ..WebSocketTokenData::new(address, None)
+            }
+        }
+        (None, None) => WebSocketTokenData::guest(),
+    };
+    if token_data.private_key.is_none() && !server.config().allow_guests {
+        return Err(ErrorUnauthorized("Guest connections are disabled"));
+    }
+    if let Some(scopes) = details.scopes {
+        // Resuming must not be a way to get more than the original token allowed
+        if token_data.resume.is_some() && !scopes.is_subset(&token_data.scopes) {
+            return Err(InvalidRequest::parameter(
+                "scopes",
+                "Can't widen the scopes of a resumed session",
+            )
+            .into());
+        }
+        token_data.scopes = scopes;
+    }
+
+    let address = token_data.address.clone();
+    server.admit(&address, ip).await?;
+    let token = server.obtain_token(token_data).await;
+
+    server.audit(
+        AuditRecord::new(AuditAction::TokenIssued)
+            .address(address)
+            .ip(ip),
+    );
+
+    let response = WebSocketStartResponse {
+        ok: true,
+        url: format!("{}/gateway/{token}", server.config().public_url),
+        expires: 30,
+    };
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Stop issuing tokens and tell every session to reconnect, ahead of this instance being replaced.
+#[post("/ws/drain")]
+pub async fn drain_ws(
+    req: HttpRequest,
+    server: web::Data<WebSocketServer>,
+    body: Option<web::Json<DrainRequest>>,
+) -> Result<HttpResponse, actix_web::Error> {
+    admin::require_admin(&req, &server)?;
+
+    let request = body.map(web::Json::into_inner).unwrap_or_default();
+    let sessions = server.drain(Duration::from_millis(request.window_ms)).await;
+
+    Ok(HttpResponse::Ok().json(DrainResponse { ok: true, sessions }))
+}
+
+#[get("/events")]
+pub async fn get_events(
+    req: HttpRequest,
+    server: web::Data<WebSocketServer>,
+    query: web::Query<EventsQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let server = server.for_request(&req)?;
+    let Some(journal) = server.journal() else {
+        return Err(actix_web::error::ErrorNotFound(
+            "Event journal is not enabled",
+        ));
+    };
+
+    let events = journal
+        .page(
+            Order::Asc,
+            query.offset,
+            pagination::page_size(query.limit),
+            |x| x.seq > query.since_seq && (query.topic.is_none() || x.topic == query.topic),
+        )
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    let latest_seq = journal
+        .latest_seq()
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(EventsResponse {
+        ok: true,
+        latest_seq,
+        events,
+    }))
+}
+
+/// Upgrade a connection redeeming a `/ws/start` token into a gateway session.
+///
+/// This is the HTTP/1.1 `Upgrade` handshake. actix-http can't accept extended `CONNECT`s, so
+/// WebSockets over HTTP/2 (RFC 8441) get a listener of their own in the `http2` module, behind the
+/// feature of the same name.
+#[get("/gateway/{token}")]
+#[instrument(
+    name = "session",
+    skip_all,
+    fields(session_id = *token, address = field::Empty, ip = field::Empty)
+)]
+pub async fn ws_handler(
+    req: HttpRequest,
+    body: web::Payload,
+    token: web::Path<String>,
+    query: web::Query<GatewayQuery>,
+    server: web::Data<WebSocketServer>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let token = token.into_inner();
+    let server = server.into_inner(); // guh but okay
+    let (response, session, stream) = actix_ws::handle(&req, body)?;
+
+    let stream = stream
+        .max_frame_size(server.config().max_frame_size)
+        .aggregate_continuations()
+        .max_continuation_size(server.config().max_continuation_size);
+
+    let ip = server.client_ip(&req);
+    let Admitted {
+        server,
+        token,
+        mut data,
+    } = match runner::admit(server.clone(), &token, ip).await {
+        Ok(admitted) => admitted,
+        Err(Refusal::InvalidToken(error)) => {
+            return reject_token(&server, response, session, error);
+        }
+        Err(Refusal::Overloaded(window)) => {
+            return Ok(advise_reconnect(response, session, window, "overloaded"));
+        }
+        Err(refusal) => {
+            let status =
+                StatusCode::from_u16(refusal.status()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+            return Err(InternalError::new(refusal.to_string(), status).into());
+        }
+    };
+    data.encoding = query.encoding;
+
+    let connection = Connection {
+        token,
+        data,
+        sink: session,
+        ip,
+        codec: std::convert::identity,
+        since_seq: query.since_seq,
+        heartbeat: Heartbeat::Ping,
+    };
+    if let Some(running) = runner::start(server, connection).await {
+        actix_web::rt::spawn(running.run(stream));
+    }
+
+    Ok(response)
+}
+
+/// An HTTP request body that failed validation, answered with the gateway's error message shape.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidRequest {
+    pub error: &'static str,
+    pub message: String,
+    /// The field at fault, if the problem is down to one.
+    pub parameter: Option<String>,
+}
+
+impl InvalidRequest {
+    fn parameter(parameter: &str, message: impl Into<String>) -> Self {
+        Self {
+            error: "invalid_parameter",
+            message: message.into(),
+            parameter: Some(parameter.to_owned()),
+        }
+    }
+}
+
+impl std::fmt::Display for InvalidRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.parameter {
+            Some(parameter) => write!(f, "{}: {}", parameter, self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+impl actix_web::ResponseError for InvalidRequest {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        actix_web::http::StatusCode::BAD_REQUEST
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::BadRequest().json(WebSocketMessage {
+            ok: Some(false),
+            id: None,
+            trace_id: None,
+            idempotency_key: None,
+            r#type: WebSocketMessageInner::Error {
+                error: self.error.to_owned(),
+                message: self.message.clone(),
+                parameter: self.parameter.clone(),
+            },
+        })
+    }
+}
+
+/// Validate a `/ws/start` body. An empty body connects as a guest, and `null` fields count as unset.
+pub fn parse_start_body(body: &[u8]) -> Result<WebSocketStartConnectionBody, InvalidRequest> {
+    if body.trim_ascii().is_empty() {
+        return Ok(WebSocketStartConnectionBody::default());
+    }
+
+    let body: serde_json::Value = serde_json::from_slice(body).map_err(|e| InvalidRequest {
+        error: "invalid_body",
+        message: format!("Body is not valid JSON: {e}"),
+        parameter: None,
+    })?;
+    let Some(body) = body.as_object() else {
+        return Err(InvalidRequest {
+            error: "invalid_body",
+            message: "Body must be a JSON object".to_owned(),
+            parameter: None,
+        });
+    };
+
+    let private_key = match body.get("privatekey") {
+        None | Some(serde_json::Value::Null) => None,
+        Some(serde_json::Value::String(key)) if key.is_empty() => {
+            return Err(InvalidRequest::parameter("privatekey", "Must not be empty"));
+        }
+        Some(serde_json::Value::String(key)) => Some(Secret::new(key.as_str())),
+        Some(_) => return Err(InvalidRequest::parameter("privatekey", "Must be a string")),
+    };
+    let resume_token = match body.get("resume_token") {
+        None | Some(serde_json::Value::Null) => None,
+        Some(token) => match token.as_str().map(Uuid::from_str) {
+            Some(Ok(token)) => Some(token),
+            _ => return Err(InvalidRequest::parameter("resume_token", "Must be a UUID")),
+        },
+    };
+
+    let scopes = match body.get("scopes") {
+        None | Some(serde_json::Value::Null) => None,
+        Some(scopes) => match ScopeList::deserialize(scopes) {
+            Ok(scopes) if scopes.is_empty() => {
+                return Err(InvalidRequest::parameter("scopes", "Must not be empty"));
+            }
+            Ok(scopes) => Some(scopes),
+            Err(_) => {
+                return Err(InvalidRequest::parameter(
+                    "scopes",
+                    "Must be a list of read_only, transact or admin",
+                ));
+            }
+        },
+    };
+
+    Ok(WebSocketStartConnectionBody {
+        private_key,
+        resume_token,
+        scopes,
+    })
+}
+
+/// Why a client message was turned away before reaching a handler.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RejectedMessage {
+    /// The id of the request, if it could be found in the message.
+    pub id: Option<usize>,
+    pub error: &'static str,
+    pub message: String,
+}
+
+/// Parse a JSON message from a client, without handling it.
+pub fn parse_message(
+    string: &str,
+    max_message_size: usize,
+) -> Result<WebSocketMessage, RejectedMessage> {
+    if string.len() > max_message_size {
+        return Err(RejectedMessage {
+            id: None,
+            error: "message_too_large",
+            message: format!("Messages can be at most {max_message_size} bytes"),
+        });
+    }
+
+    serde_json::from_str(string).map_err(|e| {
+        // Still try to tie the error to the request it answers
+        let id = serde_json::from_str::<serde_json::Value>(string)
+            .ok()
+            .and_then(|x| x.get("id")?.as_u64())
+            .map(|x| x as usize);
+
+        RejectedMessage {
+            id,
+            error: "invalid_message",
+            message: e.to_string(),
+        }
+    })
+}
+
+/// Parse and handle a JSON message from a client, answering it through `outbound`.
+pub async fn handle_text(
+    server: &WebSocketServer,
+    outbound: &OutboundQueue,
+    token: Uuid,
+    address: &str,
+    idempotency_scope: &str,
+    string: &str,
+) {
+    if !server.routes.is_empty()
+        && !server.config().echo_mode
+        && string.len() <= server.config().max_message_size
+        && let Ok(payload) = serde_json::from_str::<serde_json::Value>(string)
+        && let Some(kind) = payload.get("type").and_then(|x| x.as_str())
+        && let Some(route) = server.routes.get(kind)
+    {
+        server.sample_message(kind, string.len());
+        let route = route.clone();
+        handle_route(
+            server,
+            outbound,
+            token,
+            address,
+            idempotency_scope,
+            route,
+            payload,
+        )
+        .await;
+        return;
+    }
+
+    let mut msg = match parse_message(string, server.config().max_message_size) {
+        Ok(msg) => msg,
+        Err(rejected) => {
+            server.sample_message("invalid", string.len());
+            tracing::debug!("Received an invalid message: {}", rejected.message);
+            reply_error(
+                outbound,
+                rejected.id,
+                None,
+                rejected.error,
+                rejected.message,
+            )
+            .await;
+            return;
+        }
+    };
+    server.sample_message(msg.r#type.kind(), string.len());
+
+    let span = tracing::info_span!(
+        "ws_message",
+        message_type = msg.r#type.kind(),
+        session_id = %token,
+        address = %address,
+        trace_id = field::Empty,
+        outcome = field::Empty,
+        duration_ms = field::Empty,
+    );
+    let trace_id = msg
+        .trace_id
+        .get_or_insert_with(|| span.in_scope(telemetry::correlation_id))
+        .clone();
+    span.record("trace_id", trace_id);
+
+    let kind = msg.r#type.kind();
+    let started = Instant::now();
+    if server.config().echo_mode {
+        echo(outbound, &msg).await;
+        finish_message(server, &span, kind, started, "echoed");
+        return;
+    }
+
+    let state = server.session_state(&token).await;
+    if let Err(rejected) = state.accepts(&msg.r#type) {
+        span.in_scope(|| tracing::info!("Rejected message while {state:?}"));
+        send_error(outbound, &msg, rejected.code(), rejected.to_string()).await;
+
+        finish_message(server, &span, kind, started, "rejected");
+        return;
+    }
+
+    if msg.r#type.is_state_changing()
+        && let Some(message) = server.maintenance_message()
+    {
+        send_error(outbound, &msg, "server_maintenance", message).await;
+
+        finish_message(server, &span, kind, started, "maintenance");
+        return;
+    }
+
+    if let Err(denied) = server.authorize(&token, &msg).await {
+        span.in_scope(|| tracing::info!("Denied message: {denied}"));
+        send_error(outbound, &msg, denied.code(), denied.to_string()).await;
+
+        finish_message(server, &span, kind, started, "denied");
+        return;
+    }
+
+    if let Err(rejection) = server.middleware.run(token, address, &mut msg) {
+        span.in_scope(|| tracing::info!("Middleware rejected message: {}", rejection.message));
+        send_error(outbound, &msg, &rejection.error, rejection.message).await;
+
+        finish_message(server, &span, kind, started, "rejected");
+        return;
+    }
+
+    if server.listeners.wants_message(msg.r#type.kind()) {
+        let session = SessionHandle::new(token, address, server.clone());
+        server.listeners.message(session, Arc::new(msg.clone()));
+    }
+
+    let idempotency_key = msg
+        .idempotency_key
+        .clone()
+        .filter(|_| msg.r#type.is_state_changing())
+        .map(|key| IdempotencyKey {
+            scope: idempotency_scope.to_owned(),
+            key,
+        });
+
+    if let Some(key) = &idempotency_key {
+        let outcome = match server.idempotency.begin(key.clone()) {
+            Begin::New => None,
+            Begin::Replay(response) => {
+                let response = with_id(response, msg.id);
+                let _ = outbound.respond(response).await;
+                Some("replayed")
+            }
+            Begin::InFlight => {
+                let message = "A message with this ref is still being handled";
+                send_error(outbound, &msg, "duplicate_ref", message.to_owned()).await;
+                Some("duplicate")
+            }
+        };
+
+        if let Some(outcome) = outcome {
+            finish_message(server, &span, kind, started, outcome);
+            return;
+        }
+    }
+
+    #[cfg(feature = "nats")]
+    server.forward_client_message(&token, address, &msg).await;
+
+    let responder = Responder {
+        outbound,
+        id: msg.id,
+        trace_id: msg.trace_id.clone(),
+        idempotency: idempotency_key
+            .as_ref()
+            .map(|key| (&*server.idempotency, key)),
+    };
+    let (id, trace_id) = (msg.id, msg.trace_id.clone());
+    let handled =
+        handle_websocket_message(&responder, &token, server, msg).instrument(span.clone());
+    let result = with_handler_timeout(server, &span, kind, handled).await;
+
+    if !matches!(result, Ok(Ok(())))
+        && let Some(key) = &idempotency_key
+    {
+        server.idempotency.abandon(key);
+    }
+    let outcome = match result {
+        Ok(Ok(())) => "ok",
+        Ok(Err(e)) => {
+            span.in_scope(|| tracing::warn!("Failed to handle message: {e}"));
+            server.emit(GatewayEvent::MessageFailed {
+                session: token,
+                message_type: kind.to_owned(),
+                error: e.to_string(),
+            });
+            "error"
+        }
+        Err(timeout) => {
+            server.emit(GatewayEvent::MessageFailed {
+                session: token,
+                message_type: kind.to_owned(),
+                error: "Timed out".to_owned(),
+            });
+            let message = timeout_message(timeout);
+            reply_error(outbound, id, trace_id, "operation_timeout", message).await;
+            "timeout"
+        }
+    };
+    finish_message(server, &span, kind, started, outcome);
+}
+
+/// Run the handler for a message of type `kind`, giving up on it if it takes longer than the
+/// type's configured timeout.
+async fn with_handler_timeout<T>(
+    server: &WebSocketServer,
+    span: &tracing::Span,
+    kind: &str,
+    handler: impl Future<Output = T>,
+) -> Result<T, Duration> {
+    let Some(timeout) = server.handler_timeout(kind) else {
+        return Ok(handler.await);
+    };
+
+    time::timeout(timeout, handler).await.map_err(|_| {
+        span.in_scope(|| tracing::warn!("The {kind} handler took longer than {timeout:?}"));
+        timeout
+    })
+}
+
+fn timeout_message(timeout: Duration) -> String {
+    format!(
+        "Handling the message took longer than {}ms",
+        timeout.as_millis()
+    )
+}
+
+/// Record how handling a message of type `kind` turned out, on its span and in the latency
+/// metrics.
+fn finish_message(
+    server: &WebSocketServer,
+    span: &tracing::Span,
+    kind: &str,
+    started: Instant,
+    outcome: &str,
+) {
+    let elapsed = started.elapsed();
+    span.record("duration_ms", elapsed.as_secs_f64() * 1000.0);
+    span.record("outcome", outcome);
+    server
+        .metrics
+        .message_latency
+        .observe(kind, outcome, elapsed);
+}
+
+/// Handle a message with the typed route registered for its type, answering it through `outbound`.
+///
+/// It's checked and passed around the same way as the built-in types in [`handle_text`], just as
+/// JSON rather than parsed.
+async fn handle_route(
+    server: &WebSocketServer,
+    outbound: &OutboundQueue,
+    token: Uuid,
+    address: &str,
+    idempotency_scope: &str,
+    route: RegisteredRoute,
+    mut payload: serde_json::Value,
+) {
+    let kind = payload["type"].as_str().unwrap_or_default().to_owned();
+    let id = payload
+        .get("id")
+        .and_then(|x| x.as_u64())
+        .map(|x| x as usize);
+
+    let span = tracing::info_span!(
+        "ws_message",
+        message_type = %kind,
+        session_id = %token,
+        address = %address,
+        trace_id = field::Empty,
+        outcome = field::Empty,
+        duration_ms = field::Empty,
+    );
+    let trace_id = payload
+        .get("trace_id")
+        .and_then(|x| x.as_str())
+        .map(str::to_owned)
+        .unwrap_or_else(|| span.in_scope(telemetry::correlation_id));
+    span.record("trace_id", &trace_id);
+
+    let started = Instant::now();
+    let state = server.session_state(&token).await;
+    if let Err(rejected) = state.accepts_messages() {
+        span.in_scope(|| tracing::info!("Rejected message while {state:?}"));
+        let message = rejected.to_string();
+        reply_error(outbound, id, Some(trace_id), rejected.code(), message).await;
+
+        finish_message(server, &span, &kind, started, "rejected");
+        return;
+    }
+
+    if route.state_changing
+        && let Some(message) = server.maintenance_message()
+    {
+        reply_error(outbound, id, Some(trace_id), "server_maintenance", message).await;
+
+        finish_message(server, &span, &kind, started, "maintenance");
+        return;
+    }
+
+    if let Err(denied) = server.authorize_kind(&token, &kind, route.scope).await {
+        span.in_scope(|| tracing::info!("Denied message: {denied}"));
+        let message = denied.to_string();
+        reply_error(outbound, id, Some(trace_id), denied.code(), message).await;
+
+        finish_message(server, &span, &kind, started, "denied");
+        return;
+    }
+
+    if let Err(rejection) = server
+        .middleware
+        .run_routed(token, address, &kind, &mut payload)
+    {
+        span.in_scope(|| tracing::info!("Middleware rejected message: {}", rejection.message));
+        let message = rejection.message;
+        reply_error(outbound, id, Some(trace_id), &rejection.error, message).await;
+
+        finish_message(server, &span, &kind, started, "rejected");
+        return;
+    }
+
+    if server.listeners.wants_route(&kind) {
+        let session = SessionHandle::new(token, address, server.clone());
+        server.listeners.route(session, &kind, &payload);
+    }
+
+    let idempotency_key = payload
+        .get("ref")
+        .and_then(|x| x.as_str())
+        .filter(|_| route.state_changing)
+        .map(|key| IdempotencyKey {
+            scope: idempotency_scope.to_owned(),
+            key: key.to_owned(),
+        });
+
+    if let Some(key) = &idempotency_key {
+        let outcome = match server.idempotency.begin(key.clone()) {
+            Begin::New => None,
+            Begin::Replay(response) => {
+                let _ = outbound.respond(with_id(response, id)).await;
+                Some("replayed")
+            }
+            Begin::InFlight => {
+                let message = "A message with this ref is still being handled".to_owned();
+                reply_error(
+                    outbound,
+                    id,
+                    Some(trace_id.clone()),
+                    "duplicate_ref",
+                    message,
+                )
+                .await;
+                Some("duplicate")
+            }
+        };
+
+        if let Some(outcome) = outcome {
+            finish_message(server, &span, &kind, started, outcome);
+            return;
+        }
+    }
+
+    let context = RouteContext {
+        session: token,
+        address: address.to_owned(),
+        server: server.clone(),
+    };
+    let handled = (route.handler)(context, payload).instrument(span.clone());
+    let result = match with_handler_timeout(server, &span, &kind, handled).await {
+        Ok(result) => result,
+        Err(timeout) => Err(Rejection::new(
+            "operation_timeout",
+            timeout_message(timeout),
+        )),
+    };
+
+    let response = match result {
+        Ok(serde_json::Value::Object(mut response)) => {
+            finish_message(server, &span, &kind, started, "ok");
+            response.insert("ok".to_owned(), true.into());
+            if let Some(id) = id {
+                response.insert("id".to_owned(), id.into());
+            }
+            response.insert("trace_id".to_owned(), trace_id.into());
+            response.insert("type".to_owned(), "response".into());
+            response.insert("responding_to".to_owned(), kind.into());
+            response
+        }
+        Ok(_) => {
+            span.in_scope(|| tracing::error!("The {kind} route didn't respond with an object"));
+            server.emit(GatewayEvent::MessageFailed {
+                session: token,
+                message_type: kind.clone(),
+                error: "The route didn't respond with an object".to_owned(),
+            });
+            if let Some(key) = &idempotency_key {
+                server.idempotency.abandon(key);
+            }
+            finish_message(server, &span, &kind, started, "error");
+            let message = "Something went wrong building the response".to_owned();
+            reply_error(outbound, id, Some(trace_id), "internal_error", message).await;
+            return;
+        }
+        Err(rejection) => {
+            span.in_scope(|| tracing::info!("Rejected message: {}", rejection.message));
+            if let Some(key) = &idempotency_key {
+                server.idempotency.abandon(key);
+            }
+            finish_message(server, &span, &kind, started, "rejected");
+            reply_error(
+                outbound,
+                id,
+                Some(trace_id),
+                &rejection.error,
+                rejection.message,
+            )
+            .await;
+            return;
+        }
+    };
+
+    let response = serde_json::Value::Object(response).to_string();
+    if let Some(key) = &idempotency_key {
+        server.idempotency.complete(key, response.clone());
+    }
+    let _ = outbound.respond(response).await;
+}
+
+/// Handle a binary frame from a session, passing it to the binary handler if there is one.
+pub async fn handle_binary(
+    server: &WebSocketServer,
+    outbound: &OutboundQueue,
+    token: Uuid,
+    address: &str,
+    bytes: Bytes,
+) {
+    server.sample_message("binary", bytes.len());
+    let Some(handler) = &server.binary else {
+        return; // Binary data is just ignored
+    };
+
+    let max_binary_payload = server.config().max_binary_payload;
+    if bytes.len() > max_binary_payload {
+        let message = format!("Binary frames can be at most {max_binary_payload} bytes");
+        reply_error(outbound, None, None, "binary_too_large", message).await;
+        return;
+    }
+
+    if let Some(reply) = handler.on_binary(token, address, bytes).await {
+        let _ = outbound.respond_binary(reply).await;
+    }
+}
+
+/// Turn away a connection with a bad token, failing the upgrade unless the server is configured to
+/// accept it and close with an `invalid_token` error instead.
+fn reject_token(
+    server: &WebSocketServer,
+    response: HttpResponse,
+    mut session: Session,
+    error: String,
+) -> Result<HttpResponse, actix_web::Error> {
+    if !server.config().close_on_invalid_token {
+        return Err(ErrorBadRequest(error));
+    }
+
+    let message = WebSocketMessage {
+        ok: Some(false),
+        id: None,
+        trace_id: None,
+        idempotency_key: None,
+        r#type: WebSocketMessageInner::Error {
+            error: "invalid_token".to_owned(),
+            message: error,
+            parameter: None,
+        },
+    };
+    let message = to_text(&message, "invalid_token error");
+
+    actix_web::rt::spawn(async move {
+        if let Some(message) = message {
+            let _ = session.text(message).await;
+        }
+
+        let reason = CloseReason {
+            code: CloseCode::Other(INVALID_TOKEN_CLOSE_CODE),
+            description: Some("InvalidToken".to_owned()),
+        };
+        let _ = session.close(Some(reason)).await;
+    });
+
+    Ok(response)
+}
+
+/// Build a `reconnect` advisory asking the client to come back after a random delay within `window`.
+pub(crate) fn reconnect_advisory(window: Duration, reason: &str) -> Option<String> {
+    let window_ms = window.as_millis() as u64;
+    let message = WebSocketMessage {
+        ok: None,
+        id: None,
+        trace_id: None,
+        idempotency_key: None,
+        r#type: WebSocketMessageInner::Reconnect {
+            wait_ms: if window_ms > 0 {
+                rand::random_range(0..window_ms)
+            } else {
+                0
+            },
+            reason: reason.to_owned(),
+        },
+    };
+
+    to_text(&message, "reconnect advisory")
+}
+
+/// Accept the upgrade only to send a `reconnect` advisory and close again.
+fn advise_reconnect(
+    response: HttpResponse,
+    mut session: Session,
+    window: Duration,
+    reason: &str,
+) -> HttpResponse {
+    let advisory = reconnect_advisory(window, reason);
+
+    actix_web::rt::spawn(async move {
+        if let Some(advisory) = advisory {
+            let _ = session.text(advisory).await;
+        }
+
+        let reason = CloseReason {
+            code: CloseCode::Again,
+            description: Some("TryAgainLater".to_owned()),
+        };
+        let _ = session.close(Some(reason)).await;
+    });
+
+    response
+}
+
+/// Fill in the current session span's fields once the token has been redeemed.
+pub(crate) fn record_session(address: &str, ip: Option<IpAddr>) {
+    let span = tracing::Span::current();
+    span.record("address", address);
+    if let Some(ip) = ip {
+        span.record("ip", field::display(ip));
+    }
+}
+
+/// Keeps a panicking session task from leaving its session half cleaned up.
+#[derive(Clone)]
+pub(crate) struct Supervisor {
+    pub(crate) token: Uuid,
+    pub(crate) outbound: OutboundQueue,
+    /// Shared by all of the session's tasks, so one exiting takes the others down with it.
+    pub(crate) cancel: CancellationToken,
+    /// The session's span, which every task runs in so their logs can be told apart by connection.
+    pub(crate) span: tracing::Span,
+}
+
+impl Supervisor {
+    /// Run `task`, and if it panics, close the socket with an internal error.
+    ///
+    /// Whichever way it exits, the session's other tasks are cancelled, and the message loop's
+    /// [`SessionGuard`] cleans up after them.
+    pub(crate) async fn run(self, name: &'static str, task: impl Future<Output = ()>) {
+        let _cancel = self.cancel.clone().drop_guard();
+        let task = task.instrument(self.span.clone());
+        let Err(panic) = AssertUnwindSafe(task).catch_unwind().await else {
+            return;
+        };
+
+        let message = panic
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown panic");
+        self.span.in_scope(|| {
+            tracing::error!(session = %self.token, task = name, "Session task panicked: {message}")
+        });
+
+        let reason = CloseReason {
+            code: CloseCode::Error,
+            description: Some("Internal error".to_owned()),
+        };
+        self.outbound.close(Some(reason));
+    }
+}
+
+/// Parse an event payload for filtering, treating anything that isn't JSON as a plain string.
+fn parse_event(payload: &str) -> serde_json::Value {
+    serde_json::from_str(payload).unwrap_or_else(|_| serde_json::Value::from(payload))
+}
+
+/// Merge coalesced events into a single message, or `None` if there's nothing to send.
+fn batch_message(events: Vec<&(ByteString, serde_json::Value)>) -> Option<ByteString> {
+    match events.as_slice() {
+        [] => None,
+        // Nothing to merge, so don't make clients unwrap it
+        [(msg, _)] => Some(msg.clone()),
+        events => {
+            let events = events.iter().map(|(_, event)| event.clone()).collect();
+            let batch = WebSocketMessage {
+                ok: None,
+                id: None,
+                trace_id: None,
+                idempotency_key: None,
+                r#type: WebSocketMessageInner::Batch { events },
+            };
+
+            to_text(&batch, "batch").map(Into::into)
+        }
+    }
+}
+
+/// Sends a handler's responses, remembering them for replay when the message carried an idempotency key.
+struct Responder<'a> {
+    outbound: &'a OutboundQueue,
+    /// Id and trace id of the request being handled, echoed back in responses.
+    id: Option<usize>,
+    trace_id: Option<String>,
+    idempotency: Option<(&'a IdempotencyCache, &'a IdempotencyKey)>,
+}
+
+impl Responder<'_> {
+    /// Respond to the request being handled, answering with an internal error instead if the
+    /// response can't be serialized.
+    async fn send_response(&self, data: WebSocketMessageResponse) -> anyhow::Result<()> {
+        let responding_to = data.kind();
+        let message = WebSocketMessage {
+            ok: Some(true),
+            id: self.id,
+            trace_id: self.trace_id.clone(),
+            idempotency_key: None,
+            r#type: WebSocketMessageInner::Response { data },
+        };
+
+        match to_text(&message, &format!("{responding_to} response")) {
+            Some(message) => self.respond(message).await,
+            None => {
+                let message = "Something went wrong building the response".to_owned();
+                reply_error(
+                    self.outbound,
+                    self.id,
+                    self.trace_id.clone(),
+                    "internal_error",
+                    message,
+                )
+                .await;
+                Ok(())
+            }
+        }
+    }
+
+    /// Answer with an error, pinned on one of the request's fields if it's down to one.
+    async fn send_error(
+        &self,
+        error: &str,
+        message: impl Into<String>,
+        parameter: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let message = WebSocketMessage {
+            ok: Some(false),
+            id: self.id,
+            trace_id: self.trace_id.clone(),
+            idempotency_key: None,
+            r#type: WebSocketMessageInner::Error {
+                error: error.to_owned(),
+                message: message.into(),
+                parameter: parameter.map(str::to_owned),
+            },
+        };
+
+        match to_text(&message, &format!("{error} error")) {
+            Some(message) => self.respond(message).await,
+            None => Ok(()),
+        }
+    }
+
+    #[cfg(feature = "krist")]
+    async fn send_invalid_parameter(
+        &self,
+        parameter: &str,
+        error: ValidationError,
+    ) -> anyhow::Result<()> {
+        self.send_error("invalid_parameter", error.to_string(), Some(parameter))
+            .await
+    }
+
+    async fn respond(&self, message: String) -> anyhow::Result<()> {
+        if let Some((cache, key)) = self.idempotency {
+            cache.complete(key, message.clone());
+        }
+
+        self.outbound.respond(message).await
+    }
+}
+
+/// Point a remembered response at the retry it is being replayed to.
+fn with_id(response: String, id: Option<usize>) -> String {
+    let Ok(serde_json::Value::Object(mut object)) = serde_json::from_str(&response) else {
+        return response;
+    };
+
+    match id {
+        Some(id) => object.insert("id".to_owned(), id.into()),
+        None => object.remove("id"),
+    };
+
+    serde_json::Value::Object(object).to_string()
+}
+
+/// Answer a message with how the server understood it, re-serialized the way the server would
+/// write it, for client developers checking their encoding against it.
+async fn echo(outbound: &OutboundQueue, msg: &WebSocketMessage) {
+    let echo = serde_json::json!({
+        "ok": true,
+        "id": msg.id,
+        "type": "echo",
+        "message": msg,
+    });
+    let _ = outbound.respond(echo.to_string()).await;
+}
+
+/// Reply to a client message with an error.
+async fn send_error(
+    outbound: &OutboundQueue,
+    msg: &WebSocketMessage,
+    error: &str,
+    message: String,
+) {
+    reply_error(outbound, msg.id, msg.trace_id.clone(), error, message).await;
+}
+
+/// Reply with an error to a request, which may not have parsed far enough to pass to [`send_error`].
+async fn reply_error(
+    outbound: &OutboundQueue,
+    id: Option<usize>,
+    trace_id: Option<String>,
+    error: &str,
+    message: String,
+) {
+    let response = WebSocketMessage {
+        ok: Some(false),
+        id,
+        trace_id,
+        idempotency_key: None,
+        r#type: WebSocketMessageInner::Error {
+            error: error.to_owned(),
+            message,
+            parameter: None,
+        },
+    };
+    if let Some(response) = to_text(&response, &format!("{error} error")) {
+        let _ = outbound.respond(response).await;
+    }
+}
+
+/// Serialize `value`, described as `what`, to send to a client, logging rather than panicking
+/// if it can't be.
+pub(crate) fn to_text(value: &impl Serialize, what: &str) -> Option<String> {
+    serde_json::to_string(value)
+        .inspect_err(|e| tracing::error!("Failed to serialize {what}: {e}"))
+        .ok()
+}
+
+/// What's known about `address`, as sent in `address` and `login` responses.
+#[cfg(feature = "krist")]
+fn address_info(
+    server: &WebSocketServer,
+    address: &str,
+    fetch_names: bool,
+) -> anyhow::Result<serde_json::Value> {
+    let balance = match &server.balances {
+        Some(balances) => balances.get(address)?,
+        None => 0,
+    };
+    let mut info = serde_json::json!({ "address": address, "balance": balance });
+
+    if fetch_names && let Some(names) = &server.names {
+        info["names"] = names.count_owned(address)?.into();
+    }
+    Ok(info)
+}
+
+/// A page of the events journaled to `topic` that `filter` picks. Each is unwrapped from its `field` if it has one, e.g. the `transaction` of a transaction
+/// event.
+#[cfg(feature = "krist")]
+fn history(
+    journal: &EventJournal,
+    topic: &WebSocketSubscriptionType,
+    field: &str,
+    (limit, offset, order): (Option<usize>, usize, Order),
+    filter: impl Fn(&serde_json::Value) -> bool,
+) -> anyhow::Result<Paginated<serde_json::Value>> {
+    let limit = pagination::page_size(limit);
+    let parse =
+        |entry: &JournalEntry| serde_json::from_str::<serde_json::Value>(&entry.payload).ok();
+
+    let page = journal.page(order, offset, limit, |entry| {
+        entry.topic.as_ref() == Some(topic) && parse(entry).is_some_and(|event| filter(&event))
+    })?;
+    let events = page
+        .items
+        .iter()
+        .filter_map(parse)
+        .map(
+            |mut event| match event.as_object_mut().and_then(|x| x.remove(field)) {
+                Some(inner) => inner,
+                None => event,
+            },
+        )
+        .collect();
+
+    Ok(Paginated::new(page.total, page.offset, events))
+}
+
+async fn handle_websocket_message(
+    responder: &Responder<'_>,
+    uuid: &Uuid,
+    server: &WebSocketServer,
+    message: WebSocketMessage,
+) -> anyhow::Result<()> {
+    match message.r#type {
+        WebSocketMessageInner::Hello { motd: _ } => {} // Not sent by client
+        WebSocketMessageInner::Keepalive { .. } => {}  // Not sent by client
+        WebSocketMessageInner::Warning { .. } => {}    // Not sent by client
+        WebSocketMessageInner::Reconnect { .. } => {}  // Not sent by client
+        WebSocketMessageInner::Error { .. } => {}      // Not sent by client
+        WebSocketMessageInner::Batch { .. } => {}      // Not sent by client
+        WebSocketMessageInner::Response { data: _ } => {} // Not sent by client
+        #[cfg(feature = "krist")]
+        WebSocketMessageInner::Work => {
+            responder
+                .send_response(WebSocketMessageResponse::Work {
+                    work: server.work(),
+                })
+                .await?;
+        }
+        #[cfg(feature = "krist")]
+        WebSocketMessageInner::SubmitBlock { address, nonce } => {
+            if let Err(e) = validation::validate_address(&address) {
+                return responder.send_invalid_parameter("address", e).await;
+            }
+            if let Err(e) = validation::validate_nonce(&nonce) {
+                return responder.send_invalid_parameter("nonce", e).await;
+            }
+            let Some(balances) = &server.balances else {
+                let message = "Mining is not enabled";
+                return responder
+                    .send_error("mining_unavailable", message, None)
+                    .await;
+            };
+            // Recorded up front so a retry racing this one is refused, and forgotten again if
+            // the reward can't be paid out
+            if !server.submissions.record(&address, &nonce) {
+                let message = "This solution has already been submitted";
+                return responder
+                    .send_error("duplicate_submission", message, None)
+                    .await;
+            }
+
+            let reward = server.config().economy.block_reward;
+            if let Err(e) = server.open_account(&**balances, &address).await {
+                server.submissions.forget(&address, &nonce);
+                return Err(e);
+            }
+            let change = match balances.credit(&address, reward.into(), BalanceChangeReason::Reward)
+            {
+                Ok(change) => change,
+                Err(e) => {
+                    tracing::error!("Failed to pay out the block reward to {address}: {e}");
+                    server.submissions.forget(&address, &nonce);
+                    let message = "The block reward couldn't be paid out, try again";
+                    return responder.send_error("internal_error", message, None).await;
+                }
+            };
+            // Only fails while shutting down, when subscribers are going away anyway
+            let _ = server.publish_balance(&change).await;
+            server.emit(GatewayEvent::Block {
+                block: serde_json::json!({
+                    "address": address,
+                    "nonce": nonce,
+                    "value": reward,
+                    "time": keepalive::server_time(SystemTime::now()),
+                }),
+            });
+
+            responder
+                .send_response(WebSocketMessageResponse::SubmitBlock {
+                    address,
+                    reward,
+                    balance: change.new,
+                })
+                .await?;
+        }
+        #[cfg(feature = "krist")]
+        WebSocketMessageInner::MakeTransaction {
+            private_key,
+            to,
+            amount,
+            metadata,
+        } => {
+            // Neither a locked address nor its sessions may send, whoever's key they send with
+            let from = address::from_private_key(private_key.expose());
+            let address = server.session_address(uuid).await.unwrap_or_default();
+            if let Some(address) = [&address, &from]
+                .into_iter()
+                .find(|x| server.is_address_locked(x))
+            {
+                let message = format!("{address} is locked");
+                return responder.send_error("address_locked", message, None).await;
+            }
+
+            let economy = &server.config().economy;
+            if let Err(e) = validation::validate_recipient(&to) {
+                return responder.send_invalid_parameter("to", e).await;
+            }
+            if let Err(e) = validation::validate_amount(amount, economy.min_transaction_amount) {
+                return responder.send_invalid_parameter("amount", e).await;
+            }
+            if let Some(metadata) = &metadata
+                && let Err(e) =
+                    validation::validate_metadata_within(metadata, economy.max_metadata_length)
+            {
+                return responder.send_invalid_parameter("metadata", e).await;
+            }
+            let Some(balances) = &server.balances else {
+                let message = "Transactions are not enabled";
+                return responder
+                    .send_error("transactions_unavailable", message, None)
+                    .await;
+            };
+
+            let recipient = match to.rsplit_once('@').map_or(to.as_str(), |(_, name)| name) {
+                name if name.ends_with(".kst") => {
+                    let Some(names) = &server.names else {
+                        let message = "Names are not enabled";
+                        return responder
+                            .send_error("names_unavailable", message, None)
+                            .await;
+                    };
+                    let name = name.strip_suffix(".kst").unwrap_or(name);
+                    let Some(record) = names.get(name)? else {
+                        let message = format!("No name {name}.kst");
+                        return responder.send_error("name_not_found", message, None).await;
+                    };
+                    record.owner
+                }
+                _ => to.clone(),
+            };
+
+            server.open_account(&**balances, &from).await?;
+            server.open_account(&**balances, &recipient).await?;
+            let reason = BalanceChangeReason::Transaction;
+            let Some(debit) = balances.debit(&from, amount.into(), reason)? else {
+                let message = format!("{from} doesn't have {amount} to send");
+                return responder
+                    .send_error("insufficient_funds", message, None)
+                    .await;
+            };
+            let credit = match balances.credit(&recipient, amount.into(), reason) {
+                Ok(credit) => credit,
+                Err(e) => {
+                    // Put the funds back rather than let them vanish
+                    balances.credit(&from, amount.into(), reason)?;
+                    return Err(e);
+                }
+            };
+
+            let transaction = serde_json::json!({
+                "from": from,
+                "to": recipient,
+                "value": amount,
+                "time": keepalive::server_time(SystemTime::now()),
+                "sent_name": (recipient != to).then_some(&to),
+                "metadata": metadata,
+                "type": "transfer",
+            });
+            let event = serde_json::json!({
+                "type": "event",
+                "event": "transaction",
+                "transaction": transaction,
+            });
+            // Only fails while shutting down, when subscribers are going away anyway
+            let _ = server
+                .publish(WebSocketSubscriptionType::Transactions, event.to_string())
+                .await;
+            let _ = server.publish_balance(&debit).await;
+            let _ = server.publish_balance(&credit).await;
+            server.emit(GatewayEvent::Transaction {
+                transaction: transaction.clone(),
+            });
+            server.audit(
+                AuditRecord::new(AuditAction::Transaction {
+                    to: recipient,
+                    amount,
+                })
+                .session(*uuid)
+                .address(from),
+            );
+
+            responder
+                .send_response(WebSocketMessageResponse::MakeTransaction { transaction })
+                .await?;
+        }
+        WebSocketMessageInner::GetValidSubscriptionLevels => {
+            responder
+                .send_response(WebSocketMessageResponse::GetValidSubscriptionLevels {
+                    valid_subscription_levels: serde_json::json!(WebSocketSubscriptionType::ALL),
+                })
+                .await?;
+        }
+        #[cfg(feature = "krist")]
+        WebSocketMessageInner::Address {
+            address,
+            fetch_names,
+        } => {
+            if let Err(e) = validation::validate_address(&address) {
+                return responder.send_invalid_parameter("address", e).await;
+            }
+
+            let address = address_info(server, &address, fetch_names.unwrap_or_default())?;
+            responder
+                .send_response(WebSocketMessageResponse::Address { address })
+                .await?;
+        }
+        #[cfg(feature = "krist")]
+        WebSocketMessageInner::GetTransactions {
+            address,
+            limit,
+            offset,
+            order,
+        } => {
+            if let Some(address) = &address
+                && let Err(e) = validation::validate_address(address)
+            {
+                return responder.send_invalid_parameter("address", e).await;
+            }
+            let Some(journal) = server.journal() else {
+                let message = "Event journal is not enabled";
+                return responder
+                    .send_error("history_unavailable", message, None)
+                    .await;
+            };
+
+            let involved: Vec<EventFilter> = address
+                .iter()
+                .flat_map(|address| ["from", "to"].map(|x| EventFilter::field(x, address.as_str())))
+                .collect();
+            let page = history(
+                journal,
+                &WebSocketSubscriptionType::Transactions,
+                "transaction",
+                (limit, offset, order),
+                |event| involved.is_empty() || involved.iter().any(|x| x.matches(event)),
+            )?;
+
+            responder
+                .send_response(WebSocketMessageResponse::GetTransactions { page })
+                .await?;
+        }
+        #[cfg(feature = "krist")]
+        WebSocketMessageInner::GetBlocks {
+            limit,
+            offset,
+            order,
+        } => {
+            let Some(journal) = server.journal() else {
+                let message = "Event journal is not enabled";
+                return responder
+                    .send_error("history_unavailable", message, None)
+                    .await;
+            };
+
+            let page = history(
+                journal,
+                &WebSocketSubscriptionType::Blocks,
+                "block",
+                (limit, offset, order),
+                |_| true,
+            )?;
+
+            responder
+                .send_response(WebSocketMessageResponse::GetBlocks { page })
+                .await?;
+        }
+        #[cfg(feature = "krist")]
+        WebSocketMessageInner::GetName { name } => {
+            let Some(names) = &server.names else {
+                let message = "Names are not enabled";
+                return responder
+                    .send_error("names_unavailable", message, None)
+                    .await;
+            };
+            if let Err(e) = validation::validate_name(&name) {
+                return responder.send_invalid_parameter("name", e).await;
+            }
+
+            let name = name.strip_suffix(".kst").unwrap_or(&name);
+            let Some(name) = names.get(name)? else {
+                let message = format!("No name {name}.kst");
+                return responder.send_error("name_not_found", message, None).await;
+            };
+
+            responder
+                .send_response(WebSocketMessageResponse::GetName { name })
+                .await?;
+        }
+        #[cfg(feature = "krist")]
+        WebSocketMessageInner::RegisterName { name } => {
+            let (Some(names), Some(balances)) = (&server.names, &server.balances) else {
+                let message = "Name registration is not enabled";
+                return responder
+                    .send_error("names_unavailable", message, None)
+                    .await;
+            };
+            if let Err(e) = validation::validate_name(&name) {
+                return responder.send_invalid_parameter("name", e).await;
+            }
+            let address = server.session_address(uuid).await.unwrap_or_default();
+            if is_guest_address(&address) {
+                let rejected = StateRejected::NotLoggedIn;
+                return responder
+                    .send_error(rejected.code(), rejected.to_string(), None)
+                    .await;
+            }
+            if server.is_address_locked(&address) {
+                let message = format!("{address} is locked");
+                return responder.send_error("address_locked", message, None).await;
+            }
+
+            let name = name.strip_suffix(".kst").unwrap_or(&name);
+            let cost = server.config().economy.name_cost;
+            let reason = BalanceChangeReason::NameRegistration;
+            server.open_account(&**balances, &address).await?;
+            let Some(debit) = balances.debit(&address, cost.into(), reason)? else {
+                let message = format!("{address} doesn't have the {cost} a name costs");
+                return responder
+                    .send_error("insufficient_funds", message, None)
+                    .await;
+            };
+
+            let record = NameRecord::new(name, &address);
+            let inserted = names.insert(&record);
+            if !matches!(inserted, Ok(true)) {
+                // Give the cost back, as the name wasn't registered
+                balances.credit(&address, cost.into(), reason)?;
+            }
+            if !inserted? {
+                let message = format!("{name}.kst is already taken");
+                return responder.send_error("name_taken", message, None).await;
+            }
+
+            let _ = server.publish_name(&record).await;
+            let _ = server.publish_balance(&debit).await;
+
+            responder
+                .send_response(WebSocketMessageResponse::RegisterName { name: record })
+                .await?;
+        }
+        #[cfg(feature = "krist")]
+        WebSocketMessageInner::UpdateName { name, a } => {
+            let Some(names) = &server.names else {
+                let message = "Names are not enabled";
+                return responder
+                    .send_error("names_unavailable", message, None)
+                    .await;
+            };
+            if let Err(e) = validation::validate_name(&name) {
+                return responder.send_invalid_parameter("name", e).await;
+            }
+            let a = a.filter(|a| !a.is_empty());
+            if let Some(a) = &a
+                && let Err(e) = validation::validate_a_record(a)
+            {
+                return responder.send_invalid_parameter("a", e).await;
+            }
+
+            let name = name.strip_suffix(".kst").unwrap_or(&name);
+            let Some(mut record) = names.get(name)? else {
+                let message = format!("No name {name}.kst");
+                return responder.send_error("name_not_found", message, None).await;
+            };
+            let address = server.session_address(uuid).await.unwrap_or_default();
+            if record.owner != address {
+                let message = format!("{name}.kst is not owned by {address}");
+                return responder.send_error("not_name_owner", message, None).await;
+            }
+            if record.seized {
+                let message = format!("{name}.kst has been seized");
+                return responder.send_error("name_seized", message, None).await;
+            }
+
+            record.set_a(a);
+            names.put(&record)?;
+
+            let _ = server.publish_name(&record).await;
+
+            responder
+                .send_response(WebSocketMessageResponse::UpdateName { name: record })
+                .await?;
+        }
+        WebSocketMessageInner::Me => {
+            let Some(address) = server.session_address(uuid).await else {
+                return Ok(());
+            };
+
+            responder
+                .send_response(WebSocketMessageResponse::Me {
+                    is_guest: is_guest_address(&address),
+                    address: Some(serde_json::json!({ "address": address })),
+                })
+                .await?;
+        }
+        WebSocketMessageInner::GetSubscriptionLevel => {
+            let subscription_list = server.get_subscription_list(uuid).await;
+            let subscription_list: Vec<String> = subscription_list
+                .into_iter()
+                .map(|x| x.into_string())
+                .collect();
+
+            responder
+                .send_response(WebSocketMessageResponse::GetSubscriptionLevel {
+                    subscription_level: subscription_list,
+                })
+                .await?;
+        }
+        WebSocketMessageInner::GetSubscriptionStats => {
+            let stats = server
+                .get_subscription_stats(uuid)
+                .await
+                .unwrap_or_default();
+
+            responder
+                .send_response(WebSocketMessageResponse::GetSubscriptionStats { stats })
+                .await?;
+        }
+        WebSocketMessageInner::Logout => {
+            if !server.transition(uuid, SessionState::Ready).await {
+                return Ok(());
+            }
+            let guest = WebSocketTokenData::guest().address;
+            server.set_session_address(uuid, guest).await;
+
+            responder
+                .send_response(WebSocketMessageResponse::Logout { is_guest: true })
+                .await?;
+        }
+        WebSocketMessageInner::Login { private_key } => {
+            let address = address::from_private_key(private_key.expose());
+            if server.runtime_config().is_banned(&address, None) {
+                let message = format!("{address} is banned");
+                return responder.send_error("banned", message, None).await;
+            }
+            #[cfg(feature = "krist")]
+            if server.is_address_locked(&address) {
+                let message = format!("{address} is locked");
+                return responder.send_error("address_locked", message, None).await;
+            }
+
+            if !server.set_session_address(uuid, address.clone()).await {
+                return Ok(());
+            }
+            server.transition(uuid, SessionState::Authenticated).await;
+            #[cfg(feature = "krist")]
+            if let Some(balances) = &server.balances {
+                server.open_account(&**balances, &address).await?;
+            }
+
+            #[cfg(feature = "krist")]
+            let address = address_info(server, &address, false)?;
+            #[cfg(not(feature = "krist"))]
+            let address = serde_json::json!({ "address": address });
+            responder
+                .send_response(WebSocketMessageResponse::Login {
+                    is_guest: false,
+                    address,
+                })
+                .await?;
+        }
+        WebSocketMessageInner::Subscribe { event, filter } => {
+            if let Some(filter) = &filter
+                && let Err(e) = filter.validate()
+            {
+                return responder
+                    .send_error("invalid_parameter", e.to_string(), Some("filter"))
+                    .await;
+            }
+
+            if let Err(e) = server.subscribe_to_event(uuid, event.clone()).await {
+                return responder
+                    .send_error("too_many_subscriptions", e.to_string(), None)
+                    .await;
+            }
+            server.set_event_filter(uuid, event, filter).await;
+
+            let subscription_list = server.get_subscription_list(uuid).await;
+            let subscription_list: Vec<String> = subscription_list
+                .into_iter()
+                .map(|x| x.into_string())
+                .collect();
+
+            responder
+                .send_response(WebSocketMessageResponse::Subscribe {
+                    subscription_level: subscription_list,
+                })
+                .await?;
+        }
+        WebSocketMessageInner::Unsubscribe { event } => {
+            server.unsubscribe_from_event(uuid, &event).await;
+
+            let subscription_list = server.get_subscription_list(uuid).await;
+            let subscription_list: Vec<String> = subscription_list
+                .into_iter()
+                .map(|x| x.into_string())
+                .collect();
+
+            responder
+                .send_response(WebSocketMessageResponse::Unsubscribe {
+                    subscription_level: subscription_list,
+                })
+                .await?;
+        }
+        WebSocketMessageInner::Tag { tags } => {
+            let tags = match server.tag_session(uuid, tags).await {
+                Ok(Some(tags)) => tags,
+                Ok(None) => return Ok(()),
+                Err(e) => {
+                    return responder
+                        .send_error("invalid_parameter", e.to_string(), Some("tags"))
+                        .await;
+                }
+            };
+
+            responder
+                .send_response(WebSocketMessageResponse::Tag { tags })
+                .await?;
+        }
+    }
+
+    Ok(())
 }
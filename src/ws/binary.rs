@@ -0,0 +1,18 @@
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use uuid::Uuid;
+
+/// Receives the binary frames sessions send, e.g. file chunks or compact telemetry.
+///
+/// Binary frames are ignored unless a handler is registered with the server. They are handled by
+/// the session's ordered message worker, so a slow handler holds up the session's later messages
+/// but never its heartbeats or closes.
+pub trait BinaryHandler: Send + Sync {
+    /// Handle a binary frame, optionally returning a binary frame to send back.
+    fn on_binary<'a>(
+        &'a self,
+        session: Uuid,
+        address: &'a str,
+        payload: Bytes,
+    ) -> BoxFuture<'a, Option<Bytes>>;
+}
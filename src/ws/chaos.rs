@@ -0,0 +1,99 @@
+//! Fault injection for soak tests: wraps a session's sink and randomly delays, drops or fails
+//! what's written to it, or disconnects the client outright.
+
+use actix_ws::{CloseCode, CloseReason, Closed};
+use bytes::Bytes;
+use bytestring::ByteString;
+
+use crate::config::ChaosConfig;
+use crate::ws::sink::SessionSink;
+
+pub struct ChaosSink<S> {
+    /// Taken when a forced disconnect closes the session.
+    inner: Option<S>,
+    config: ChaosConfig,
+}
+
+impl<S: SessionSink> ChaosSink<S> {
+    pub fn new(inner: S, config: ChaosConfig) -> Self {
+        Self {
+            inner: Some(inner),
+            config,
+        }
+    }
+
+    /// Roll for latency, disconnects and send failures ahead of writing a frame.
+    async fn before_send(&mut self) -> Result<&mut S, Closed> {
+        if let Some((min, max)) = self.config.latency {
+            let delay = if min < max {
+                rand::random_range(min..=max)
+            } else {
+                min
+            };
+            tokio::time::sleep(delay).await;
+        }
+
+        if rand::random_bool(self.config.disconnect_rate) {
+            tracing::debug!("Chaos: forcing a disconnect");
+            if let Some(inner) = self.inner.take() {
+                let _ = inner
+                    .close(Some(CloseReason {
+                        code: CloseCode::Away,
+                        description: Some("Chaos".to_owned()),
+                    }))
+                    .await;
+            }
+            return Err(Closed);
+        }
+
+        if rand::random_bool(self.config.send_failure_rate) {
+            tracing::debug!("Chaos: failing a send");
+            return Err(Closed);
+        }
+
+        self.inner.as_mut().ok_or(Closed)
+    }
+
+    fn dropped(&self) -> bool {
+        let dropped = rand::random_bool(self.config.drop_rate);
+        if dropped {
+            tracing::debug!("Chaos: dropping a frame");
+        }
+        dropped
+    }
+}
+
+impl<S: SessionSink> SessionSink for ChaosSink<S> {
+    async fn text(&mut self, msg: ByteString) -> Result<(), Closed> {
+        let dropped = self.dropped();
+        let inner = self.before_send().await?;
+        if dropped {
+            return Ok(());
+        }
+        inner.text(msg).await
+    }
+
+    async fn binary(&mut self, bytes: Bytes) -> Result<(), Closed> {
+        let dropped = self.dropped();
+        let inner = self.before_send().await?;
+        if dropped {
+            return Ok(());
+        }
+        inner.binary(bytes).await
+    }
+
+    async fn ping(&mut self, bytes: Bytes) -> Result<(), Closed> {
+        self.before_send().await?.ping(bytes).await
+    }
+
+    async fn pong(&mut self, bytes: Bytes) -> Result<(), Closed> {
+        self.before_send().await?.pong(bytes).await
+    }
+
+    async fn close(self, reason: Option<CloseReason>) -> Result<(), Closed> {
+        match self.inner {
+            Some(inner) => inner.close(reason).await,
+            None => Err(Closed),
+        }
+    }
+}
@@ -0,0 +1,215 @@
+//! Runs a session's message and binary frame handlers off its receive loop, so a slow handler
+//! doesn't hold up heartbeats and closes, with handlers across the whole server sharing a bounded pool.
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use bytestring::ByteString;
+use futures::{StreamExt, stream::FuturesUnordered};
+use serde::Deserialize;
+use tokio::{sync::mpsc, task::JoinHandle};
+use uuid::Uuid;
+
+use super::{OutboundQueue, Supervisor, WebSocketServer, handle_binary, handle_text};
+use crate::config::Processing;
+
+/// Most messages waiting on a session's handlers before its receive loop stops reading more.
+const MESSAGE_QUEUE_CAPACITY: usize = 32;
+
+/// Just enough of a message to tell how it's processed.
+#[derive(Deserialize)]
+struct MessageKind<'a> {
+    #[serde(rename = "type", borrow)]
+    kind: Option<&'a str>,
+}
+
+/// A frame from a session waiting for its handler.
+enum Inbound {
+    Text(ByteString),
+    Binary(Bytes),
+}
+
+/// One of a session's workers, and the queue of messages feeding it.
+struct Worker {
+    messages: mpsc::Sender<Inbound>,
+    task: JoinHandle<()>,
+}
+
+impl Worker {
+    async fn send(&self, inbound: Inbound) -> anyhow::Result<()> {
+        self.messages
+            .send(inbound)
+            .await
+            .map_err(|_| anyhow::anyhow!("Session's handlers have stopped"))
+    }
+
+    async fn finish(self) {
+        drop(self.messages);
+        let _ = self.task.await;
+    }
+}
+
+/// Everything a session's workers need to handle its messages.
+struct Session {
+    server: Arc<WebSocketServer>,
+    outbound: OutboundQueue,
+    token: Uuid,
+    address: String,
+    idempotency_scope: String,
+}
+
+impl Session {
+    /// Handle a frame once there's room for it in the server's pool.
+    async fn handle(&self, inbound: Inbound) {
+        let Ok(_permit) = self.server.handler_permits.acquire().await else {
+            return; // The pool is never closed
+        };
+        match inbound {
+            Inbound::Text(text) => {
+                handle_text(
+                    &self.server,
+                    &self.outbound,
+                    self.token,
+                    &self.address,
+                    &self.idempotency_scope,
+                    &text,
+                )
+                .await
+            }
+            Inbound::Binary(bytes) => {
+                handle_binary(
+                    &self.server,
+                    &self.outbound,
+                    self.token,
+                    &self.address,
+                    bytes,
+                )
+                .await
+            }
+        }
+    }
+}
+
+/// A session's messages waiting for their handlers.
+///
+/// [`Processing::Ordered`] messages are handled one at a time, in the order they arrived.
+/// [`Processing::Unordered`] ones are handled as soon as they arrive, alongside anything else.
+/// Binary frames are always ordered.
+pub(crate) struct Dispatcher {
+    server: Arc<WebSocketServer>,
+    ordered: Worker,
+    /// Only started if some message type is configured to be processed out of order.
+    unordered: Option<Worker>,
+}
+
+impl Dispatcher {
+    pub(crate) fn spawn(
+        supervisor: Supervisor,
+        server: Arc<WebSocketServer>,
+        address: String,
+        idempotency_scope: String,
+    ) -> Self {
+        let session = Arc::new(Session {
+            server: server.clone(),
+            outbound: supervisor.outbound.clone(),
+            token: supervisor.token,
+            address,
+            idempotency_scope,
+        });
+
+        let unordered = server
+            .config()
+            .message_processing
+            .values()
+            .any(|x| *x == Processing::Unordered)
+            .then(|| spawn_unordered(supervisor.clone(), session.clone()));
+        let ordered = spawn_ordered(supervisor, session);
+
+        Self {
+            server,
+            ordered,
+            unordered,
+        }
+    }
+
+    /// Queue a message for its handler, waiting for room if the session is sending faster than
+    /// its messages are handled. Fails once the session's handlers have stopped.
+    pub(crate) async fn dispatch(&self, text: ByteString) -> anyhow::Result<()> {
+        let worker = match &self.unordered {
+            Some(unordered) if self.processing(&text) == Processing::Unordered => unordered,
+            _ => &self.ordered,
+        };
+
+        worker.send(Inbound::Text(text)).await
+    }
+
+    /// Queue a binary frame for the binary handler, in order with the session's other messages.
+    /// Fails once the session's handlers have stopped.
+    pub(crate) async fn dispatch_binary(&self, bytes: Bytes) -> anyhow::Result<()> {
+        self.ordered.send(Inbound::Binary(bytes)).await
+    }
+
+    /// Stop taking messages and wait for the ones already queued to be handled.
+    pub(crate) async fn finish(self) {
+        match self.unordered {
+            Some(unordered) => {
+                futures::join!(self.ordered.finish(), unordered.finish());
+            }
+            None => self.ordered.finish().await,
+        }
+    }
+
+    /// How a message is processed, going by its type. Anything that can't be told is ordered.
+    fn processing(&self, text: &str) -> Processing {
+        let Ok(MessageKind { kind: Some(kind) }) = serde_json::from_str(text) else {
+            return Processing::Ordered;
+        };
+        self.server
+            .config()
+            .message_processing
+            .get(kind)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+fn spawn_ordered(supervisor: Supervisor, session: Arc<Session>) -> Worker {
+    let (messages, mut receiver) = mpsc::channel::<Inbound>(MESSAGE_QUEUE_CAPACITY);
+    let cancel = supervisor.cancel.clone();
+
+    let task = tokio::spawn(supervisor.run("ordered handlers", async move {
+        while let Some(inbound) = tokio::select! {
+            inbound = receiver.recv() => inbound,
+            _ = cancel.cancelled() => None,
+        } {
+            session.handle(inbound).await;
+        }
+    }));
+
+    Worker { messages, task }
+}
+
+fn spawn_unordered(supervisor: Supervisor, session: Arc<Session>) -> Worker {
+    let (messages, mut receiver) = mpsc::channel::<Inbound>(MESSAGE_QUEUE_CAPACITY);
+    let cancel = supervisor.cancel.clone();
+
+    let task = tokio::spawn(supervisor.run("unordered handlers", async move {
+        let mut running = FuturesUnordered::new();
+        let mut open = true;
+
+        while open || !running.is_empty() {
+            tokio::select! {
+                inbound = receiver.recv(), if open && running.len() < MESSAGE_QUEUE_CAPACITY => {
+                    match inbound {
+                        Some(inbound) => running.push(session.handle(inbound)),
+                        None => open = false,
+                    }
+                }
+                Some(()) = running.next() => {}
+                _ = cancel.cancelled() => break,
+            }
+        }
+    }));
+
+    Worker { messages, task }
+}
@@ -0,0 +1,56 @@
+use tracing::Instrument;
+use uuid::Uuid;
+
+use super::WebSocketServer;
+
+/// Removes a session from the server when dropped, however the code holding it exits.
+///
+/// Cleanup is idempotent, and only ever touches the session the guard was made for, so a session that
+/// was already cleaned up or superseded by another under the same uuid is left alone.
+pub struct SessionGuard {
+    server: WebSocketServer,
+    uuid: Uuid,
+    /// Unique to each inserted session, telling it apart from any later one reusing the uuid.
+    resume_token: Uuid,
+}
+
+impl SessionGuard {
+    pub(crate) fn new(server: WebSocketServer, uuid: Uuid, resume_token: Uuid) -> Self {
+        Self {
+            server,
+            uuid,
+            resume_token,
+        }
+    }
+
+    pub fn uuid(&self) -> Uuid {
+        self.uuid
+    }
+
+    /// Clean up the session now, rather than waiting for the guard to be dropped.
+    pub async fn cleanup(&self) {
+        self.server
+            .cleanup_session_instance(&self.uuid, self.resume_token)
+            .await;
+    }
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        // Drop can't await, so hand the cleanup to the runtime
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            tracing::warn!("Session {} dropped outside of a runtime", self.uuid);
+            return;
+        };
+
+        let server = self.server.clone();
+        let uuid = self.uuid;
+        let resume_token = self.resume_token;
+        runtime.spawn(
+            async move {
+                server.cleanup_session_instance(&uuid, resume_token).await;
+            }
+            .in_current_span(),
+        );
+    }
+}
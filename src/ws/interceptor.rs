@@ -0,0 +1,92 @@
+//! Hooks every outgoing message is run past before it's written to a session, e.g. to redact
+//! fields, stamp messages with the server time, sample noisy events or rewrite payloads for a
+//! particular deployment.
+
+use std::sync::Arc;
+
+use bytestring::ByteString;
+use uuid::Uuid;
+
+/// Where an outgoing message came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageKind {
+    /// Sent ahead of everything else, like the hello, warnings and advisories.
+    Control,
+    /// A response to something the client sent.
+    Response,
+    /// A broadcast or published event.
+    Event,
+}
+
+/// A message on its way to a session, parsed so interceptors can inspect and rewrite it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutgoingMessage {
+    pub session: Uuid,
+    pub kind: MessageKind,
+    /// The message as JSON, or a JSON string if it wasn't JSON to begin with.
+    pub payload: serde_json::Value,
+}
+
+/// What to do with a message once an interceptor has seen it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// Pass it on to the next interceptor, and then the session.
+    Continue,
+    /// Don't send it, skipping any interceptors after this one.
+    Drop,
+}
+
+pub trait OutboundInterceptor: Send + Sync {
+    fn intercept(&self, message: &mut OutgoingMessage) -> Decision;
+}
+
+impl<F> OutboundInterceptor for F
+where
+    F: Fn(&mut OutgoingMessage) -> Decision + Send + Sync,
+{
+    fn intercept(&self, message: &mut OutgoingMessage) -> Decision {
+        self(message)
+    }
+}
+
+/// Interceptors in the order they were added, run in that order.
+#[derive(Clone, Default)]
+pub struct InterceptorChain {
+    interceptors: Vec<Arc<dyn OutboundInterceptor>>,
+}
+
+impl InterceptorChain {
+    pub fn push(&mut self, interceptor: impl OutboundInterceptor + 'static) {
+        self.interceptors.push(Arc::new(interceptor));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.interceptors.is_empty()
+    }
+
+    /// Run `text` through the chain, returning what should be sent instead, or `None` if it was
+    /// dropped. Messages are only parsed when there are interceptors to run.
+    pub fn run(&self, session: Uuid, kind: MessageKind, text: ByteString) -> Option<ByteString> {
+        if self.is_empty() {
+            return Some(text);
+        }
+
+        let payload =
+            serde_json::from_str(&text).unwrap_or_else(|_| serde_json::Value::from(&*text));
+        let mut message = OutgoingMessage {
+            session,
+            kind,
+            payload,
+        };
+        for interceptor in &self.interceptors {
+            if interceptor.intercept(&mut message) == Decision::Drop {
+                return None;
+            }
+        }
+
+        match message.payload {
+            serde_json::Value::String(text) => Some(text.into()),
+            payload => Some(payload.to_string().into()),
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{Map, Value};
+use uuid::Uuid;
+
+/// Fields the server sets on every message itself, which providers can't override.
+const RESERVED_FIELDS: [&str; 6] = ["type", "server_time", "ok", "id", "trace_id", "ref"];
+
+/// Supplies the fields sent alongside `server_time` in the keepalives sessions are sent between
+/// pings, e.g. the current chain height or which shard the session is on.
+pub trait KeepaliveProvider: Send + Sync {
+    fn fields(&self, session: Uuid, address: &str) -> Map<String, Value>;
+}
+
+impl<F> KeepaliveProvider for F
+where
+    F: Fn(Uuid, &str) -> Map<String, Value> + Send + Sync,
+{
+    fn fields(&self, session: Uuid, address: &str) -> Map<String, Value> {
+        self(session, address)
+    }
+}
+
+/// The fields `provider` supplies for a keepalive, less any the server sets itself.
+pub(crate) fn provided_fields(
+    provider: &dyn KeepaliveProvider,
+    session: Uuid,
+    address: &str,
+) -> Map<String, Value> {
+    let mut fields = provider.fields(session, address);
+    fields.retain(|name, _| !RESERVED_FIELDS.contains(&name.as_str()));
+    fields
+}
+
+/// `at` as an RFC 3339 UTC timestamp with millisecond precision, e.g.
+/// `2025-01-31T12:00:00.000Z`, as keepalives give `server_time`.
+pub fn server_time(at: SystemTime) -> String {
+    let since_epoch = at.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, time) = (secs / 86400, secs % 86400);
+
+    // Civil date from days since the epoch, per Howard Hinnant's `civil_from_days`
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        time / 3600,
+        time % 3600 / 60,
+        time % 60,
+        since_epoch.subsec_millis()
+    )
+}
@@ -0,0 +1,146 @@
+//! Async callbacks embedders register for what clients do, e.g. for analytics or side effects of
+//! their own, without touching the handlers.
+//!
+//! Listeners are spawned rather than awaited, so a slow one never holds up the session it's
+//! listening to.
+
+use std::{collections::HashMap, future::Future, sync::Arc};
+
+use bytestring::ByteString;
+use futures::future::BoxFuture;
+use uuid::Uuid;
+
+use crate::models::websocket::messages::WebSocketMessage;
+
+use super::WebSocketServer;
+use super::routes::Route;
+
+/// The session a listener is hearing about, and a way to act on it.
+#[derive(Clone)]
+pub struct SessionHandle {
+    uuid: Uuid,
+    address: String,
+    server: WebSocketServer,
+}
+
+impl SessionHandle {
+    pub(crate) fn new(uuid: Uuid, address: impl Into<String>, server: WebSocketServer) -> Self {
+        Self {
+            uuid,
+            address: address.into(),
+            server,
+        }
+    }
+
+    pub fn uuid(&self) -> Uuid {
+        self.uuid
+    }
+
+    /// The session's address at the time of the activity.
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    pub fn server(&self) -> &WebSocketServer {
+        &self.server
+    }
+
+    /// Send the session a message, queued behind its pending responses.
+    pub async fn send(&self, msg: impl Into<ByteString>) -> anyhow::Result<()> {
+        let Some(outbound) = self.server.outbound(&self.uuid).await else {
+            anyhow::bail!("Session {} is gone", self.uuid);
+        };
+        outbound.respond(msg).await
+    }
+
+    pub async fn kick(&self, reason: Option<String>) {
+        self.server.kick(&self.uuid, reason).await;
+    }
+}
+
+type MessageListener =
+    dyn Fn(SessionHandle, Arc<WebSocketMessage>) -> BoxFuture<'static, ()> + Send + Sync;
+/// Gives up on the message, returning `None`, if it doesn't deserialize into the route's type.
+type RouteListener =
+    dyn Fn(SessionHandle, &serde_json::Value) -> Option<BoxFuture<'static, ()>> + Send + Sync;
+type LoginListener = dyn Fn(SessionHandle) -> BoxFuture<'static, ()> + Send + Sync;
+
+/// Listeners registered with a server, by what they listen for.
+#[derive(Clone, Default)]
+pub struct Listeners {
+    messages: HashMap<String, Vec<Arc<MessageListener>>>,
+    routes: HashMap<&'static str, Vec<Arc<RouteListener>>>,
+    logins: Vec<Arc<LoginListener>>,
+}
+
+impl Listeners {
+    pub fn on_message_type<F, Fut>(&mut self, kind: impl Into<String>, listener: F)
+    where
+        F: Fn(SessionHandle, Arc<WebSocketMessage>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let listener: Arc<MessageListener> =
+            Arc::new(move |session, message| Box::pin(listener(session, message)));
+        self.messages.entry(kind.into()).or_default().push(listener);
+    }
+
+    pub fn on_route<R, F, Fut>(&mut self, listener: F)
+    where
+        R: Route + Sync,
+        F: Fn(SessionHandle, Arc<R>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let listener: Arc<RouteListener> = Arc::new(move |session, payload| {
+            let request = serde_json::from_value::<R>(payload.clone()).ok()?;
+            Some(Box::pin(listener(session, Arc::new(request))))
+        });
+        self.routes.entry(R::TYPE).or_default().push(listener);
+    }
+
+    pub fn on_login<F, Fut>(&mut self, listener: F)
+    where
+        F: Fn(SessionHandle) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.logins
+            .push(Arc::new(move |session| Box::pin(listener(session))));
+    }
+
+    /// Whether anything is listening for messages of type `kind`, so they're only cloned when needed.
+    pub fn wants_message(&self, kind: &str) -> bool {
+        self.messages.contains_key(kind)
+    }
+
+    pub fn message(&self, session: SessionHandle, message: Arc<WebSocketMessage>) {
+        let Some(listeners) = self.messages.get(message.r#type.kind()) else {
+            return;
+        };
+
+        for listener in listeners {
+            tokio::spawn(listener(session.clone(), message.clone()));
+        }
+    }
+
+    /// Whether anything is listening for messages of type `kind` handled by a route.
+    pub fn wants_route(&self, kind: &str) -> bool {
+        self.routes.contains_key(kind)
+    }
+
+    pub fn route(&self, session: SessionHandle, kind: &str, payload: &serde_json::Value) {
+        let Some(listeners) = self.routes.get(kind) else {
+            return;
+        };
+
+        for listener in listeners {
+            if let Some(listening) = listener(session.clone(), payload) {
+                tokio::spawn(listening);
+            }
+        }
+    }
+
+    pub fn login(&self, session: SessionHandle) {
+        for listener in &self.logins {
+            tokio::spawn(listener(session.clone()));
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! Checks every message a session sends is run past before it reaches its handler, so concerns
+//! like extra auth, rate limits, validation and logging compose instead of living in the handler.
+
+use std::sync::Arc;
+
+use uuid::Uuid;
+
+use crate::models::websocket::messages::WebSocketMessage;
+
+/// A message from a session on its way to its handler.
+#[derive(Debug)]
+pub struct IncomingMessage<'a> {
+    pub session: Uuid,
+    pub address: &'a str,
+    /// The message's `type`, e.g. `subscribe`.
+    pub kind: &'a str,
+    /// May be rewritten, e.g. to normalize fields before the handler sees them.
+    pub message: MessageBody<'a>,
+}
+
+/// What an incoming message holds, depending on which handler it's for.
+#[derive(Debug)]
+pub enum MessageBody<'a> {
+    /// One of the server's own message types.
+    Parsed(&'a mut WebSocketMessage),
+    /// A message with a route of its own, as the JSON its route will deserialize.
+    Routed(&'a mut serde_json::Value),
+}
+
+/// Sent back as an error in place of the handler's response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejection {
+    /// Machine-readable error code, e.g. `rate_limited`.
+    pub error: String,
+    pub message: String,
+}
+
+impl Rejection {
+    pub fn new(error: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            error: error.into(),
+            message: message.into(),
+        }
+    }
+}
+
+pub trait InboundMiddleware: Send + Sync {
+    /// Let the message through, or turn it away with an error.
+    fn handle(&self, incoming: &mut IncomingMessage) -> Result<(), Rejection>;
+}
+
+impl<F> InboundMiddleware for F
+where
+    F: Fn(&mut IncomingMessage) -> Result<(), Rejection> + Send + Sync,
+{
+    fn handle(&self, incoming: &mut IncomingMessage) -> Result<(), Rejection> {
+        self(incoming)
+    }
+}
+
+/// Middleware in the order it was added, run in that order until one of them rejects the message.
+#[derive(Clone, Default)]
+pub struct MiddlewareChain {
+    middleware: Vec<Arc<dyn InboundMiddleware>>,
+}
+
+impl MiddlewareChain {
+    pub fn push(&mut self, middleware: impl InboundMiddleware + 'static) {
+        self.middleware.push(Arc::new(middleware));
+    }
+
+    pub fn run(
+        &self,
+        session: Uuid,
+        address: &str,
+        message: &mut WebSocketMessage,
+    ) -> Result<(), Rejection> {
+        let kind = message.r#type.kind();
+        self.run_incoming(IncomingMessage {
+            session,
+            address,
+            kind,
+            message: MessageBody::Parsed(message),
+        })
+    }
+
+    /// Run the middleware over a message of type `kind` headed for its route.
+    pub fn run_routed(
+        &self,
+        session: Uuid,
+        address: &str,
+        kind: &str,
+        payload: &mut serde_json::Value,
+    ) -> Result<(), Rejection> {
+        self.run_incoming(IncomingMessage {
+            session,
+            address,
+            kind,
+            message: MessageBody::Routed(payload),
+        })
+    }
+
+    fn run_incoming(&self, mut incoming: IncomingMessage) -> Result<(), Rejection> {
+        self.middleware
+            .iter()
+            .try_for_each(|middleware| middleware.handle(&mut incoming))
+    }
+}
@@ -0,0 +1,445 @@
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU32, AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use actix_ws::{CloseCode, CloseReason, Closed};
+use anyhow::anyhow;
+use bytes::Bytes;
+use bytestring::ByteString;
+use tokio::sync::mpsc::{self, error::SendTimeoutError, error::TrySendError};
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::config::SlowConsumerConfig;
+use crate::encoding::{EncodedEvent, Encoding, Frame};
+use crate::metrics::Metrics;
+use crate::models::websocket::messages::{WebSocketMessage, WebSocketMessageInner};
+
+use super::interceptor::{InterceptorChain, MessageKind};
+use super::sink::SessionSink;
+use super::to_text;
+
+/// Maximum number of responses queued for a session before request handling waits on it.
+const RESPONSE_QUEUE_CAPACITY: usize = 64;
+
+/// Maximum number of control frames queued for a session before it is closed for not reading them.
+const CONTROL_QUEUE_CAPACITY: usize = 32;
+
+/// Result of handing an event to a session's outbound queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PushOutcome {
+    Queued,
+    /// The session is lagging and the event was dropped for it.
+    Dropped,
+    /// The session has lagged for too long and should be disconnected.
+    Evict,
+    Closed,
+}
+
+/// Rewrites text messages on their way to the socket, for transports that frame them differently.
+pub type TextCodec = fn(ByteString) -> ByteString;
+
+/// Frames that jump ahead of everything else, so heartbeats and closes aren't starved by events.
+enum Control {
+    Text(ByteString),
+    /// Text that skips the codec, for the transport's own framing.
+    Raw(ByteString),
+    Ping(Bytes),
+    /// The latest pong, taken when it is written, so a client pinging faster than it reads only
+    /// ever has one waiting.
+    Pong,
+    Close(Option<CloseReason>),
+}
+
+/// A response to a client request, which may be a binary frame from the binary channel.
+enum Response {
+    Text(ByteString),
+    Binary(Bytes),
+    /// Not a frame: told once every response queued ahead of it has been written.
+    Flush(oneshot::Sender<()>),
+}
+
+/// An event waiting in the queue, along with when it stops being worth delivering.
+struct QueuedEvent {
+    msg: Frame,
+    expires_at: Option<Instant>,
+    /// Told once the event has been written to the socket.
+    written: Option<oneshot::Sender<()>>,
+}
+
+/// Per-session queue of outbound messages, drained by a dedicated writer task.
+///
+/// Messages are written in priority order: control frames first, then responses, then events.
+/// Only events are ever dropped when the session falls behind, besides pongs superseded by newer
+/// ones; a session that stops reading control frames altogether is closed.
+#[derive(Clone)]
+pub struct OutboundQueue {
+    control: mpsc::Sender<Control>,
+    pong: Arc<Mutex<Option<Bytes>>>,
+    /// Cancelled once the control queue overflows, closing the session.
+    overflowed: CancellationToken,
+    responses: mpsc::Sender<Response>,
+    events: mpsc::Sender<QueuedEvent>,
+    strikes: Arc<AtomicU32>,
+    expired: Arc<AtomicU64>,
+    config: SlowConsumerConfig,
+    /// Session messages are intercepted on behalf of.
+    session: Uuid,
+    interceptors: Arc<InterceptorChain>,
+}
+
+impl OutboundQueue {
+    pub fn spawn(
+        session: impl SessionSink,
+        config: SlowConsumerConfig,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Self::spawn_with_codec(
+            session,
+            config,
+            metrics,
+            std::convert::identity,
+            Encoding::Json,
+        )
+    }
+
+    /// Like [`Self::spawn`], but passing every text message through `codec` before it is written,
+    /// then transcoding it to `encoding`. Events pushed already encoded are written as they are.
+    pub fn spawn_with_codec(
+        session: impl SessionSink,
+        config: SlowConsumerConfig,
+        metrics: Arc<Metrics>,
+        codec: TextCodec,
+        encoding: Encoding,
+    ) -> Self {
+        let (control, mut control_receiver) = mpsc::channel(CONTROL_QUEUE_CAPACITY);
+        let pong = Arc::new(Mutex::new(None));
+        let overflowed = CancellationToken::new();
+        let (responses, mut response_receiver) = mpsc::channel(RESPONSE_QUEUE_CAPACITY);
+        let (events, mut event_receiver) = mpsc::channel::<QueuedEvent>(config.queue_capacity);
+        let strikes = Arc::new(AtomicU32::new(0));
+        let expired = Arc::new(AtomicU64::new(0));
+
+        let queue = Self {
+            control,
+            pong: pong.clone(),
+            overflowed: overflowed.clone(),
+            responses,
+            events,
+            strikes: strikes.clone(),
+            expired: expired.clone(),
+            config: config.clone(),
+            session: Uuid::nil(),
+            interceptors: Arc::default(),
+        };
+
+        // The writer logs under whichever session span it was spawned from
+        let mut session = session;
+        tokio::spawn(async move {
+            loop {
+                let result = tokio::select! {
+                    biased;
+
+                    _ = overflowed.cancelled() => {
+                        tracing::warn!("Closing session for not reading its control frames");
+                        let reason = CloseReason {
+                            code: CloseCode::Policy,
+                            description: Some("SlowConsumer".to_owned()),
+                        };
+                        let _ = session.close(Some(reason)).await;
+                        break;
+                    }
+                    Some(control) = control_receiver.recv() => match control {
+                        Control::Text(msg) => write_text(&mut session, codec, encoding, msg).await,
+                        Control::Raw(msg) => session.text(msg).await,
+                        Control::Ping(bytes) => session.ping(bytes).await,
+                        Control::Pong => {
+                            let bytes = pong.lock().unwrap_or_else(|e| e.into_inner()).take();
+                            match bytes {
+                                Some(bytes) => session.pong(bytes).await,
+                                None => Ok(()),
+                            }
+                        }
+                        Control::Close(reason) => {
+                            let _ = session.close(reason).await;
+                            break;
+                        }
+                    },
+                    Some(response) = response_receiver.recv() => match response {
+                        Response::Text(msg) => write_text(&mut session, codec, encoding, msg).await,
+                        Response::Binary(bytes) => session.binary(bytes).await,
+                        Response::Flush(done) => {
+                            let _ = done.send(());
+                            Ok(())
+                        }
+                    },
+                    Some(event) = event_receiver.recv() => {
+                        if event.expires_at.is_some_and(|x| x <= Instant::now()) {
+                            expired.fetch_add(1, Ordering::Relaxed);
+                            metrics.events_expired.fetch_add(1, Ordering::Relaxed);
+                            continue;
+                        }
+
+                        let QueuedEvent { msg, written, .. } = event;
+                        let write = async {
+                            match msg {
+                                Frame::Text(msg) => write_text(&mut session, codec, encoding, msg).await,
+                                Frame::Binary(bytes) => session.binary(bytes).await,
+                            }
+                        };
+                        match tokio::time::timeout(config.send_timeout, write).await {
+                            Ok(Ok(())) => {
+                                if event_receiver.is_empty() {
+                                    strikes.store(0, Ordering::Relaxed);
+                                }
+                                if let Some(written) = written {
+                                    let _ = written.send(());
+                                }
+                                Ok(())
+                            }
+                            Ok(Err(e)) => Err(e),
+                            Err(_) => {
+                                let strikes = strikes.fetch_add(1, Ordering::Relaxed) + 1;
+                                tracing::warn!("Timed out sending event to session ({strikes} strikes)");
+                                Ok(())
+                            }
+                        }
+                    }
+                    else => break,
+                };
+
+                if result.is_err() {
+                    break;
+                }
+            }
+        }
+        .in_current_span());
+
+        queue
+    }
+
+    /// Run responses, control messages and events for `session` past `interceptors` before they
+    /// are queued.
+    pub fn with_interceptors(mut self, session: Uuid, interceptors: Arc<InterceptorChain>) -> Self {
+        self.session = session;
+        self.interceptors = interceptors;
+        self
+    }
+
+    /// Run a message past the interceptors, returning `None` if one of them dropped it.
+    pub fn intercept(&self, kind: MessageKind, msg: ByteString) -> Option<ByteString> {
+        self.interceptors.run(self.session, kind, msg)
+    }
+
+    /// Get the frame to push for an event being fanned out, after running it past the interceptors.
+    ///
+    /// Without interceptors, the event's shared encoding is used as it is.
+    pub fn intercept_event(&self, event: &EncodedEvent, encoding: Encoding) -> Option<Frame> {
+        if self.interceptors.is_empty() {
+            return Some(event.get(encoding));
+        }
+
+        let msg = self.intercept(MessageKind::Event, event.json().clone())?;
+        Some(encoding.encode(msg))
+    }
+
+    /// Number of events waiting to be written to the socket.
+    pub fn depth(&self) -> usize {
+        self.events.max_capacity() - self.events.capacity()
+    }
+
+    pub fn strikes(&self) -> u32 {
+        self.strikes.load(Ordering::Relaxed)
+    }
+
+    /// Number of events dropped for outliving their TTL before they could be written.
+    pub fn expired(&self) -> u64 {
+        self.expired.load(Ordering::Relaxed)
+    }
+
+    /// Queue a response to a client request, waiting for space rather than dropping it.
+    pub async fn respond(&self, msg: impl Into<ByteString>) -> anyhow::Result<()> {
+        let Some(msg) = self.intercept(MessageKind::Response, msg.into()) else {
+            return Ok(());
+        };
+        self.send_response(Response::Text(msg)).await
+    }
+
+    /// Queue a binary frame in response to one the client sent.
+    pub async fn respond_binary(&self, bytes: impl Into<Bytes>) -> anyhow::Result<()> {
+        self.send_response(Response::Binary(bytes.into())).await
+    }
+
+    /// Wait for every response queued so far to be written to the socket.
+    pub async fn flush(&self) -> anyhow::Result<()> {
+        let (done, receiver) = oneshot::channel();
+        self.send_response(Response::Flush(done)).await?;
+        receiver.await.map_err(|_| anyhow!("Session is closed"))
+    }
+
+    async fn send_response(&self, response: Response) -> anyhow::Result<()> {
+        self.responses
+            .send(response)
+            .await
+            .map_err(|_| anyhow!("Session is closed"))
+    }
+
+    /// Send a message ahead of any queued responses and events.
+    pub fn control(&self, msg: impl Into<ByteString>) -> anyhow::Result<()> {
+        let Some(msg) = self.intercept(MessageKind::Control, msg.into()) else {
+            return Ok(());
+        };
+        self.send_control(Control::Text(msg))
+    }
+
+    /// Send a text frame ahead of everything else, without passing it through the codec.
+    pub fn raw(&self, msg: impl Into<ByteString>) -> anyhow::Result<()> {
+        self.send_control(Control::Raw(msg.into()))
+    }
+
+    pub fn ping(&self, bytes: impl Into<Bytes>) -> anyhow::Result<()> {
+        self.send_control(Control::Ping(bytes.into()))
+    }
+
+    /// Answer a ping, replacing rather than queueing behind any pong not written yet.
+    pub fn pong(&self, bytes: impl Into<Bytes>) -> anyhow::Result<()> {
+        if self.control.is_closed() {
+            return Err(anyhow!("Session is closed"));
+        }
+
+        let stale = self
+            .pong
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .replace(bytes.into());
+        match stale {
+            Some(_) => Ok(()),
+            None => self.send_control(Control::Pong),
+        }
+    }
+
+    /// Close the session once any control frames ahead of it are written, discarding everything else.
+    pub fn close(&self, reason: Option<CloseReason>) {
+        let _ = self.send_control(Control::Close(reason));
+    }
+
+    /// Queue a control frame, closing the session instead if it has stopped reading them.
+    fn send_control(&self, control: Control) -> anyhow::Result<()> {
+        match self.control.try_send(control) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Closed(_)) => Err(anyhow!("Session is closed")),
+            Err(TrySendError::Full(_)) => {
+                self.overflowed.cancel();
+                Err(anyhow!("Session isn't reading its control frames"))
+            }
+        }
+    }
+
+    /// Queue an event, dropping it or asking for the session to be evicted if it is lagging.
+    pub async fn push(&self, msg: impl Into<Frame>) -> PushOutcome {
+        self.push_with_ttl(msg, None).await
+    }
+
+    /// Queue an event that is dropped instead of delivered if it is still queued after `ttl`.
+    pub async fn push_with_ttl(&self, msg: impl Into<Frame>, ttl: Option<Duration>) -> PushOutcome {
+        self.push_event(msg.into(), ttl, None).await
+    }
+
+    /// Like [`Self::push_with_ttl`], also returning a receiver that completes once the event has
+    /// been written to the socket, and is dropped if it never is.
+    pub async fn push_tracked(
+        &self,
+        msg: impl Into<Frame>,
+        ttl: Option<Duration>,
+    ) -> (PushOutcome, oneshot::Receiver<()>) {
+        let (written, receiver) = oneshot::channel();
+        let outcome = self.push_event(msg.into(), ttl, Some(written)).await;
+        (outcome, receiver)
+    }
+
+    async fn push_event(
+        &self,
+        msg: Frame,
+        ttl: Option<Duration>,
+        written: Option<oneshot::Sender<()>>,
+    ) -> PushOutcome {
+        if self.strikes() > self.config.max_strikes {
+            return PushOutcome::Evict;
+        }
+
+        let event = QueuedEvent {
+            msg,
+            expires_at: ttl.map(|x| Instant::now() + x),
+            written,
+        };
+        let event = match self.events.try_send(event) {
+            Ok(()) => return PushOutcome::Queued,
+            Err(TrySendError::Closed(_)) => return PushOutcome::Closed,
+            Err(TrySendError::Full(event)) => event,
+        };
+
+        if self.strike() > self.config.max_strikes {
+            return PushOutcome::Evict;
+        }
+
+        if self.config.drop_events_when_lagging {
+            return PushOutcome::Dropped;
+        }
+
+        match self
+            .events
+            .send_timeout(event, self.config.send_timeout)
+            .await
+        {
+            Ok(()) => PushOutcome::Queued,
+            Err(SendTimeoutError::Closed(_)) => PushOutcome::Closed,
+            Err(SendTimeoutError::Timeout(_)) => match self.strike() > self.config.max_strikes {
+                true => PushOutcome::Evict,
+                false => PushOutcome::Dropped,
+            },
+        }
+    }
+
+    fn strike(&self) -> u32 {
+        let strikes = self.strikes.fetch_add(1, Ordering::Relaxed) + 1;
+
+        // Let the client know on its first strike, bypassing the full queue
+        if strikes == 1 {
+            let message = WebSocketMessage {
+                ok: None,
+                id: None,
+                trace_id: None,
+                idempotency_key: None,
+                r#type: WebSocketMessageInner::Warning {
+                    warning: "slow_consumer".to_owned(),
+                    message: "Events are being sent faster than you are reading them".to_owned(),
+                },
+            };
+            if let Some(message) = to_text(&message, "slow_consumer warning") {
+                let _ = self.control(message);
+            }
+        }
+
+        strikes
+    }
+}
+
+/// Write a text message through the codec, in the session's encoding.
+async fn write_text(
+    session: &mut impl SessionSink,
+    codec: TextCodec,
+    encoding: Encoding,
+    msg: ByteString,
+) -> Result<(), Closed> {
+    match encoding.encode(codec(msg)) {
+        Frame::Text(text) => session.text(text).await,
+        Frame::Binary(bytes) => session.binary(bytes).await,
+    }
+}
@@ -0,0 +1,107 @@
+//! Typed handlers for message types of a downstream user's own.
+//!
+//! A route deserializes its request into a type of its choosing and answers with the response
+//! type it declares, instead of going through the server's own message enum. Routes are looked up
+//! by the message's `type` before the built-in types are parsed, so they can also take over a
+//! built-in type. They still pass through inbound middleware, as the JSON they arrived as, and can
+//! be listened for with [`WebSocketServer::on_route`].
+
+use std::{collections::HashMap, future::Future, sync::Arc};
+
+use futures::future::BoxFuture;
+use serde::{Serialize, de::DeserializeOwned};
+use uuid::Uuid;
+
+use crate::policy::Scope;
+
+use super::WebSocketServer;
+use super::middleware::Rejection;
+
+/// A request type with a route of its own.
+pub trait Route: DeserializeOwned + Send + 'static {
+    /// The message's `type`, e.g. `make_transaction`.
+    const TYPE: &'static str;
+    /// Scope a session's token needs to send it, unless the policy says otherwise.
+    const SCOPE: Scope = Scope::ReadOnly;
+    /// Whether it changes state, so is turned away during read-only maintenance. By default that's
+    /// any route needing more than [`Scope::ReadOnly`].
+    const STATE_CHANGING: bool = !matches!(Self::SCOPE, Scope::ReadOnly);
+
+    /// Sent back alongside `responding_to`, so it has to serialize to a JSON object.
+    type Response: Serialize;
+}
+
+/// The session a routed message came from.
+#[derive(Clone)]
+pub struct RouteContext {
+    pub session: Uuid,
+    pub address: String,
+    pub server: WebSocketServer,
+}
+
+type HandlerFuture = BoxFuture<'static, Result<serde_json::Value, Rejection>>;
+type Handler = dyn Fn(RouteContext, serde_json::Value) -> HandlerFuture + Send + Sync;
+
+#[derive(Clone)]
+pub(crate) struct RegisteredRoute {
+    pub(crate) scope: Scope,
+    pub(crate) state_changing: bool,
+    pub(crate) handler: Arc<Handler>,
+}
+
+/// Routes by message type, registered with [`Routes::on`].
+#[derive(Clone, Default)]
+pub struct Routes {
+    routes: HashMap<&'static str, RegisteredRoute>,
+}
+
+impl Routes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handle messages of type `R::TYPE` with `handler`, replacing any route already registered for it.
+    pub fn on<R, F, Fut>(mut self, handler: F) -> Self
+    where
+        R: Route,
+        F: Fn(RouteContext, R) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R::Response, Rejection>> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        let erased = move |context: RouteContext, payload: serde_json::Value| {
+            let handler = handler.clone();
+            let handled: HandlerFuture = Box::pin(async move {
+                let request: R = serde_json::from_value(payload)
+                    .map_err(|e| Rejection::new("invalid_message", e.to_string()))?;
+                let response = handler(context, request).await?;
+
+                serde_json::to_value(response).map_err(|e| {
+                    tracing::error!("Failed to serialize {} response: {e}", R::TYPE);
+                    Rejection::new(
+                        "internal_error",
+                        "Something went wrong building the response",
+                    )
+                })
+            });
+            handled
+        };
+
+        self.routes.insert(
+            R::TYPE,
+            RegisteredRoute {
+                scope: R::SCOPE,
+                state_changing: R::STATE_CHANGING,
+                handler: Arc::new(erased),
+            },
+        );
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    pub(crate) fn get(&self, kind: &str) -> Option<&RegisteredRoute> {
+        self.routes.get(kind)
+    }
+}
@@ -0,0 +1,529 @@
+//! Runs a session once a transport has accepted it, so the WebSocket, HTTP/2, WebTransport and
+//! Socket.IO endpoints only differ in how they frame messages.
+
+use std::{net::IpAddr, str::FromStr, sync::Arc, time::Duration};
+
+use actix_web::http::StatusCode;
+use actix_ws::{AggregatedMessage, AggregatedMessageStream, CloseCode, CloseReason, ProtocolError};
+use tokio::{
+    sync::Mutex,
+    time::{self, Instant},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::field;
+use uuid::Uuid;
+
+use super::{
+    CLIENT_TIMEOUT, HEARTBEAT_INTERVAL, InsertedSession, OutboundQueue, PING_EPOCH, SessionGuard,
+    Supervisor, WebSocketServer, dispatch::Dispatcher, outbound::TextCodec, record_session,
+    sink::SessionSink, to_text,
+};
+use crate::audit::{AuditAction, AuditRecord};
+use crate::models::websocket::{
+    WebSocketTokenData,
+    messages::{WebSocketMessage, WebSocketMessageInner},
+    state::SessionState,
+};
+use crate::recorder::{Frame, RecordingHeader, SessionRecorder};
+
+/// Messages from a client, with the transport's framing already taken off.
+pub(crate) trait Inbound {
+    /// The next message, or `None` once the client has gone away.
+    fn recv(&mut self) -> impl Future<Output = Option<Result<AggregatedMessage, ProtocolError>>>;
+}
+
+impl Inbound for AggregatedMessageStream {
+    async fn recv(&mut self) -> Option<Result<AggregatedMessage, ProtocolError>> {
+        AggregatedMessageStream::recv(self).await
+    }
+}
+
+/// How a session notices its client has gone away.
+pub(crate) enum Heartbeat {
+    /// WebSocket pings, closing the session once they go unanswered, with keepalives in between.
+    Ping,
+    /// Keepalives alone, for transports that notice clients going away by themselves.
+    #[cfg(feature = "webtransport")]
+    Keepalive,
+    /// The transport's own, which it runs with [`Running::supervise`].
+    #[cfg(feature = "socketio")]
+    Transport,
+}
+
+/// A session its transport has accepted, with the token it connected with.
+pub(crate) struct Connection<S> {
+    pub token: Uuid,
+    pub data: WebSocketTokenData,
+    pub sink: S,
+    pub ip: Option<IpAddr>,
+    /// Applied to every outgoing text message, for transports that wrap them.
+    pub codec: TextCodec,
+    /// Sequence number to replay missed events from, if the client asked for it.
+    pub since_seq: Option<u64>,
+    pub heartbeat: Heartbeat,
+}
+
+/// A session that has been registered and greeted, waiting for its messages to be handled.
+pub(crate) struct Running {
+    server: Arc<WebSocketServer>,
+    token: Uuid,
+    outbound: OutboundQueue,
+    guard: SessionGuard,
+    supervisor: Supervisor,
+    dispatcher: Dispatcher,
+    recorder: Option<SessionRecorder>,
+    alive: Arc<Mutex<Instant>>,
+}
+
+/// A connection whose token has been redeemed, and which the server has made room for.
+pub(crate) struct Admitted {
+    /// The gateway the token was issued by, which is a namespace's if it wasn't the root one.
+    pub server: Arc<WebSocketServer>,
+    pub token: Uuid,
+    pub data: WebSocketTokenData,
+}
+
+/// Why a connection was turned away before its session could start.
+#[derive(Debug)]
+pub(crate) enum Refusal {
+    /// The token is malformed, unknown or already used.
+    InvalidToken(String),
+    /// The token is a guest's, and guests aren't allowed to connect.
+    GuestsDisallowed,
+    /// The server is full, so the client should come back within this window.
+    Overloaded(Duration),
+    /// Turned away for any other reason, e.g. a ban, with the HTTP status to answer with.
+    Refused { status: u16, message: String },
+}
+
+impl Refusal {
+    /// The HTTP status to refuse the connection with, for transports that refuse before upgrading.
+    pub(crate) fn status(&self) -> u16 {
+        match self {
+            Self::InvalidToken(_) => StatusCode::BAD_REQUEST.as_u16(),
+            Self::GuestsDisallowed => StatusCode::UNAUTHORIZED.as_u16(),
+            Self::Overloaded(_) => StatusCode::SERVICE_UNAVAILABLE.as_u16(),
+            Self::Refused { status, .. } => *status,
+        }
+    }
+}
+
+impl std::fmt::Display for Refusal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidToken(message) | Self::Refused { message, .. } => f.write_str(message),
+            Self::GuestsDisallowed => f.write_str("Guest connections are disabled"),
+            Self::Overloaded(_) => f.write_str("Too many connected sessions"),
+        }
+    }
+}
+
+/// Redeem the token a connection came with and admit it, the same way over every transport.
+///
+/// In echo mode, malformed and unknown tokens are let through as guests.
+pub(crate) async fn admit(
+    server: Arc<WebSocketServer>,
+    token: &str,
+    ip: Option<IpAddr>,
+) -> Result<Admitted, Refusal> {
+    let token = match Uuid::from_str(token) {
+        Ok(token) => token,
+        Err(_) if server.config().echo_mode => Uuid::new_v4(),
+        Err(e) => return Err(Refusal::InvalidToken(e.to_string())),
+    };
+    let server = match server.namespace_for_token(&token).await {
+        Some(namespace) => Arc::new(namespace),
+        None => server,
+    };
+    let data = match server.use_token(&token).await {
+        Ok(data) => data,
+        Err(_) if server.config().echo_mode => WebSocketTokenData::guest(),
+        Err(e) => {
+            let action = AuditAction::AuthFailed {
+                reason: e.to_string(),
+            };
+            server.audit(AuditRecord::new(action).session(token).ip(ip));
+
+            return Err(Refusal::InvalidToken(e.to_string()));
+        }
+    };
+    if data.private_key.is_none() && !server.config().allow_guests {
+        return Err(Refusal::GuestsDisallowed);
+    }
+
+    let admitted = server.admit(&data.address, ip).await.map_err(|e| {
+        let status = e.as_response_error().status_code();
+        (status, e.to_string())
+    });
+    match admitted {
+        Ok(()) => Ok(Admitted {
+            server,
+            token,
+            data,
+        }),
+        // Turned away for being full rather than banned, so spread the retries out
+        Err((StatusCode::SERVICE_UNAVAILABLE, _)) => Err(Refusal::Overloaded(
+            server.config().overload_reconnect_window,
+        )),
+        Err((status, message)) => Err(Refusal::Refused {
+            status: status.as_u16(),
+            message,
+        }),
+    }
+}
+
+/// Register a session and send its hello, starting its heartbeat and handlers.
+///
+/// Returns `None` if the session was kicked before it even got going.
+pub(crate) async fn start(
+    server: Arc<WebSocketServer>,
+    connection: Connection<impl SessionSink>,
+) -> Option<Running> {
+    let Connection {
+        token,
+        data,
+        sink,
+        ip,
+        codec,
+        since_seq,
+        heartbeat,
+    } = connection;
+
+    tracing::Span::current().record("session_id", field::display(token));
+    record_session(&data.address, ip);
+    tracing::info!(
+        "Inserting new session (address: {}, ip: {ip:?})",
+        data.address
+    );
+    let address = data.address.clone();
+    // Guests get a new address every time they connect, so their retries are told apart by session
+    let idempotency_scope = match data.private_key {
+        Some(_) => address.clone(),
+        None => token.to_string(),
+    };
+    let authenticated = data.private_key.is_some();
+    let resumed_seq = data.resume.as_ref().and_then(|x| x.last_seq);
+    let scopes = data.scopes.clone();
+    server.audit(
+        AuditRecord::new(AuditAction::TokenUsed)
+            .session(token)
+            .address(&address)
+            .ip(ip),
+    );
+
+    let cancel = CancellationToken::new();
+    let InsertedSession {
+        resume_token,
+        guard,
+        replaced,
+    } = server
+        .insert_session(token, sink, ip, data, cancel.clone(), codec)
+        .await;
+    if replaced {
+        tracing::warn!("Connecting session {token} closed an older session with the same id");
+    }
+    let outbound = server.outbound(&token).await?;
+
+    let motd = serde_json::json!({
+        "motd": server.runtime_config().motd,
+        "resume_token": resume_token,
+        "scopes": scopes,
+        "capabilities": server.capabilities(),
+    });
+    #[cfg(feature = "krist")]
+    let motd = {
+        let mut motd = motd;
+        motd["economy"] = serde_json::json!(server.config().economy);
+        motd
+    };
+    let hello = WebSocketMessage {
+        ok: Some(true),
+        id: None,
+        trace_id: None,
+        idempotency_key: None,
+        r#type: WebSocketMessageInner::Hello { motd },
+    };
+    if let Some(hello) = to_text(&hello, "hello") {
+        let _ = outbound.respond(hello).await;
+    }
+
+    let ready = match authenticated {
+        true => SessionState::Authenticated,
+        false => SessionState::Ready,
+    };
+    server.transition(&token, ready).await;
+
+    if let Some(since_seq) = since_seq.or(resumed_seq) {
+        server.replay(&token, since_seq).await;
+    }
+
+    let alive = Arc::new(Mutex::new(Instant::now()));
+    let supervisor = Supervisor {
+        token,
+        outbound: outbound.clone(),
+        cancel: cancel.clone(),
+        span: tracing::Span::current(),
+    };
+
+    match heartbeat {
+        Heartbeat::Ping => {
+            tokio::spawn(supervisor.clone().run(
+                "heartbeat",
+                ping(
+                    server.clone(),
+                    token,
+                    address.clone(),
+                    outbound.clone(),
+                    alive.clone(),
+                    cancel,
+                ),
+            ));
+        }
+        #[cfg(feature = "webtransport")]
+        Heartbeat::Keepalive => {
+            tokio::spawn(supervisor.clone().run(
+                "keepalive",
+                keepalive(
+                    server.clone(),
+                    token,
+                    address.clone(),
+                    outbound.clone(),
+                    cancel,
+                ),
+            ));
+        }
+        #[cfg(feature = "socketio")]
+        Heartbeat::Transport => {}
+    }
+
+    let recorder = server.config().record_dir.as_deref().and_then(|dir| {
+        let header = RecordingHeader::new(token, &address, authenticated);
+        SessionRecorder::create(dir, &header)
+            .inspect_err(|e| tracing::warn!("Failed to start recording session: {e:#}"))
+            .ok()
+    });
+
+    let dispatcher = Dispatcher::spawn(
+        supervisor.clone(),
+        server.clone(),
+        address,
+        idempotency_scope,
+    );
+
+    Some(Running {
+        server,
+        token,
+        outbound,
+        guard,
+        supervisor,
+        dispatcher,
+        recorder,
+        alive,
+    })
+}
+
+impl Running {
+    /// Run one of the transport's own tasks alongside the session, ending with it.
+    #[cfg(any(feature = "socketio", feature = "webtransport"))]
+    pub(crate) fn supervise<F>(
+        &self,
+        name: &'static str,
+        task: impl FnOnce(OutboundQueue, CancellationToken) -> F,
+    ) where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let task = task(self.outbound.clone(), self.supervisor.cancel.clone());
+        tokio::spawn(self.supervisor.clone().run(name, task));
+    }
+
+    /// Handle messages from `inbound` until the client or the server ends the session.
+    pub(crate) async fn run(self, mut inbound: impl Inbound) {
+        let Self {
+            server,
+            token,
+            outbound,
+            guard,
+            supervisor,
+            dispatcher,
+            mut recorder,
+            alive,
+        } = self;
+        let cancel = supervisor.cancel.clone();
+
+        supervisor
+            .run("message loop", async move {
+                loop {
+                    let msg = tokio::select! {
+                        msg = inbound.recv() => msg,
+                        _ = cancel.cancelled() => break,
+                    };
+                    let msg = match msg {
+                        Some(Ok(msg)) => msg,
+                        Some(Err(ProtocolError::Overflow)) => {
+                            server.close_oversized(&outbound);
+                            break;
+                        }
+                        _ => break,
+                    };
+
+                    match msg {
+                        AggregatedMessage::Ping(bytes) => {
+                            if outbound.pong(bytes).is_err() {
+                                tracing::warn!("Failed to send pong back to session, closing it");
+                                break;
+                            }
+                        }
+
+                        AggregatedMessage::Text(string) => {
+                            record_frame(&mut recorder, || Frame::Text(string.to_string()));
+                            if dispatcher.dispatch(string).await.is_err() {
+                                break;
+                            }
+                        }
+
+                        AggregatedMessage::Close(reason) => {
+                            // Messages sent before the close still get their answers
+                            dispatcher.finish().await;
+                            outbound.close(reason);
+
+                            tracing::info!("Got close, cleaning up");
+                            guard.cleanup().await;
+
+                            return;
+                        }
+
+                        AggregatedMessage::Pong(bytes) => {
+                            if !record_pong(&server, &outbound, token, &alive, &bytes).await {
+                                guard.cleanup().await;
+                                return;
+                            }
+                        }
+
+                        AggregatedMessage::Binary(bytes) => {
+                            record_frame(&mut recorder, || Frame::Binary(bytes.to_vec()));
+                            if dispatcher.dispatch_binary(bytes).await.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                outbound.close(None);
+                guard.cleanup().await;
+            })
+            .await;
+    }
+}
+
+/// Ping a session every [`HEARTBEAT_INTERVAL`], closing it once it hasn't answered within
+/// [`CLIENT_TIMEOUT`], and send a keepalive along with every ping but the first.
+async fn ping(
+    server: Arc<WebSocketServer>,
+    token: Uuid,
+    address: String,
+    outbound: OutboundQueue,
+    alive: Arc<Mutex<Instant>>,
+    cancel: CancellationToken,
+) {
+    let mut interval = time::interval(HEARTBEAT_INTERVAL);
+    // The hello just sent does for a first keepalive
+    let mut first = true;
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = cancel.cancelled() => break,
+        }
+
+        let sent_at = PING_EPOCH.elapsed().as_nanos() as u64;
+        if outbound.ping(sent_at.to_be_bytes().to_vec()).is_err() {
+            break;
+        }
+        if !std::mem::take(&mut first) {
+            let keepalive = server.keepalive(token, &address);
+            if let Some(keepalive) = to_text(&keepalive, "keepalive") {
+                let _ = outbound.control(keepalive);
+            }
+        }
+
+        if Instant::now().duration_since(*alive.lock().await) > CLIENT_TIMEOUT {
+            outbound.close(None);
+            break;
+        }
+    }
+}
+
+/// Send a session a keepalive every [`HEARTBEAT_INTERVAL`], without pinging it.
+#[cfg(feature = "webtransport")]
+async fn keepalive(
+    server: Arc<WebSocketServer>,
+    token: Uuid,
+    address: String,
+    outbound: OutboundQueue,
+    cancel: CancellationToken,
+) {
+    let mut interval = time::interval(HEARTBEAT_INTERVAL);
+    // The hello just sent does for a first keepalive
+    interval.tick().await;
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = cancel.cancelled() => break,
+        }
+
+        let keepalive = server.keepalive(token, &address);
+        if let Some(keepalive) = to_text(&keepalive, "keepalive")
+            && outbound.control(keepalive).is_err()
+        {
+            break;
+        }
+    }
+}
+
+/// Note a pong from a session and measure its latency from the [`ping`] it answers.
+///
+/// Returns `false` once the session has been closed for exceeding the configured max latency.
+async fn record_pong(
+    server: &WebSocketServer,
+    outbound: &OutboundQueue,
+    token: Uuid,
+    alive: &Mutex<Instant>,
+    bytes: &[u8],
+) -> bool {
+    *alive.lock().await = Instant::now();
+
+    let Ok(sent_at) = <[u8; 8]>::try_from(bytes) else {
+        return true; // Unsolicited pong, nothing to measure
+    };
+    let sent_at = Duration::from_nanos(u64::from_be_bytes(sent_at));
+    let sample = PING_EPOCH.elapsed().saturating_sub(sent_at);
+
+    let latency = server.record_latency(&token, sample).await;
+    if let (Some(latency), Some(max_latency)) = (latency, server.config().max_latency)
+        && latency > max_latency
+    {
+        tracing::info!("Session {token} exceeded max latency ({latency:?})");
+        let reason = CloseReason {
+            code: CloseCode::Policy,
+            description: Some("Latency too high".to_owned()),
+        };
+        outbound.close(Some(reason));
+        return false;
+    }
+
+    true
+}
+
+/// Append an inbound frame to the session's recording, if it has one, giving up on the recording
+/// once it can't be written to.
+fn record_frame(recorder: &mut Option<SessionRecorder>, frame: impl FnOnce() -> Frame) {
+    let Some(active) = recorder else {
+        return;
+    };
+
+    if let Err(e) = active.record(frame()) {
+        tracing::warn!("Failed to record frame, stopping the recording: {e:#}");
+        *recorder = None;
+    }
+}
@@ -0,0 +1,123 @@
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    sync::{
+        Arc,
+        atomic::{self, AtomicBool, AtomicU64},
+    },
+};
+
+use bytestring::ByteString;
+use tokio::{sync::mpsc, time::Instant};
+
+use super::WebSocketServer;
+
+/// Cancels a scheduled broadcast, as long as it hasn't been sent yet.
+#[derive(Debug, Clone)]
+pub struct BroadcastHandle {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl BroadcastHandle {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, atomic::Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(atomic::Ordering::Relaxed)
+    }
+}
+
+struct ScheduledBroadcast {
+    at: Instant,
+    /// Keeps broadcasts scheduled for the same instant in the order they were scheduled.
+    seq: u64,
+    msg: ByteString,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl PartialEq for ScheduledBroadcast {
+    fn eq(&self, other: &Self) -> bool {
+        (self.at, self.seq) == (other.at, other.seq)
+    }
+}
+
+impl Eq for ScheduledBroadcast {}
+
+impl PartialOrd for ScheduledBroadcast {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ScheduledBroadcast {
+    // Reversed, so the heap pops the earliest broadcast first
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.at, other.seq).cmp(&(self.at, self.seq))
+    }
+}
+
+/// Task sending broadcasts once their time comes.
+pub(crate) struct Scheduler {
+    sender: mpsc::UnboundedSender<ScheduledBroadcast>,
+    next_seq: AtomicU64,
+}
+
+impl Scheduler {
+    pub(crate) fn spawn(server: WebSocketServer) -> Self {
+        let (sender, mut receiver) = mpsc::unbounded_channel::<ScheduledBroadcast>();
+
+        tokio::spawn(async move {
+            let mut queue = BinaryHeap::new();
+
+            loop {
+                let next = queue.peek().map(|x: &ScheduledBroadcast| x.at);
+                let due = async {
+                    match next {
+                        Some(at) => tokio::time::sleep_until(at).await,
+                        None => std::future::pending().await,
+                    }
+                };
+
+                tokio::select! {
+                    scheduled = receiver.recv() => match scheduled {
+                        Some(scheduled) => queue.push(scheduled),
+                        None => break,
+                    },
+                    () = due => {
+                        let Some(scheduled) = queue.pop() else {
+                            continue;
+                        };
+
+                        if !scheduled.cancelled.load(atomic::Ordering::Relaxed)
+                            && let Err(e) = server.broadcast(scheduled.msg).await
+                        {
+                            tracing::warn!("Dropped scheduled broadcast: {e}");
+                        }
+                    }
+                }
+            }
+        });
+
+        Self {
+            sender,
+            next_seq: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn schedule(&self, at: Instant, msg: ByteString) -> BroadcastHandle {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let scheduled = ScheduledBroadcast {
+            at,
+            seq: self.next_seq.fetch_add(1, atomic::Ordering::Relaxed),
+            msg,
+            cancelled: cancelled.clone(),
+        };
+
+        if self.sender.send(scheduled).is_err() {
+            tracing::error!("Broadcast scheduler is gone, dropping scheduled broadcast");
+        }
+
+        BroadcastHandle { cancelled }
+    }
+}
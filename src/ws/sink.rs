@@ -0,0 +1,114 @@
+use std::sync::{Arc, Mutex};
+
+use actix_ws::{CloseReason, Closed, Session};
+use bytes::Bytes;
+use bytestring::ByteString;
+
+/// Where a session's outbound frames end up, so handlers can be exercised without a real socket.
+pub trait SessionSink: Send + 'static {
+    fn text(&mut self, msg: ByteString) -> impl Future<Output = Result<(), Closed>> + Send;
+
+    fn binary(&mut self, bytes: Bytes) -> impl Future<Output = Result<(), Closed>> + Send;
+
+    fn ping(&mut self, bytes: Bytes) -> impl Future<Output = Result<(), Closed>> + Send;
+
+    fn pong(&mut self, bytes: Bytes) -> impl Future<Output = Result<(), Closed>> + Send;
+
+    fn close(self, reason: Option<CloseReason>) -> impl Future<Output = Result<(), Closed>> + Send;
+}
+
+impl SessionSink for Session {
+    async fn text(&mut self, msg: ByteString) -> Result<(), Closed> {
+        Session::text(self, msg).await
+    }
+
+    async fn binary(&mut self, bytes: Bytes) -> Result<(), Closed> {
+        Session::binary(self, bytes).await
+    }
+
+    async fn ping(&mut self, bytes: Bytes) -> Result<(), Closed> {
+        Session::ping(self, &bytes).await
+    }
+
+    async fn pong(&mut self, bytes: Bytes) -> Result<(), Closed> {
+        Session::pong(self, &bytes).await
+    }
+
+    async fn close(self, reason: Option<CloseReason>) -> Result<(), Closed> {
+        Session::close(self, reason).await
+    }
+}
+
+/// A frame written to a [`RecordingSink`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordedFrame {
+    Text(ByteString),
+    Binary(Bytes),
+    Ping(Bytes),
+    Pong(Bytes),
+    Close(Option<CloseReason>),
+}
+
+/// Keeps every frame written to it, for inspecting what a handler sent.
+///
+/// Clones share the same recording, so keep one around before handing the sink to the server.
+#[derive(Debug, Clone, Default)]
+pub struct RecordingSink {
+    frames: Arc<Mutex<Vec<RecordedFrame>>>,
+}
+
+impl RecordingSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn frames(&self) -> Vec<RecordedFrame> {
+        self.frames
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// The text frames written so far, parsed as JSON.
+    pub fn messages(&self) -> Vec<serde_json::Value> {
+        self.frames()
+            .into_iter()
+            .filter_map(|frame| match frame {
+                RecordedFrame::Text(text) => serde_json::from_str(&text).ok(),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn record(&self, frame: RecordedFrame) -> Result<(), Closed> {
+        let mut frames = self.frames.lock().unwrap_or_else(|e| e.into_inner());
+        if matches!(frames.last(), Some(RecordedFrame::Close(_))) {
+            return Err(Closed);
+        }
+
+        frames.push(frame);
+        Ok(())
+    }
+}
+
+impl SessionSink for RecordingSink {
+    async fn text(&mut self, msg: ByteString) -> Result<(), Closed> {
+        self.record(RecordedFrame::Text(msg))
+    }
+
+    async fn binary(&mut self, bytes: Bytes) -> Result<(), Closed> {
+        self.record(RecordedFrame::Binary(bytes))
+    }
+
+    async fn ping(&mut self, bytes: Bytes) -> Result<(), Closed> {
+        self.record(RecordedFrame::Ping(bytes))
+    }
+
+    async fn pong(&mut self, bytes: Bytes) -> Result<(), Closed> {
+        self.record(RecordedFrame::Pong(bytes))
+    }
+
+    async fn close(self, reason: Option<CloseReason>) -> Result<(), Closed> {
+        self.record(RecordedFrame::Close(reason))
+    }
+}
@@ -0,0 +1,44 @@
+//! Sessions started with a private key are given the address Krist derives from it.
+
+use actix_ws_fuckery::address;
+use actix_ws_fuckery::models::websocket::messages::{
+    WebSocketMessageInner, WebSocketMessageResponse,
+};
+use actix_ws_fuckery::test_utils::TestServer;
+
+#[test]
+fn addresses_match_krist() {
+    assert_eq!(address::from_private_key("hunter2"), "k8fdqdhr5q");
+    assert_eq!(address::from_private_key("secret"), "k0ybd768c9");
+    assert_eq!(address::from_private_key(""), "krqtnrp18z");
+}
+
+#[cfg(feature = "krist")]
+#[test]
+fn addresses_are_valid_v2_addresses() {
+    use actix_ws_fuckery::validation;
+
+    for key in ["a", "hunter2", "correct horse battery staple", "🦀"] {
+        let address = address::from_private_key(key);
+        assert!(address.starts_with('k'), "{address}");
+        assert!(validation::is_valid_address(&address), "{address}");
+    }
+}
+
+#[actix_web::test]
+async fn sessions_get_their_private_keys_address() {
+    let server = TestServer::start().await.unwrap();
+    let mut client = server.connect_as("hunter2").await.unwrap();
+
+    let response = client.request(WebSocketMessageInner::Me).await.unwrap();
+    let WebSocketMessageInner::Response {
+        data: WebSocketMessageResponse::Me { is_guest, address },
+    } = response.r#type
+    else {
+        panic!("Expected a me response, got {response:?}");
+    };
+    assert!(!is_guest);
+    assert_eq!(address.unwrap()["address"], "k8fdqdhr5q");
+
+    server.stop().await;
+}
@@ -0,0 +1,108 @@
+//! Going over a churn threshold raises an alarm once per second, through hooks and as a gateway
+//! event.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix_ws_fuckery::alarms::{ChurnAlarm, ChurnKind, ChurnMonitor, ChurnThresholds};
+use actix_ws_fuckery::audit::{AuditAction, AuditRecord};
+use actix_ws_fuckery::config::WebSocketServerConfig;
+use actix_ws_fuckery::events::GatewayEvent;
+use actix_ws_fuckery::models::websocket::WebSocketTokenData;
+use actix_ws_fuckery::ws::{WebSocketServer, sink::RecordingSink};
+use futures::StreamExt;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+#[tokio::test(start_paused = true)]
+async fn alarms_go_off_once_a_second() {
+    let monitor = ChurnMonitor::new(ChurnThresholds {
+        connects_per_sec: Some(2),
+        ..Default::default()
+    });
+
+    assert_eq!(monitor.record(ChurnKind::Connects), None);
+    assert_eq!(monitor.record(ChurnKind::Connects), None);
+    let alarm = ChurnAlarm {
+        kind: ChurnKind::Connects,
+        threshold: 2,
+    };
+    assert_eq!(monitor.record(ChurnKind::Connects), Some(alarm.clone()));
+    assert_eq!(monitor.record(ChurnKind::Connects), None);
+    // Without a threshold, nothing is counted
+    assert_eq!(monitor.record(ChurnKind::Disconnects), None);
+
+    tokio::time::advance(Duration::from_secs(1)).await;
+    assert_eq!(monitor.record(ChurnKind::Connects), None);
+    assert_eq!(monitor.record(ChurnKind::Connects), None);
+    assert_eq!(monitor.record(ChurnKind::Connects), Some(alarm));
+}
+
+#[actix_web::test]
+async fn connection_storms_reach_hooks_and_events() {
+    let fired = Arc::new(Mutex::new(Vec::new()));
+    let hook_fired = fired.clone();
+    let server = WebSocketServer::with_config(WebSocketServerConfig {
+        churn_alarms: ChurnThresholds {
+            connects_per_sec: Some(1),
+            ..Default::default()
+        },
+        ..Default::default()
+    })
+    .with_alarm_hook(move |alarm: &ChurnAlarm| hook_fired.lock().unwrap().push(alarm.kind));
+    let mut events = Box::pin(server.events());
+
+    let mut sessions = Vec::new();
+    for _ in 0..2 {
+        let inserted = server
+            .insert_session(
+                Uuid::new_v4(),
+                RecordingSink::new(),
+                None,
+                WebSocketTokenData::guest(),
+                CancellationToken::new(),
+                std::convert::identity,
+            )
+            .await;
+        sessions.push(inserted);
+    }
+
+    assert_eq!(*fired.lock().unwrap(), [ChurnKind::Connects]);
+    let alarm = tokio::time::timeout(Duration::from_secs(1), async {
+        loop {
+            if let Some(event @ GatewayEvent::ChurnAlarm { .. }) = events.next().await {
+                return event;
+            }
+        }
+    })
+    .await
+    .expect("No alarm was emitted");
+    assert_eq!(
+        alarm,
+        GatewayEvent::ChurnAlarm {
+            kind: ChurnKind::Connects,
+            threshold: 1
+        }
+    );
+}
+
+#[actix_web::test]
+async fn auth_failures_are_counted() {
+    let fired = Arc::new(Mutex::new(Vec::new()));
+    let hook_fired = fired.clone();
+    let server = WebSocketServer::with_config(WebSocketServerConfig {
+        churn_alarms: ChurnThresholds {
+            auth_failures_per_sec: Some(0),
+            ..Default::default()
+        },
+        ..Default::default()
+    })
+    .with_alarm_hook(move |alarm: &ChurnAlarm| hook_fired.lock().unwrap().push(alarm.kind));
+
+    let action = AuditAction::AuthFailed {
+        reason: "Expected token to exist".to_owned(),
+    };
+    server.audit(AuditRecord::new(action));
+
+    assert_eq!(*fired.lock().unwrap(), [ChurnKind::AuthFailures]);
+}
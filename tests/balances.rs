@@ -0,0 +1,115 @@
+//! Balance changes are published to `balances` subscribers, and to the address's own sessions
+//! subscribed to `ownBalances`.
+#![cfg(feature = "krist")]
+
+use std::time::Duration;
+
+use actix_web::rt::time;
+use actix_ws_fuckery::balances::{BalanceChange, BalanceChangeReason};
+use actix_ws_fuckery::models::websocket::{
+    WebSocketSubscriptionType, WebSocketTokenData, state::SessionState,
+};
+use actix_ws_fuckery::ws::{InsertedSession, WebSocketServer, sink::RecordingSink};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+const OWNER: &str = "kowner0000";
+
+struct TestSession {
+    sink: RecordingSink,
+    _inserted: InsertedSession,
+}
+
+async fn connect(
+    server: &WebSocketServer,
+    address: &str,
+    subscriptions: &[WebSocketSubscriptionType],
+) -> TestSession {
+    let sink = RecordingSink::new();
+    let uuid = Uuid::new_v4();
+    let inserted = server
+        .insert_session(
+            uuid,
+            sink.clone(),
+            None,
+            WebSocketTokenData::new(address.to_owned(), Some("secret".to_owned())),
+            CancellationToken::new(),
+            std::convert::identity,
+        )
+        .await;
+    server.transition(&uuid, SessionState::Ready).await;
+    for topic in subscriptions {
+        server
+            .subscribe_to_event(&uuid, topic.clone())
+            .await
+            .unwrap();
+    }
+
+    TestSession {
+        sink,
+        _inserted: inserted,
+    }
+}
+
+fn balance_events(session: &TestSession) -> Vec<serde_json::Value> {
+    session
+        .sink
+        .messages()
+        .into_iter()
+        .filter(|x| x["event"] == "balance")
+        .collect()
+}
+
+#[actix_web::test]
+async fn changes_reach_subscribers_once() {
+    let server = WebSocketServer::new();
+    let owner = connect(&server, OWNER, &[WebSocketSubscriptionType::OwnBalances]).await;
+    let both = connect(
+        &server,
+        OWNER,
+        &[
+            WebSocketSubscriptionType::Balances,
+            WebSocketSubscriptionType::OwnBalances,
+        ],
+    )
+    .await;
+    let watcher = connect(
+        &server,
+        "kwatcher00",
+        &[WebSocketSubscriptionType::Balances],
+    )
+    .await;
+    let bystander = connect(
+        &server,
+        "kbystander",
+        &[WebSocketSubscriptionType::OwnBalances],
+    )
+    .await;
+
+    let change = BalanceChange::new(OWNER, 10, 35, BalanceChangeReason::Reward);
+    server.publish_balance(&change).await.unwrap();
+
+    time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(balance_events(&owner).len(), 1);
+    assert_eq!(balance_events(&both).len(), 1);
+    assert!(balance_events(&bystander).is_empty());
+
+    let events = balance_events(&watcher);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["address"], OWNER);
+    assert_eq!(events[0]["old"], 10);
+    assert_eq!(events[0]["new"], 35);
+    assert_eq!(events[0]["reason"], "reward");
+}
+
+#[actix_web::test]
+async fn sessions_start_without_balance_events() {
+    let server = WebSocketServer::new();
+    let session = connect(&server, OWNER, &[]).await;
+
+    let change = BalanceChange::new(OWNER, 35, 5, BalanceChangeReason::Transaction);
+    server.publish_balance(&change).await.unwrap();
+
+    time::sleep(Duration::from_millis(50)).await;
+    assert!(balance_events(&session).is_empty());
+}
@@ -0,0 +1,150 @@
+//! Broadcasts wait as long as their mode asks: not at all, until every queue has taken them, or
+//! until they've been written out.
+
+use std::time::Duration;
+
+use actix_web::rt::time;
+use actix_ws::{CloseReason, Closed};
+use actix_ws_fuckery::config::{SlowConsumerConfig, WebSocketServerConfig};
+use actix_ws_fuckery::models::websocket::WebSocketTokenData;
+use actix_ws_fuckery::ws::{
+    BroadcastMode, BroadcastReport, InsertedSession, WebSocketServer,
+    sink::{RecordingSink, SessionSink},
+};
+use bytes::Bytes;
+use bytestring::ByteString;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+async fn connect(server: &WebSocketServer, sink: &RecordingSink) -> InsertedSession {
+    server
+        .insert_session(
+            Uuid::new_v4(),
+            sink.clone(),
+            None,
+            WebSocketTokenData::new("guest".to_owned(), None),
+            CancellationToken::new(),
+            std::convert::identity,
+        )
+        .await
+}
+
+#[actix_web::test]
+async fn awaiting_delivery_counts_written_sessions() {
+    let server = WebSocketServer::new();
+    let sinks = [RecordingSink::new(), RecordingSink::new()];
+    let _first = connect(&server, &sinks[0]).await;
+    let _second = connect(&server, &sinks[1]).await;
+
+    let mode = BroadcastMode::AwaitDelivery(Duration::from_secs(1));
+    let report = server.broadcast_with(r#"{"n":1}"#, mode).await.unwrap();
+    assert_eq!(
+        report,
+        BroadcastReport {
+            queued: 2,
+            delivered: 2
+        }
+    );
+    for sink in &sinks {
+        assert_eq!(sink.messages(), [serde_json::json!({ "n": 1 })]);
+    }
+
+    let report = server
+        .broadcast_with(r#"{"n":2}"#, BroadcastMode::AwaitEnqueue)
+        .await
+        .unwrap();
+    assert_eq!(report.queued, 2);
+    assert_eq!(report.delivered, 0);
+}
+
+#[actix_web::test]
+async fn fire_and_forget_returns_before_delivery() {
+    let server = WebSocketServer::new();
+    let sink = RecordingSink::new();
+    let _inserted = connect(&server, &sink).await;
+
+    let report = server
+        .broadcast_with(r#"{"n":1}"#, BroadcastMode::FireAndForget)
+        .await
+        .unwrap();
+    assert_eq!(report, BroadcastReport::default());
+
+    time::timeout(Duration::from_secs(1), async {
+        while sink.messages().is_empty() {
+            time::sleep(Duration::from_millis(5)).await;
+        }
+    })
+    .await
+    .expect("Broadcast never arrived");
+}
+
+/// Never finishes sending anything.
+struct StuckSink;
+
+impl SessionSink for StuckSink {
+    async fn text(&mut self, _msg: ByteString) -> Result<(), Closed> {
+        std::future::pending().await
+    }
+
+    async fn binary(&mut self, _bytes: Bytes) -> Result<(), Closed> {
+        std::future::pending().await
+    }
+
+    async fn ping(&mut self, _bytes: Bytes) -> Result<(), Closed> {
+        std::future::pending().await
+    }
+
+    async fn pong(&mut self, _bytes: Bytes) -> Result<(), Closed> {
+        std::future::pending().await
+    }
+
+    async fn close(self, _reason: Option<CloseReason>) -> Result<(), Closed> {
+        Ok(())
+    }
+}
+
+#[actix_web::test]
+async fn waiting_on_a_full_queue_leaves_the_server_unlocked() {
+    let config = WebSocketServerConfig {
+        slow_consumer: SlowConsumerConfig {
+            queue_capacity: 1,
+            send_timeout: Duration::from_secs(60),
+            max_strikes: u32::MAX,
+            drop_events_when_lagging: false,
+        },
+        ..Default::default()
+    };
+    let server = WebSocketServer::with_config(config);
+    let _stuck = server
+        .insert_session(
+            Uuid::new_v4(),
+            StuckSink,
+            None,
+            WebSocketTokenData::new("guest".to_owned(), None),
+            CancellationToken::new(),
+            std::convert::identity,
+        )
+        .await;
+
+    // The first is stuck being written, the second fills the queue and the third waits for room
+    let broadcasting = server.clone();
+    let broadcasts = actix_web::rt::spawn(async move {
+        for n in 0..3 {
+            let msg = format!(r#"{{"n":{n}}}"#);
+            let _ = broadcasting
+                .broadcast_with(msg, BroadcastMode::AwaitEnqueue)
+                .await;
+        }
+    });
+    time::sleep(Duration::from_millis(100)).await;
+    assert!(!broadcasts.is_finished(), "Broadcasts never had to wait");
+
+    let sink = RecordingSink::new();
+    time::timeout(Duration::from_secs(1), async {
+        let _other = connect(&server, &sink).await;
+        server.broadcast_to_tag_local("nobody", r#"{"n":4}"#).await;
+        assert_eq!(server.session_count().await, 2);
+    })
+    .await
+    .expect("The server stayed locked while a broadcast waited");
+}
@@ -0,0 +1,107 @@
+//! Soak tests run against a server injecting faults, checking that sessions are cleaned up and
+//! clients recover however their connection goes wrong.
+#![cfg(debug_assertions)]
+
+use std::time::Duration;
+
+use actix_web::rt::time;
+use actix_ws_fuckery::client::{GatewayClient, GatewayClientConfig, ReconnectPolicy};
+use actix_ws_fuckery::config::{ChaosConfig, WebSocketServerConfig};
+use actix_ws_fuckery::models::websocket::messages::WebSocketMessageInner;
+use actix_ws_fuckery::test_utils::TestServer;
+
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+async fn start(chaos: ChaosConfig) -> TestServer {
+    TestServer::start_with_config(WebSocketServerConfig {
+        chaos: Some(chaos),
+        ..Default::default()
+    })
+    .await
+    .unwrap()
+}
+
+/// Connect, retrying as the hello itself may fall victim to the chaos.
+async fn connect(config: GatewayClientConfig) -> GatewayClient {
+    for _ in 0..20 {
+        if let Ok(Ok(client)) = time::timeout(TIMEOUT, GatewayClient::connect(config.clone())).await
+        {
+            return client;
+        }
+    }
+    panic!("Never managed to connect");
+}
+
+/// Send `me` requests, returning how many were answered.
+async fn soak(client: &mut GatewayClient, requests: usize) -> usize {
+    let mut answered = 0;
+    for _ in 0..requests {
+        if let Ok(Ok(_)) = time::timeout(TIMEOUT, client.request(WebSocketMessageInner::Me)).await {
+            answered += 1;
+        }
+    }
+    answered
+}
+
+async fn wait_for_no_sessions(server: &TestServer) {
+    time::timeout(Duration::from_secs(5), async {
+        while server.server().session_count().await > 0 {
+            time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("Sessions were left behind");
+}
+
+#[actix_web::test]
+async fn clients_reconnect_through_forced_disconnects() {
+    let server = start(ChaosConfig {
+        disconnect_rate: 0.05,
+        latency: Some((Duration::ZERO, Duration::from_millis(5))),
+        ..Default::default()
+    })
+    .await;
+    let config = GatewayClientConfig {
+        reconnect: Some(ReconnectPolicy {
+            max_attempts: 20,
+            initial_delay: Duration::from_millis(10),
+            ..Default::default()
+        }),
+        ..GatewayClientConfig::new(server.base_url())
+    };
+
+    let mut client = connect(config).await;
+    let first_token = client.resume_token();
+    let answered = soak(&mut client, 100).await;
+
+    assert!(answered > 50, "Only {answered} answered");
+    assert_ne!(client.resume_token(), first_token, "Never reconnected");
+
+    let _ = client.close().await;
+    wait_for_no_sessions(&server).await;
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn failed_and_dropped_sends_leave_nothing_behind() {
+    let server = start(ChaosConfig {
+        send_failure_rate: 0.02,
+        drop_rate: 0.05,
+        ..Default::default()
+    })
+    .await;
+
+    let mut clients = Vec::new();
+    for _ in 0..5 {
+        clients.push(connect(server.client_config()).await);
+    }
+    for client in &mut clients {
+        soak(client, 20).await;
+    }
+    for client in clients {
+        let _ = client.close().await;
+    }
+
+    wait_for_no_sessions(&server).await;
+    server.stop().await;
+}
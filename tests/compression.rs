@@ -0,0 +1,163 @@
+//! HTTP responses over the size threshold are compressed with whichever of gzip and brotli the
+//! client prefers.
+
+use actix_http::encoding::Decoder;
+use actix_web::http::header::{self, ContentEncoding};
+use actix_ws_fuckery::compression::CompressionConfig;
+use actix_ws_fuckery::config::WebSocketServerConfig;
+use actix_ws_fuckery::journal::JournalConfig;
+use actix_ws_fuckery::models::websocket::WebSocketSubscriptionType;
+use actix_ws_fuckery::test_utils::TestServer;
+use actix_ws_fuckery::ws::WebSocketServer;
+use bytes::Bytes;
+use futures::TryStreamExt;
+use uuid::Uuid;
+
+/// A server journaling events, with enough of them published for `/events` to be worth compressing.
+async fn start(build: impl FnOnce(WebSocketServer) -> WebSocketServer) -> TestServer {
+    let path = std::env::temp_dir().join(format!("compression-journal-{}", Uuid::new_v4()));
+    let server = TestServer::start_with(WebSocketServerConfig::default(), |server| {
+        build(
+            server
+                .with_journal(JournalConfig::new(path.to_string_lossy()))
+                .unwrap(),
+        )
+    })
+    .await
+    .unwrap();
+
+    for height in 0..50 {
+        let event = serde_json::json!({ "event": "block", "block": { "height": height } });
+        server
+            .server()
+            .publish(WebSocketSubscriptionType::Blocks, event.to_string())
+            .await
+            .unwrap();
+    }
+    server
+}
+
+/// Fetch `path` without decompressing it, returning its `Content-Encoding` and `Vary`.
+async fn get(
+    server: &TestServer,
+    path: &str,
+    accept_encoding: &str,
+) -> (Option<String>, Option<String>) {
+    let response = awc::Client::default()
+        .get(format!("{}{path}", server.base_url()))
+        .insert_header((header::ACCEPT_ENCODING, accept_encoding))
+        .no_decompress()
+        .send()
+        .await
+        .unwrap();
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .map(|x: &header::HeaderValue| x.to_str().unwrap().to_owned())
+    };
+
+    (header(header::CONTENT_ENCODING), header(header::VARY))
+}
+
+#[test]
+fn encodings_are_negotiated_by_preference() {
+    let config = CompressionConfig::default();
+    assert_eq!(
+        config.negotiate("gzip, deflate, br"),
+        ContentEncoding::Brotli
+    );
+    assert_eq!(
+        config.negotiate("gzip;q=1.0, br;q=0.5"),
+        ContentEncoding::Gzip
+    );
+    assert_eq!(
+        config.negotiate("br;q=0, gzip;q=0"),
+        ContentEncoding::Identity
+    );
+    assert_eq!(config.negotiate("deflate, zstd"), ContentEncoding::Identity);
+    assert_eq!(config.negotiate(""), ContentEncoding::Identity);
+
+    let gzip_only = CompressionConfig {
+        brotli: false,
+        ..Default::default()
+    };
+    assert_eq!(gzip_only.negotiate("br, gzip;q=0.1"), ContentEncoding::Gzip);
+}
+
+#[actix_web::test]
+async fn large_responses_are_compressed() {
+    let server = start(|x| x.with_compression(CompressionConfig::default())).await;
+
+    let (encoding, vary) = get(&server, "/events", "gzip").await;
+    assert_eq!(encoding.as_deref(), Some("gzip"));
+    assert_eq!(vary.as_deref(), Some("Accept-Encoding"));
+
+    let (encoding, _) = get(&server, "/events", "gzip;q=0.5, br").await;
+    assert_eq!(encoding.as_deref(), Some("br"));
+
+    let (encoding, _) = get(&server, "/events", "identity").await;
+    assert_eq!(encoding, None);
+
+    // Still the same once decompressed
+    let url = format!("{}/events", server.base_url());
+    let fetch = |accept_encoding| {
+        let request = awc::Client::default()
+            .get(&url)
+            .insert_header((header::ACCEPT_ENCODING, accept_encoding));
+        async move {
+            let response = request.send().await.unwrap();
+            let headers = response.headers().clone();
+            let body: Vec<Bytes> = Decoder::from_headers(response, &headers)
+                .try_collect()
+                .await
+                .unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body.concat()).unwrap()
+        }
+    };
+    let events = fetch("identity").await;
+    assert!(events["items"].as_array().unwrap().len() > 10);
+    assert_eq!(fetch("br").await, events);
+    assert_eq!(fetch("gzip").await, events);
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn small_responses_are_left_alone() {
+    let server = start(|x| x.with_compression(CompressionConfig::default())).await;
+
+    let (encoding, vary) = get(&server, "/events?limit=1", "gzip, br").await;
+    assert_eq!(encoding, None);
+    assert_eq!(vary, None);
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn nothing_is_compressed_without_compression_configured() {
+    let server = start(std::convert::identity).await;
+
+    let (encoding, _) = get(&server, "/events", "gzip, br").await;
+    assert_eq!(encoding, None);
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn the_gateway_still_upgrades() {
+    let server = start(|x| x.with_compression(CompressionConfig::default())).await;
+
+    let token = server.issue_token("kcompress0", None).await;
+    let url = format!("ws://{}/gateway/{token}", server.addr());
+    let (response, socket) = awc::Client::default()
+        .ws(url)
+        .header(header::ACCEPT_ENCODING, "gzip, br")
+        .connect()
+        .await
+        .unwrap();
+    assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+
+    drop(socket);
+    server.stop().await;
+}
@@ -0,0 +1,221 @@
+//! Message handlers run off the session's receive loop, one message at a time per session unless
+//! their type may be processed out of order, in a pool shared by the whole server.
+
+use std::{collections::HashMap, time::Duration};
+
+use actix_web::rt::time;
+use actix_ws_fuckery::config::{Processing, WebSocketServerConfig};
+use actix_ws_fuckery::test_utils::{TestServer, TestSocket};
+use actix_ws_fuckery::ws::binary::BinaryHandler;
+use actix_ws_fuckery::ws::routes::{Route, RouteContext, Routes};
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Takes `ms` milliseconds to answer.
+#[derive(Deserialize)]
+struct Slow {
+    ms: u64,
+}
+
+#[derive(Serialize)]
+struct SlowResponse {}
+
+impl Route for Slow {
+    const TYPE: &'static str = "slow";
+    type Response = SlowResponse;
+}
+
+/// Takes a second to send every binary frame back.
+struct SlowEcho;
+
+impl BinaryHandler for SlowEcho {
+    fn on_binary<'a>(
+        &'a self,
+        _session: Uuid,
+        _address: &'a str,
+        payload: Bytes,
+    ) -> BoxFuture<'a, Option<Bytes>> {
+        Box::pin(async move {
+            time::sleep(Duration::from_secs(1)).await;
+            Some(payload)
+        })
+    }
+}
+
+async fn start(config: WebSocketServerConfig) -> TestServer {
+    let routes = Routes::new().on(|_: RouteContext, slow: Slow| async move {
+        time::sleep(Duration::from_millis(slow.ms)).await;
+        Ok(SlowResponse {})
+    });
+
+    TestServer::start_with(config, |server| server.with_routes(routes))
+        .await
+        .unwrap()
+}
+
+async fn connect(server: &TestServer, address: &str) -> TestSocket {
+    let token = server.issue_token(address, None).await;
+    let mut socket = server.socket(token).await.unwrap();
+    assert_eq!(socket.next_text().await["type"], "hello");
+    socket
+}
+
+#[actix_web::test]
+async fn slow_handlers_dont_hold_up_the_receive_loop() {
+    let server = start(WebSocketServerConfig::default()).await;
+    let mut socket = connect(&server, "kslow00000").await;
+
+    socket
+        .write(serde_json::json!({ "id": 1, "type": "slow", "ms": 1_000 }))
+        .await;
+    socket
+        .write_frame(awc::ws::Message::Ping("still there?".into()))
+        .await;
+
+    // Answered while the handler is still sleeping
+    assert_eq!(
+        socket.next_frame().await,
+        awc::ws::Frame::Pong("still there?".into())
+    );
+    assert_eq!(socket.next_text().await["id"], 1);
+
+    drop(socket);
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn slow_binary_handlers_dont_hold_up_the_receive_loop() {
+    let server = TestServer::start_with(WebSocketServerConfig::default(), |server| {
+        server.with_binary_handler(SlowEcho)
+    })
+    .await
+    .unwrap();
+    let mut socket = connect(&server, "kslowbin00").await;
+
+    socket
+        .write_frame(awc::ws::Message::Binary(Bytes::from_static(&[1, 2, 3])))
+        .await;
+    socket
+        .write_frame(awc::ws::Message::Ping("still there?".into()))
+        .await;
+
+    // Answered while the handler is still sleeping
+    assert_eq!(
+        socket.next_frame().await,
+        awc::ws::Frame::Pong("still there?".into())
+    );
+    assert_eq!(
+        socket.next_frame().await,
+        awc::ws::Frame::Binary(Bytes::from_static(&[1, 2, 3]))
+    );
+
+    drop(socket);
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn a_sessions_messages_are_answered_in_order() {
+    let server = start(WebSocketServerConfig::default()).await;
+    let mut socket = connect(&server, "kordered00").await;
+
+    socket
+        .write(serde_json::json!({ "id": 1, "type": "slow", "ms": 300 }))
+        .await;
+    socket
+        .write(serde_json::json!({ "id": 2, "type": "slow", "ms": 0 }))
+        .await;
+    socket
+        .write(serde_json::json!({ "id": 3, "type": "subscribe", "event": "names" }))
+        .await;
+
+    for id in 1..=3 {
+        assert_eq!(socket.next_text().await["id"], id);
+    }
+
+    drop(socket);
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn handlers_share_a_pool_across_sessions() {
+    let server = start(WebSocketServerConfig {
+        max_concurrent_handlers: 1,
+        ..Default::default()
+    })
+    .await;
+    let mut hog = connect(&server, "khog000000").await;
+    let mut other = connect(&server, "kother0000").await;
+
+    hog.write(serde_json::json!({ "id": 1, "type": "slow", "ms": 500 }))
+        .await;
+    time::sleep(Duration::from_millis(50)).await;
+    let started = std::time::Instant::now();
+    other
+        .write(serde_json::json!({ "id": 2, "type": "slow", "ms": 0 }))
+        .await;
+
+    assert_eq!(other.next_text().await["id"], 2);
+    assert!(started.elapsed() >= Duration::from_millis(300));
+    assert_eq!(hog.next_text().await["id"], 1);
+
+    drop((hog, other));
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn unordered_messages_dont_wait_on_each_other_or_ordered_ones() {
+    let server = start(WebSocketServerConfig {
+        message_processing: HashMap::from([("slow".to_owned(), Processing::Unordered)]),
+        ..Default::default()
+    })
+    .await;
+    let mut socket = connect(&server, "kunordered").await;
+
+    socket
+        .write(serde_json::json!({ "id": 1, "type": "slow", "ms": 500 }))
+        .await;
+    socket
+        .write(serde_json::json!({ "id": 2, "type": "slow", "ms": 0 }))
+        .await;
+    socket
+        .write(serde_json::json!({ "id": 3, "type": "subscribe", "event": "names" }))
+        .await;
+
+    let mut ids = Vec::new();
+    for _ in 0..3 {
+        ids.push(socket.next_text().await["id"].as_u64().unwrap());
+    }
+    assert_eq!(ids.last(), Some(&1), "{ids:?}");
+
+    drop(socket);
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn ordered_types_keep_their_order_alongside_unordered_ones() {
+    let server = start(WebSocketServerConfig {
+        message_processing: HashMap::from([("subscribe".to_owned(), Processing::Unordered)]),
+        ..Default::default()
+    })
+    .await;
+    let mut socket = connect(&server, "kmixed0000").await;
+
+    socket
+        .write(serde_json::json!({ "id": 1, "type": "slow", "ms": 300 }))
+        .await;
+    socket
+        .write(serde_json::json!({ "id": 2, "type": "slow", "ms": 0 }))
+        .await;
+    socket
+        .write(serde_json::json!({ "id": 3, "type": "subscribe", "event": "names" }))
+        .await;
+
+    assert_eq!(socket.next_text().await["id"], 3);
+    assert_eq!(socket.next_text().await["id"], 1);
+    assert_eq!(socket.next_text().await["id"], 2);
+
+    drop(socket);
+    server.stop().await;
+}
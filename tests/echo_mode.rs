@@ -0,0 +1,53 @@
+//! In echo mode any token gets a session, and messages are answered with their canonical
+//! serialization instead of being handled.
+
+use actix_ws_fuckery::config::WebSocketServerConfig;
+use actix_ws_fuckery::models::websocket::{WebSocketSubscriptionType, is_guest_address};
+use actix_ws_fuckery::test_utils::TestServer;
+
+#[actix_web::test]
+async fn messages_are_echoed_instead_of_handled() {
+    let server = TestServer::start_with_config(WebSocketServerConfig {
+        echo_mode: true,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+
+    let mut socket = server.socket("anything-goes").await.unwrap();
+    let hello = socket.next_text().await;
+    assert_eq!(hello["type"], "hello");
+
+    let message = serde_json::json!({ "id": 5, "type": "subscribe", "event": "names" });
+    let echo = socket.send(message).await;
+    assert_eq!(echo["type"], "echo");
+    assert_eq!(echo["id"], 5);
+    assert_eq!(echo["message"]["type"], "subscribe");
+    assert_eq!(echo["message"]["event"], "names");
+
+    let sessions = server.server().list_sessions().await;
+    assert_eq!(sessions.len(), 1);
+    assert!(is_guest_address(&sessions[0].address));
+    assert!(
+        !sessions[0]
+            .subscriptions
+            .contains(&WebSocketSubscriptionType::Names)
+    );
+
+    let error = socket
+        .send(serde_json::json!({ "id": 6, "type": "bogus" }))
+        .await;
+    assert_eq!(error["ok"], false);
+
+    drop(socket);
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn bad_tokens_are_still_refused_normally() {
+    let server = TestServer::start().await.unwrap();
+
+    assert!(server.socket("anything-goes").await.is_err());
+
+    server.stop().await;
+}
@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+use actix_codec::Framed;
+use actix_web::rt::time;
+use actix_ws_fuckery::encoding::{EncodedEvent, Encoding, Frame};
+use actix_ws_fuckery::test_utils::TestServer;
+use awc::BoxedSocket;
+use bytestring::ByteString;
+use futures::StreamExt;
+use serde_json::{Value, json};
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+fn decode(encoding: Encoding, frame: &Frame) -> Value {
+    match (encoding, frame) {
+        (Encoding::Json, Frame::Text(text)) => serde_json::from_str(text).unwrap(),
+        (Encoding::MessagePack, Frame::Binary(bytes)) => rmp_serde::from_slice(bytes).unwrap(),
+        (Encoding::Cbor, Frame::Binary(bytes)) => ciborium::from_reader(&bytes[..]).unwrap(),
+        _ => panic!("{encoding:?} produced the wrong kind of frame: {frame:?}"),
+    }
+}
+
+#[test]
+fn every_encoding_carries_the_same_message() {
+    let message = json!({ "type": "event", "event": "block", "height": 12, "tags": ["a", "b"] });
+    let event = EncodedEvent::new(ByteString::from(message.to_string()));
+
+    for encoding in Encoding::ALL {
+        assert_eq!(decode(encoding, &event.get(encoding)), message);
+    }
+}
+
+#[test]
+fn payloads_that_arent_json_are_sent_as_strings() {
+    let frame = Encoding::MessagePack.encode(ByteString::from_static("hi there"));
+    assert_eq!(decode(Encoding::MessagePack, &frame), json!("hi there"));
+}
+
+#[actix_web::test]
+async fn sessions_get_broadcasts_in_their_own_encoding() {
+    let server = TestServer::start().await.unwrap();
+    let mut sockets = Vec::new();
+    for encoding in Encoding::ALL {
+        let token = server.issue_token("guest", None).await;
+        let query = serde_json::to_value(encoding).unwrap();
+        let url = format!(
+            "ws://{}/gateway/{token}?encoding={}",
+            server.addr(),
+            query.as_str().unwrap()
+        );
+        let (_, socket) = awc::Client::default().ws(url).connect().await.unwrap();
+        sockets.push((encoding, socket));
+    }
+
+    let mut received = Vec::new();
+    for (encoding, socket) in &mut sockets {
+        received.push(next_message(*encoding, socket).await);
+    }
+    assert!(received.iter().all(|hello| hello["type"] == "hello"));
+
+    server.server().broadcast(r#"{"number":7}"#).await.unwrap();
+    for (encoding, socket) in &mut sockets {
+        assert_eq!(
+            next_message(*encoding, socket).await,
+            json!({ "number": 7 })
+        );
+    }
+
+    server.stop().await;
+}
+
+/// Read the next message, skipping heartbeats.
+async fn next_message(
+    encoding: Encoding,
+    socket: &mut Framed<BoxedSocket, awc::ws::Codec>,
+) -> Value {
+    loop {
+        let frame = time::timeout(TIMEOUT, socket.next())
+            .await
+            .expect("Timed out waiting for a message")
+            .expect("Socket closed")
+            .unwrap();
+        let frame = match frame {
+            awc::ws::Frame::Text(text) => Frame::Text(ByteString::try_from(text).unwrap()),
+            awc::ws::Frame::Binary(bytes) => Frame::Binary(bytes),
+            awc::ws::Frame::Ping(_) | awc::ws::Frame::Pong(_) => continue,
+            other => panic!("Unexpected frame {other:?}"),
+        };
+        return decode(encoding, &frame);
+    }
+}
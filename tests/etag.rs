@@ -0,0 +1,121 @@
+//! Query responses carry an ETag, and clients sending it back are told with `304 Not Modified`
+//! while nothing has changed.
+
+use actix_web::http::{StatusCode, header};
+use actix_ws_fuckery::compression::CompressionConfig;
+use actix_ws_fuckery::config::WebSocketServerConfig;
+use actix_ws_fuckery::journal::JournalConfig;
+use actix_ws_fuckery::models::websocket::WebSocketSubscriptionType;
+use actix_ws_fuckery::test_utils::TestServer;
+use uuid::Uuid;
+
+async fn start() -> TestServer {
+    let path = std::env::temp_dir().join(format!("etag-journal-{}", Uuid::new_v4()));
+    TestServer::start_with(WebSocketServerConfig::default(), |server| {
+        server
+            .with_journal(JournalConfig::new(path.to_string_lossy()))
+            .unwrap()
+            .with_compression(CompressionConfig::default())
+    })
+    .await
+    .unwrap()
+}
+
+async fn publish(server: &TestServer, height: u64) {
+    let event = serde_json::json!({ "event": "block", "block": { "height": height } });
+    server
+        .server()
+        .publish(WebSocketSubscriptionType::Blocks, event.to_string())
+        .await
+        .unwrap();
+}
+
+/// Fetch `/events`, returning the status, the ETag and the body's length.
+async fn get_events(
+    server: &TestServer,
+    headers: &[(header::HeaderName, &str)],
+) -> (StatusCode, Option<String>, usize) {
+    let mut request = awc::Client::default().get(format!("{}/events", server.base_url()));
+    for (name, value) in headers {
+        request = request.insert_header((name.clone(), *value));
+    }
+    let mut response = request.send().await.unwrap();
+    let etag = response
+        .headers()
+        .get(header::ETAG)
+        .map(|x| x.to_str().unwrap().to_owned());
+    let body = response.body().await.unwrap();
+
+    (response.status(), etag, body.len())
+}
+
+#[actix_web::test]
+async fn unchanged_responses_are_not_sent_again() {
+    let server = start().await;
+    publish(&server, 1).await;
+    publish(&server, 2).await;
+
+    let (status, etag, length) = get_events(&server, &[]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(length > 0);
+    let etag = etag.expect("No ETag was sent");
+    assert!(etag.starts_with("W/\""), "{etag}");
+
+    let (status, again, length) = get_events(&server, &[(header::IF_NONE_MATCH, &etag)]).await;
+    assert_eq!(status, StatusCode::NOT_MODIFIED);
+    assert_eq!(again.as_deref(), Some(&*etag));
+    assert_eq!(length, 0);
+
+    // Compressing the response doesn't change its tag
+    let headers = [
+        (header::IF_NONE_MATCH, &*etag),
+        (header::ACCEPT_ENCODING, "gzip"),
+    ];
+    let (status, _, _) = get_events(&server, &headers).await;
+    assert_eq!(status, StatusCode::NOT_MODIFIED);
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn changed_responses_get_a_new_tag() {
+    let server = start().await;
+    publish(&server, 1).await;
+    publish(&server, 2).await;
+
+    let (_, etag, _) = get_events(&server, &[]).await;
+    let etag = etag.unwrap();
+    publish(&server, 3).await;
+
+    let (status, new_etag, length) = get_events(&server, &[(header::IF_NONE_MATCH, &etag)]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(length > 0);
+    assert_ne!(new_etag.unwrap(), etag);
+
+    let stale = "W/\"0000000000000000\", W/\"1111111111111111\"";
+    let (status, _, _) = get_events(&server, &[(header::IF_NONE_MATCH, stale)]).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _, _) = get_events(&server, &[(header::IF_NONE_MATCH, "*")]).await;
+    assert_eq!(status, StatusCode::NOT_MODIFIED);
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn errors_are_not_tagged() {
+    let server = TestServer::start().await.unwrap();
+
+    let (status, etag, _) = get_events(&server, &[]).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(etag, None);
+
+    server.stop().await;
+}
+
+#[test]
+fn tags_are_stable() {
+    // The truncated SHA-256 of "hello", which every node and toolchain has to agree on
+    let tag = actix_ws_fuckery::etag::etag(b"hello");
+    assert_eq!(tag.to_string(), r#"W/"2cf24dba5fb0a30e""#);
+}
@@ -0,0 +1,130 @@
+//! Broadcast events keep the shape recorded in `tests/schemas`, or declare that they broke it by
+//! bumping their version.
+//!
+//! Run with `UPDATE_SCHEMAS=1` to record the current shapes once a change checks out.
+
+use std::path::{Path, PathBuf};
+
+use actix_ws_fuckery::schema::{EventSchema, Schema, registry};
+use serde_json::json;
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/schemas")
+}
+
+#[test]
+fn events_match_their_golden_schemas() {
+    let update = std::env::var_os("UPDATE_SCHEMAS").is_some();
+    let dir = golden_dir();
+    let mut problems = Vec::new();
+
+    let events = registry();
+    for event in &events {
+        let path = dir.join(format!("{}.json", event.event));
+        let golden: Option<EventSchema> = std::fs::read(&path)
+            .ok()
+            .map(|x| serde_json::from_slice(&x).unwrap());
+
+        if let Some(golden) = &golden {
+            if let Err(e) = event.check_compatible(golden) {
+                problems.push(e.to_string());
+                continue;
+            }
+            if golden == event {
+                continue;
+            }
+        }
+
+        if update {
+            let json = serde_json::to_string_pretty(event).unwrap();
+            std::fs::write(&path, json + "\n").unwrap();
+        } else {
+            problems.push(format!("{} doesn't match {}", event.event, path.display()));
+        }
+    }
+
+    // Krist events are only registered with the feature, but their goldens are always there
+    if cfg!(feature = "krist") {
+        for entry in std::fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.file_stem().unwrap().to_string_lossy();
+            if !events.iter().any(|x| x.event == name) {
+                problems.push(format!("{name} is no longer broadcast"));
+            }
+        }
+    }
+
+    assert!(
+        problems.is_empty(),
+        "{}\nRun with UPDATE_SCHEMAS=1 to record compatible changes",
+        problems.join("\n")
+    );
+}
+
+#[test]
+fn schemas_are_worked_out_from_samples() {
+    let samples = [
+        json!({ "id": 1, "to": "kabc", "meta": null, "tags": ["a"] }),
+        json!({ "id": 2.5, "to": "kdef", "meta": "x", "tags": [], "extra": true }),
+    ];
+    let schema = Schema::of_samples(&samples).unwrap();
+
+    let expected: Schema = serde_json::from_value(json!({
+        "type": "object",
+        "required": {
+            "id": { "type": "number" },
+            "to": { "type": "string" },
+            "meta": { "type": "nullable", "of": { "type": "string" } },
+            "tags": { "type": "array", "items": { "type": "any" } },
+        },
+        "optional": { "extra": { "type": "bool" } },
+    }))
+    .unwrap();
+    assert_eq!(schema, expected);
+}
+
+#[test]
+fn breaking_changes_are_spotted() {
+    let old = Schema::of(&json!({ "id": 1, "to": "kabc", "meta": { "note": "x" } }));
+
+    // Adding fields doesn't break anyone
+    let added = Schema::of(&json!({ "id": 1, "to": "kabc", "meta": { "note": "x" }, "new": 1 }));
+    assert_eq!(old.breaking_changes(&added), Vec::<String>::new());
+
+    let broken = Schema::of_samples(&[
+        json!({ "id": "1", "meta": { "note": null } }),
+        json!({ "id": "2", "meta": { "note": "y" } }),
+        json!({ "id": "3" }),
+    ])
+    .unwrap();
+    assert_eq!(
+        old.breaking_changes(&broken),
+        [
+            "$.id changed from an integer to a string",
+            "$.meta may now be missing",
+            "$.meta.note may now be null",
+            "$.to was removed",
+        ]
+    );
+}
+
+#[test]
+fn breaking_changes_need_a_version_bump() {
+    let previous: EventSchema = serde_json::from_value(json!({
+        "event": "example",
+        "version": 1,
+        "schema": Schema::of(&json!({ "event": "example", "count": 1 })),
+    }))
+    .unwrap();
+
+    let mut current = previous.clone();
+    current.schema = Schema::of(&json!({ "event": "example" }));
+    let error = current.check_compatible(&previous).unwrap_err().to_string();
+    assert!(error.contains("$.count was removed"), "{error}");
+
+    current.version = 2;
+    current.check_compatible(&previous).unwrap();
+
+    current.version = 0;
+    assert!(current.check_compatible(&previous).is_err());
+}
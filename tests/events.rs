@@ -0,0 +1,91 @@
+//! Embedders can follow what happens on the gateway as a stream of events.
+
+use std::pin::pin;
+use std::time::Duration;
+
+use actix_web::rt::time;
+use actix_ws_fuckery::events::GatewayEvent;
+use actix_ws_fuckery::models::websocket::{WebSocketSubscriptionType, WebSocketTokenData};
+use actix_ws_fuckery::ws::{WebSocketServer, sink::RecordingSink};
+use futures::{Stream, StreamExt};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+async fn next(events: &mut (impl Stream<Item = GatewayEvent> + Unpin)) -> GatewayEvent {
+    time::timeout(Duration::from_secs(1), events.next())
+        .await
+        .expect("No event arrived")
+        .expect("Event stream ended")
+}
+
+#[actix_web::test]
+async fn session_lifecycle_is_streamed() {
+    let server = WebSocketServer::new();
+    let mut events = pin!(server.events());
+
+    let uuid = Uuid::new_v4();
+    let inserted = server
+        .insert_session(
+            uuid,
+            RecordingSink::new(),
+            None,
+            WebSocketTokenData::new("kabc".to_owned(), None),
+            CancellationToken::new(),
+            std::convert::identity,
+        )
+        .await;
+    assert_eq!(
+        next(&mut events).await,
+        GatewayEvent::SessionConnected {
+            session: uuid,
+            address: "kabc".to_owned()
+        }
+    );
+
+    server
+        .subscribe_to_event(&uuid, WebSocketSubscriptionType::Motd)
+        .await
+        .unwrap();
+    assert_eq!(
+        next(&mut events).await,
+        GatewayEvent::SubscriptionChanged {
+            session: uuid,
+            topic: WebSocketSubscriptionType::Motd,
+            subscribed: true
+        }
+    );
+
+    server
+        .unsubscribe_from_event(&uuid, &WebSocketSubscriptionType::Motd)
+        .await;
+    assert_eq!(
+        next(&mut events).await,
+        GatewayEvent::SubscriptionChanged {
+            session: uuid,
+            topic: WebSocketSubscriptionType::Motd,
+            subscribed: false
+        }
+    );
+
+    drop(inserted);
+    assert_eq!(
+        next(&mut events).await,
+        GatewayEvent::SessionDisconnected {
+            session: uuid,
+            address: "kabc".to_owned()
+        }
+    );
+}
+
+#[actix_web::test]
+async fn refused_broadcasts_are_streamed() {
+    let server = WebSocketServer::new();
+    server.shutdown().await;
+    let mut events = pin!(server.events());
+
+    assert!(server.broadcast("late").await.is_err());
+    let GatewayEvent::BroadcastFailed { topic, .. } = next(&mut events).await else {
+        panic!("Expected a failed broadcast");
+    };
+    assert_eq!(topic, None);
+}
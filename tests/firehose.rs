@@ -0,0 +1,99 @@
+//! Admins can tail gateway events live over `/ws/admin`.
+
+use std::time::Duration;
+
+use actix_web::{http::StatusCode, rt::time};
+use actix_ws_fuckery::config::WebSocketServerConfig;
+use actix_ws_fuckery::events::GatewayEvent;
+use actix_ws_fuckery::test_utils::TestServer;
+use futures::StreamExt;
+use uuid::Uuid;
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+async fn start() -> TestServer {
+    TestServer::start_with_config(WebSocketServerConfig {
+        admin_token: Some("secret".to_owned()),
+        ..Default::default()
+    })
+    .await
+    .unwrap()
+}
+
+#[actix_web::test]
+async fn firehose_needs_the_admin_token() {
+    let server = start().await;
+
+    let url = format!("{}/ws/admin", server.base_url());
+    let response = awc::Client::default().get(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn firehose_streams_gateway_events() {
+    let server = start().await;
+
+    let url = format!("ws://{}/ws/admin", server.addr());
+    let (_, mut firehose) = awc::Client::default()
+        .ws(&url)
+        .bearer_auth("secret")
+        .connect()
+        .await
+        .unwrap();
+
+    let client = server.connect().await.unwrap();
+    server.server().emit(GatewayEvent::EventDropped {
+        session: Uuid::nil(),
+        topic: None,
+    });
+    drop(client);
+
+    let mut kinds = Vec::new();
+    while kinds.len() < 3 {
+        let frame = time::timeout(TIMEOUT, firehose.next())
+            .await
+            .expect("The firehose went quiet");
+        let Some(Ok(awc::ws::Frame::Text(event))) = frame else {
+            continue;
+        };
+        let event: serde_json::Value = serde_json::from_slice(&event).unwrap();
+        kinds.push(event["event"].as_str().unwrap().to_owned());
+    }
+    assert_eq!(
+        kinds,
+        ["session_connected", "event_dropped", "session_disconnected"]
+    );
+
+    drop(firehose);
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn firehose_closes_on_shutdown() {
+    let server = start().await;
+
+    let url = format!("ws://{}/ws/admin", server.addr());
+    let (_, mut firehose) = awc::Client::default()
+        .ws(&url)
+        .bearer_auth("secret")
+        .connect()
+        .await
+        .unwrap();
+
+    server.server().shutdown().await;
+    let closed = time::timeout(TIMEOUT, async {
+        loop {
+            match firehose.next().await {
+                Some(Ok(awc::ws::Frame::Close(_))) | None => return,
+                Some(_) => {}
+            }
+        }
+    })
+    .await;
+    assert!(closed.is_ok(), "The firehose was left open");
+
+    drop(firehose);
+    server.stop().await;
+}
@@ -0,0 +1,444 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use actix_web::{http::StatusCode, rt::time};
+use actix_ws_fuckery::client::Incoming;
+#[cfg(feature = "krist")]
+use actix_ws_fuckery::client::{GatewayClient, GatewayClientConfig};
+#[cfg(feature = "krist")]
+use actix_ws_fuckery::config::EconomyConfig;
+use actix_ws_fuckery::config::{NamespaceConfig, RuntimeConfig, WebSocketServerConfig};
+use actix_ws_fuckery::encoding::Encoding;
+use actix_ws_fuckery::models::websocket::{
+    WebSocketSubscriptionType,
+    messages::{Capability, PROTOCOL_VERSION, WebSocketMessageInner, WebSocketMessageResponse},
+};
+#[cfg(feature = "krist")]
+use actix_ws_fuckery::policy::Scope;
+use actix_ws_fuckery::test_utils::TestServer;
+use futures::{SinkExt, StreamExt};
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+#[actix_web::test]
+async fn connect_says_hello() {
+    let server = TestServer::start().await.unwrap();
+    let client = server.connect().await.unwrap();
+
+    assert!(client.resume_token().is_some());
+    assert_eq!(server.server().session_count().await, 1);
+
+    server.stop().await;
+}
+
+#[cfg(feature = "krist")]
+#[actix_web::test]
+async fn hello_carries_the_economy() {
+    let server = TestServer::start_with_config(WebSocketServerConfig {
+        economy: EconomyConfig {
+            name_cost: 1000,
+            ..Default::default()
+        },
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let token = server.issue_token("guest", None).await;
+
+    let url = format!("ws://{}/gateway/{token}", server.addr());
+    let (_, mut socket) = awc::Client::default().ws(url).connect().await.unwrap();
+    let Some(Ok(awc::ws::Frame::Text(hello))) = socket.next().await else {
+        panic!("Expected a hello");
+    };
+    let hello: serde_json::Value = serde_json::from_slice(&hello).unwrap();
+    assert_eq!(hello["economy"]["name_cost"], 1000);
+    assert_eq!(hello["economy"]["min_transaction_amount"], 1);
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn hello_advertises_capabilities() {
+    let server = TestServer::start_with_config(WebSocketServerConfig {
+        max_message_size: 4096,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+
+    let token = server.issue_token("guest", None).await;
+    let url = format!("ws://{}/gateway/{token}", server.addr());
+    let (_, mut socket) = awc::Client::default().ws(url).connect().await.unwrap();
+    let Some(Ok(awc::ws::Frame::Text(hello))) = socket.next().await else {
+        panic!("Expected a hello");
+    };
+    let hello: serde_json::Value = serde_json::from_slice(&hello).unwrap();
+    let advertised: Vec<&str> = hello["capabilities"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|x| x["capability"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        advertised,
+        [
+            "protocol",
+            "encodings",
+            "max_payload",
+            "subscriptions",
+            "resume"
+        ]
+    );
+    drop(socket);
+
+    let client = server.connect().await.unwrap();
+    let capabilities = client.capabilities();
+    assert!(capabilities.contains(&Capability::Protocol {
+        version: PROTOCOL_VERSION
+    }));
+    assert!(capabilities.contains(&Capability::MaxPayload { bytes: 4096 }));
+    assert!(capabilities.contains(&Capability::Encodings {
+        encodings: Encoding::ALL.to_vec()
+    }));
+    assert!(capabilities.contains(&Capability::Resume {
+        across_restarts: false
+    }));
+    assert!(capabilities.iter().any(|x| matches!(
+        x,
+        Capability::Subscriptions { levels } if levels.contains(&WebSocketSubscriptionType::Motd)
+    )));
+
+    drop(client);
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn issued_token_opens_gateway() {
+    let server = TestServer::start().await.unwrap();
+    let token = server.issue_token("guest", None).await;
+
+    let url = format!("ws://{}/gateway/{token}", server.addr());
+    let (_, mut socket) = awc::Client::default().ws(url).connect().await.unwrap();
+    let Some(Ok(awc::ws::Frame::Text(hello))) = socket.next().await else {
+        panic!("Expected a hello");
+    };
+    let hello: serde_json::Value = serde_json::from_slice(&hello).unwrap();
+    assert_eq!(hello["type"], "hello");
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn guests_get_their_own_address() {
+    let server = TestServer::start().await.unwrap();
+    let mut first = server.connect().await.unwrap();
+    let mut second = server.connect().await.unwrap();
+
+    let mut addresses = Vec::new();
+    for client in [&mut first, &mut second] {
+        let response = client.request(WebSocketMessageInner::Me).await.unwrap();
+        let WebSocketMessageInner::Response {
+            data: WebSocketMessageResponse::Me { is_guest, address },
+        } = response.r#type
+        else {
+            panic!("Expected a me response, got {response:?}");
+        };
+        assert!(is_guest);
+        let address = address.unwrap()["address"].as_str().unwrap().to_owned();
+        assert!(
+            address.starts_with("guest-"),
+            "Unexpected address {address}"
+        );
+        addresses.push(address);
+    }
+    assert_ne!(addresses[0], addresses[1]);
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn subscribe_and_unsubscribe() {
+    let server = TestServer::start().await.unwrap();
+    let mut client = server.connect().await.unwrap();
+
+    let response = client
+        .subscribe(WebSocketSubscriptionType::Names)
+        .await
+        .unwrap();
+    let WebSocketMessageInner::Response {
+        data: WebSocketMessageResponse::Subscribe { subscription_level },
+    } = response.r#type
+    else {
+        panic!("Expected a subscribe response, got {response:?}");
+    };
+    assert!(subscription_level.contains(&"names".to_owned()));
+    assert!(
+        client
+            .subscriptions()
+            .contains(&WebSocketSubscriptionType::Names)
+    );
+
+    let response = client
+        .unsubscribe(WebSocketSubscriptionType::Names)
+        .await
+        .unwrap();
+    let WebSocketMessageInner::Response {
+        data: WebSocketMessageResponse::Unsubscribe { subscription_level },
+    } = response.r#type
+    else {
+        panic!("Expected an unsubscribe response, got {response:?}");
+    };
+    assert!(!subscription_level.contains(&"names".to_owned()));
+    assert!(client.subscriptions().is_empty());
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn publish_reaches_subscribers_only() {
+    let server = TestServer::start().await.unwrap();
+    let mut subscribed = server.connect().await.unwrap();
+    let mut unsubscribed = server.connect().await.unwrap();
+    subscribed
+        .subscribe(WebSocketSubscriptionType::Names)
+        .await
+        .unwrap();
+
+    let event = serde_json::json!({ "type": "event", "event": "name", "name": "test" });
+    server
+        .server()
+        .publish(WebSocketSubscriptionType::Names, event.to_string())
+        .await
+        .unwrap();
+
+    let received = time::timeout(TIMEOUT, subscribed.recv()).await.unwrap();
+    let Ok(Incoming::Event(received)) = received else {
+        panic!("Expected an event, got {received:?}");
+    };
+    assert_eq!(received, event);
+
+    let nothing = time::timeout(Duration::from_millis(200), unsubscribed.recv()).await;
+    assert!(nothing.is_err(), "Unsubscribed client got {nothing:?}");
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn broadcast_reaches_everyone() {
+    let server = TestServer::start().await.unwrap();
+    let mut first = server.connect().await.unwrap();
+    let mut second = server.connect().await.unwrap();
+
+    server.server().broadcast(r#"{"number":42}"#).await.unwrap();
+
+    for client in [&mut first, &mut second] {
+        let received = time::timeout(TIMEOUT, client.recv()).await.unwrap();
+        let Ok(Incoming::Event(received)) = received else {
+            panic!("Expected a broadcast, got {received:?}");
+        };
+        assert_eq!(received, serde_json::json!({ "number": 42 }));
+    }
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn disconnect_removes_session() {
+    let server = TestServer::start().await.unwrap();
+    let client = server.connect().await.unwrap();
+    assert_eq!(server.server().session_count().await, 1);
+
+    client.close().await.unwrap();
+
+    time::timeout(TIMEOUT, async {
+        while server.server().session_count().await > 0 {
+            time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("Session was never cleaned up");
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn http_requests_are_rate_limited() {
+    let server = TestServer::start_with_config(WebSocketServerConfig {
+        runtime: RuntimeConfig {
+            http_rate_limit: Some(2),
+            ..Default::default()
+        },
+        namespaces: HashMap::from([("tenant".to_owned(), NamespaceConfig::new("tenant-key"))]),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let http = awc::Client::default();
+    let url = format!("{}/ws/start", server.base_url());
+
+    for remaining in ["1", "0"] {
+        let response = http.post(&url).send().await.unwrap();
+        assert!(response.status().is_success());
+        assert_eq!(response.headers().get("ratelimit-limit").unwrap(), "2");
+        assert_eq!(
+            response.headers().get("ratelimit-remaining").unwrap(),
+            remaining
+        );
+    }
+
+    let response = http.post(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(response.headers().contains_key("retry-after"));
+
+    // Made up API keys don't get around the limit
+    for key in ["made-up", "made-up-too"] {
+        let response = http
+            .post(&url)
+            .insert_header(("X-API-Key", key))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    // Namespaces' API keys get their own allowance
+    let response = http
+        .post(&url)
+        .insert_header(("X-API-Key", "tenant-key"))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn invalid_start_body_names_the_parameter() {
+    let server = TestServer::start().await.unwrap();
+    let http = awc::Client::default();
+    let url = format!("{}/ws/start", server.base_url());
+
+    let mut response = http
+        .post(&url)
+        .send_json(&serde_json::json!({ "privatekey": 5 }))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let error: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(error["ok"], false);
+    assert_eq!(error["error"], "invalid_parameter");
+    assert_eq!(error["parameter"], "privatekey");
+
+    let mut response = http.post(&url).send_body("{nope").await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let error: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(error["error"], "invalid_body");
+    assert!(error.get("parameter").is_none());
+
+    server.stop().await;
+}
+
+#[cfg(feature = "krist")]
+#[actix_web::test]
+async fn read_only_token_cannot_transact() {
+    let server = TestServer::start().await.unwrap();
+    let config = GatewayClientConfig {
+        private_key: Some("hunter2".into()),
+        scopes: Some([Scope::ReadOnly].into()),
+        ..server.client_config()
+    };
+    let mut client = GatewayClient::connect(config).await.unwrap();
+
+    let response = client.request(WebSocketMessageInner::Work).await.unwrap();
+    assert_eq!(response.ok, Some(true));
+
+    let response = client
+        .request(WebSocketMessageInner::MakeTransaction {
+            private_key: "hunter2".into(),
+            to: "kabc".to_owned(),
+            amount: 1,
+            metadata: None,
+        })
+        .await
+        .unwrap();
+    let WebSocketMessageInner::Error { error, .. } = response.r#type else {
+        panic!("Expected an error, got {response:?}");
+    };
+    assert_eq!(error, "missing_scope");
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn admin_scope_needs_the_admin_token() {
+    let server = TestServer::start_with_config(WebSocketServerConfig {
+        admin_token: Some("secret".to_owned()),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let http = awc::Client::default();
+    let url = format!("{}/ws/start", server.base_url());
+    let body = serde_json::json!({ "scopes": ["admin"] });
+
+    let response = http.post(&url).send_json(&body).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = http
+        .post(&url)
+        .bearer_auth("secret")
+        .send_json(&body)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let mut response = http
+        .post(&url)
+        .send_json(&serde_json::json!({ "scopes": ["everything"] }))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let error: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(error["parameter"], "scopes");
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn oversized_frame_closes_with_1009() {
+    let server = TestServer::start_with_config(WebSocketServerConfig {
+        max_frame_size: 1024,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let token = server.issue_token("guest", None).await;
+
+    let url = format!("ws://{}/gateway/{token}", server.addr());
+    let (_, mut socket) = awc::Client::default().ws(url).connect().await.unwrap();
+    let _hello = socket.next().await;
+    socket
+        .send(awc::ws::Message::Text("x".repeat(4096).into()))
+        .await
+        .unwrap();
+
+    // Skip the heartbeat ping that may have gone out in the meantime
+    let mut frame = socket.next().await;
+    while let Some(Ok(awc::ws::Frame::Ping(_))) = frame {
+        frame = socket.next().await;
+    }
+    let Some(Ok(awc::ws::Frame::Text(error))) = frame else {
+        panic!("Expected an error message, got {frame:?}");
+    };
+    let error: serde_json::Value = serde_json::from_slice(&error).unwrap();
+    assert_eq!(error["error"], "message_too_large");
+
+    let Some(Ok(awc::ws::Frame::Close(Some(reason)))) = socket.next().await else {
+        panic!("Expected a close frame");
+    };
+    assert_eq!(reason.code, awc::ws::CloseCode::Size);
+    assert_eq!(server.server().metrics().snapshot().oversized_messages, 1);
+
+    server.stop().await;
+}
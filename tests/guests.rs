@@ -0,0 +1,81 @@
+//! Deployments can refuse guests, so only clients with a private key get in.
+
+use actix_web::http::StatusCode;
+use actix_ws_fuckery::config::WebSocketServerConfig;
+use actix_ws_fuckery::test_utils::TestServer;
+use awc::error::WsClientError;
+
+async fn start(allow_guests: bool) -> TestServer {
+    TestServer::start_with_config(WebSocketServerConfig {
+        allow_guests,
+        ..Default::default()
+    })
+    .await
+    .unwrap()
+}
+
+#[actix_web::test]
+async fn starting_without_a_private_key_is_refused() {
+    let server = start(false).await;
+    let url = format!("{}/ws/start", server.base_url());
+    let http = awc::Client::default();
+
+    let response = http.post(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = http
+        .post(&url)
+        .send_json(&serde_json::json!({ "privatekey": null }))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = http
+        .post(&url)
+        .send_json(&serde_json::json!({ "privatekey": "secret" }))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn unauthenticated_gateway_connections_are_refused() {
+    let server = start(false).await;
+
+    let token = server.issue_token("guest", None).await;
+    let url = format!("ws://{}/gateway/{token}", server.addr());
+    let result = awc::Client::default().ws(url).connect().await;
+    assert!(
+        matches!(
+            result,
+            Err(WsClientError::InvalidResponseStatus(
+                StatusCode::UNAUTHORIZED
+            ))
+        ),
+        "{:?}",
+        result.map(|(response, _)| response)
+    );
+    assert_eq!(server.server().session_count().await, 0);
+
+    let token = server
+        .issue_token("kauthed00", Some("secret".to_owned()))
+        .await;
+    let url = format!("ws://{}/gateway/{token}", server.addr());
+    let (_, socket) = awc::Client::default().ws(url).connect().await.unwrap();
+
+    drop(socket);
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn guests_are_allowed_by_default() {
+    assert!(WebSocketServerConfig::default().allow_guests);
+
+    let server = start(true).await;
+    let client = server.connect().await.unwrap();
+
+    drop(client);
+    server.stop().await;
+}
@@ -0,0 +1,109 @@
+//! Handlers that take longer than their message type's timeout are given up on, and the client
+//! is told with an `operation_timeout` error.
+
+use std::{collections::HashMap, time::Duration};
+
+use actix_web::rt::time;
+use actix_ws_fuckery::config::{RuntimeConfig, WebSocketServerConfig};
+use actix_ws_fuckery::test_utils::TestSession;
+use actix_ws_fuckery::ws::{
+    WebSocketServer,
+    routes::{Route, RouteContext, Routes},
+};
+use serde::{Deserialize, Serialize};
+
+/// Takes `ms` milliseconds to answer.
+#[derive(Deserialize)]
+struct Slow {
+    ms: u64,
+}
+
+#[derive(Serialize)]
+struct SlowResponse {
+    slept: u64,
+}
+
+impl Route for Slow {
+    const TYPE: &'static str = "slow";
+    type Response = SlowResponse;
+}
+
+fn server(timeouts: &[(&str, u64)]) -> WebSocketServer {
+    let routes = Routes::new().on(|_: RouteContext, slow: Slow| async move {
+        time::sleep(Duration::from_millis(slow.ms)).await;
+        Ok(SlowResponse { slept: slow.ms })
+    });
+    let runtime = RuntimeConfig {
+        handler_timeout_ms: HashMap::from_iter(timeouts.iter().map(|&(k, v)| (k.to_owned(), v))),
+        ..Default::default()
+    };
+
+    WebSocketServer::with_config(WebSocketServerConfig {
+        runtime,
+        ..Default::default()
+    })
+    .with_routes(routes)
+}
+
+/// Handle `message` as if a guest session sent it, returning everything sent to it.
+async fn send(server: &WebSocketServer, message: serde_json::Value) -> Vec<serde_json::Value> {
+    let session = TestSession::guest(server).await;
+    session.send(message).await;
+    session.messages()
+}
+
+#[tokio::test(start_paused = true)]
+async fn slow_handlers_are_answered_with_a_timeout() {
+    let server = server(&[("slow", 100)]);
+
+    let message = serde_json::json!({ "id": 4, "type": "slow", "ms": 60_000 });
+    let started = time::Instant::now();
+    let replies = send(&server, message).await;
+    assert!(started.elapsed() < Duration::from_secs(1));
+
+    assert_eq!(replies.len(), 1, "{replies:?}");
+    assert_eq!(replies[0]["ok"], false);
+    assert_eq!(replies[0]["id"], 4);
+    assert_eq!(replies[0]["error"], "operation_timeout");
+}
+
+#[tokio::test(start_paused = true)]
+async fn handlers_within_their_timeout_answer_normally() {
+    let server = server(&[("slow", 100)]);
+
+    let replies = send(&server, serde_json::json!({ "type": "slow", "ms": 50 })).await;
+    assert_eq!(replies[0]["ok"], true);
+    assert_eq!(replies[0]["slept"], 50);
+}
+
+#[tokio::test(start_paused = true)]
+async fn message_types_can_override_the_default_timeout() {
+    let overridden = server(&[("*", 100), ("slow", 5_000)]);
+    let replies = send(
+        &overridden,
+        serde_json::json!({ "type": "slow", "ms": 1_000 }),
+    )
+    .await;
+    assert_eq!(replies[0]["ok"], true);
+
+    let server = server(&[("*", 100)]);
+    let replies = send(&server, serde_json::json!({ "type": "slow", "ms": 1_000 })).await;
+    assert_eq!(replies[0]["error"], "operation_timeout");
+
+    // Built-in handlers are held to the default too, and quick ones don't notice
+    let message = serde_json::json!({ "id": 1, "type": "subscribe", "event": "names" });
+    let replies = send(&server, message).await;
+    assert_eq!(replies[0]["ok"], true);
+}
+
+#[tokio::test(start_paused = true)]
+async fn handlers_can_take_as_long_as_they_like_by_default() {
+    let server = server(&[]);
+
+    let replies = send(
+        &server,
+        serde_json::json!({ "type": "slow", "ms": 600_000 }),
+    )
+    .await;
+    assert_eq!(replies[0]["ok"], true);
+}
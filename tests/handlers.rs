@@ -0,0 +1,388 @@
+use actix_ws_fuckery::address;
+#[cfg(feature = "krist")]
+use actix_ws_fuckery::balances::{BalanceChangeReason, BalanceStore, MemoryBalanceStore};
+#[cfg(feature = "krist")]
+use actix_ws_fuckery::config::EconomyConfig;
+use actix_ws_fuckery::config::WebSocketServerConfig;
+use actix_ws_fuckery::filter::MAX_FILTER_FIELDS;
+use actix_ws_fuckery::models::websocket::{
+    WebSocketSubscriptionType, WebSocketTokenData, is_guest_address, state::SessionState,
+};
+#[cfg(feature = "krist")]
+use actix_ws_fuckery::names::{MemoryNameStore, NameRecord, NameStore};
+use actix_ws_fuckery::test_utils::TestSession;
+use actix_ws_fuckery::ws::WebSocketServer;
+
+#[cfg(feature = "krist")]
+#[actix_web::test]
+async fn work_is_answered() {
+    let session = TestSession::guest(&WebSocketServer::new()).await;
+
+    let reply = session
+        .send(serde_json::json!({ "id": 1, "type": "work" }))
+        .await;
+    assert_eq!(reply["ok"], true);
+    assert_eq!(reply["id"], 1);
+    assert_eq!(reply["responding_to"], "work");
+    assert_eq!(reply["work"], 69420);
+}
+
+#[cfg(not(feature = "krist"))]
+#[actix_web::test]
+async fn work_is_unknown_without_krist() {
+    let session = TestSession::guest(&WebSocketServer::new()).await;
+
+    let reply = session
+        .send(serde_json::json!({ "id": 1, "type": "work" }))
+        .await;
+    assert_eq!(reply["ok"], false);
+    assert_eq!(reply["error"], "invalid_message");
+}
+
+#[cfg(feature = "krist")]
+#[actix_web::test]
+async fn invalid_address_names_the_parameter() {
+    let session = TestSession::guest(&WebSocketServer::new()).await;
+
+    let reply = session
+        .send(serde_json::json!({ "id": 1, "type": "address", "address": "kabc" }))
+        .await;
+    assert_eq!(reply["ok"], false);
+    assert_eq!(reply["error"], "invalid_parameter");
+    assert_eq!(reply["parameter"], "address");
+    assert_eq!(reply["message"], "Must be 10 characters long, not 4");
+}
+
+#[cfg(feature = "krist")]
+#[actix_web::test]
+async fn transactions_follow_the_configured_economy() {
+    let server = WebSocketServer::with_config(WebSocketServerConfig {
+        economy: EconomyConfig {
+            min_transaction_amount: 10,
+            max_metadata_length: 4,
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    let session = TestSession::guest(&server).await;
+
+    let transaction = |amount: u32, metadata: &str| {
+        serde_json::json!({
+            "id": 1,
+            "type": "make_transaction",
+            "privatekey": "secret",
+            "to": "kabcdef123",
+            "amount": amount,
+            "metadata": metadata,
+        })
+    };
+    let reply = session.send(transaction(5, "hi")).await;
+    assert_eq!(reply["parameter"], "amount");
+    assert_eq!(reply["message"], "Must be at least 10, not 5");
+
+    let reply = session.send(transaction(10, "hello")).await;
+    assert_eq!(reply["parameter"], "metadata");
+    assert_eq!(reply["message"], "Must be at most 4 characters long, not 5");
+}
+
+#[cfg(feature = "krist")]
+#[actix_web::test]
+async fn transactions_move_funds() {
+    let balances = MemoryBalanceStore::default();
+    let sender = address::from_private_key("secret");
+    balances
+        .credit(&sender, 10, BalanceChangeReason::Reward)
+        .unwrap();
+    let server = WebSocketServer::new().with_balance_store(balances.clone());
+    let session = TestSession::guest(&server).await;
+
+    let transaction = |id: usize, amount: u32| {
+        serde_json::json!({
+            "id": id,
+            "type": "make_transaction",
+            "privatekey": "secret",
+            "to": "kabcdef123",
+            "amount": amount,
+        })
+    };
+    let reply = session.send(transaction(1, 4)).await;
+    assert_eq!(reply["ok"], true, "{reply}");
+    assert_eq!(reply["responding_to"], "make_transaction");
+    assert_eq!(reply["transaction"]["from"], sender);
+    assert_eq!(reply["transaction"]["to"], "kabcdef123");
+    assert_eq!(reply["transaction"]["value"], 4);
+    assert_eq!(balances.get(&sender).unwrap(), 6);
+    assert_eq!(balances.get("kabcdef123").unwrap(), 4);
+
+    let reply = session.send(transaction(2, 7)).await;
+    assert_eq!(reply["ok"], false);
+    assert_eq!(reply["error"], "insufficient_funds");
+    assert_eq!(balances.get(&sender).unwrap(), 6);
+}
+
+#[cfg(feature = "krist")]
+#[actix_web::test]
+async fn transactions_need_a_ledger() {
+    let session = TestSession::guest(&WebSocketServer::new()).await;
+
+    let reply = session
+        .send(serde_json::json!({
+            "id": 1,
+            "type": "make_transaction",
+            "privatekey": "secret",
+            "to": "kabcdef123",
+            "amount": 1,
+        }))
+        .await;
+    assert_eq!(reply["ok"], false);
+    assert_eq!(reply["error"], "transactions_unavailable");
+}
+
+#[cfg(feature = "krist")]
+#[actix_web::test]
+async fn addresses_are_looked_up() {
+    let balances = MemoryBalanceStore::default();
+    balances
+        .credit("kabcdef123", 25, BalanceChangeReason::Reward)
+        .unwrap();
+    let names = MemoryNameStore::default();
+    names.put(&NameRecord::new("one", "kabcdef123")).unwrap();
+    names.put(&NameRecord::new("two", "kabcdef123")).unwrap();
+    names.put(&NameRecord::new("other", "kother0000")).unwrap();
+    let server = WebSocketServer::new()
+        .with_balance_store(balances)
+        .with_name_store(names);
+    let session = TestSession::guest(&server).await;
+
+    let reply = session
+        .send(serde_json::json!({ "id": 1, "type": "address", "address": "kabcdef123" }))
+        .await;
+    assert_eq!(reply["ok"], true, "{reply}");
+    assert_eq!(
+        reply["address"],
+        serde_json::json!({ "address": "kabcdef123", "balance": 25 })
+    );
+
+    let reply = session
+        .send(serde_json::json!({
+            "id": 2,
+            "type": "address",
+            "address": "kabcdef123",
+            "fetchNames": true,
+        }))
+        .await;
+    assert_eq!(reply["address"]["names"], 2);
+}
+
+#[actix_web::test]
+async fn invalid_message_keeps_its_id() {
+    let session = TestSession::guest(&WebSocketServer::new()).await;
+
+    let reply = session
+        .send(serde_json::json!({ "id": 3, "type": "nope" }))
+        .await;
+    assert_eq!(reply["ok"], false);
+    assert_eq!(reply["id"], 3);
+    assert_eq!(reply["error"], "invalid_message");
+}
+
+#[actix_web::test]
+async fn subscribe_updates_session() {
+    let server = WebSocketServer::new();
+    let session = TestSession::guest(&server).await;
+
+    let reply = session
+        .send(serde_json::json!({ "id": 2, "type": "subscribe", "event": "names" }))
+        .await;
+    assert_eq!(reply["responding_to"], "subscribe");
+
+    let subscriptions = server.get_subscription_list(&session.uuid()).await;
+    assert!(subscriptions.contains(&WebSocketSubscriptionType::Names));
+}
+
+#[actix_web::test]
+async fn oversized_filters_name_the_parameter() {
+    let server = WebSocketServer::new();
+    let session = TestSession::guest(&server).await;
+
+    let filter: serde_json::Map<String, serde_json::Value> = (0..=MAX_FILTER_FIELDS)
+        .map(|i| (format!("field{i}"), i.into()))
+        .collect();
+    let message = serde_json::json!({
+        "id": 5,
+        "type": "subscribe",
+        "event": "transactions",
+        "filter": filter,
+    });
+    let reply = session.send(message).await;
+    assert_eq!(reply["ok"], false);
+    assert_eq!(reply["id"], 5);
+    assert_eq!(reply["error"], "invalid_parameter");
+    assert_eq!(reply["parameter"], "filter");
+
+    let subscriptions = server.get_subscription_list(&session.uuid()).await;
+    assert!(!subscriptions.contains(&WebSocketSubscriptionType::Transactions));
+}
+
+#[actix_web::test]
+async fn subscribing_past_the_limit_is_refused() {
+    // Otherwise the limit would never be reached
+    assert!(
+        WebSocketServerConfig::default().max_subscriptions < WebSocketSubscriptionType::ALL.len()
+    );
+
+    let server = WebSocketServer::with_config(WebSocketServerConfig {
+        max_subscriptions: 1,
+        ..Default::default()
+    });
+    let session = TestSession::guest(&server).await;
+    for topic in server.get_subscription_list(&session.uuid()).await {
+        server.unsubscribe_from_event(&session.uuid(), &topic).await;
+    }
+
+    let subscribe = |id: usize, event: &str| serde_json::json!({ "id": id, "type": "subscribe", "event": event });
+    let reply = session.send(subscribe(1, "names")).await;
+    assert_eq!(reply["ok"], true);
+    // Already subscribed, so not a new topic
+    let reply = session.send(subscribe(2, "names")).await;
+    assert_eq!(reply["ok"], true);
+
+    let reply = session.send(subscribe(3, "motd")).await;
+    assert_eq!(reply["ok"], false);
+    assert_eq!(reply["id"], 3);
+    assert_eq!(reply["error"], "too_many_subscriptions");
+    assert_eq!(
+        reply["message"],
+        "Sessions can subscribe to at most 1 topics"
+    );
+    let subscriptions = server.get_subscription_list(&session.uuid()).await;
+    assert_eq!(subscriptions, [WebSocketSubscriptionType::Names].into());
+}
+
+#[actix_web::test]
+async fn rejected_tags_are_answered() {
+    let session = TestSession::guest(&WebSocketServer::new()).await;
+
+    let reply = session
+        .send(serde_json::json!({ "id": 1, "type": "tag", "tags": ["beta"] }))
+        .await;
+    assert_eq!(reply["ok"], true);
+    assert_eq!(reply["tags"], serde_json::json!(["beta"]));
+
+    let reply = session
+        .send(serde_json::json!({ "id": 2, "type": "tag", "tags": [""] }))
+        .await;
+    assert_eq!(reply["ok"], false);
+    assert_eq!(reply["id"], 2);
+    assert_eq!(reply["error"], "invalid_parameter");
+    assert_eq!(reply["parameter"], "tags");
+
+    let tags: Vec<String> = (0..64).map(|i| format!("tag{i}")).collect();
+    let reply = session
+        .send(serde_json::json!({ "id": 3, "type": "tag", "tags": tags }))
+        .await;
+    assert_eq!(reply["error"], "invalid_parameter");
+    assert!(
+        reply["message"].as_str().unwrap().contains("at most"),
+        "{reply}"
+    );
+}
+
+#[actix_web::test]
+async fn refused_tag_batches_leave_tags_unchanged() {
+    let session = TestSession::guest(&WebSocketServer::new()).await;
+    session
+        .send(serde_json::json!({ "id": 1, "type": "tag", "tags": ["beta"] }))
+        .await;
+
+    // The invalid tag comes last, after ones that would otherwise have been attached
+    let reply = session
+        .send(serde_json::json!({ "id": 2, "type": "tag", "tags": ["alpha", "gamma", ""] }))
+        .await;
+    assert_eq!(reply["error"], "invalid_parameter");
+
+    // One over the limit once the existing tag is counted
+    let tags: Vec<String> = (0..32).map(|i| format!("tag{i}")).collect();
+    let reply = session
+        .send(serde_json::json!({ "id": 3, "type": "tag", "tags": tags }))
+        .await;
+    assert_eq!(reply["error"], "invalid_parameter");
+
+    let reply = session
+        .send(serde_json::json!({ "id": 4, "type": "tag", "tags": [] }))
+        .await;
+    assert_eq!(reply["ok"], true, "{reply}");
+    assert_eq!(reply["tags"], serde_json::json!(["beta"]));
+}
+
+#[actix_web::test]
+async fn handshaking_session_is_turned_away() {
+    let data = WebSocketTokenData::new("guest".to_owned(), None);
+    let session =
+        TestSession::connect(&WebSocketServer::new(), data, SessionState::Handshaking).await;
+
+    let reply = session
+        .send(serde_json::json!({ "id": 4, "type": "me" }))
+        .await;
+    assert_eq!(reply["ok"], false);
+    assert_eq!(reply["id"], 4);
+}
+
+#[actix_web::test]
+async fn subscription_levels_are_listed() {
+    let server = WebSocketServer::new();
+    let session = TestSession::guest(&server).await;
+
+    let reply = session
+        .send(serde_json::json!({ "id": 1, "type": "get_valid_subscription_levels" }))
+        .await;
+    assert_eq!(reply["ok"], true, "{reply}");
+    let levels = reply["valid_subscription_levels"].as_array().unwrap();
+    assert_eq!(levels.len(), WebSocketSubscriptionType::ALL.len());
+    assert!(levels.contains(&"ownBalances".into()));
+
+    session
+        .send(serde_json::json!({ "id": 2, "type": "subscribe", "event": "names" }))
+        .await;
+    let reply = session
+        .send(serde_json::json!({ "id": 3, "type": "get_subscription_level" }))
+        .await;
+    assert_eq!(reply["responding_to"], "get_subscription_level");
+    let subscribed = reply["subscription_level"].as_array().unwrap();
+    assert!(subscribed.contains(&"names".into()), "{reply}");
+}
+
+#[actix_web::test]
+async fn sessions_log_in_and_out() {
+    let server = WebSocketServer::new();
+    let session = TestSession::guest(&server).await;
+    let address = address::from_private_key("hunter2");
+
+    let reply = session
+        .send(serde_json::json!({ "id": 1, "type": "login", "privatekey": "hunter2" }))
+        .await;
+    assert_eq!(reply["ok"], true, "{reply}");
+    assert_eq!(reply["is_guest"], false);
+    assert_eq!(reply["address"]["address"], address);
+    assert_eq!(
+        server.session_address(&session.uuid()).await.unwrap(),
+        address
+    );
+    assert_eq!(
+        server.session_state(&session.uuid()).await,
+        SessionState::Authenticated
+    );
+
+    let reply = session
+        .send(serde_json::json!({ "id": 2, "type": "logout" }))
+        .await;
+    assert_eq!(reply["ok"], true, "{reply}");
+    assert_eq!(reply["is_guest"], true);
+    assert!(is_guest_address(
+        &server.session_address(&session.uuid()).await.unwrap()
+    ));
+    assert_eq!(
+        server.session_state(&session.uuid()).await,
+        SessionState::Ready
+    );
+}
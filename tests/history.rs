@@ -0,0 +1,136 @@
+//! Past transactions and blocks can be paged through over the gateway, backed by the event journal.
+#![cfg(feature = "krist")]
+
+use actix_ws_fuckery::journal::JournalConfig;
+use actix_ws_fuckery::models::websocket::WebSocketSubscriptionType;
+use actix_ws_fuckery::test_utils::TestSession;
+use actix_ws_fuckery::ws::WebSocketServer;
+use uuid::Uuid;
+
+/// A server with a journal of three transactions and two blocks, and the journal's path.
+async fn server() -> (WebSocketServer, std::path::PathBuf) {
+    let path = std::env::temp_dir().join(format!("history-journal-{}", Uuid::new_v4()));
+    let server = WebSocketServer::new()
+        .with_journal(JournalConfig::new(path.to_string_lossy()))
+        .unwrap();
+
+    for (id, from, to) in [
+        (1, "kaaaaaaaaa", "kbbbbbbbbb"),
+        (2, "kbbbbbbbbb", "kccccccccc"),
+        (3, "kccccccccc", "kaaaaaaaaa"),
+    ] {
+        let event = serde_json::json!({
+            "type": "event",
+            "event": "transaction",
+            "transaction": { "id": id, "from": from, "to": to },
+        });
+        server
+            .publish(WebSocketSubscriptionType::Transactions, event.to_string())
+            .await
+            .unwrap();
+    }
+    for height in [1, 2] {
+        let event = serde_json::json!({ "event": "block", "block": { "height": height } });
+        server
+            .publish(WebSocketSubscriptionType::Blocks, event.to_string())
+            .await
+            .unwrap();
+    }
+
+    (server, path)
+}
+
+/// Handle `message` as if a guest session sent it, returning the reply.
+async fn send(server: &WebSocketServer, message: serde_json::Value) -> serde_json::Value {
+    TestSession::guest(server).await.send(message).await
+}
+
+fn ids(transactions: &serde_json::Value) -> Vec<u64> {
+    transactions
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|x| x["id"].as_u64().unwrap())
+        .collect()
+}
+
+#[actix_web::test]
+async fn transactions_are_paged_newest_first() {
+    let (server, path) = server().await;
+
+    let message = serde_json::json!({ "id": 1, "type": "get_transactions", "limit": 2 });
+    let reply = send(&server, message).await;
+    assert_eq!(reply["responding_to"], "get_transactions");
+    assert_eq!(reply["total"], 3);
+    assert_eq!(reply["count"], 2);
+    assert_eq!(reply["offset"], 0);
+    assert_eq!(ids(&reply["items"]), [3, 2]);
+
+    let message = serde_json::json!({
+        "id": 2,
+        "type": "get_transactions",
+        "offset": 1,
+        "order": "asc",
+    });
+    let reply = send(&server, message).await;
+    assert_eq!(reply["offset"], 1);
+    assert_eq!(ids(&reply["items"]), [2, 3]);
+
+    drop(server);
+    let _ = std::fs::remove_dir_all(path);
+}
+
+#[actix_web::test]
+async fn transactions_can_be_narrowed_to_an_address() {
+    let (server, path) = server().await;
+
+    let message = serde_json::json!({
+        "id": 1,
+        "type": "get_transactions",
+        "address": "kaaaaaaaaa",
+    });
+    let reply = send(&server, message).await;
+    assert_eq!(reply["total"], 2);
+    assert_eq!(ids(&reply["items"]), [3, 1]);
+
+    let message = serde_json::json!({ "id": 2, "type": "get_transactions", "address": "nope" });
+    let reply = send(&server, message).await;
+    assert_eq!(reply["error"], "invalid_parameter");
+    assert_eq!(reply["parameter"], "address");
+
+    drop(server);
+    let _ = std::fs::remove_dir_all(path);
+}
+
+#[actix_web::test]
+async fn blocks_are_paged() {
+    let (server, path) = server().await;
+
+    let reply = send(
+        &server,
+        serde_json::json!({ "id": 1, "type": "get_blocks" }),
+    )
+    .await;
+    assert_eq!(reply["responding_to"], "get_blocks");
+    assert_eq!(reply["total"], 2);
+    assert_eq!(
+        reply["items"],
+        serde_json::json!([{ "height": 2 }, { "height": 1 }])
+    );
+
+    drop(server);
+    let _ = std::fs::remove_dir_all(path);
+}
+
+#[actix_web::test]
+async fn history_needs_the_journal() {
+    let server = WebSocketServer::new();
+
+    let reply = send(
+        &server,
+        serde_json::json!({ "id": 1, "type": "get_blocks" }),
+    )
+    .await;
+    assert_eq!(reply["ok"], false);
+    assert_eq!(reply["error"], "history_unavailable");
+}
@@ -0,0 +1,295 @@
+//! WebSockets over HTTP/2 (RFC 8441) carry gateway sessions on extended CONNECT streams, any number
+//! of them sharing one connection.
+#![cfg(feature = "http2")]
+
+use std::{collections::HashSet, net::SocketAddr, time::Duration};
+
+use actix_codec::{Decoder, Encoder};
+use actix_http::ws::CloseCode;
+use actix_http::ws::{Codec, Frame, Item, Message};
+use actix_ws_fuckery::config::{RuntimeConfig, WebSocketServerConfig};
+use actix_ws_fuckery::http2;
+use actix_ws_fuckery::models::websocket::WebSocketTokenData;
+use actix_ws_fuckery::ws::WebSocketServer;
+use bytes::{Bytes, BytesMut};
+use h2::{RecvStream, SendStream, client::SendRequest, ext::Protocol};
+use http::{Method, Request, StatusCode};
+use serde_json::Value;
+use tokio::net::{TcpListener, TcpStream};
+use uuid::Uuid;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An HTTP/2 listener for `server`, returning its address.
+async fn start(server: &WebSocketServer) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(http2::serve(server.clone(), listener));
+
+    addr
+}
+
+/// Open an HTTP/2 connection to `addr`, once the server has said it takes extended CONNECTs.
+async fn connect(addr: SocketAddr) -> SendRequest<Bytes> {
+    let io = TcpStream::connect(addr).await.unwrap();
+    let (requests, connection) = h2::client::handshake(io).await.unwrap();
+    tokio::spawn(connection);
+
+    let requests = requests.ready().await.unwrap();
+    tokio::time::timeout(TIMEOUT, async {
+        while !requests.is_extended_connect_protocol_enabled() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+
+    requests
+}
+
+/// A WebSocket opened over an HTTP/2 stream.
+struct Socket {
+    send: SendStream<Bytes>,
+    recv: RecvStream,
+    codec: Codec,
+    buf: BytesMut,
+}
+
+impl Socket {
+    /// The next text message, parsed as JSON, skipping over pings and keepalives.
+    async fn next(&mut self) -> Value {
+        tokio::time::timeout(TIMEOUT, async {
+            loop {
+                match self.codec.decode(&mut self.buf).unwrap() {
+                    Some(Frame::Text(text)) => {
+                        let message: Value = serde_json::from_slice(&text).unwrap();
+                        if message["type"] != "keepalive" {
+                            return message;
+                        }
+                    }
+                    Some(Frame::Close(reason)) => panic!("Closed: {reason:?}"),
+                    Some(_) => {}
+                    None => {
+                        let data = self.recv.data().await.unwrap().unwrap();
+                        let _ = self.recv.flow_control().release_capacity(data.len());
+                        self.buf.extend_from_slice(&data);
+                    }
+                }
+            }
+        })
+        .await
+        .unwrap()
+    }
+
+    /// The close frame ending the WebSocket, passing over any messages before it.
+    async fn closed(&mut self) -> Option<actix_http::ws::CloseReason> {
+        tokio::time::timeout(TIMEOUT, async {
+            loop {
+                match self.codec.decode(&mut self.buf).unwrap() {
+                    Some(Frame::Close(reason)) => return reason,
+                    Some(_) => {}
+                    None => {
+                        let data = self.recv.data().await.unwrap().unwrap();
+                        let _ = self.recv.flow_control().release_capacity(data.len());
+                        self.buf.extend_from_slice(&data);
+                    }
+                }
+            }
+        })
+        .await
+        .unwrap()
+    }
+
+    fn send(&mut self, message: Message) {
+        let mut buf = BytesMut::new();
+        self.codec.encode(message, &mut buf).unwrap();
+        self.send.send_data(buf.freeze(), false).unwrap();
+    }
+}
+
+/// Send an extended CONNECT for `path`, returning the response status and, if it was accepted,
+/// the WebSocket.
+async fn open(requests: &mut SendRequest<Bytes>, path: &str) -> (StatusCode, Option<Socket>) {
+    open_with_headers(requests, path, &[]).await
+}
+
+/// Like [`open`], with extra headers on the CONNECT.
+async fn open_with_headers(
+    requests: &mut SendRequest<Bytes>,
+    path: &str,
+    headers: &[(&str, &str)],
+) -> (StatusCode, Option<Socket>) {
+    let mut request =
+        Request::connect(format!("http://localhost{path}")).header("sec-websocket-version", "13");
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    let mut request = request.body(()).unwrap();
+    request.extensions_mut().insert(Protocol::from("websocket"));
+
+    let (response, send) = requests.send_request(request, false).unwrap();
+    let response = tokio::time::timeout(TIMEOUT, response)
+        .await
+        .unwrap()
+        .unwrap();
+    let status = response.status();
+    let socket = (status == StatusCode::OK).then(|| Socket {
+        send,
+        recv: response.into_body(),
+        codec: Codec::new().client_mode(),
+        buf: BytesMut::new(),
+    });
+
+    (status, socket)
+}
+
+async fn wait_for_sessions(server: &WebSocketServer, count: usize) {
+    tokio::time::timeout(TIMEOUT, async {
+        while server.session_count().await != count {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn sessions_speak_the_gateway_protocol() {
+    let server = WebSocketServer::new();
+    let addr = start(&server).await;
+    let data = WebSocketTokenData::new("khttp2xyz0".to_owned(), Some("secret".to_owned()));
+    let token = server.obtain_token(data).await;
+
+    let mut requests = connect(addr).await;
+    let (status, socket) = open(&mut requests, &format!("/gateway/{token}")).await;
+    assert_eq!(status, StatusCode::OK);
+    let mut socket = socket.unwrap();
+
+    let hello = socket.next().await;
+    assert_eq!(hello["type"], "hello", "{hello}");
+    assert_eq!(server.session_count().await, 1);
+
+    socket.send(Message::Text(r#"{"id":1,"type":"me"}"#.into()));
+    let reply = socket.next().await;
+    assert_eq!(reply["ok"], true, "{reply}");
+    assert_eq!(reply["id"], 1);
+    assert_eq!(reply["responding_to"], "me");
+    assert_eq!(reply["is_guest"], false);
+
+    // Fragmented messages are put back together
+    socket.send(Message::Continuation(Item::FirstText(r#"{"id":2,"#.into())));
+    socket.send(Message::Continuation(Item::Continue(r#""type""#.into())));
+    socket.send(Message::Continuation(Item::Last(r#":"me"}"#.into())));
+    let reply = socket.next().await;
+    assert_eq!(reply["id"], 2, "{reply}");
+    assert_eq!(reply["responding_to"], "me");
+
+    socket.send(Message::Close(None));
+    wait_for_sessions(&server, 0).await;
+}
+
+#[tokio::test]
+async fn oversized_fragmented_messages_close_with_1009() {
+    let server = WebSocketServer::with_config(WebSocketServerConfig {
+        max_continuation_size: 16,
+        ..Default::default()
+    });
+    let addr = start(&server).await;
+    let token = server.obtain_token(WebSocketTokenData::guest()).await;
+
+    let mut requests = connect(addr).await;
+    let (_, socket) = open(&mut requests, &format!("/gateway/{token}")).await;
+    let mut socket = socket.unwrap();
+    socket.next().await;
+
+    socket.send(Message::Continuation(Item::FirstText(r#"{"id":1,"#.into())));
+    socket.send(Message::Continuation(Item::Continue(
+        r#""type":"me"}"#.into(),
+    )));
+    let error = socket.next().await;
+    assert_eq!(error["error"], "message_too_large", "{error}");
+
+    let reason = socket.closed().await.unwrap();
+    assert_eq!(reason.code, CloseCode::Size);
+}
+
+#[tokio::test]
+async fn sessions_share_a_connection() {
+    let server = WebSocketServer::new();
+    let addr = start(&server).await;
+    let mut requests = connect(addr).await;
+
+    let mut sockets = Vec::new();
+    for _ in 0..3 {
+        let token = server.obtain_token(WebSocketTokenData::guest()).await;
+        let (status, socket) = open(&mut requests, &format!("/gateway/{token}")).await;
+        assert_eq!(status, StatusCode::OK);
+        sockets.push(socket.unwrap());
+    }
+
+    for socket in &mut sockets {
+        let hello = socket.next().await;
+        assert_eq!(hello["type"], "hello", "{hello}");
+    }
+    assert_eq!(server.session_count().await, 3);
+
+    // Ending one stream leaves the others be
+    let mut closed = sockets.remove(0);
+    closed.send(Message::Close(None));
+    wait_for_sessions(&server, 2).await;
+
+    sockets[0].send(Message::Text(r#"{"id":2,"type":"me"}"#.into()));
+    let reply = sockets[0].next().await;
+    assert_eq!(reply["id"], 2, "{reply}");
+    assert_eq!(reply["is_guest"], true);
+}
+
+#[tokio::test]
+async fn bad_requests_are_refused() {
+    let server = WebSocketServer::new();
+    let addr = start(&server).await;
+    let mut requests = connect(addr).await;
+
+    let (status, _) = open(&mut requests, &format!("/gateway/{}", Uuid::new_v4())).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _) = open(&mut requests, "/elsewhere").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // A plain request rather than an extended CONNECT
+    let token = server.obtain_token(WebSocketTokenData::guest()).await;
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("http://localhost/gateway/{token}"))
+        .body(())
+        .unwrap();
+    let (response, _) = requests.send_request(request, true).unwrap();
+    assert_eq!(response.await.unwrap().status(), StatusCode::NOT_FOUND);
+
+    assert_eq!(server.session_count().await, 0);
+}
+
+#[tokio::test]
+async fn bans_apply_to_the_client_behind_a_trusted_proxy() {
+    let server = WebSocketServer::with_config(WebSocketServerConfig {
+        trusted_proxies: vec!["127.0.0.1".parse().unwrap()],
+        runtime: RuntimeConfig {
+            banned_ips: HashSet::from(["203.0.113.7".parse().unwrap()]),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    let addr = start(&server).await;
+    let mut requests = connect(addr).await;
+
+    let token = server.obtain_token(WebSocketTokenData::guest()).await;
+    let forwarded = [("x-forwarded-for", "203.0.113.7")];
+    let path = format!("/gateway/{token}");
+    let (status, _) = open_with_headers(&mut requests, &path, &forwarded).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // The proxy itself isn't banned
+    let token = server.obtain_token(WebSocketTokenData::guest()).await;
+    let (status, _) = open(&mut requests, &format!("/gateway/{token}")).await;
+    assert_eq!(status, StatusCode::OK);
+}
@@ -0,0 +1,102 @@
+//! Outbound interceptors see every message on its way to a session, in the order they were added,
+//! and can rewrite or drop it.
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+use std::time::Duration;
+
+use actix_web::rt::time;
+use actix_ws_fuckery::models::websocket::{WebSocketTokenData, state::SessionState};
+use actix_ws_fuckery::ws::{
+    self, InsertedSession, WebSocketServer,
+    interceptor::{Decision, MessageKind, OutgoingMessage},
+    sink::RecordingSink,
+};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+async fn connect(server: &WebSocketServer, uuid: Uuid, sink: &RecordingSink) -> InsertedSession {
+    let inserted = server
+        .insert_session(
+            uuid,
+            sink.clone(),
+            None,
+            WebSocketTokenData::new("guest".to_owned(), None),
+            CancellationToken::new(),
+            std::convert::identity,
+        )
+        .await;
+    server.transition(&uuid, SessionState::Ready).await;
+    inserted
+}
+
+/// Wait for the sink to have received `count` messages.
+async fn wait_for(sink: &RecordingSink, count: usize) -> Vec<serde_json::Value> {
+    time::timeout(Duration::from_secs(1), async {
+        loop {
+            let messages = sink.messages();
+            if messages.len() >= count {
+                return messages;
+            }
+            time::sleep(Duration::from_millis(5)).await;
+        }
+    })
+    .await
+    .expect("Messages never arrived")
+}
+
+#[actix_web::test]
+async fn responses_can_be_enriched() {
+    let server =
+        WebSocketServer::new().with_outbound_interceptor(|message: &mut OutgoingMessage| {
+            if message.kind == MessageKind::Response
+                && let Some(object) = message.payload.as_object_mut()
+            {
+                object.insert("server_time".to_owned(), 1234.into());
+            }
+            Decision::Continue
+        });
+    let sink = RecordingSink::new();
+    let uuid = Uuid::new_v4();
+    let _inserted = connect(&server, uuid, &sink).await;
+
+    let outbound = server.outbound(&uuid).await.unwrap();
+    let me = serde_json::json!({ "id": 1, "type": "me" });
+    ws::handle_text(&server, &outbound, uuid, "guest", "", &me.to_string()).await;
+
+    let messages = wait_for(&sink, 1).await;
+    assert_eq!(messages[0]["type"], "response");
+    assert_eq!(messages[0]["server_time"], 1234);
+}
+
+#[actix_web::test]
+async fn dropped_events_skip_the_rest_of_the_chain() {
+    let seen = Arc::new(AtomicUsize::new(0));
+    let counter = seen.clone();
+    let server = WebSocketServer::new()
+        .with_outbound_interceptor(|message: &mut OutgoingMessage| {
+            match message.payload["sampled_out"] == true {
+                true => Decision::Drop,
+                false => Decision::Continue,
+            }
+        })
+        .with_outbound_interceptor(move |_: &mut OutgoingMessage| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            Decision::Continue
+        });
+    let sink = RecordingSink::new();
+    let _inserted = connect(&server, Uuid::new_v4(), &sink).await;
+
+    let dropped = serde_json::json!({ "event": "motd", "sampled_out": true });
+    let kept = serde_json::json!({ "event": "motd", "sampled_out": false });
+    server.broadcast(dropped.to_string()).await.unwrap();
+    server.broadcast(kept.to_string()).await.unwrap();
+
+    let messages = wait_for(&sink, 1).await;
+    time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(messages, [kept]);
+    assert_eq!(sink.messages().len(), 1);
+    assert_eq!(seen.load(Ordering::Relaxed), 1);
+}
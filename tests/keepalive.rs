@@ -0,0 +1,83 @@
+//! Sessions are sent keepalives between pings, carrying the server's time and whatever else the
+//! embedder's keepalive provider supplies.
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use actix_web::rt::time;
+use actix_ws_fuckery::client::Incoming;
+use actix_ws_fuckery::config::WebSocketServerConfig;
+use actix_ws_fuckery::models::websocket::messages::WebSocketMessageInner;
+use actix_ws_fuckery::test_utils::TestServer;
+use actix_ws_fuckery::ws::{WebSocketServer, keepalive::server_time};
+use serde_json::{Map, Value, json};
+use uuid::Uuid;
+
+fn shard(_session: Uuid, address: &str) -> Map<String, Value> {
+    let fields = json!({
+        "shard": 3,
+        "address": address,
+        // Set by the server, so ignored
+        "server_time": "never",
+        "type": "hijacked",
+    });
+    fields.as_object().unwrap().clone()
+}
+
+#[test]
+fn server_time_is_rfc_3339() {
+    assert_eq!(server_time(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+
+    // A leap day, in milliseconds
+    let leap_day = UNIX_EPOCH + Duration::from_millis(951_827_696_789);
+    assert_eq!(server_time(leap_day), "2000-02-29T12:34:56.789Z");
+
+    let new_year = UNIX_EPOCH + Duration::from_secs(1_767_225_599);
+    assert_eq!(server_time(new_year), "2025-12-31T23:59:59.000Z");
+}
+
+#[test]
+fn keepalives_carry_provided_fields() {
+    let plain = WebSocketServer::new().keepalive(Uuid::nil(), "kabc");
+    let plain = serde_json::to_value(&plain).unwrap();
+    assert_eq!(plain["type"], "keepalive");
+    assert_eq!(plain.as_object().unwrap().len(), 2, "{plain}");
+
+    let server = WebSocketServer::new().with_keepalive_provider(shard);
+    let keepalive = serde_json::to_value(server.keepalive(Uuid::nil(), "kabc")).unwrap();
+    assert_eq!(keepalive["type"], "keepalive");
+    assert_eq!(keepalive["shard"], 3);
+    assert_eq!(keepalive["address"], "kabc");
+
+    let server_time = keepalive["server_time"].as_str().unwrap();
+    assert_ne!(server_time, "never");
+    assert!(
+        server_time.len() == 24 && server_time.ends_with('Z'),
+        "{server_time}"
+    );
+}
+
+#[actix_web::test]
+async fn sessions_are_sent_keepalives() {
+    let server = TestServer::start_with(WebSocketServerConfig::default(), |server| {
+        server.with_keepalive_provider(shard)
+    })
+    .await
+    .unwrap();
+    let mut client = server.connect().await.unwrap();
+
+    let fields = time::timeout(Duration::from_secs(8), async {
+        loop {
+            if let Incoming::Message(message) = client.recv().await.unwrap()
+                && let WebSocketMessageInner::Keepalive { fields, .. } = message.r#type
+            {
+                return fields;
+            }
+        }
+    })
+    .await
+    .expect("No keepalive arrived");
+    assert_eq!(fields["shard"], 3);
+
+    drop(client);
+    server.stop().await;
+}
@@ -0,0 +1,110 @@
+//! Handling times are recorded per message type and HTTP route, by outcome, and exported through
+//! the admin metrics endpoint.
+
+use std::time::Duration;
+
+use actix_web::http::StatusCode;
+use actix_web::rt::time;
+use actix_ws_fuckery::config::WebSocketServerConfig;
+use actix_ws_fuckery::metrics::{Histogram, LATENCY_BUCKETS_MS, MetricsSnapshot};
+use actix_ws_fuckery::models::websocket::{
+    WebSocketSessionInfo, WebSocketSubscriptionType, messages::WebSocketMessageInner,
+    state::SessionState,
+};
+use actix_ws_fuckery::pagination::Paginated;
+use actix_ws_fuckery::test_utils::TestServer;
+
+#[test]
+fn durations_land_in_their_buckets() {
+    let mut histogram = Histogram::default();
+    histogram.observe(Duration::from_micros(500));
+    histogram.observe(Duration::from_millis(1));
+    histogram.observe(Duration::from_millis(3));
+    histogram.observe(Duration::from_secs(60));
+
+    assert_eq!(histogram.count, 4);
+    assert_eq!(histogram.buckets.len(), LATENCY_BUCKETS_MS.len() + 1);
+    assert_eq!(histogram.buckets[..3], [2, 0, 1]);
+    assert_eq!(histogram.buckets.last(), Some(&1));
+    assert!((histogram.sum_ms - 60_004.5).abs() < 1e-6);
+}
+
+#[actix_web::test]
+async fn metrics_endpoint_reports_latencies() {
+    let server = TestServer::start_with_config(WebSocketServerConfig {
+        admin_token: Some("secret".to_owned()),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let mut client = server.connect().await.unwrap();
+    client
+        .subscribe(WebSocketSubscriptionType::Names)
+        .await
+        .unwrap();
+
+    // Timings are recorded once the reply has gone out
+    time::sleep(Duration::from_millis(50)).await;
+
+    let url = format!("{}/admin/metrics", server.base_url());
+    let http = awc::Client::default();
+    let _ = http.get(&url).send().await.unwrap();
+    let mut response = http.get(&url).bearer_auth("secret").send().await.unwrap();
+    let metrics: MetricsSnapshot = response.json().await.unwrap();
+
+    assert_eq!(metrics.message_latency["subscribe"]["ok"].count, 1);
+    assert_eq!(metrics.http_latency["/admin/metrics"]["401"].count, 1);
+    assert_eq!(metrics.http_latency["/gateway/{token}"]["101"].count, 1);
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn rejected_messages_are_timed_apart() {
+    let server = TestServer::start().await.unwrap();
+    let mut client = server.connect().await.unwrap();
+    let response = client.request(WebSocketMessageInner::Logout).await.unwrap();
+    assert_eq!(response.ok, Some(false));
+    time::sleep(Duration::from_millis(50)).await;
+
+    let metrics = server.server().metrics().snapshot();
+    assert_eq!(metrics.message_latency["logout"]["rejected"].count, 1);
+    assert!(!metrics.message_latency["logout"].contains_key("ok"));
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn admins_list_sessions_with_their_state_and_latency() {
+    let server = TestServer::start_with_config(WebSocketServerConfig {
+        admin_token: Some("secret".to_owned()),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let _guest = server.connect().await.unwrap();
+    let _owner = server.connect_as("hunter2").await.unwrap();
+
+    let url = format!("{}/admin/sessions", server.base_url());
+    let http = awc::Client::default();
+    let response = http.get(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let mut response = http.get(&url).bearer_auth("secret").send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let sessions: Paginated<WebSocketSessionInfo> = response.json().await.unwrap();
+    assert_eq!(sessions.total, 2);
+    let mut states: Vec<_> = sessions.items.iter().map(|x| x.state).collect();
+    states.sort_by_key(|x| *x as u8);
+    assert_eq!(states, [SessionState::Ready, SessionState::Authenticated]);
+
+    // Latency is only known once a heartbeat has come back
+    let mut response = http.get(&url).bearer_auth("secret").send().await.unwrap();
+    let sessions: serde_json::Value = response.json().await.unwrap();
+    assert!(
+        sessions["items"][0].get("latencyMs").is_some(),
+        "{sessions}"
+    );
+
+    server.stop().await;
+}
@@ -0,0 +1,161 @@
+//! Once the server starts shutting down, events are refused or journaled for the next process
+//! instead of racing the sessions being torn down. Draining only turns new sessions away.
+
+use std::time::Duration;
+
+use actix_web::{http::StatusCode, rt::time};
+use actix_ws_fuckery::client::{GatewayClient, GatewayClientConfig, Incoming};
+use actix_ws_fuckery::config::{DrainingPublish, WebSocketServerConfig};
+use actix_ws_fuckery::journal::JournalConfig;
+use actix_ws_fuckery::models::websocket::{
+    DrainResponse, WebSocketSubscriptionType, messages::WebSocketMessageInner, state::ServerState,
+};
+use actix_ws_fuckery::test_utils::{TestServer, TestSession};
+use actix_ws_fuckery::ws::{ServerDraining, WebSocketServer};
+use awc::ws::Frame;
+use futures::StreamExt;
+use uuid::Uuid;
+
+#[actix_web::test]
+async fn events_are_refused_after_shutdown() {
+    let server = WebSocketServer::new();
+    assert_eq!(server.server_state(), ServerState::Running);
+    assert_eq!(server.broadcast("before").await, Ok(()));
+
+    server.shutdown().await;
+    assert_eq!(server.server_state(), ServerState::Stopped);
+    assert_eq!(server.broadcast("after").await, Err(ServerDraining));
+    assert_eq!(
+        server
+            .publish(WebSocketSubscriptionType::Blocks, "after")
+            .await,
+        Err(ServerDraining)
+    );
+}
+
+#[actix_web::test]
+async fn events_are_journaled_after_shutdown_when_configured() {
+    let path = std::env::temp_dir().join(format!("draining-journal-{}", Uuid::new_v4()));
+    let server = WebSocketServer::with_config(WebSocketServerConfig {
+        draining_publish: DrainingPublish::Journal,
+        ..Default::default()
+    })
+    .with_journal(JournalConfig::new(path.to_string_lossy()))
+    .unwrap();
+
+    server
+        .publish(WebSocketSubscriptionType::Blocks, r#"{"block":1}"#)
+        .await
+        .unwrap();
+    let last_seq = server.journal().unwrap().latest_seq().unwrap().unwrap();
+
+    server.shutdown().await;
+    server
+        .publish(WebSocketSubscriptionType::Blocks, r#"{"block":2}"#)
+        .await
+        .unwrap();
+
+    // Sessions resume from the last event they were sent before the shutdown
+    let events = server.journal().unwrap().since(last_seq, 10).unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].topic, Some(WebSocketSubscriptionType::Blocks));
+    assert!(events[0].payload.contains(r#""block":2"#));
+
+    drop(server);
+    let _ = std::fs::remove_dir_all(path);
+}
+
+#[actix_web::test]
+async fn events_still_reach_sessions_while_draining() {
+    let server = WebSocketServer::new();
+    let session = TestSession::guest(&server).await;
+    server
+        .subscribe_to_event(&session.uuid(), WebSocketSubscriptionType::Blocks)
+        .await
+        .unwrap();
+
+    server.drain(Duration::from_secs(1)).await;
+    assert_eq!(server.server_state(), ServerState::Draining);
+
+    let event = serde_json::json!({ "event": "block", "block": { "height": 1 } });
+    server
+        .publish(WebSocketSubscriptionType::Blocks, event.to_string())
+        .await
+        .unwrap();
+    assert_eq!(server.broadcast("still here").await, Ok(()));
+    server
+        .outbound(&session.uuid())
+        .await
+        .unwrap()
+        .flush()
+        .await
+        .unwrap();
+    assert!(
+        session.messages().iter().any(|x| x["event"] == "block"),
+        "{:?}",
+        session.messages()
+    );
+}
+
+#[actix_web::test]
+async fn drain_stops_tokens_and_asks_sessions_to_reconnect() {
+    let server = TestServer::start_with_config(WebSocketServerConfig {
+        admin_token: Some("secret".to_owned()),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let config = GatewayClientConfig {
+        follow_reconnect_advisories: false,
+        ..server.client_config()
+    };
+    let mut client = GatewayClient::connect(config).await.unwrap();
+    let token = server.issue_token("guest", None).await;
+    let http = awc::Client::default();
+    let drain_url = format!("{}/ws/drain", server.base_url());
+
+    let response = http.post(&drain_url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let mut response = http
+        .post(&drain_url)
+        .bearer_auth("secret")
+        .send_json(&serde_json::json!({ "window_ms": 1000 }))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let drained: DrainResponse = response.json().await.unwrap();
+    assert_eq!(drained.sessions, 1);
+    assert_eq!(server.server().server_state(), ServerState::Draining);
+
+    let received = time::timeout(Duration::from_secs(2), client.recv())
+        .await
+        .unwrap();
+    let Ok(Incoming::Message(message)) = received else {
+        panic!("Expected a message, got {received:?}");
+    };
+    let WebSocketMessageInner::Reconnect { wait_ms, reason } = message.r#type else {
+        panic!("Expected a reconnect advisory, got {message:?}");
+    };
+    assert!(wait_ms < 1000);
+    assert_eq!(reason, "draining");
+
+    let response = http
+        .post(format!("{}/ws/start", server.base_url()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    // Nor are tokens issued before the drain redeemed
+    let url = format!("ws://{}/gateway/{token}", server.addr());
+    let (_, mut socket) = awc::Client::default().ws(url).connect().await.unwrap();
+    let Some(Ok(Frame::Text(advisory))) = socket.next().await else {
+        panic!("Expected a reconnect advisory");
+    };
+    let advisory: serde_json::Value = serde_json::from_slice(&advisory).unwrap();
+    assert_eq!(advisory["type"], "reconnect", "{advisory}");
+    assert_eq!(server.server().session_count().await, 1);
+
+    server.stop().await;
+}
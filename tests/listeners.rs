@@ -0,0 +1,88 @@
+//! Embedders hear about what clients do through listeners, without the handlers knowing.
+
+use std::time::Duration;
+
+use actix_web::rt::time;
+use actix_ws_fuckery::models::websocket::{
+    WebSocketSubscriptionType, WebSocketTokenData, messages::WebSocketMessageInner,
+    state::SessionState,
+};
+use actix_ws_fuckery::ws::{self, WebSocketServer, sink::RecordingSink};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+#[actix_web::test]
+async fn message_listeners_hear_their_type() {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let server = WebSocketServer::new().on_message_type("subscribe", move |session, message| {
+        let tx = tx.clone();
+        async move {
+            let _ = tx.send((session.uuid(), session.address().to_owned(), message));
+        }
+    });
+
+    let uuid = Uuid::new_v4();
+    let sink = RecordingSink::new();
+    let _inserted = server
+        .insert_session(
+            uuid,
+            sink,
+            None,
+            WebSocketTokenData::new("guest".to_owned(), None),
+            CancellationToken::new(),
+            std::convert::identity,
+        )
+        .await;
+    server.transition(&uuid, SessionState::Ready).await;
+
+    let outbound = server.outbound(&uuid).await.unwrap();
+    for message in [
+        serde_json::json!({ "id": 1, "type": "me" }),
+        serde_json::json!({ "id": 2, "type": "subscribe", "event": "motd" }),
+    ] {
+        ws::handle_text(&server, &outbound, uuid, "guest", "", &message.to_string()).await;
+    }
+
+    let (session, address, message) = time::timeout(TIMEOUT, rx.recv()).await.unwrap().unwrap();
+    assert_eq!(session, uuid);
+    assert_eq!(address, "guest");
+    assert_eq!(message.id, Some(2));
+    let WebSocketMessageInner::Subscribe { event, .. } = &message.r#type else {
+        panic!("Expected a subscribe, got {message:?}");
+    };
+    assert_eq!(*event, WebSocketSubscriptionType::Motd);
+
+    let nothing = time::timeout(Duration::from_millis(100), rx.recv()).await;
+    assert!(nothing.is_err(), "Heard about another message: {nothing:?}");
+}
+
+#[actix_web::test]
+async fn login_listeners_hear_authenticated_sessions() {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let server = WebSocketServer::new().on_login(move |session| {
+        let tx = tx.clone();
+        async move {
+            let _ = tx.send(session.address().to_owned());
+        }
+    });
+
+    let uuid = Uuid::new_v4();
+    let data = WebSocketTokenData::new("kabc".to_owned(), Some("secret".to_owned()));
+    let _inserted = server
+        .insert_session(
+            uuid,
+            RecordingSink::new(),
+            None,
+            data,
+            CancellationToken::new(),
+            std::convert::identity,
+        )
+        .await;
+    server.transition(&uuid, SessionState::Authenticated).await;
+
+    let address = time::timeout(TIMEOUT, rx.recv()).await.unwrap().unwrap();
+    assert_eq!(address, "kabc");
+}
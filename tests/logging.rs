@@ -0,0 +1,221 @@
+//! JSON log lines carry gateway activity under stable field names, and secrets are kept out of
+//! logs in every format.
+
+use std::fmt::Debug;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use actix_ws_fuckery::models::websocket::{
+    WebSocketTokenData, messages::WebSocketMessage, state::SessionState,
+};
+use actix_ws_fuckery::telemetry::{self, LogFormat};
+use actix_ws_fuckery::ws::{self, WebSocketServer, sink::RecordingSink};
+use tokio_util::sync::CancellationToken;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use uuid::Uuid;
+
+#[derive(Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Buffer {
+    fn text(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+    }
+
+    fn lines(&self) -> Vec<serde_json::Value> {
+        let buffer = self.0.lock().unwrap();
+        String::from_utf8_lossy(&buffer)
+            .lines()
+            .map(|x| serde_json::from_str(x).unwrap())
+            .collect()
+    }
+}
+
+#[actix_web::test]
+async fn json_lines_carry_stable_fields() {
+    let buffer = Buffer::default();
+    let writer = buffer.clone();
+    let subscriber = tracing_subscriber::registry()
+        .with(telemetry::fmt_layer(LogFormat::Json, move || {
+            writer.clone()
+        }));
+    let _default = tracing::subscriber::set_default(subscriber);
+
+    let server = WebSocketServer::new();
+    let uuid = Uuid::new_v4();
+    let _inserted = server
+        .insert_session(
+            uuid,
+            RecordingSink::new(),
+            None,
+            WebSocketTokenData::new("kaddress00".to_owned(), None),
+            CancellationToken::new(),
+            std::convert::identity,
+        )
+        .await;
+
+    // Still handshaking, so the message is rejected inside its span
+    assert_eq!(server.session_state(&uuid).await, SessionState::Handshaking);
+    let outbound = server.outbound(&uuid).await.unwrap();
+    let message = serde_json::json!({ "id": 1, "type": "me" }).to_string();
+    ws::handle_text(&server, &outbound, uuid, "kaddress00", "", &message).await;
+
+    let lines = buffer.lines();
+    assert!(
+        lines.iter().any(|x| x["event"] == "session_connected"),
+        "{lines:?}"
+    );
+
+    let rejected = lines
+        .iter()
+        .find(|x| x["span"]["name"] == "ws_message")
+        .expect("Nothing was logged for the message");
+    assert_eq!(rejected["span"]["session_id"], uuid.to_string());
+    assert_eq!(rejected["span"]["address"], "kaddress00");
+    assert_eq!(rejected["span"]["message_type"], "me");
+}
+
+#[test]
+fn formats_parse() {
+    assert_eq!("json".parse::<LogFormat>().unwrap(), LogFormat::Json);
+    assert_eq!("text".parse::<LogFormat>().unwrap(), LogFormat::Text);
+    assert!("xml".parse::<LogFormat>().is_err());
+}
+
+/// Log a login for a session as `format`, returning what was written.
+async fn log_login(format: LogFormat) -> Buffer {
+    let buffer = Buffer::default();
+    let writer = buffer.clone();
+    let subscriber =
+        tracing_subscriber::registry().with(telemetry::fmt_layer(format, move || writer.clone()));
+    let _default = tracing::subscriber::set_default(subscriber);
+
+    login().await;
+    buffer
+}
+
+/// Log in with a private key on a session that's already logged in, then log the message the way
+/// an embedder might, as the gateway itself doesn't log payloads.
+async fn login() {
+    let server = WebSocketServer::new();
+    let uuid = Uuid::new_v4();
+    let _inserted = server
+        .insert_session(
+            uuid,
+            RecordingSink::new(),
+            None,
+            WebSocketTokenData::new("kaddress00".to_owned(), None),
+            CancellationToken::new(),
+            std::convert::identity,
+        )
+        .await;
+    // Already logged in, so the login is logged and then turned away
+    server.transition(&uuid, SessionState::Authenticated).await;
+
+    let outbound = server.outbound(&uuid).await.unwrap();
+    let message = serde_json::json!({ "id": 42, "type": "login", "privatekey": "hunter2" });
+    ws::handle_text(
+        &server,
+        &outbound,
+        uuid,
+        "kaddress00",
+        "",
+        &message.to_string(),
+    )
+    .await;
+
+    let message: WebSocketMessage = serde_json::from_value(message).unwrap();
+    tracing::info!("{message:?}");
+}
+
+/// Records the fields of every span and event, as layers exporting them elsewhere (like
+/// OpenTelemetry's) see them, without going through the fmt layer.
+#[derive(Clone, Default)]
+struct FieldRecorder(Arc<Mutex<Vec<String>>>);
+
+impl Visit for FieldRecorder {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        let mut fields = self.0.lock().unwrap();
+        fields.push(format!("{} = {value:?}", field.name()));
+    }
+}
+
+impl<S: Subscriber> Layer<S> for FieldRecorder {
+    fn on_new_span(&self, attrs: &Attributes<'_>, _id: &Id, _ctx: Context<'_, S>) {
+        attrs.record(&mut self.clone());
+    }
+
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        event.record(&mut self.clone());
+    }
+}
+
+#[actix_web::test]
+async fn private_keys_are_redacted_from_text_logs() {
+    let logs = log_login(LogFormat::Text).await.text();
+    assert!(logs.contains("Login"), "{logs}");
+    assert!(logs.contains("[redacted]"), "{logs}");
+    assert!(!logs.contains("hunter2"), "{logs}");
+}
+
+#[actix_web::test]
+async fn private_keys_are_redacted_from_json_logs() {
+    let buffer = log_login(LogFormat::Json).await;
+    let logs = buffer.text();
+    assert!(logs.contains("[redacted]"), "{logs}");
+    assert!(!logs.contains("hunter2"), "{logs}");
+    // Still valid JSON
+    assert!(!buffer.lines().is_empty());
+}
+
+#[actix_web::test]
+async fn private_keys_never_reach_other_layers() {
+    let recorder = FieldRecorder::default();
+    let subscriber = tracing_subscriber::registry().with(recorder.clone());
+    let _default = tracing::subscriber::set_default(subscriber);
+
+    login().await;
+
+    let fields = recorder.0.lock().unwrap().join("\n");
+    assert!(fields.contains("Login"), "{fields}");
+    assert!(fields.contains("[redacted]"), "{fields}");
+    assert!(!fields.contains("hunter2"), "{fields}");
+}
+
+#[test]
+fn redaction_keeps_everything_else() {
+    let line = r#"WebSocketMessage { id: Some(42), type: Login { private_key: "hunter2" } }"#;
+    assert_eq!(
+        telemetry::redact(line),
+        r#"WebSocketMessage { id: Some(42), type: Login { private_key: "[redacted]" } }"#
+    );
+
+    let line = r#"{"type":"make_transaction","privatekey":"a\"b","to":"kabcdef123"}"#;
+    assert_eq!(
+        telemetry::redact(line),
+        r#"{"type":"make_transaction","privatekey":"[redacted]","to":"kabcdef123"}"#
+    );
+
+    let line = r#"{"message":"private_key: Some(\"a\\\\\") and more"}"#;
+    assert_eq!(
+        telemetry::redact(line),
+        r#"{"message":"private_key: Some(\"[redacted]\") and more"}"#
+    );
+
+    let line = "Checking whether private_key_hash matches";
+    assert_eq!(telemetry::redact(line), line);
+}
@@ -0,0 +1,230 @@
+//! During read-only maintenance, state-changing messages are turned away while queries are still
+//! answered and events still delivered.
+
+use std::sync::{Arc, Mutex};
+
+use actix_web::http::StatusCode;
+use actix_ws_fuckery::audit::{AuditAction, AuditRecord, AuditSink};
+use actix_ws_fuckery::config::WebSocketServerConfig;
+use actix_ws_fuckery::models::websocket::MaintenanceStatus;
+#[cfg(feature = "krist")]
+use actix_ws_fuckery::models::websocket::{
+    WebSocketSubscriptionType, WebSocketTokenData, state::SessionState,
+};
+use actix_ws_fuckery::policy::Scope;
+use actix_ws_fuckery::test_utils::{TestServer, TestSession};
+use actix_ws_fuckery::ws::{
+    WebSocketServer,
+    routes::{Route, RouteContext, Routes},
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Default)]
+struct RecordingAuditSink(Arc<Mutex<Vec<AuditAction>>>);
+
+impl AuditSink for RecordingAuditSink {
+    fn record(&self, record: &AuditRecord) {
+        self.0.lock().unwrap().push(record.action.clone());
+    }
+}
+
+#[cfg(feature = "krist")]
+fn transaction(id: usize, amount: u32) -> serde_json::Value {
+    serde_json::json!({
+        "id": id,
+        "type": "make_transaction",
+        "privatekey": "secret",
+        "to": "kabcdef123",
+        "amount": amount,
+    })
+}
+
+#[cfg(feature = "krist")]
+#[actix_web::test]
+async fn only_state_changing_messages_are_turned_away() {
+    let server = WebSocketServer::new();
+    let data = WebSocketTokenData::new("kmaintain0".to_owned(), Some("secret".to_owned()));
+    let session = TestSession::connect(&server, data, SessionState::Authenticated).await;
+
+    assert!(server.start_maintenance(Some("Migrating".to_owned())));
+    assert!(!server.start_maintenance(Some("Migrating".to_owned())));
+
+    let reply = session.send(transaction(1, 5)).await;
+    assert_eq!(reply["ok"], false);
+    assert_eq!(reply["id"], 1);
+    assert_eq!(reply["error"], "server_maintenance");
+    assert!(reply["message"].as_str().unwrap().contains("Migrating"));
+
+    let update = serde_json::json!({ "id": 2, "type": "update_name", "name": "example" });
+    let reply = session.send(update).await;
+    assert_eq!(reply["error"], "server_maintenance");
+
+    let subscribe = serde_json::json!({ "id": 3, "type": "subscribe", "event": "blocks" });
+    let reply = session.send(subscribe).await;
+    assert_eq!(reply["responding_to"], "subscribe");
+
+    let event = serde_json::json!({ "event": "block", "block": { "height": 1 } });
+    server
+        .publish(WebSocketSubscriptionType::Blocks, event.to_string())
+        .await
+        .unwrap();
+    server
+        .outbound(&session.uuid())
+        .await
+        .unwrap()
+        .flush()
+        .await
+        .unwrap();
+    assert_eq!(session.messages().last().unwrap()["event"], "block");
+
+    assert!(server.end_maintenance());
+    assert!(!server.end_maintenance());
+
+    // Handled again, getting as far as validation
+    let reply = session.send(transaction(4, 0)).await;
+    assert_eq!(reply["error"], "invalid_parameter");
+    assert_eq!(reply["parameter"], "amount");
+}
+
+/// Moves funds, so needs [`Scope::Transact`] and is taken to change state.
+#[derive(Deserialize)]
+struct Transfer {}
+
+/// Needs no scope, but declares that it changes state.
+#[derive(Deserialize)]
+struct Rebuild {}
+
+/// Only reads.
+#[derive(Deserialize)]
+struct Height {}
+
+#[derive(Serialize)]
+struct Done {}
+
+impl Route for Transfer {
+    const TYPE: &'static str = "transfer";
+    const SCOPE: Scope = Scope::Transact;
+    type Response = Done;
+}
+
+impl Route for Rebuild {
+    const TYPE: &'static str = "rebuild";
+    const STATE_CHANGING: bool = true;
+    type Response = Done;
+}
+
+impl Route for Height {
+    const TYPE: &'static str = "height";
+    type Response = Done;
+}
+
+#[actix_web::test]
+async fn state_changing_routes_are_turned_away() {
+    let routes = Routes::new()
+        .on(|_: RouteContext, _: Transfer| async { Ok(Done {}) })
+        .on(|_: RouteContext, _: Rebuild| async { Ok(Done {}) })
+        .on(|_: RouteContext, _: Height| async { Ok(Done {}) });
+    let server = WebSocketServer::new().with_routes(routes);
+    let session = TestSession::guest(&server).await;
+    server.start_maintenance(None);
+
+    for (id, kind) in [(1, "transfer"), (2, "rebuild")] {
+        let reply = session
+            .send(serde_json::json!({ "id": id, "type": kind }))
+            .await;
+        assert_eq!(reply["ok"], false, "{reply}");
+        assert_eq!(reply["id"], id);
+        assert_eq!(reply["error"], "server_maintenance");
+    }
+
+    let reply = session
+        .send(serde_json::json!({ "id": 3, "type": "height" }))
+        .await;
+    assert_eq!(reply["ok"], true, "{reply}");
+
+    server.end_maintenance();
+    let reply = session
+        .send(serde_json::json!({ "id": 4, "type": "transfer" }))
+        .await;
+    assert_eq!(reply["ok"], true, "{reply}");
+}
+
+#[actix_web::test]
+async fn admins_toggle_maintenance() {
+    let audit = RecordingAuditSink::default();
+    let config = WebSocketServerConfig {
+        admin_token: Some("secret".to_owned()),
+        ..Default::default()
+    };
+    let server = TestServer::start_with(config, |server| server.with_audit_sink(audit.clone()))
+        .await
+        .unwrap();
+    let http = awc::Client::default();
+    let url = format!("{}/admin/maintenance", server.base_url());
+
+    let response = http.post(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let mut response = http
+        .post(&url)
+        .bearer_auth("secret")
+        .send_json(&serde_json::json!({ "reason": "Migrating" }))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let status: MaintenanceStatus = response.json().await.unwrap();
+    assert_eq!(
+        status,
+        MaintenanceStatus {
+            enabled: true,
+            reason: Some("Migrating".to_owned())
+        }
+    );
+
+    let mut response = http.get(&url).bearer_auth("secret").send().await.unwrap();
+    let status: MaintenanceStatus = response.json().await.unwrap();
+    assert!(status.enabled);
+
+    let mut response = http
+        .delete(&url)
+        .bearer_auth("secret")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let status: MaintenanceStatus = response.json().await.unwrap();
+    assert_eq!(status, MaintenanceStatus::default());
+
+    let response = http
+        .delete(&url)
+        .bearer_auth("secret")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let actions: Vec<AuditAction> = audit
+        .0
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|x| {
+            matches!(
+                x,
+                AuditAction::MaintenanceStarted { .. } | AuditAction::MaintenanceEnded
+            )
+        })
+        .cloned()
+        .collect();
+    assert_eq!(
+        actions,
+        [
+            AuditAction::MaintenanceStarted {
+                reason: Some("Migrating".to_owned())
+            },
+            AuditAction::MaintenanceEnded,
+        ]
+    );
+
+    server.stop().await;
+}
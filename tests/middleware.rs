@@ -0,0 +1,56 @@
+//! Inbound middleware runs over every message a session sends before its handler does, and can
+//! turn it away with an error instead.
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+
+use actix_ws_fuckery::test_utils::TestSession;
+use actix_ws_fuckery::ws::{
+    WebSocketServer,
+    middleware::{IncomingMessage, Rejection},
+};
+
+/// A server letting everything through but `tag`, counting the messages that get past it.
+fn server(reached: Arc<AtomicUsize>) -> WebSocketServer {
+    WebSocketServer::new()
+        .with_inbound_middleware(|incoming: &mut IncomingMessage| match incoming.kind {
+            "tag" => Err(Rejection::new("no_tags", "Not today")),
+            _ => Ok(()),
+        })
+        .with_inbound_middleware(move |_: &mut IncomingMessage| {
+            reached.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        })
+}
+
+/// Handle `message` as if a guest session sent it, returning the reply.
+async fn send(server: &WebSocketServer, message: serde_json::Value) -> serde_json::Value {
+    TestSession::guest(server).await.send(message).await
+}
+
+#[actix_web::test]
+async fn rejection_short_circuits_the_handler() {
+    let reached = Arc::new(AtomicUsize::new(0));
+    let server = server(reached.clone());
+
+    let message = serde_json::json!({ "id": 1, "type": "tag", "tags": ["vip"] });
+    let reply = send(&server, message).await;
+    assert_eq!(reply["ok"], false);
+    assert_eq!(reply["id"], 1);
+    assert_eq!(reply["error"], "no_tags");
+    assert_eq!(reply["message"], "Not today");
+    assert_eq!(reached.load(Ordering::Relaxed), 0);
+}
+
+#[actix_web::test]
+async fn accepted_messages_reach_their_handler() {
+    let reached = Arc::new(AtomicUsize::new(0));
+    let server = server(reached.clone());
+
+    let reply = send(&server, serde_json::json!({ "id": 1, "type": "me" })).await;
+    assert_eq!(reply["ok"], true);
+    assert_eq!(reply["type"], "response");
+    assert_eq!(reached.load(Ordering::Relaxed), 1);
+}
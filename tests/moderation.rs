@@ -0,0 +1,359 @@
+//! Admins can lock addresses out of logging in and transacting, and seize and restore names, with
+//! each action audited and the change sent out to whoever it concerns.
+#![cfg(feature = "krist")]
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix_web::{http::StatusCode, rt::time};
+use actix_ws_fuckery::address;
+use actix_ws_fuckery::audit::{AuditAction, AuditRecord, AuditSink};
+use actix_ws_fuckery::balances::{BalanceChangeReason, BalanceStore, MemoryBalanceStore};
+use actix_ws_fuckery::config::{RuntimeConfig, WebSocketServerConfig};
+use actix_ws_fuckery::locks::{LockStore, MemoryLockStore, SledLockStore};
+use actix_ws_fuckery::models::websocket::{
+    WebSocketSubscriptionType, WebSocketTokenData, state::SessionState,
+};
+use actix_ws_fuckery::names::{MemoryNameStore, NameRecord, NameStore};
+use actix_ws_fuckery::test_utils::{TestServer, TestSession};
+use actix_ws_fuckery::ws::{self, InsertedSession, WebSocketServer, sink::RecordingSink};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+#[derive(Clone, Default)]
+struct RecordingAuditSink(Arc<Mutex<Vec<AuditAction>>>);
+
+impl AuditSink for RecordingAuditSink {
+    fn record(&self, record: &AuditRecord) {
+        self.0.lock().unwrap().push(record.action.clone());
+    }
+}
+
+async fn start(audit: RecordingAuditSink, names: MemoryNameStore) -> TestServer {
+    let config = WebSocketServerConfig {
+        admin_token: Some("secret".to_owned()),
+        ..Default::default()
+    };
+    TestServer::start_with(config, |server| {
+        server.with_audit_sink(audit).with_name_store(names)
+    })
+    .await
+    .unwrap()
+}
+
+#[actix_web::test]
+async fn locked_addresses_cannot_log_in() {
+    let audit = RecordingAuditSink::default();
+    let server = start(audit.clone(), MemoryNameStore::default()).await;
+    let http = awc::Client::default();
+    let address = address::from_private_key("hunter2");
+    let lock_url = format!("{}/admin/addresses/{address}/lock", server.base_url());
+
+    let response = http
+        .post(&lock_url)
+        .bearer_auth("secret")
+        .send_json(&serde_json::json!({ "reason": "Fraud" }))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(server.server().is_address_locked(&address));
+
+    let start_url = format!("{}/ws/start", server.base_url());
+    let response = http
+        .post(&start_url)
+        .send_json(&serde_json::json!({ "privatekey": "hunter2" }))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = http
+        .delete(&lock_url)
+        .bearer_auth("secret")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = http
+        .post(&start_url)
+        .send_json(&serde_json::json!({ "privatekey": "hunter2" }))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    assert_eq!(
+        *audit.0.lock().unwrap(),
+        [
+            AuditAction::AddressLocked {
+                reason: Some("Fraud".to_owned())
+            },
+            AuditAction::AddressUnlocked,
+            AuditAction::TokenIssued,
+        ]
+    );
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn names_can_be_seized_and_restored() {
+    let names = MemoryNameStore::default();
+    names
+        .put(&NameRecord::new("example", "kowner0000"))
+        .unwrap();
+    let audit = RecordingAuditSink::default();
+    let server = start(audit.clone(), names.clone()).await;
+    let http = awc::Client::default();
+    let seize_url = format!("{}/admin/names/example/seize", server.base_url());
+
+    let response = http.post(&seize_url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let mut response = http
+        .post(&seize_url)
+        .bearer_auth("secret")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let record: NameRecord = response.json().await.unwrap();
+    assert!(record.seized);
+    assert!(names.get("example").unwrap().unwrap().seized);
+
+    let mut response = http
+        .delete(&seize_url)
+        .bearer_auth("secret")
+        .send()
+        .await
+        .unwrap();
+    let record: NameRecord = response.json().await.unwrap();
+    assert!(!record.seized);
+
+    let missing = format!("{}/admin/names/missing/seize", server.base_url());
+    let response = http
+        .post(&missing)
+        .bearer_auth("secret")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    assert_eq!(
+        *audit.0.lock().unwrap(),
+        [
+            AuditAction::NameSeized {
+                name: "example".to_owned(),
+                reason: None
+            },
+            AuditAction::NameRestored {
+                name: "example".to_owned()
+            },
+        ]
+    );
+
+    server.stop().await;
+}
+
+/// A logged in session subscribed to `names`, on a server with one name owned by it.
+async fn owner_session(server: &WebSocketServer) -> (Uuid, RecordingSink, InsertedSession) {
+    let sink = RecordingSink::new();
+    let uuid = Uuid::new_v4();
+    let data = WebSocketTokenData::new("kowner0000".to_owned(), Some("secret".to_owned()));
+    let inserted = server
+        .insert_session(
+            uuid,
+            sink.clone(),
+            None,
+            data,
+            CancellationToken::new(),
+            std::convert::identity,
+        )
+        .await;
+    server.transition(&uuid, SessionState::Ready).await;
+    server
+        .subscribe_to_event(&uuid, WebSocketSubscriptionType::Names)
+        .await
+        .unwrap();
+
+    (uuid, sink, inserted)
+}
+
+async fn last_message(sink: &RecordingSink) -> serde_json::Value {
+    time::sleep(Duration::from_millis(50)).await;
+    sink.messages().last().cloned().expect("Nothing was sent")
+}
+
+#[actix_web::test]
+async fn sessions_hear_about_moderation() {
+    let names = MemoryNameStore::default();
+    names
+        .put(&NameRecord::new("example", "kowner0000"))
+        .unwrap();
+    let server = WebSocketServer::new().with_name_store(names);
+    let (uuid, sink, _inserted) = owner_session(&server).await;
+    let outbound = server.outbound(&uuid).await.unwrap();
+
+    server.seize_name("example", None).await.unwrap().unwrap();
+    let event = last_message(&sink).await;
+    assert_eq!(event["event"], "name");
+    assert_eq!(event["name"]["seized"], true);
+
+    let update = serde_json::json!({ "id": 1, "type": "update_name", "name": "example", "a": "x" });
+    ws::handle_text(
+        &server,
+        &outbound,
+        uuid,
+        "kowner0000",
+        "",
+        &update.to_string(),
+    )
+    .await;
+    assert_eq!(last_message(&sink).await["error"], "name_seized");
+
+    server
+        .lock_address("kowner0000", Some("Fraud".to_owned()))
+        .await
+        .unwrap();
+    let event = last_message(&sink).await;
+    assert_eq!(event["event"], "address_locked");
+    assert_eq!(event["reason"], "Fraud");
+    // Told why, then shown out
+    assert!(server.session_address(&uuid).await.is_none());
+
+    // Nor can anyone else send from a locked address
+    server
+        .lock_address(&address::from_private_key("secret"), None)
+        .await
+        .unwrap();
+    let session =
+        TestSession::connect(&server, WebSocketTokenData::guest(), SessionState::Ready).await;
+    let transaction = serde_json::json!({
+        "id": 2,
+        "type": "make_transaction",
+        "privatekey": "secret",
+        "to": "kabcdef123",
+        "amount": 1,
+    });
+    assert_eq!(session.send(transaction).await["error"], "address_locked");
+}
+
+#[actix_web::test]
+async fn locks_outlive_the_server() {
+    let locks = MemoryLockStore::default();
+    let server = WebSocketServer::new()
+        .with_lock_store(locks.clone())
+        .unwrap();
+    server
+        .lock_address("kfraud0000", Some("Fraud".to_owned()))
+        .await
+        .unwrap();
+    server.lock_address("kunlocked0", None).await.unwrap();
+    server.unlock_address("kunlocked0").await.unwrap();
+    drop(server);
+
+    let server = WebSocketServer::new().with_lock_store(locks).unwrap();
+    assert!(server.is_address_locked("kfraud0000"));
+    assert!(!server.is_address_locked("kunlocked0"));
+}
+
+#[test]
+fn sled_store_keeps_locks_across_reopening() {
+    let path = std::env::temp_dir().join(format!("lock-store-{}", Uuid::new_v4()));
+
+    let store = SledLockStore::open(&path).unwrap();
+    store.lock("kfraud0000", Some("Fraud")).unwrap();
+    store.lock("kquiet0000", None).unwrap();
+    store.lock("kunlocked0", None).unwrap();
+    store.unlock("kunlocked0").unwrap();
+    drop(store);
+
+    // Give the previous handle's background flusher time to let go of the lock
+    let store = (0..50)
+        .find_map(|_| {
+            SledLockStore::open(&path)
+                .inspect_err(|_| std::thread::sleep(Duration::from_millis(20)))
+                .ok()
+        })
+        .unwrap();
+    assert_eq!(
+        store.load().unwrap(),
+        [
+            ("kfraud0000".to_owned(), Some("Fraud".to_owned())),
+            ("kquiet0000".to_owned(), None),
+        ]
+        .into()
+    );
+    drop(store);
+
+    let _ = std::fs::remove_dir_all(path);
+}
+
+#[actix_web::test]
+async fn logins_transactions_and_bans_are_audited() {
+    let audit = RecordingAuditSink::default();
+    let balances = MemoryBalanceStore::default();
+    let address = address::from_private_key("hunter2");
+    balances
+        .credit(&address, 5, BalanceChangeReason::Reward)
+        .unwrap();
+    let server = WebSocketServer::new()
+        .with_audit_sink(audit.clone())
+        .with_balance_store(balances);
+    let session =
+        TestSession::connect(&server, WebSocketTokenData::guest(), SessionState::Ready).await;
+
+    let login = serde_json::json!({ "id": 1, "type": "login", "privatekey": "hunter2" });
+    assert_eq!(session.send(login.clone()).await["ok"], true);
+    let transaction = serde_json::json!({
+        "id": 2,
+        "type": "make_transaction",
+        "privatekey": "hunter2",
+        "to": "kabcdef123",
+        "amount": 2,
+    });
+    assert_eq!(session.send(transaction).await["ok"], true);
+    let logout = serde_json::json!({ "id": 3, "type": "logout" });
+    assert_eq!(session.send(logout).await["ok"], true);
+    assert_eq!(session.send(login).await["ok"], true);
+
+    server
+        .reload_config(RuntimeConfig {
+            banned_addresses: [address.clone()].into(),
+            ..Default::default()
+        })
+        .await;
+    assert!(server.session_address(&session.uuid()).await.is_none());
+
+    assert_eq!(
+        *audit.0.lock().unwrap(),
+        [
+            AuditAction::Login,
+            AuditAction::Transaction {
+                to: "kabcdef123".to_owned(),
+                amount: 2,
+            },
+            AuditAction::Logout,
+            AuditAction::Login,
+            AuditAction::Disconnect,
+            AuditAction::Ban { reason: None },
+        ]
+    );
+}
+
+#[actix_web::test]
+async fn disconnecting_after_logging_out_is_not_audited_again() {
+    let audit = RecordingAuditSink::default();
+    let server = WebSocketServer::new().with_audit_sink(audit.clone());
+    let session =
+        TestSession::connect(&server, WebSocketTokenData::guest(), SessionState::Ready).await;
+
+    let login = serde_json::json!({ "id": 1, "type": "login", "privatekey": "hunter2" });
+    assert_eq!(session.send(login).await["ok"], true);
+    let logout = serde_json::json!({ "id": 2, "type": "logout" });
+    assert_eq!(session.send(logout).await["ok"], true);
+    server.cleanup_session(&session.uuid()).await;
+
+    assert_eq!(
+        *audit.0.lock().unwrap(),
+        [AuditAction::Login, AuditAction::Logout]
+    );
+}
@@ -0,0 +1,182 @@
+//! Names can be looked up, and repointed by their owner, with the change published to `names`
+//! and `ownNames` subscribers.
+#![cfg(feature = "krist")]
+
+use std::time::Duration;
+
+use actix_web::rt::time;
+use actix_ws_fuckery::balances::{BalanceStore, MemoryBalanceStore};
+use actix_ws_fuckery::config::{EconomyConfig, WebSocketServerConfig};
+use actix_ws_fuckery::models::websocket::{
+    WebSocketSubscriptionType, WebSocketTokenData, state::SessionState,
+};
+use actix_ws_fuckery::names::{MemoryNameStore, NameRecord, NameStore};
+use actix_ws_fuckery::test_utils::TestSession;
+use actix_ws_fuckery::ws::WebSocketServer;
+
+const OWNER: &str = "kowner0000";
+
+fn server() -> (WebSocketServer, MemoryNameStore) {
+    let store = MemoryNameStore::default();
+    store.put(&NameRecord::new("example", OWNER)).unwrap();
+
+    (WebSocketServer::new().with_name_store(store.clone()), store)
+}
+
+async fn connect(
+    server: &WebSocketServer,
+    address: &str,
+    subscriptions: &[WebSocketSubscriptionType],
+) -> TestSession {
+    let data = WebSocketTokenData::new(address.to_owned(), Some("secret".to_owned()));
+    let session = TestSession::connect(server, data, SessionState::Ready).await;
+    let uuid = session.uuid();
+    for topic in [
+        WebSocketSubscriptionType::OwnTransactions,
+        WebSocketSubscriptionType::Blocks,
+    ] {
+        server.unsubscribe_from_event(&uuid, &topic).await;
+    }
+    for topic in subscriptions {
+        server
+            .subscribe_to_event(&uuid, topic.clone())
+            .await
+            .unwrap();
+    }
+
+    session
+}
+
+fn name_events(session: &TestSession) -> Vec<serde_json::Value> {
+    session
+        .messages()
+        .into_iter()
+        .filter(|x| x["event"] == "name")
+        .collect()
+}
+
+#[actix_web::test]
+async fn names_can_be_looked_up() {
+    let (server, _) = server();
+    let session = connect(&server, "kguest0000", &[]).await;
+
+    let message = serde_json::json!({ "id": 1, "type": "get_name", "name": "example.kst" });
+    let reply = session.send(message).await;
+    assert_eq!(reply["responding_to"], "get_name");
+    assert_eq!(reply["name"]["name"], "example");
+    assert_eq!(reply["name"]["owner"], OWNER);
+
+    let message = serde_json::json!({ "id": 2, "type": "get_name", "name": "missing" });
+    let reply = session.send(message).await;
+    assert_eq!(reply["error"], "name_not_found");
+}
+
+#[actix_web::test]
+async fn owner_can_update_and_subscribers_hear_of_it() {
+    let (server, store) = server();
+    let owner = connect(&server, OWNER, &[WebSocketSubscriptionType::OwnNames]).await;
+    let watcher = connect(&server, "kwatcher00", &[WebSocketSubscriptionType::Names]).await;
+    let bystander = connect(
+        &server,
+        "kbystander",
+        &[WebSocketSubscriptionType::OwnNames],
+    )
+    .await;
+
+    let message = serde_json::json!({
+        "id": 1,
+        "type": "update_name",
+        "name": "example",
+        "a": "https://example.com",
+    });
+    let reply = owner.send(message).await;
+    assert_eq!(reply["ok"], true, "{reply}");
+    assert_eq!(reply["name"]["a"], "https://example.com");
+
+    let record = store.get("example").unwrap().unwrap();
+    assert_eq!(record.a.as_deref(), Some("https://example.com"));
+    assert!(record.updated.is_some());
+
+    time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(name_events(&owner).len(), 1);
+    assert_eq!(name_events(&watcher).len(), 1);
+    assert_eq!(name_events(&watcher)[0]["name"]["a"], "https://example.com");
+    assert!(name_events(&bystander).is_empty());
+}
+
+#[actix_web::test]
+async fn only_the_owner_can_update() {
+    let (server, store) = server();
+    let session = connect(&server, "kthief0000", &[]).await;
+
+    let message =
+        serde_json::json!({ "id": 1, "type": "update_name", "name": "example", "a": "x" });
+    let reply = session.send(message).await;
+    assert_eq!(reply["ok"], false);
+    assert_eq!(reply["error"], "not_name_owner");
+    assert_eq!(store.get("example").unwrap().unwrap().a, None);
+}
+
+#[actix_web::test]
+async fn a_records_are_validated() {
+    let (server, _) = server();
+    let owner = connect(&server, OWNER, &[]).await;
+
+    let message = serde_json::json!({
+        "id": 1,
+        "type": "update_name",
+        "name": "example",
+        "a": "a".repeat(256),
+    });
+    let reply = owner.send(message).await;
+    assert_eq!(reply["error"], "invalid_parameter");
+    assert_eq!(reply["parameter"], "a");
+}
+
+#[actix_web::test]
+async fn registering_a_name_costs_the_configured_amount() {
+    let store = MemoryNameStore::default();
+    store.put(&NameRecord::new("example", OWNER)).unwrap();
+    let balances = MemoryBalanceStore::default();
+    let server = WebSocketServer::with_config(WebSocketServerConfig {
+        economy: EconomyConfig {
+            starting_balance: 150,
+            name_cost: 100,
+            ..Default::default()
+        },
+        ..Default::default()
+    })
+    .with_name_store(store.clone())
+    .with_balance_store(balances.clone());
+    let session = connect(&server, "kbuyer0000", &[]).await;
+
+    let register = |id: usize, name: &str| serde_json::json!({ "id": id, "type": "register_name", "name": name });
+    let reply = session.send(register(1, "example")).await;
+    assert_eq!(reply["error"], "name_taken", "{reply}");
+    // Refunded, on top of the starting balance
+    assert_eq!(balances.get("kbuyer0000").unwrap(), 150);
+
+    let reply = session.send(register(2, "fresh.kst")).await;
+    assert_eq!(reply["ok"], true, "{reply}");
+    assert_eq!(reply["name"]["name"], "fresh");
+    assert_eq!(reply["name"]["owner"], "kbuyer0000");
+    assert_eq!(store.get("fresh").unwrap().unwrap().owner, "kbuyer0000");
+    assert_eq!(balances.get("kbuyer0000").unwrap(), 50);
+
+    let reply = session.send(register(3, "another")).await;
+    assert_eq!(reply["error"], "insufficient_funds", "{reply}");
+    assert!(store.get("another").unwrap().is_none());
+}
+
+#[actix_web::test]
+async fn guests_cannot_register_names() {
+    let server = WebSocketServer::new()
+        .with_name_store(MemoryNameStore::default())
+        .with_balance_store(MemoryBalanceStore::default());
+    let session =
+        TestSession::connect(&server, WebSocketTokenData::guest(), SessionState::Ready).await;
+
+    let message = serde_json::json!({ "id": 1, "type": "register_name", "name": "mine" });
+    let reply = session.send(message).await;
+    assert_eq!(reply["error"], "not_logged_in", "{reply}");
+}
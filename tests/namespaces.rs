@@ -0,0 +1,97 @@
+//! Namespaces share a process but nothing else: sessions and events stay within the namespace
+//! their API key picked.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use actix_web::{http::StatusCode, rt::time};
+use actix_ws_fuckery::client::{GatewayClient, GatewayClientConfig, Incoming};
+use actix_ws_fuckery::config::{NamespaceConfig, WebSocketServerConfig};
+use actix_ws_fuckery::models::websocket::WebSocketSubscriptionType;
+use actix_ws_fuckery::test_utils::TestServer;
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+async fn start() -> TestServer {
+    TestServer::start_with_config(WebSocketServerConfig {
+        namespaces: HashMap::from([
+            ("alpha".to_owned(), NamespaceConfig::new("alpha-key")),
+            ("beta".to_owned(), NamespaceConfig::new("beta-key")),
+        ]),
+        ..Default::default()
+    })
+    .await
+    .unwrap()
+}
+
+async fn connect(server: &TestServer, api_key: &str) -> GatewayClient {
+    let config = GatewayClientConfig {
+        api_key: Some(api_key.to_owned()),
+        ..server.client_config()
+    };
+    GatewayClient::connect(config).await.unwrap()
+}
+
+#[actix_web::test]
+async fn sessions_land_in_their_namespace() {
+    let server = start().await;
+    let _alpha = connect(&server, "alpha-key").await;
+    let _beta = connect(&server, "beta-key").await;
+    let _root = server.connect().await.unwrap();
+
+    let alpha = server.server().namespace("alpha").unwrap();
+    let beta = server.server().namespace("beta").unwrap();
+    assert_eq!(alpha.session_count().await, 1);
+    assert_eq!(beta.session_count().await, 1);
+    assert_eq!(server.server().session_count().await, 1);
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn events_do_not_leak_between_namespaces() {
+    let server = start().await;
+    let mut alpha = connect(&server, "alpha-key").await;
+    let mut beta = connect(&server, "beta-key").await;
+    for client in [&mut alpha, &mut beta] {
+        client
+            .subscribe(WebSocketSubscriptionType::Names)
+            .await
+            .unwrap();
+    }
+
+    let event = serde_json::json!({ "type": "event", "event": "name", "name": "test" });
+    server
+        .server()
+        .namespace("alpha")
+        .unwrap()
+        .publish(WebSocketSubscriptionType::Names, event.to_string())
+        .await
+        .unwrap();
+
+    let received = time::timeout(TIMEOUT, alpha.recv()).await.unwrap();
+    let Ok(Incoming::Event(received)) = received else {
+        panic!("Expected an event, got {received:?}");
+    };
+    assert_eq!(received, event);
+
+    let nothing = time::timeout(Duration::from_millis(200), beta.recv()).await;
+    assert!(nothing.is_err(), "Other namespace got {nothing:?}");
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn unknown_api_key_is_refused() {
+    let server = start().await;
+
+    let response = awc::Client::default()
+        .post(format!("{}/ws/start", server.base_url()))
+        .insert_header(("X-API-Key", "gamma-key"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    server.stop().await;
+}
@@ -0,0 +1,111 @@
+//! Control frames jump ahead of everything else, but a client that stops reading them can't make
+//! them pile up without end.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::rt::time;
+use actix_ws::{CloseCode, CloseReason, Closed};
+use actix_ws_fuckery::config::SlowConsumerConfig;
+use actix_ws_fuckery::ws::{
+    outbound::OutboundQueue,
+    sink::{RecordedFrame, RecordingSink, SessionSink},
+};
+use bytes::Bytes;
+use bytestring::ByteString;
+use tokio::sync::Semaphore;
+
+/// Records frames, but only writes one for each permit let through the gate.
+struct GatedSink {
+    gate: Arc<Semaphore>,
+    sink: RecordingSink,
+}
+
+impl GatedSink {
+    async fn pass(&self) {
+        self.gate.acquire().await.unwrap().forget();
+    }
+}
+
+impl SessionSink for GatedSink {
+    async fn text(&mut self, msg: ByteString) -> Result<(), Closed> {
+        self.pass().await;
+        self.sink.text(msg).await
+    }
+
+    async fn binary(&mut self, bytes: Bytes) -> Result<(), Closed> {
+        self.pass().await;
+        self.sink.binary(bytes).await
+    }
+
+    async fn ping(&mut self, bytes: Bytes) -> Result<(), Closed> {
+        self.pass().await;
+        self.sink.ping(bytes).await
+    }
+
+    async fn pong(&mut self, bytes: Bytes) -> Result<(), Closed> {
+        self.pass().await;
+        self.sink.pong(bytes).await
+    }
+
+    async fn close(self, reason: Option<CloseReason>) -> Result<(), Closed> {
+        self.sink.close(reason).await
+    }
+}
+
+/// A queue whose writer is stuck on a ping until permits are added to the returned gate.
+async fn stalled() -> (OutboundQueue, Arc<Semaphore>, RecordingSink) {
+    let gate = Arc::new(Semaphore::new(0));
+    let sink = RecordingSink::new();
+    let gated = GatedSink {
+        gate: gate.clone(),
+        sink: sink.clone(),
+    };
+    let queue = OutboundQueue::spawn(gated, SlowConsumerConfig::default(), Arc::default());
+
+    queue.ping(Bytes::from_static(b"0")).unwrap();
+    time::sleep(Duration::from_millis(50)).await;
+    (queue, gate, sink)
+}
+
+#[actix_web::test]
+async fn only_the_latest_pong_is_written() {
+    let (queue, gate, sink) = stalled().await;
+
+    for n in ["1", "2", "3"] {
+        queue.pong(Bytes::from(n)).unwrap();
+    }
+    gate.add_permits(10);
+    time::sleep(Duration::from_millis(50)).await;
+
+    assert_eq!(
+        sink.frames(),
+        [
+            RecordedFrame::Ping(Bytes::from_static(b"0")),
+            RecordedFrame::Pong(Bytes::from_static(b"3")),
+        ]
+    );
+}
+
+#[actix_web::test]
+async fn sessions_not_reading_control_frames_are_closed() {
+    let (queue, gate, sink) = stalled().await;
+
+    let refused = (0..1000).position(|_| queue.ping(Bytes::from_static(b"1")).is_err());
+    assert!(refused.is_some_and(|x| x < 100), "Queued {refused:?} pings");
+    gate.add_permits(1);
+    time::sleep(Duration::from_millis(50)).await;
+
+    let reason = CloseReason {
+        code: CloseCode::Policy,
+        description: Some("SlowConsumer".to_owned()),
+    };
+    assert_eq!(
+        sink.frames(),
+        [
+            RecordedFrame::Ping(Bytes::from_static(b"0")),
+            RecordedFrame::Close(Some(reason)),
+        ]
+    );
+    assert!(queue.pong(Bytes::from_static(b"2")).is_err());
+}
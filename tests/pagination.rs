@@ -0,0 +1,120 @@
+//! Every list clients page through comes back in the same shape, with page sizes capped.
+
+use actix_ws_fuckery::config::WebSocketServerConfig;
+use actix_ws_fuckery::journal::JournalConfig;
+use actix_ws_fuckery::models::websocket::WebSocketSubscriptionType;
+use actix_ws_fuckery::pagination::{
+    DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE, PageQuery, Paginated, page_size,
+};
+use actix_ws_fuckery::test_utils::TestServer;
+use uuid::Uuid;
+
+#[test]
+fn pages_are_cut_from_lists() {
+    let query = PageQuery {
+        limit: Some(3),
+        offset: 4,
+    };
+    let page = Paginated::from_items(0..10, &query);
+    assert_eq!(page, Paginated::new(10, 4, vec![4, 5, 6]));
+    assert_eq!(page.count, 3);
+    assert!(page.has_more());
+
+    let query = PageQuery {
+        limit: None,
+        offset: 8,
+    };
+    let page = Paginated::from_items(0..10, &query);
+    assert_eq!(page.items, [8, 9]);
+    assert!(!page.has_more());
+
+    let page = Paginated::from_items(
+        0..10,
+        &PageQuery {
+            limit: None,
+            offset: 20,
+        },
+    );
+    assert_eq!((page.total, page.count), (10, 0));
+}
+
+#[test]
+fn page_sizes_are_capped() {
+    assert_eq!(page_size(None), DEFAULT_PAGE_SIZE);
+    assert_eq!(page_size(Some(7)), 7);
+    assert_eq!(page_size(Some(usize::MAX)), MAX_PAGE_SIZE);
+
+    let query = PageQuery {
+        limit: Some(MAX_PAGE_SIZE * 2),
+        offset: 0,
+    };
+    let page = Paginated::from_items(0..MAX_PAGE_SIZE * 3, &query);
+    assert_eq!(page.count, MAX_PAGE_SIZE);
+    assert_eq!(page.total, MAX_PAGE_SIZE * 3);
+}
+
+#[test]
+fn pages_serialize_the_same_everywhere() {
+    let page = Paginated::new(5, 2, vec!["a", "b"]).map(str::to_uppercase);
+    assert_eq!(
+        serde_json::to_value(&page).unwrap(),
+        serde_json::json!({ "total": 5, "count": 2, "offset": 2, "items": ["A", "B"] })
+    );
+}
+
+#[actix_web::test]
+async fn events_are_paged() {
+    let path = std::env::temp_dir().join(format!("pagination-journal-{}", Uuid::new_v4()));
+    let server = TestServer::start_with(WebSocketServerConfig::default(), |server| {
+        server
+            .with_journal(JournalConfig::new(path.to_string_lossy()))
+            .unwrap()
+    })
+    .await
+    .unwrap();
+    for height in 0..5 {
+        let event = serde_json::json!({ "event": "block", "block": { "height": height } });
+        server
+            .server()
+            .publish(WebSocketSubscriptionType::Blocks, event.to_string())
+            .await
+            .unwrap();
+    }
+
+    let get = async |query: &str| -> serde_json::Value {
+        awc::Client::default()
+            .get(format!("{}/events?{query}", server.base_url()))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap()
+    };
+    let seqs = |page: &serde_json::Value| -> Vec<u64> {
+        page["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|x| x["seq"].as_u64().unwrap())
+            .collect()
+    };
+
+    let all = seqs(&get("").await);
+    assert!(all.len() >= 4, "{all:?}");
+
+    let page = get("limit=2&offset=1").await;
+    assert_eq!(page["ok"], true);
+    assert_eq!(page["total"], all.len());
+    assert_eq!(page["count"], 2);
+    assert_eq!(page["offset"], 1);
+    assert_eq!(seqs(&page), all[1..3]);
+
+    // Events already seen aren't counted
+    let page = get(&format!("since_seq={}", all[2])).await;
+    assert_eq!(page["total"], all.len() - 3);
+    assert_eq!(seqs(&page), all[3..]);
+
+    server.stop().await;
+    let _ = std::fs::remove_dir_all(path);
+}
@@ -0,0 +1,398 @@
+//! Property tests for serialize → deserialize round-trips over the protocol types.
+//!
+//! Comparisons go through `serde_json::Value`, as the message types don't implement `PartialEq`.
+
+use std::collections::HashMap;
+
+use actix_ws_fuckery::filter::EventFilter;
+#[cfg(feature = "krist")]
+use actix_ws_fuckery::journal::Order;
+use actix_ws_fuckery::models::secret::Secret;
+use actix_ws_fuckery::models::websocket::{
+    SubscriptionStats, WebSocketSubscriptionType,
+    messages::{WebSocketMessage, WebSocketMessageInner, WebSocketMessageResponse},
+};
+#[cfg(feature = "krist")]
+use actix_ws_fuckery::names::NameRecord;
+#[cfg(feature = "krist")]
+use actix_ws_fuckery::pagination::Paginated;
+use proptest::prelude::*;
+use proptest::{collection::vec, option, sample::select, strategy::Union};
+use serde_json::{Map, Value, json};
+
+/// Top-level keys the envelope and tags own, which flattened payloads must never produce.
+const RESERVED_KEYS: &[&str] = &["ok", "id", "trace_id", "ref", "type", "responding_to"];
+
+fn string() -> impl Strategy<Value = String> {
+    "[akZ09 \"\\\\\né🦀{]{0,12}"
+}
+
+fn key() -> impl Strategy<Value = String> {
+    string()
+        .prop_map(|x| format!("k{x}"))
+        .prop_filter("reserved key", |key| !RESERVED_KEYS.contains(&key.as_str()))
+}
+
+fn object_of(value: impl Strategy<Value = Value>) -> impl Strategy<Value = Map<String, Value>> {
+    vec((key(), value), 0..4).prop_map(|entries| entries.into_iter().collect())
+}
+
+/// Arbitrary non-null JSON, as `null` doesn't survive the `Option` fields it ends up in.
+fn value(depth: u32) -> BoxedStrategy<Value> {
+    let leaf = prop_oneof![
+        any::<bool>().prop_map(Value::Bool),
+        any::<u32>().prop_map(|x| json!(x)),
+        (-1000.0..1000.0_f64).prop_map(|x| json!(x)),
+        string().prop_map(Value::String),
+    ];
+    leaf.prop_recursive(depth, 16, 4, |inner| {
+        prop_oneof![
+            vec(inner.clone(), 0..4).prop_map(Value::Array),
+            object_of(inner).prop_map(Value::Object),
+        ]
+    })
+    .boxed()
+}
+
+fn object(depth: u32) -> impl Strategy<Value = Map<String, Value>> {
+    object_of(value(depth))
+}
+
+fn topic() -> impl Strategy<Value = WebSocketSubscriptionType> {
+    select(WebSocketSubscriptionType::ALL)
+}
+
+fn secret() -> impl Strategy<Value = Secret> {
+    string().prop_map(Secret::from)
+}
+
+fn strings() -> impl Strategy<Value = Vec<String>> {
+    vec(string(), 0..4)
+}
+
+fn size() -> impl Strategy<Value = usize> {
+    any::<u32>().prop_map(|x| x as usize)
+}
+
+#[cfg(feature = "krist")]
+fn order() -> impl Strategy<Value = Order> {
+    select(&[Order::Asc, Order::Desc][..])
+}
+
+#[cfg(feature = "krist")]
+prop_compose! {
+    fn name()(
+        name in string(),
+        owner in string(),
+        a in option::of(string()),
+        registered in any::<u64>(),
+        updated in option::of(any::<u64>()),
+        seized in any::<bool>(),
+    ) -> NameRecord {
+        NameRecord { name, owner, a, registered, updated, seized }
+    }
+}
+
+#[cfg(feature = "krist")]
+prop_compose! {
+    fn page()(
+        total in size(),
+        offset in any::<u16>(),
+        items in vec(value(2), 0..4),
+    ) -> Paginated<Value> {
+        Paginated::new(total, offset.into(), items)
+    }
+}
+
+fn response() -> impl Strategy<Value = WebSocketMessageResponse> {
+    let stats = (any::<u32>(), any::<u32>()).prop_map(|(delivered, dropped)| SubscriptionStats {
+        delivered: delivered.into(),
+        dropped: dropped.into(),
+    });
+    let variants = vec![
+        #[cfg(feature = "krist")]
+        size()
+            .prop_map(|work| WebSocketMessageResponse::Work { work })
+            .boxed(),
+        #[cfg(feature = "krist")]
+        value(2)
+            .prop_map(|transaction| WebSocketMessageResponse::MakeTransaction { transaction })
+            .boxed(),
+        value(2)
+            .prop_map(|valid_subscription_levels| {
+                WebSocketMessageResponse::GetValidSubscriptionLevels {
+                    valid_subscription_levels,
+                }
+            })
+            .boxed(),
+        #[cfg(feature = "krist")]
+        value(2)
+            .prop_map(|address| WebSocketMessageResponse::Address { address })
+            .boxed(),
+        (any::<bool>(), option::of(value(2)))
+            .prop_map(|(is_guest, address)| WebSocketMessageResponse::Me { is_guest, address })
+            .boxed(),
+        strings()
+            .prop_map(
+                |subscription_level| WebSocketMessageResponse::GetSubscriptionLevel {
+                    subscription_level,
+                },
+            )
+            .boxed(),
+        vec((topic(), stats), 0..4)
+            .prop_map(|stats| WebSocketMessageResponse::GetSubscriptionStats {
+                stats: stats.into_iter().collect::<HashMap<_, _>>(),
+            })
+            .boxed(),
+        any::<bool>()
+            .prop_map(|is_guest| WebSocketMessageResponse::Logout { is_guest })
+            .boxed(),
+        (any::<bool>(), value(2))
+            .prop_map(|(is_guest, address)| WebSocketMessageResponse::Login { is_guest, address })
+            .boxed(),
+        strings()
+            .prop_map(|subscription_level| WebSocketMessageResponse::Subscribe {
+                subscription_level,
+            })
+            .boxed(),
+        strings()
+            .prop_map(|subscription_level| WebSocketMessageResponse::Unsubscribe {
+                subscription_level,
+            })
+            .boxed(),
+        #[cfg(feature = "krist")]
+        page()
+            .prop_map(|page| WebSocketMessageResponse::GetTransactions { page })
+            .boxed(),
+        #[cfg(feature = "krist")]
+        page()
+            .prop_map(|page| WebSocketMessageResponse::GetBlocks { page })
+            .boxed(),
+        #[cfg(feature = "krist")]
+        name()
+            .prop_map(|name| WebSocketMessageResponse::GetName { name })
+            .boxed(),
+        #[cfg(feature = "krist")]
+        name()
+            .prop_map(|name| WebSocketMessageResponse::RegisterName { name })
+            .boxed(),
+        #[cfg(feature = "krist")]
+        name()
+            .prop_map(|name| WebSocketMessageResponse::UpdateName { name })
+            .boxed(),
+        #[cfg(feature = "krist")]
+        (string(), any::<u32>(), any::<u64>())
+            .prop_map(
+                |(address, reward, balance)| WebSocketMessageResponse::SubmitBlock {
+                    address,
+                    reward,
+                    balance,
+                },
+            )
+            .boxed(),
+        strings()
+            .prop_map(|tags| WebSocketMessageResponse::Tag { tags })
+            .boxed(),
+    ];
+    Union::new(variants)
+}
+
+fn inner() -> impl Strategy<Value = WebSocketMessageInner> {
+    let variants = vec![
+        object(2)
+            .prop_map(|motd| WebSocketMessageInner::Hello {
+                motd: Value::Object(motd),
+            })
+            .boxed(),
+        (string(), object(1))
+            .prop_map(|(server_time, fields)| WebSocketMessageInner::Keepalive {
+                server_time,
+                fields,
+            })
+            .boxed(),
+        (string(), string())
+            .prop_map(|(warning, message)| WebSocketMessageInner::Warning { warning, message })
+            .boxed(),
+        (string(), string(), option::of(string()))
+            .prop_map(|(error, message, parameter)| WebSocketMessageInner::Error {
+                error,
+                message,
+                parameter,
+            })
+            .boxed(),
+        vec(value(2), 0..4)
+            .prop_map(|events| WebSocketMessageInner::Batch { events })
+            .boxed(),
+        response()
+            .prop_map(|data| WebSocketMessageInner::Response { data })
+            .boxed(),
+        #[cfg(feature = "krist")]
+        Just(WebSocketMessageInner::Work).boxed(),
+        #[cfg(feature = "krist")]
+        (secret(), string(), any::<u32>(), option::of(string()))
+            .prop_map(|(private_key, to, amount, metadata)| {
+                WebSocketMessageInner::MakeTransaction {
+                    private_key,
+                    to,
+                    amount,
+                    metadata,
+                }
+            })
+            .boxed(),
+        Just(WebSocketMessageInner::GetValidSubscriptionLevels).boxed(),
+        #[cfg(feature = "krist")]
+        (string(), option::of(any::<bool>()))
+            .prop_map(|(address, fetch_names)| WebSocketMessageInner::Address {
+                address,
+                fetch_names,
+            })
+            .boxed(),
+        Just(WebSocketMessageInner::Me).boxed(),
+        Just(WebSocketMessageInner::GetSubscriptionLevel).boxed(),
+        Just(WebSocketMessageInner::GetSubscriptionStats).boxed(),
+        Just(WebSocketMessageInner::Logout).boxed(),
+        secret()
+            .prop_map(|private_key| WebSocketMessageInner::Login { private_key })
+            .boxed(),
+        (topic(), option::of(object(1)))
+            .prop_map(|(event, filter)| WebSocketMessageInner::Subscribe {
+                event,
+                filter: filter.map(EventFilter),
+            })
+            .boxed(),
+        topic()
+            .prop_map(|event| WebSocketMessageInner::Unsubscribe { event })
+            .boxed(),
+        (any::<u64>(), string())
+            .prop_map(|(wait_ms, reason)| WebSocketMessageInner::Reconnect { wait_ms, reason })
+            .boxed(),
+        #[cfg(feature = "krist")]
+        (option::of(string()), option::of(size()), size(), order())
+            .prop_map(
+                |(address, limit, offset, order)| WebSocketMessageInner::GetTransactions {
+                    address,
+                    limit,
+                    offset,
+                    order,
+                },
+            )
+            .boxed(),
+        #[cfg(feature = "krist")]
+        (option::of(size()), size(), order())
+            .prop_map(|(limit, offset, order)| WebSocketMessageInner::GetBlocks {
+                limit,
+                offset,
+                order,
+            })
+            .boxed(),
+        #[cfg(feature = "krist")]
+        string()
+            .prop_map(|name| WebSocketMessageInner::GetName { name })
+            .boxed(),
+        #[cfg(feature = "krist")]
+        string()
+            .prop_map(|name| WebSocketMessageInner::RegisterName { name })
+            .boxed(),
+        #[cfg(feature = "krist")]
+        (string(), option::of(string()))
+            .prop_map(|(name, a)| WebSocketMessageInner::UpdateName { name, a })
+            .boxed(),
+        #[cfg(feature = "krist")]
+        (string(), string())
+            .prop_map(|(address, nonce)| WebSocketMessageInner::SubmitBlock { address, nonce })
+            .boxed(),
+        strings()
+            .prop_map(|tags| WebSocketMessageInner::Tag { tags })
+            .boxed(),
+    ];
+    Union::new(variants)
+}
+
+prop_compose! {
+    fn message()(
+        ok in option::of(any::<bool>()),
+        id in option::of(size()),
+        trace_id in option::of(string()),
+        idempotency_key in option::of(string()),
+        r#type in inner(),
+    ) -> WebSocketMessage {
+        WebSocketMessage { ok, id, trace_id, idempotency_key, r#type }
+    }
+}
+
+/// Count the occurrences of `"key":` in serialized JSON, to catch keys written twice.
+fn key_count(json: &str, key: &str) -> usize {
+    json.matches(&format!("\"{key}\":")).count()
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(2000))]
+
+    #[test]
+    fn messages_round_trip(message in message()) {
+        let json = serde_json::to_string(&message).unwrap();
+
+        let parsed: WebSocketMessage = serde_json::from_str(&json)
+            .map_err(|e| TestCaseError::fail(format!("Failed to parse {json}: {e}")))?;
+        prop_assert_eq!(
+            serde_json::to_value(&parsed).unwrap(),
+            serde_json::from_str::<Value>(&json).unwrap(),
+            "{} changed after a round-trip",
+            json
+        );
+    }
+
+    #[test]
+    fn field_names_are_stable(message in message()) {
+        let json = serde_json::to_string(&message).unwrap();
+        let value: Value = serde_json::from_str(&json).unwrap();
+
+        prop_assert_eq!(&value["type"], message.r#type.kind(), "{}", json);
+        prop_assert_eq!(key_count(&json, "type"), 1, "{}", json);
+        prop_assert_eq!(value.get("ok").is_some(), message.ok.is_some(), "{}", json);
+        prop_assert_eq!(value.get("id").is_some(), message.id.is_some(), "{}", json);
+        prop_assert_eq!(
+            value.get("ref").is_some(),
+            message.idempotency_key.is_some(),
+            "{}",
+            json
+        );
+
+        match &message.r#type {
+            WebSocketMessageInner::Response { data } => {
+                prop_assert_eq!(&value["responding_to"], data.kind(), "{}", json);
+                prop_assert_eq!(key_count(&json, "responding_to"), 1, "{}", json);
+            }
+            WebSocketMessageInner::Login { .. } => {
+                prop_assert!(value.get("privatekey").is_some(), "{}", json);
+            }
+            #[cfg(feature = "krist")]
+            WebSocketMessageInner::MakeTransaction { .. } => {
+                prop_assert!(value.get("privatekey").is_some(), "{}", json);
+            }
+            #[cfg(feature = "krist")]
+            WebSocketMessageInner::Address { fetch_names, .. } => {
+                prop_assert_eq!(&value["fetchNames"], &json!(fetch_names), "{}", json);
+            }
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn hello_flattens_its_motd(motd in object(2)) {
+        let message = WebSocketMessage {
+            ok: Some(true),
+            id: None,
+            trace_id: None,
+            idempotency_key: None,
+            r#type: WebSocketMessageInner::Hello {
+                motd: Value::Object(motd.clone()),
+            },
+        };
+        let value = serde_json::to_value(&message).unwrap();
+
+        for (key, expected) in &motd {
+            prop_assert_eq!(&value[key], expected);
+        }
+    }
+}
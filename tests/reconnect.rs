@@ -0,0 +1,143 @@
+//! Clients find their own way back onto the gateway, backing off with jitter and following
+//! `reconnect` advisories, so they don't all come back at once after a drain or being turned away
+//! from a full server.
+
+use std::time::Duration;
+
+use actix_web::rt::time;
+use actix_ws_fuckery::client::{GatewayClient, GatewayClientConfig, Incoming, ReconnectPolicy};
+use actix_ws_fuckery::config::{RuntimeConfig, WebSocketServerConfig};
+use actix_ws_fuckery::models::websocket::WebSocketSubscriptionType;
+use actix_ws_fuckery::test_utils::TestServer;
+use futures::StreamExt;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Subscribe to names, have the server advise reconnecting, and check the client still gets name
+/// events on whichever session it ends up with.
+async fn follow_advisory(policy: ReconnectPolicy) {
+    let server = TestServer::start().await.unwrap();
+    let config = GatewayClientConfig {
+        reconnect: Some(policy),
+        ..GatewayClientConfig::new(server.base_url())
+    };
+    let mut client = GatewayClient::connect(config).await.unwrap();
+    client
+        .subscribe(WebSocketSubscriptionType::Names)
+        .await
+        .unwrap();
+    let first_token = client.resume_token();
+    let first_session = server.server().list_sessions().await[0].uuid;
+
+    let advisory = serde_json::json!({ "type": "reconnect", "wait_ms": 50, "reason": "test" });
+    server
+        .server()
+        .broadcast(advisory.to_string())
+        .await
+        .unwrap();
+
+    let event = serde_json::json!({ "type": "event", "event": "name", "name": "test" });
+    let publish = async {
+        // Only publish once the client has moved over to its new session
+        loop {
+            let sessions = server
+                .server()
+                .sessions_for_topic(&WebSocketSubscriptionType::Names)
+                .await;
+            if sessions.iter().any(|x| *x != first_session) {
+                break;
+            }
+            time::sleep(Duration::from_millis(10)).await;
+        }
+        server
+            .server()
+            .publish(WebSocketSubscriptionType::Names, event.to_string())
+            .await
+            .unwrap();
+    };
+
+    let (received, ()) = futures::join!(time::timeout(TIMEOUT, client.recv()), publish);
+    let Ok(Ok(Incoming::Event(received))) = received else {
+        panic!("Expected an event, got {received:?}");
+    };
+    assert_eq!(received, event);
+    assert_ne!(client.resume_token(), first_token, "Never reconnected");
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn client_follows_reconnect_advisories() {
+    follow_advisory(ReconnectPolicy {
+        initial_delay: Duration::from_millis(10),
+        ..Default::default()
+    })
+    .await;
+}
+
+#[actix_web::test]
+async fn client_resubscribes_without_resuming() {
+    follow_advisory(ReconnectPolicy {
+        initial_delay: Duration::from_millis(10),
+        resume: false,
+        ..Default::default()
+    })
+    .await;
+}
+
+#[test]
+fn backoff_grows_exponentially_up_to_the_cap() {
+    let policy = ReconnectPolicy {
+        initial_delay: Duration::from_millis(100),
+        max_delay: Duration::from_secs(1),
+        multiplier: 2.0,
+        jitter: 0.0,
+        ..Default::default()
+    };
+    let delays: Vec<_> = (1..=6).map(|x| policy.delay(x).as_millis()).collect();
+    assert_eq!(delays, [100, 200, 400, 800, 1000, 1000]);
+
+    let policy = ReconnectPolicy {
+        jitter: 0.5,
+        ..policy
+    };
+    for _ in 0..100 {
+        let delay = policy.delay(3);
+        assert!(delay >= Duration::from_millis(200) && delay <= Duration::from_millis(400));
+    }
+}
+
+#[actix_web::test]
+async fn full_server_advises_connections_to_come_back() {
+    let server = TestServer::start_with_config(WebSocketServerConfig {
+        runtime: RuntimeConfig {
+            max_sessions: Some(1),
+            ..Default::default()
+        },
+        overload_reconnect_window: Duration::from_secs(1),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    // Issued while there was still room
+    let token = server.issue_token("guest", None).await;
+    let _client = server.connect().await.unwrap();
+
+    let url = format!("ws://{}/gateway/{token}", server.addr());
+    let (_, mut socket) = awc::Client::default().ws(url).connect().await.unwrap();
+    let Some(Ok(awc::ws::Frame::Text(advisory))) = socket.next().await else {
+        panic!("Expected a reconnect advisory");
+    };
+    let advisory: serde_json::Value = serde_json::from_slice(&advisory).unwrap();
+    assert_eq!(advisory["type"], "reconnect");
+    assert_eq!(advisory["reason"], "overloaded");
+    assert!(advisory["wait_ms"].as_u64().unwrap() < 1000);
+
+    let Some(Ok(awc::ws::Frame::Close(Some(reason)))) = socket.next().await else {
+        panic!("Expected a close frame");
+    };
+    assert_eq!(reason.code, awc::ws::CloseCode::Again);
+    assert_eq!(server.server().session_count().await, 1);
+
+    server.stop().await;
+}
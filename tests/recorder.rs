@@ -0,0 +1,179 @@
+//! Sessions' inbound frames can be recorded to a file and replayed through the handlers later.
+
+use std::time::Duration;
+
+use actix_ws_fuckery::config::WebSocketServerConfig;
+use actix_ws_fuckery::recorder::{
+    self, Frame, InboundFrame, Pacing, Recording, RecordingHeader, recording_path,
+};
+use actix_ws_fuckery::test_utils::TestServer;
+use actix_ws_fuckery::ws::{WebSocketServer, binary::BinaryHandler, sink::RecordedFrame};
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use uuid::Uuid;
+
+/// Sends every binary frame straight back.
+struct EchoBinaryHandler;
+
+impl BinaryHandler for EchoBinaryHandler {
+    fn on_binary<'a>(
+        &'a self,
+        _session: Uuid,
+        _address: &'a str,
+        payload: Bytes,
+    ) -> BoxFuture<'a, Option<Bytes>> {
+        Box::pin(async move { Some(payload) })
+    }
+}
+
+fn recording(frames: Vec<InboundFrame>) -> Recording {
+    Recording {
+        header: RecordingHeader::new(Uuid::new_v4(), "kguest0000", false),
+        frames,
+    }
+}
+
+fn text(at_ms: u64, message: serde_json::Value) -> InboundFrame {
+    InboundFrame {
+        at_ms,
+        frame: Frame::Text(message.to_string()),
+    }
+}
+
+fn replies(frames: &[RecordedFrame]) -> Vec<serde_json::Value> {
+    frames
+        .iter()
+        .filter_map(|frame| match frame {
+            RecordedFrame::Text(text) => serde_json::from_str(text).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+#[actix_web::test]
+async fn inbound_frames_are_recorded_without_private_keys() {
+    let dir = std::env::temp_dir().join(format!("recordings-{}", Uuid::new_v4()));
+    let server = TestServer::start_with_config(WebSocketServerConfig {
+        record_dir: Some(dir.clone()),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+
+    let token = server
+        .issue_token("krecorded0", Some("hunter2".to_owned()))
+        .await;
+    let mut socket = server.socket(token).await.unwrap();
+    assert_eq!(socket.next_text().await["type"], "hello");
+
+    let login = serde_json::json!({ "id": 1, "type": "login", "privatekey": "hunter2" });
+    assert_eq!(socket.send(login).await["ok"], false);
+    let subscribe = serde_json::json!({ "id": 2, "type": "subscribe", "event": "names" });
+    assert_eq!(socket.send(subscribe.clone()).await["ok"], true);
+
+    let recording = Recording::load(recording_path(&dir, token)).unwrap();
+    assert_eq!(recording.header.session, token);
+    assert_eq!(recording.header.address, "krecorded0");
+    assert!(recording.header.authenticated);
+
+    assert_eq!(recording.frames.len(), 2);
+    let Frame::Text(login) = &recording.frames[0].frame else {
+        panic!("Login wasn't recorded as text");
+    };
+    assert!(!login.contains("hunter2"), "{login}");
+    assert!(login.contains("\"type\":\"login\""), "{login}");
+    assert_eq!(
+        recording.frames[1].frame,
+        Frame::Text(subscribe.to_string())
+    );
+    assert!(recording.frames[0].at_ms <= recording.frames[1].at_ms);
+
+    drop(socket);
+    server.stop().await;
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[actix_web::test]
+async fn recordings_survive_a_round_trip_through_disk() {
+    let dir = std::env::temp_dir().join(format!("recordings-{}", Uuid::new_v4()));
+    let header = RecordingHeader::new(Uuid::new_v4(), "kguest0000", false);
+    let mut recorder = recorder::SessionRecorder::create(&dir, &header).unwrap();
+    recorder.record(Frame::Text("{}".to_owned())).unwrap();
+    recorder.record(Frame::Binary(vec![1, 2, 3])).unwrap();
+
+    let recording = Recording::load(recording_path(&dir, header.session)).unwrap();
+    assert_eq!(recording.header, header);
+    let frames: Vec<_> = recording.frames.into_iter().map(|x| x.frame).collect();
+    assert_eq!(
+        frames,
+        [Frame::Text("{}".to_owned()), Frame::Binary(vec![1, 2, 3])]
+    );
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[actix_web::test]
+async fn replay_feeds_frames_through_the_handlers() {
+    let server = WebSocketServer::new().with_binary_handler(EchoBinaryHandler);
+    let recording = recording(vec![
+        text(
+            0,
+            serde_json::json!({ "id": 1, "type": "subscribe", "event": "names" }),
+        ),
+        text(5, serde_json::json!({ "id": 2, "type": "bogus" })),
+        InboundFrame {
+            at_ms: 10,
+            frame: Frame::Binary(vec![4, 2]),
+        },
+    ]);
+
+    let frames = recorder::replay(&server, &recording, Pacing::Immediate).await;
+    let replies = replies(&frames);
+    assert_eq!(replies.len(), 2, "{replies:?}");
+    assert_eq!(replies[0]["id"], 1);
+    assert_eq!(replies[0]["ok"], true);
+    assert_eq!(replies[1]["id"], 2);
+    assert_eq!(replies[1]["ok"], false);
+    assert!(frames.contains(&RecordedFrame::Binary(vec![4, 2].into())));
+
+    // The replayed session is gone once it's done
+    assert!(server.outbound(&recording.header.session).await.is_none());
+}
+
+#[tokio::test(start_paused = true)]
+async fn replay_can_keep_the_recorded_gaps() {
+    let server = WebSocketServer::new();
+    let recording = recording(vec![
+        text(
+            0,
+            serde_json::json!({ "id": 1, "type": "subscribe", "event": "names" }),
+        ),
+        text(
+            30_000,
+            serde_json::json!({ "id": 2, "type": "unsubscribe", "event": "names" }),
+        ),
+    ]);
+
+    let started = tokio::time::Instant::now();
+    let frames = recorder::replay(&server, &recording, Pacing::Recorded).await;
+    assert!(started.elapsed() >= Duration::from_secs(30));
+    assert_eq!(replies(&frames).len(), 2);
+
+    let started = tokio::time::Instant::now();
+    recorder::replay(&server, &recording, Pacing::Immediate).await;
+    assert!(started.elapsed() < Duration::from_secs(30));
+}
+
+#[tokio::test]
+async fn bad_recordings_are_refused() {
+    let path = std::env::temp_dir().join(format!("recording-{}.jsonl", Uuid::new_v4()));
+    std::fs::write(&path, "").unwrap();
+    assert!(Recording::load(&path).is_err());
+
+    let header = serde_json::to_string(&RecordingHeader::new(Uuid::new_v4(), "k", false)).unwrap();
+    std::fs::write(&path, format!("{header}\nnot json\n")).unwrap();
+    let error = Recording::load(&path).unwrap_err();
+    assert!(format!("{error}").contains("line 2"), "{error}");
+
+    let _ = std::fs::remove_file(path);
+}
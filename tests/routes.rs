@@ -0,0 +1,206 @@
+//! Typed routes get their own request and response types, and go through the same checks,
+//! middleware, listeners and retry handling as the built-in message types.
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+use std::time::Duration;
+
+use actix_web::rt::time;
+use actix_ws_fuckery::models::websocket::{WebSocketTokenData, state::SessionState};
+use actix_ws_fuckery::policy::{Scope, ScopeList};
+use actix_ws_fuckery::test_utils::TestSession;
+use actix_ws_fuckery::ws::{
+    WebSocketServer,
+    middleware::{IncomingMessage, MessageBody, Rejection},
+    routes::{Route, RouteContext, Routes},
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+#[derive(Deserialize)]
+struct Echo {
+    text: String,
+}
+
+#[derive(Serialize)]
+struct EchoResponse {
+    text: String,
+    address: String,
+}
+
+impl Route for Echo {
+    const TYPE: &'static str = "echo";
+    type Response = EchoResponse;
+}
+
+/// Takes over the built-in `work`, needing a scope to send it.
+#[derive(Deserialize)]
+struct Work {}
+
+#[derive(Serialize)]
+struct WorkResponse {
+    work: usize,
+}
+
+impl Route for Work {
+    const TYPE: &'static str = "work";
+    const SCOPE: Scope = Scope::Transact;
+    type Response = WorkResponse;
+}
+
+/// Changes state, so retries with the same ref are answered from the first attempt.
+#[derive(Deserialize)]
+struct Transfer {}
+
+#[derive(Serialize)]
+struct TransferResponse {
+    attempt: usize,
+}
+
+impl Route for Transfer {
+    const TYPE: &'static str = "transfer";
+    const SCOPE: Scope = Scope::Transact;
+    type Response = TransferResponse;
+}
+
+fn server() -> WebSocketServer {
+    let routes = Routes::new()
+        .on(|context: RouteContext, echo: Echo| async move {
+            match echo.text.is_empty() {
+                true => Err(Rejection::new("empty_text", "Say something")),
+                false => Ok(EchoResponse {
+                    text: echo.text,
+                    address: context.address,
+                }),
+            }
+        })
+        .on(|_, _: Work| async { Ok(WorkResponse { work: 1 }) });
+
+    WebSocketServer::new().with_routes(routes)
+}
+
+/// Handle `message` as if a guest session with `scopes` sent it, returning the reply.
+async fn send(
+    server: &WebSocketServer,
+    scopes: ScopeList,
+    message: serde_json::Value,
+) -> serde_json::Value {
+    let mut data = WebSocketTokenData::new("guest".to_owned(), None);
+    data.scopes = scopes;
+    let session = TestSession::connect(server, data, SessionState::Ready).await;
+    session.send(message).await
+}
+
+#[actix_web::test]
+async fn routes_answer_with_their_response_type() {
+    let server = server();
+
+    let message = serde_json::json!({ "id": 3, "type": "echo", "text": "hi" });
+    let reply = send(&server, Scope::default_scopes(), message).await;
+    assert_eq!(reply["ok"], true);
+    assert_eq!(reply["id"], 3);
+    assert_eq!(reply["type"], "response");
+    assert_eq!(reply["responding_to"], "echo");
+    assert_eq!(reply["text"], "hi");
+    assert_eq!(reply["address"], "guest");
+}
+
+#[actix_web::test]
+async fn routes_can_reject_and_refuse_bad_requests() {
+    let server = server();
+
+    let message = serde_json::json!({ "id": 1, "type": "echo", "text": "" });
+    let reply = send(&server, Scope::default_scopes(), message).await;
+    assert_eq!(reply["ok"], false);
+    assert_eq!(reply["error"], "empty_text");
+
+    let message = serde_json::json!({ "id": 2, "type": "echo" });
+    let reply = send(&server, Scope::default_scopes(), message).await;
+    assert_eq!(reply["ok"], false);
+    assert_eq!(reply["id"], 2);
+    assert_eq!(reply["error"], "invalid_message");
+}
+
+#[actix_web::test]
+async fn routes_take_over_built_in_types_and_their_scopes() {
+    let server = server();
+
+    let message = serde_json::json!({ "id": 1, "type": "work" });
+    let reply = send(&server, Scope::default_scopes(), message.clone()).await;
+    assert_eq!(reply["work"], 1);
+
+    let reply = send(&server, [Scope::ReadOnly].into(), message).await;
+    assert_eq!(reply["ok"], false);
+    assert_eq!(reply["error"], "missing_scope");
+}
+
+#[actix_web::test]
+async fn middleware_and_listeners_see_routed_messages() {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let server = server()
+        .with_inbound_middleware(|incoming: &mut IncomingMessage| {
+            let MessageBody::Routed(payload) = &mut incoming.message else {
+                return Ok(());
+            };
+            match payload["text"].as_str() {
+                Some("nope") => Err(Rejection::new("no_thanks", "Not that")),
+                Some(text) => {
+                    payload["text"] = text.to_uppercase().into();
+                    Ok(())
+                }
+                None => Ok(()),
+            }
+        })
+        .on_route(move |session, echo: Arc<Echo>| {
+            let tx = tx.clone();
+            async move {
+                let _ = tx.send((session.address().to_owned(), echo.text.clone()));
+            }
+        });
+
+    let message = serde_json::json!({ "id": 1, "type": "echo", "text": "nope" });
+    let reply = send(&server, Scope::default_scopes(), message).await;
+    assert_eq!(reply["ok"], false);
+    assert_eq!(reply["id"], 1);
+    assert_eq!(reply["error"], "no_thanks");
+
+    let message = serde_json::json!({ "id": 2, "type": "echo", "text": "hi" });
+    let reply = send(&server, Scope::default_scopes(), message).await;
+    assert_eq!(reply["text"], "HI", "{reply}");
+
+    // Only the message that got past the middleware is heard about, as rewritten
+    let heard = time::timeout(Duration::from_secs(1), rx.recv()).await;
+    assert_eq!(
+        heard.unwrap().unwrap(),
+        ("guest".to_owned(), "HI".to_owned())
+    );
+    assert!(rx.try_recv().is_err());
+}
+
+#[actix_web::test]
+async fn state_changing_routes_replay_retries() {
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let counted = attempts.clone();
+    let routes = Routes::new().on(move |_: RouteContext, _: Transfer| {
+        let attempt = counted.fetch_add(1, Ordering::Relaxed) + 1;
+        async move { Ok(TransferResponse { attempt }) }
+    });
+    let server = WebSocketServer::new().with_routes(routes);
+    let data = WebSocketTokenData::new("guest".to_owned(), None);
+    let session = TestSession::connect(&server, data, SessionState::Ready).await;
+
+    let transfer =
+        |id: usize, key: &str| serde_json::json!({ "id": id, "type": "transfer", "ref": key });
+    let first = session.send(transfer(1, "abc")).await;
+    assert_eq!(first["attempt"], 1, "{first}");
+
+    let retry = session.send(transfer(2, "abc")).await;
+    assert_eq!(retry["attempt"], 1, "{retry}");
+    assert_eq!(retry["id"], 2);
+
+    let other = session.send(transfer(3, "def")).await;
+    assert_eq!(other["attempt"], 2, "{other}");
+    assert_eq!(attempts.load(Ordering::Relaxed), 2);
+}
@@ -0,0 +1,85 @@
+//! Inbound messages are sampled by type and size, and summarised for admins.
+
+use actix_web::http::StatusCode;
+use actix_ws_fuckery::config::WebSocketServerConfig;
+use actix_ws_fuckery::models::websocket::WebSocketSubscriptionType;
+use actix_ws_fuckery::sampler::{MessageSampler, MessageSummary, SIZE_BUCKETS};
+use actix_ws_fuckery::test_utils::TestServer;
+
+#[test]
+fn sizes_are_bucketed_by_type() {
+    let sampler = MessageSampler::new(1.0);
+    assert!(sampler.sample("me", 10));
+    assert!(sampler.sample("me", 64));
+    assert!(sampler.sample("me", 65));
+    assert!(sampler.sample("subscribe", 1024 * 1024));
+
+    let summary = sampler.summary();
+    assert_eq!(summary.size_buckets, SIZE_BUCKETS);
+
+    let me = &summary.types["me"];
+    assert_eq!(me.count, 3);
+    assert_eq!(me.total_bytes, 139);
+    assert_eq!(me.max_bytes, 65);
+    assert_eq!(me.sizes[..2], [2, 1]);
+
+    let subscribe = &summary.types["subscribe"];
+    assert_eq!(subscribe.sizes.last(), Some(&1));
+}
+
+#[test]
+fn nothing_is_sampled_at_zero() {
+    let sampler = MessageSampler::new(0.0);
+    assert!(!sampler.sample("me", 10));
+    assert!(sampler.summary().types.is_empty());
+}
+
+#[actix_web::test]
+async fn admins_can_read_the_summary() {
+    let server = TestServer::start_with_config(WebSocketServerConfig {
+        admin_token: Some("secret".to_owned()),
+        message_sample_rate: Some(1.0),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let mut client = server.connect().await.unwrap();
+    client
+        .subscribe(WebSocketSubscriptionType::Names)
+        .await
+        .unwrap();
+
+    let url = format!("{}/admin/messages", server.base_url());
+    let http = awc::Client::default();
+    let response = http.get(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let mut response = http.get(&url).bearer_auth("secret").send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let summary: MessageSummary = response.json().await.unwrap();
+    assert_eq!(summary.types["subscribe"].count, 1);
+    assert_eq!(server.server().metrics().snapshot().messages_sampled, 1);
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn summary_is_missing_without_sampling() {
+    let server = TestServer::start_with_config(WebSocketServerConfig {
+        admin_token: Some("secret".to_owned()),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+
+    let url = format!("{}/admin/messages", server.base_url());
+    let response = awc::Client::default()
+        .get(&url)
+        .bearer_auth("secret")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    server.stop().await;
+}
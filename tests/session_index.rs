@@ -0,0 +1,186 @@
+//! The address and topic indexes have to agree with the sessions themselves however they come and go.
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use actix_ws_fuckery::models::websocket::{WebSocketSubscriptionType, WebSocketTokenData};
+use actix_ws_fuckery::ws::{InsertedSession, WebSocketServer, sink::RecordingSink};
+use rand::{Rng, SeedableRng, rngs::StdRng, seq::IndexedRandom};
+use tokio::time;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+const ADDRESSES: &[&str] = &["guest", "kabc", "kdef", "kxyz"];
+const TASKS: u64 = 8;
+const OPERATIONS: usize = 300;
+
+async fn insert(
+    server: &WebSocketServer,
+    uuid: Uuid,
+    address: &str,
+    sink: RecordingSink,
+) -> InsertedSession {
+    server
+        .insert_session(
+            uuid,
+            sink,
+            None,
+            WebSocketTokenData::new(address.to_owned(), None),
+            CancellationToken::new(),
+            std::convert::identity,
+        )
+        .await
+}
+
+/// Check every address's and topic's index entry against a scan of the sessions.
+async fn assert_consistent(server: &WebSocketServer) {
+    let mut addresses: HashMap<String, HashSet<Uuid>> = HashMap::new();
+    let mut topics: HashMap<WebSocketSubscriptionType, HashSet<Uuid>> = HashMap::new();
+    for session in server.list_sessions().await {
+        addresses
+            .entry(session.address)
+            .or_default()
+            .insert(session.uuid);
+        for topic in session.subscriptions {
+            topics.entry(topic).or_default().insert(session.uuid);
+        }
+    }
+
+    for address in ADDRESSES {
+        assert_eq!(
+            server.sessions_for_address(address).await,
+            addresses.remove(*address).unwrap_or_default(),
+            "Index for {address} is out of sync"
+        );
+    }
+    for topic in WebSocketSubscriptionType::ALL {
+        assert_eq!(
+            server.sessions_for_topic(topic).await,
+            topics.remove(topic).unwrap_or_default(),
+            "Index for {topic} is out of sync"
+        );
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn index_survives_concurrent_churn() {
+    let server = WebSocketServer::new();
+    // Few enough uuids that sessions regularly supersede each other
+    let uuids: Vec<Uuid> = (0..32).map(|_| Uuid::new_v4()).collect();
+
+    let tasks = (0..TASKS).map(|seed| {
+        let server = server.clone();
+        let uuids = uuids.clone();
+        tokio::spawn(async move {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut inserted = Vec::new();
+
+            for _ in 0..OPERATIONS {
+                let uuid = *uuids.choose(&mut rng).unwrap();
+                let address = *ADDRESSES.choose(&mut rng).unwrap();
+                let topic = WebSocketSubscriptionType::ALL.choose(&mut rng).unwrap();
+
+                match rng.random_range(0..5) {
+                    0 => {
+                        let session = insert(&server, uuid, address, RecordingSink::new()).await;
+                        inserted.push(session);
+                    }
+                    1 => {
+                        server.cleanup_session(&uuid).await;
+                    }
+                    2 => {
+                        let _ = server.subscribe_to_event(&uuid, topic.clone()).await;
+                    }
+                    3 => server.unsubscribe_from_event(&uuid, topic).await,
+                    _ => {
+                        server.set_session_address(&uuid, address.to_owned()).await;
+                    }
+                }
+            }
+
+            inserted
+        })
+    });
+
+    // Hold on to the guards until the check, so none clean up behind its back
+    let mut inserted = Vec::new();
+    for task in tasks.collect::<Vec<_>>() {
+        inserted.extend(task.await.unwrap());
+    }
+    assert_consistent(&server).await;
+
+    drop(inserted);
+    time::timeout(Duration::from_secs(2), async {
+        while server.session_count().await > 0 {
+            time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("Sessions were never cleaned up");
+    assert_consistent(&server).await;
+}
+
+#[tokio::test]
+async fn send_to_address_follows_address_changes() {
+    let server = WebSocketServer::new();
+    let (first, second) = (RecordingSink::new(), RecordingSink::new());
+    let first_uuid = Uuid::new_v4();
+    let _first = insert(&server, first_uuid, "guest", first.clone()).await;
+    let _second = insert(&server, Uuid::new_v4(), "kabc", second.clone()).await;
+
+    assert!(
+        server
+            .set_session_address(&first_uuid, "kabc".to_owned())
+            .await
+    );
+    server
+        .send_to_address_local("kabc", r#"{"number":1}"#)
+        .await;
+    server
+        .send_to_address_local("guest", r#"{"number":2}"#)
+        .await;
+
+    for sink in [first, second] {
+        time::timeout(Duration::from_secs(1), async {
+            while sink.messages().is_empty() {
+                time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(sink.messages(), vec![serde_json::json!({ "number": 1 })]);
+    }
+}
+
+#[tokio::test]
+async fn publish_only_reaches_indexed_subscribers() {
+    let server = WebSocketServer::new();
+    let (subscribed, unsubscribed) = (RecordingSink::new(), RecordingSink::new());
+    let subscribed_uuid = Uuid::new_v4();
+    let _subscribed = insert(&server, subscribed_uuid, "guest", subscribed.clone()).await;
+    let _unsubscribed = insert(&server, Uuid::new_v4(), "guest", unsubscribed.clone()).await;
+
+    server
+        .subscribe_to_event(&subscribed_uuid, WebSocketSubscriptionType::Names)
+        .await
+        .unwrap();
+    assert_eq!(
+        server
+            .sessions_for_topic(&WebSocketSubscriptionType::Names)
+            .await,
+        HashSet::from([subscribed_uuid])
+    );
+
+    server
+        .publish(WebSocketSubscriptionType::Names, r#"{"name":"a"}"#)
+        .await
+        .unwrap();
+    time::timeout(Duration::from_secs(1), async {
+        while subscribed.messages().is_empty() {
+            time::sleep(Duration::from_millis(5)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert!(unsubscribed.messages().is_empty());
+}
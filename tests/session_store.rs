@@ -0,0 +1,156 @@
+//! Session records are written behind the sessions they describe, and picked back up as lost
+//! sessions when the server comes back from a crash.
+
+use actix_ws_fuckery::models::websocket::{WebSocketSubscriptionType, WebSocketTokenData};
+use actix_ws_fuckery::policy::Scope;
+use actix_ws_fuckery::session_store::{
+    MemorySessionStore, SessionRecord, SessionStore, SledSessionStore,
+};
+use actix_ws_fuckery::ws::{InsertedSession, WebSocketServer, sink::RecordingSink};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+async fn insert(server: &WebSocketServer, uuid: Uuid, address: &str) -> InsertedSession {
+    server
+        .insert_session(
+            uuid,
+            RecordingSink::default(),
+            None,
+            WebSocketTokenData::new(address.to_owned(), None),
+            CancellationToken::new(),
+            std::convert::identity,
+        )
+        .await
+}
+
+#[actix_web::test]
+async fn records_follow_sessions() {
+    let store = MemorySessionStore::default();
+    let server = WebSocketServer::new()
+        .with_session_store(store.clone())
+        .unwrap();
+
+    let uuid = Uuid::new_v4();
+    let inserted = insert(&server, uuid, "kabc").await;
+    server
+        .subscribe_to_event(&uuid, WebSocketSubscriptionType::Motd)
+        .await
+        .unwrap();
+    server.flush_session_store().await;
+
+    let records = store.load().unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].uuid, uuid);
+    assert_eq!(records[0].address, "kabc");
+    assert_eq!(records[0].resume_token, inserted.resume_token);
+    assert!(
+        records[0]
+            .subscriptions
+            .contains(&WebSocketSubscriptionType::Motd)
+    );
+
+    server.cleanup_session(&uuid).await;
+    server.flush_session_store().await;
+    assert!(store.load().unwrap().is_empty());
+}
+
+#[actix_web::test]
+async fn sessions_lost_in_a_crash_can_resume() {
+    let store = MemorySessionStore::default();
+    let crashed = WebSocketServer::new()
+        .with_session_store(store.clone())
+        .unwrap();
+
+    let uuid = Uuid::new_v4();
+    let inserted = insert(&crashed, uuid, "kabc").await;
+    crashed
+        .subscribe_to_event(&uuid, WebSocketSubscriptionType::Motd)
+        .await
+        .unwrap();
+    crashed.flush_session_store().await;
+    // A crash never gets as far as cleaning the session up
+    std::mem::forget(inserted.guard);
+
+    let server = WebSocketServer::new()
+        .with_session_store(store.clone())
+        .unwrap();
+    let lost = server.lost_sessions();
+    assert_eq!(lost.len(), 1);
+    assert_eq!(lost[0].uuid, uuid);
+    assert_eq!(lost[0].address, "kabc");
+    assert!(
+        store.load().unwrap().is_empty(),
+        "Lost sessions should be taken out of the store"
+    );
+
+    let resumed = server
+        .redeem_resume_token(&inserted.resume_token)
+        .await
+        .expect("Resume token from before the crash was rejected");
+    assert_eq!(resumed.address, "kabc");
+    assert_eq!(resumed.private_key, None);
+    assert!(
+        resumed
+            .subscriptions
+            .contains(&WebSocketSubscriptionType::Motd)
+    );
+
+    assert!(server.lost_sessions().is_empty());
+    assert!(
+        server
+            .redeem_resume_token(&inserted.resume_token)
+            .await
+            .is_none(),
+        "Resume tokens should only be redeemable once"
+    );
+}
+
+#[actix_web::test]
+async fn clean_shutdown_leaves_nothing_lost() {
+    let store = MemorySessionStore::default();
+    let server = WebSocketServer::new()
+        .with_session_store(store.clone())
+        .unwrap();
+
+    let inserted = insert(&server, Uuid::new_v4(), "kabc").await;
+    std::mem::forget(inserted.guard);
+    server.shutdown().await;
+
+    assert!(store.load().unwrap().is_empty());
+}
+
+/// Open a sled store again, giving its previous handle's background flusher time to let go of the lock.
+fn reopen(path: &std::path::Path) -> SledSessionStore {
+    for _ in 0..50 {
+        if let Ok(store) = SledSessionStore::open(path) {
+            return store;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    SledSessionStore::open(path).unwrap()
+}
+
+#[test]
+fn sled_store_keeps_records_across_reopening() {
+    let path = std::env::temp_dir().join(format!("session-store-{}", Uuid::new_v4()));
+    let record = SessionRecord {
+        uuid: Uuid::new_v4(),
+        address: "kabc".to_owned(),
+        connected_at: 1_700_000_000_000,
+        subscriptions: [WebSocketSubscriptionType::Blocks].into(),
+        resume_token: Uuid::new_v4(),
+        scopes: [Scope::ReadOnly].into(),
+    };
+
+    let store = SledSessionStore::open(&path).unwrap();
+    store.write(std::slice::from_ref(&record), &[]).unwrap();
+    drop(store);
+
+    let store = reopen(&path);
+    assert_eq!(store.load().unwrap(), vec![record.clone()]);
+    store.write(&[], &[record.uuid]).unwrap();
+    assert!(store.load().unwrap().is_empty());
+    drop(store);
+
+    let _ = std::fs::remove_dir_all(path);
+}
@@ -0,0 +1,73 @@
+//! Socket.IO clients are admitted just like gateway connections, going by the token in their
+//! connect packet.
+#![cfg(feature = "socketio")]
+
+use std::time::Duration;
+
+use actix_codec::Framed;
+use actix_web::rt::time;
+use actix_ws_fuckery::config::WebSocketServerConfig;
+use actix_ws_fuckery::test_utils::TestServer;
+use awc::BoxedSocket;
+use awc::ws::{Codec, Frame, Message};
+use futures::{SinkExt, StreamExt};
+use uuid::Uuid;
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Connect over Socket.IO with `token`, returning the server's answer to the connect packet.
+async fn connect(server: &TestServer, token: Uuid) -> String {
+    let url = format!(
+        "ws://{}/socket.io/?EIO=4&transport=websocket",
+        server.addr()
+    );
+    let (_, mut socket) = awc::Client::default().ws(url).connect().await.unwrap();
+    assert!(
+        next_text(&mut socket).await.starts_with('0'),
+        "Expected an open packet"
+    );
+
+    let auth = serde_json::json!({ "token": token });
+    socket
+        .send(Message::Text(format!("40{auth}").into()))
+        .await
+        .unwrap();
+    next_text(&mut socket).await
+}
+
+async fn next_text(socket: &mut Framed<BoxedSocket, Codec>) -> String {
+    match time::timeout(TIMEOUT, socket.next()).await.unwrap() {
+        Some(Ok(Frame::Text(text))) => String::from_utf8(text.to_vec()).unwrap(),
+        other => panic!("Expected a text frame, got {other:?}"),
+    }
+}
+
+#[actix_web::test]
+async fn sessions_connect_with_their_token() {
+    let server = TestServer::start().await.unwrap();
+    let token = server
+        .issue_token("ksocketio0", Some("hunter2".into()))
+        .await;
+
+    let ack = connect(&server, token).await;
+    assert!(ack.starts_with("40"), "{ack}");
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn guests_are_refused_when_disallowed() {
+    let server = TestServer::start_with_config(WebSocketServerConfig {
+        allow_guests: false,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let token = server.issue_token("kguest0000", None).await;
+
+    let refusal = connect(&server, token).await;
+    assert_eq!(refusal, r#"44{"message":"Guest connections are disabled"}"#);
+    assert_eq!(server.server().session_count().await, 0);
+
+    server.stop().await;
+}
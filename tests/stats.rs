@@ -0,0 +1,108 @@
+//! `stats` subscribers are sent the gateway's stats every `stats_interval`.
+
+use std::time::Duration;
+
+use actix_web::rt::time;
+use actix_ws_fuckery::config::WebSocketServerConfig;
+use actix_ws_fuckery::models::websocket::{
+    WebSocketSubscriptionType, WebSocketTokenData, state::SessionState,
+};
+use actix_ws_fuckery::stats::MinuteCounter;
+use actix_ws_fuckery::ws::{InsertedSession, WebSocketServer, sink::RecordingSink};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+struct TestSession {
+    sink: RecordingSink,
+    _inserted: InsertedSession,
+}
+
+async fn connect(
+    server: &WebSocketServer,
+    subscriptions: &[WebSocketSubscriptionType],
+) -> TestSession {
+    let sink = RecordingSink::new();
+    let uuid = Uuid::new_v4();
+    let inserted = server
+        .insert_session(
+            uuid,
+            sink.clone(),
+            None,
+            WebSocketTokenData::guest(),
+            CancellationToken::new(),
+            std::convert::identity,
+        )
+        .await;
+    server.transition(&uuid, SessionState::Ready).await;
+    for topic in subscriptions {
+        server
+            .subscribe_to_event(&uuid, topic.clone())
+            .await
+            .unwrap();
+    }
+
+    TestSession {
+        sink,
+        _inserted: inserted,
+    }
+}
+
+fn stats_events(session: &TestSession) -> Vec<serde_json::Value> {
+    session
+        .sink
+        .messages()
+        .into_iter()
+        .filter(|x| x["event"] == "stats")
+        .collect()
+}
+
+#[actix_web::test]
+async fn subscribers_are_sent_stats_periodically() {
+    let server = WebSocketServer::with_config(WebSocketServerConfig {
+        stats_interval: Duration::from_millis(20),
+        ..Default::default()
+    });
+    let watcher = connect(&server, &[WebSocketSubscriptionType::Stats]).await;
+    let bystander = connect(&server, &[]).await;
+
+    time::sleep(Duration::from_millis(70)).await;
+    let events = stats_events(&watcher);
+    assert!(events.len() >= 2, "{events:?}");
+    assert_eq!(events[0]["stats"]["connected"], 2);
+    assert!(stats_events(&bystander).is_empty());
+}
+
+#[cfg(feature = "krist")]
+#[actix_web::test]
+async fn stats_count_transactions_and_carry_the_work() {
+    let server = WebSocketServer::new();
+    server.set_work(1234);
+    for _ in 0..3 {
+        server
+            .publish(WebSocketSubscriptionType::Transactions, "{}")
+            .await
+            .unwrap();
+    }
+
+    let stats = server.stats().await;
+    assert_eq!(stats.work, 1234);
+    assert_eq!(stats.transactions_per_minute, 3);
+}
+
+#[tokio::test(start_paused = true)]
+async fn minute_counter_forgets_after_a_minute() {
+    let counter = MinuteCounter::default();
+    counter.record();
+    counter.record();
+    assert_eq!(counter.count(), 2);
+
+    tokio::time::advance(Duration::from_secs(30)).await;
+    counter.record();
+    assert_eq!(counter.count(), 3);
+
+    tokio::time::advance(Duration::from_secs(31)).await;
+    assert_eq!(counter.count(), 1);
+
+    tokio::time::advance(Duration::from_secs(60)).await;
+    assert_eq!(counter.count(), 0);
+}
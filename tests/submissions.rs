@@ -0,0 +1,130 @@
+//! Block solutions are only accepted once within the submission window, so a retried submission
+//! can't be rewarded twice.
+#![cfg(feature = "krist")]
+
+use std::time::Duration;
+
+use actix_ws_fuckery::balances::{BalanceStore, MemoryBalanceStore};
+use actix_ws_fuckery::config::{EconomyConfig, WebSocketServerConfig};
+use actix_ws_fuckery::models::websocket::{WebSocketTokenData, state::SessionState};
+use actix_ws_fuckery::submissions::SubmissionCache;
+use actix_ws_fuckery::test_utils::TestSession;
+use actix_ws_fuckery::ws::WebSocketServer;
+
+#[tokio::test(start_paused = true)]
+async fn duplicates_are_refused_until_they_expire() {
+    let cache = SubmissionCache::new(Duration::from_secs(60));
+
+    assert!(cache.record("kabcdef123", "1"));
+    assert!(!cache.record("kabcdef123", "1"));
+    // The same nonce for another address is a different solution
+    assert!(cache.record("k123456789", "1"));
+
+    tokio::time::advance(Duration::from_secs(60)).await;
+    assert!(cache.record("kabcdef123", "1"));
+    assert_eq!(cache.len(), 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn oldest_submissions_are_forgotten_past_capacity() {
+    let cache = SubmissionCache::with_capacity(Duration::from_secs(60), 2);
+
+    for nonce in ["1", "2", "3"] {
+        assert!(cache.record("kabcdef123", nonce));
+    }
+    assert_eq!(cache.len(), 2);
+    assert!(cache.record("kabcdef123", "1"));
+    assert!(!cache.record("kabcdef123", "3"));
+}
+
+#[tokio::test(start_paused = true)]
+async fn forgotten_submissions_can_be_retried() {
+    let cache = SubmissionCache::new(Duration::from_secs(60));
+
+    assert!(cache.record("kabcdef123", "1"));
+    cache.forget("kabcdef123", "1");
+    assert!(cache.is_empty());
+    assert!(cache.record("kabcdef123", "1"));
+}
+
+/// Handle `message` as if a logged in session sent it, returning the reply.
+async fn send(server: &WebSocketServer, message: serde_json::Value) -> serde_json::Value {
+    let data = WebSocketTokenData::new("kminer0000".to_owned(), Some("secret".to_owned()));
+    let session = TestSession::connect(server, data, SessionState::Ready).await;
+    session.send(message).await
+}
+
+fn submission(id: usize, nonce: &str) -> serde_json::Value {
+    serde_json::json!({
+        "id": id,
+        "type": "submit_block",
+        "address": "kminer0000",
+        "nonce": nonce,
+    })
+}
+
+#[actix_web::test]
+async fn accepted_solutions_are_rewarded_once() {
+    let config = WebSocketServerConfig {
+        economy: EconomyConfig {
+            block_reward: 25,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let balances = MemoryBalanceStore::default();
+    let server = WebSocketServer::with_config(config).with_balance_store(balances.clone());
+
+    let reply = send(&server, submission(1, "42")).await;
+    assert_eq!(reply["ok"], true, "{reply}");
+    assert_eq!(reply["id"], 1);
+    assert_eq!(reply["responding_to"], "submit_block");
+    assert_eq!(reply["address"], "kminer0000");
+    assert_eq!(reply["reward"], 25);
+    assert_eq!(reply["balance"], 25);
+    assert_eq!(balances.get("kminer0000").unwrap(), 25);
+
+    let reply = send(&server, submission(2, "42")).await;
+    assert_eq!(reply["error"], "duplicate_submission");
+    assert_eq!(balances.get("kminer0000").unwrap(), 25);
+
+    let reply = send(&server, submission(3, "43")).await;
+    assert_eq!(reply["balance"], 50);
+}
+
+#[actix_web::test]
+async fn submissions_without_a_balance_store_are_refused() {
+    let server = WebSocketServer::new();
+
+    let reply = send(&server, submission(1, "42")).await;
+    assert_eq!(reply["ok"], false);
+    assert_eq!(reply["error"], "mining_unavailable");
+    assert!(server.submissions().is_empty());
+}
+
+#[actix_web::test]
+async fn resubmitted_solutions_get_a_specific_error() {
+    let server = WebSocketServer::new().with_balance_store(MemoryBalanceStore::default());
+    assert!(server.submissions().record("kminer0000", "42"));
+
+    let reply = send(&server, submission(1, "42")).await;
+    assert_eq!(reply["ok"], false);
+    assert_eq!(reply["id"], 1);
+    assert_eq!(reply["error"], "duplicate_submission");
+}
+
+#[actix_web::test]
+async fn oversized_nonces_are_refused() {
+    let server = WebSocketServer::new();
+
+    let message = serde_json::json!({
+        "id": 1,
+        "type": "submit_block",
+        "address": "kminer0000",
+        "nonce": "n".repeat(25),
+    });
+    let reply = send(&server, message).await;
+    assert_eq!(reply["error"], "invalid_parameter");
+    assert_eq!(reply["parameter"], "nonce");
+    assert!(server.submissions().is_empty());
+}
@@ -0,0 +1,54 @@
+use actix_ws_fuckery::models::websocket::WebSocketSubscriptionType;
+
+#[test]
+fn display_round_trips_through_from_str() {
+    for level in WebSocketSubscriptionType::ALL {
+        let parsed: WebSocketSubscriptionType = level.to_string().parse().unwrap();
+        assert_eq!(&parsed, level);
+    }
+}
+
+#[test]
+fn serde_round_trips() {
+    for level in WebSocketSubscriptionType::ALL {
+        let json = serde_json::to_string(level).unwrap();
+        let parsed: WebSocketSubscriptionType = serde_json::from_str(&json).unwrap();
+        assert_eq!(&parsed, level);
+    }
+}
+
+#[test]
+fn serde_name_matches_display() {
+    for level in WebSocketSubscriptionType::ALL {
+        let json = serde_json::to_value(level).unwrap();
+        assert_eq!(json, serde_json::Value::String(level.to_string()));
+    }
+}
+
+#[test]
+fn names_are_unique() {
+    let mut names: Vec<&str> = WebSocketSubscriptionType::ALL
+        .iter()
+        .map(WebSocketSubscriptionType::as_str)
+        .collect();
+    names.sort();
+    names.dedup();
+    assert_eq!(names.len(), WebSocketSubscriptionType::ALL.len());
+}
+
+#[test]
+fn unknown_level_lists_valid_ones() {
+    let error = serde_json::from_str::<WebSocketSubscriptionType>("\"bogus\"").unwrap_err();
+    for level in WebSocketSubscriptionType::ALL {
+        assert!(error.to_string().contains(level.as_str()));
+    }
+}
+
+#[test]
+fn works_as_a_map_key() {
+    let json = r#"{"transactions": 1, "ownNames": 2}"#;
+    let map: std::collections::HashMap<WebSocketSubscriptionType, u32> =
+        serde_json::from_str(json).unwrap();
+    assert_eq!(map[&WebSocketSubscriptionType::Transactions], 1);
+    assert_eq!(map[&WebSocketSubscriptionType::OwnNames], 2);
+}
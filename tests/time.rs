@@ -0,0 +1,86 @@
+//! Expiry and timeout behavior, tested on tokio's paused clock so nothing actually waits.
+//!
+//! With the clock paused, sleeping advances time as soon as every task is idle.
+
+use std::time::Duration;
+
+use actix_ws_fuckery::idempotency::{Begin, IdempotencyCache, IdempotencyKey};
+use actix_ws_fuckery::models::websocket::WebSocketTokenData;
+use actix_ws_fuckery::ratelimit::RateLimiter;
+use actix_ws_fuckery::ws::{WebSocketServer, sink::RecordingSink};
+use tokio::time;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+#[tokio::test(start_paused = true)]
+async fn tokens_expire_after_thirty_seconds() {
+    let server = WebSocketServer::new();
+    let early = server
+        .obtain_token(WebSocketTokenData::new("guest".to_owned(), None))
+        .await;
+    let late = server
+        .obtain_token(WebSocketTokenData::new("guest".to_owned(), None))
+        .await;
+
+    time::sleep(Duration::from_secs(29)).await;
+    assert!(server.use_token(&early).await.is_ok());
+
+    time::sleep(Duration::from_secs(2)).await;
+    assert!(server.use_token(&late).await.is_err());
+}
+
+#[tokio::test(start_paused = true)]
+async fn rate_limit_window_resets() {
+    let limiter = RateLimiter::default();
+    assert!(limiter.check("127.0.0.1", 1));
+    assert!(!limiter.check("127.0.0.1", 1));
+
+    time::sleep(Duration::from_secs(59)).await;
+    assert!(!limiter.check("127.0.0.1", 1));
+
+    time::sleep(Duration::from_secs(1)).await;
+    assert!(limiter.check("127.0.0.1", 1));
+}
+
+#[tokio::test(start_paused = true)]
+async fn idempotent_responses_are_forgotten_after_the_window() {
+    let cache = IdempotencyCache::new(Duration::from_secs(300));
+    let key = IdempotencyKey {
+        scope: "kabc".to_owned(),
+        key: "retry-me".to_owned(),
+    };
+
+    assert_eq!(cache.begin(key.clone()), Begin::New);
+    cache.complete(&key, "response".to_owned());
+    assert_eq!(
+        cache.begin(key.clone()),
+        Begin::Replay("response".to_owned())
+    );
+
+    time::sleep(Duration::from_secs(300)).await;
+    assert_eq!(cache.begin(key), Begin::New);
+}
+
+#[tokio::test(start_paused = true)]
+async fn scheduled_broadcasts_wait_for_their_time() {
+    let server = WebSocketServer::new();
+    let sink = RecordingSink::new();
+    let _session = server
+        .insert_session(
+            Uuid::new_v4(),
+            sink.clone(),
+            None,
+            WebSocketTokenData::new("guest".to_owned(), None),
+            CancellationToken::new(),
+            std::convert::identity,
+        )
+        .await;
+
+    server.broadcast_after(Duration::from_secs(60), r#"{"number":1}"#);
+
+    time::sleep(Duration::from_secs(59)).await;
+    assert!(sink.messages().is_empty());
+
+    time::sleep(Duration::from_secs(2)).await;
+    assert_eq!(sink.messages(), vec![serde_json::json!({ "number": 1 })]);
+}
@@ -0,0 +1,112 @@
+//! The validation helpers clients can run on their input before sending it.
+#![cfg(feature = "krist")]
+
+use actix_ws_fuckery::validation::{
+    ValidationError, is_valid_address, is_valid_name, validate_address, validate_amount,
+    validate_metadata, validate_metadata_within, validate_name, validate_recipient,
+};
+
+#[test]
+fn addresses() {
+    assert!(is_valid_address("kabcdef123"));
+    assert!(is_valid_address("0123456789"));
+    assert!(is_valid_address("abcdef0123"));
+
+    assert_eq!(validate_address(""), Err(ValidationError::Empty));
+    assert_eq!(
+        validate_address("kabc"),
+        Err(ValidationError::WrongLength {
+            expected: 10,
+            actual: 4
+        })
+    );
+    assert_eq!(
+        validate_address("kABCDEF123"),
+        Err(ValidationError::InvalidCharacter {
+            character: 'A',
+            index: 1
+        })
+    );
+    // Only v2 addresses may use letters past `f`
+    assert_eq!(
+        validate_address("012345678g"),
+        Err(ValidationError::InvalidCharacter {
+            character: 'g',
+            index: 9
+        })
+    );
+}
+
+#[test]
+fn names() {
+    assert!(is_valid_name("example"));
+    assert!(is_valid_name("example.kst"));
+    assert!(is_valid_name(&"a".repeat(64)));
+
+    assert_eq!(validate_name(".kst"), Err(ValidationError::Empty));
+    assert_eq!(
+        validate_name(&"a".repeat(65)),
+        Err(ValidationError::TooLong {
+            max: 64,
+            actual: 65
+        })
+    );
+    assert_eq!(
+        validate_name("ex-ample"),
+        Err(ValidationError::InvalidCharacter {
+            character: '-',
+            index: 2
+        })
+    );
+}
+
+#[test]
+fn recipients_are_addresses_or_names() {
+    assert_eq!(validate_recipient("kabcdef123"), Ok(()));
+    assert_eq!(validate_recipient("example.kst"), Ok(()));
+    assert_eq!(validate_recipient("donate@example.kst"), Ok(()));
+    assert!(validate_recipient("example").is_err());
+    assert!(validate_recipient("donate@ex ample.kst").is_err());
+}
+
+#[test]
+fn amounts() {
+    assert_eq!(validate_amount(1, 1), Ok(()));
+    assert_eq!(
+        validate_amount(0, 1),
+        Err(ValidationError::TooSmall { min: 1, actual: 0 })
+    );
+}
+
+#[test]
+fn metadata() {
+    assert_eq!(validate_metadata("message=hi;\nreturn=kabcdef123"), Ok(()));
+    assert_eq!(validate_metadata(""), Err(ValidationError::Empty));
+    assert_eq!(
+        validate_metadata(&"a".repeat(256)),
+        Err(ValidationError::TooLong {
+            max: 255,
+            actual: 256
+        })
+    );
+    assert_eq!(
+        validate_metadata("tab\there"),
+        Err(ValidationError::InvalidCharacter {
+            character: '\t',
+            index: 3
+        })
+    );
+    assert_eq!(validate_metadata_within("hello", 5), Ok(()));
+    assert_eq!(
+        validate_metadata_within("hello!", 5),
+        Err(ValidationError::TooLong { max: 5, actual: 6 })
+    );
+    assert_eq!(
+        ValidationError::InvalidCharacter {
+            character: '\t',
+            index: 3
+        }
+        .to_string(),
+        r"Invalid character '\t' at position 3"
+    );
+}
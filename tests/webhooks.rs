@@ -0,0 +1,76 @@
+//! Logins and mined blocks are POSTed to the configured webhooks.
+#![cfg(all(feature = "webhooks", feature = "krist"))]
+
+use std::net::TcpListener;
+use std::time::Duration;
+
+use actix_web::{App, HttpServer, rt::time, web};
+use actix_ws_fuckery::balances::MemoryBalanceStore;
+use actix_ws_fuckery::models::websocket::{WebSocketTokenData, state::SessionState};
+use actix_ws_fuckery::test_utils::TestSession;
+use actix_ws_fuckery::webhooks::WebhookConfig;
+use actix_ws_fuckery::ws::WebSocketServer;
+use tokio::sync::mpsc;
+
+/// A webhook receiver passing on the body of everything POSTed to it.
+fn receiver() -> (String, mpsc::UnboundedReceiver<serde_json::Value>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let (sender, receiver) = mpsc::unbounded_channel();
+
+    let server = HttpServer::new(move || {
+        let sender = sender.clone();
+        App::new().route(
+            "/hook",
+            web::post().to(move |body: web::Json<serde_json::Value>| {
+                let _ = sender.send(body.into_inner());
+                async { "" }
+            }),
+        )
+    })
+    .workers(1)
+    .listen(listener)
+    .unwrap()
+    .run();
+    actix_web::rt::spawn(server);
+
+    (url, receiver)
+}
+
+async fn next(receiver: &mut mpsc::UnboundedReceiver<serde_json::Value>) -> serde_json::Value {
+    time::timeout(Duration::from_secs(5), receiver.recv())
+        .await
+        .expect("No webhook arrived")
+        .expect("Receiver went away")
+}
+
+#[actix_web::test]
+async fn logins_and_blocks_are_delivered() {
+    let (url, mut hooks) = receiver();
+    let mut config = WebhookConfig::new(vec![url], "secret");
+    config.events = vec!["login".to_owned(), "block".to_owned()];
+    let server = WebSocketServer::new()
+        .with_balance_store(MemoryBalanceStore::default())
+        .with_webhooks(config)
+        .unwrap();
+    let session =
+        TestSession::connect(&server, WebSocketTokenData::guest(), SessionState::Ready).await;
+
+    let login = serde_json::json!({ "id": 1, "type": "login", "privatekey": "hunter2" });
+    assert_eq!(session.send(login).await["ok"], true);
+    let hook = next(&mut hooks).await;
+    assert_eq!(hook["event"], "login");
+    assert_eq!(hook["session"], session.uuid().to_string());
+
+    let submission = serde_json::json!({
+        "id": 2,
+        "type": "submit_block",
+        "address": "kminer0000",
+        "nonce": "42",
+    });
+    assert_eq!(session.send(submission).await["ok"], true);
+    let hook = next(&mut hooks).await;
+    assert_eq!(hook["event"], "block");
+    assert_eq!(hook["block"]["address"], "kminer0000");
+    assert_eq!(hook["block"]["nonce"], "42");
+}
@@ -0,0 +1,155 @@
+//! WebTransport sessions carry the gateway protocol, one JSON message per line, over a stream the
+//! client opens once its session is accepted.
+#![cfg(feature = "webtransport")]
+
+use std::{future::poll_fn, net::SocketAddr, sync::Arc, time::Duration};
+
+use actix_ws_fuckery::models::websocket::WebSocketTokenData;
+use actix_ws_fuckery::webtransport;
+use actix_ws_fuckery::ws::WebSocketServer;
+use bytes::Bytes;
+use futures::StreamExt;
+use h3::{
+    client::{RequestStream, SendRequest},
+    ext::Protocol,
+};
+use http::StatusCode;
+use quinn::crypto::rustls::QuicClientConfig;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use tokio_util::codec::{FramedRead, LinesCodec};
+use uuid::Uuid;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A WebTransport endpoint for `server`, returning its address and the certificate to trust.
+fn start(server: &WebSocketServer) -> (SocketAddr, CertificateDer<'static>) {
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+    let cert = certified.cert.der().clone();
+    let key = PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der());
+    let tls = rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(vec![cert.clone()], PrivateKeyDer::Pkcs8(key))
+        .unwrap();
+
+    let endpoint = webtransport::endpoint("127.0.0.1:0".parse().unwrap(), tls).unwrap();
+    let addr = endpoint.local_addr().unwrap();
+    tokio::spawn(webtransport::serve(server.clone(), endpoint));
+
+    (addr, cert)
+}
+
+/// A client's QUIC connection and HTTP/3 session, with the CONNECT request it sent.
+struct Client {
+    _endpoint: quinn::Endpoint,
+    connection: quinn::Connection,
+    /// The HTTP/3 connection is closed once this is dropped.
+    _requests: SendRequest<h3_quinn::OpenStreams, Bytes>,
+    connect: RequestStream<h3_quinn::BidiStream<Bytes>, Bytes>,
+    status: StatusCode,
+}
+
+/// Open a WebTransport session to `path` on the endpoint at `addr`.
+async fn open(addr: SocketAddr, cert: CertificateDer<'static>, path: &str) -> Client {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(cert).unwrap();
+    let mut tls = rustls::ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    tls.alpn_protocols = vec![b"h3".to_vec()];
+    let config = quinn::ClientConfig::new(Arc::new(QuicClientConfig::try_from(tls).unwrap()));
+
+    let mut endpoint = quinn::Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+    endpoint.set_default_client_config(config);
+    let connection = endpoint.connect(addr, "localhost").unwrap().await.unwrap();
+
+    let (mut driver, mut requests) = h3::client::builder()
+        .enable_extended_connect(true)
+        .build::<_, _, Bytes>(h3_quinn::Connection::new(connection.clone()))
+        .await
+        .unwrap();
+    tokio::spawn(async move { poll_fn(|cx| driver.poll_close(cx)).await });
+
+    let mut request = http::Request::connect(format!("https://localhost{path}"))
+        .body(())
+        .unwrap();
+    request.extensions_mut().insert(Protocol::WEB_TRANSPORT);
+    let mut connect = requests.send_request(request).await.unwrap();
+    let status = connect.recv_response().await.unwrap().status();
+
+    Client {
+        _endpoint: endpoint,
+        connection,
+        _requests: requests,
+        connect,
+        status,
+    }
+}
+
+#[tokio::test]
+async fn sessions_speak_the_gateway_protocol() {
+    let server = WebSocketServer::new();
+    let (addr, cert) = start(&server);
+    let data = WebSocketTokenData::new("kwebtrans0".to_owned(), Some("secret".to_owned()));
+    let token = server.obtain_token(data).await;
+
+    let client = open(addr, cert, &format!("/gateway/{token}")).await;
+    assert_eq!(client.status, StatusCode::OK);
+
+    let (mut send, recv) = client.connection.open_bi().await.unwrap();
+    // A WEBTRANSPORT_STREAM signal, then the session's id, which is its CONNECT stream's
+    let session_id = client.connect.id().into_inner();
+    assert!(
+        session_id < 64,
+        "{session_id} doesn't fit a one byte varint"
+    );
+    send.write_all(&[0x40, 0x41, session_id as u8])
+        .await
+        .unwrap();
+    let mut lines = FramedRead::new(recv, LinesCodec::new());
+    let mut next = async || {
+        let line = tokio::time::timeout(TIMEOUT, lines.next()).await.unwrap();
+        serde_json::from_str::<serde_json::Value>(&line.unwrap().unwrap()).unwrap()
+    };
+
+    let hello = next().await;
+    assert_eq!(hello["type"], "hello", "{hello}");
+    assert_eq!(server.session_count().await, 1);
+
+    send.write_all(b"{\"id\":1,\"type\":\"me\"}\n")
+        .await
+        .unwrap();
+    let reply = loop {
+        let message = next().await;
+        if message["type"] != "keepalive" {
+            break message;
+        }
+    };
+    assert_eq!(reply["ok"], true, "{reply}");
+    assert_eq!(reply["id"], 1);
+    assert_eq!(reply["responding_to"], "me");
+    assert_eq!(reply["is_guest"], false);
+
+    // Hanging up ends the session
+    drop(client.connect);
+    client.connection.close(0u32.into(), b"done");
+    tokio::time::timeout(TIMEOUT, async {
+        while server.session_count().await > 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn unknown_tokens_are_refused() {
+    let server = WebSocketServer::new();
+    let (addr, cert) = start(&server);
+
+    let client = open(addr, cert.clone(), &format!("/gateway/{}", Uuid::new_v4())).await;
+    assert_eq!(client.status, StatusCode::UNAUTHORIZED);
+
+    let client = open(addr, cert, "/elsewhere").await;
+    assert_eq!(client.status, StatusCode::NOT_FOUND);
+    assert_eq!(server.session_count().await, 0);
+}